@@ -0,0 +1,98 @@
+//! 比较一个大请求分多次到达时, 每次从头解析和增量解析的代价
+//!
+//! cargo bench --bench resp
+
+use bytes::BytesMut;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use redis::resp::{
+    frame::RespFrame, BulkString, RespArray, RespDecode, RespEncode, RespError, RespParser,
+};
+
+/// 类似 MSET key0 value0 key1 value1 ... 的请求
+fn request(n: usize) -> Vec<u8> {
+    let args = (0..n)
+        .map(|i| BulkString::new(format!("key{i}")).into())
+        .collect::<Vec<RespFrame>>();
+    RespFrame::from(RespArray::new(args)).encode()
+}
+
+/// 之前的做法: 每次收到数据都从头计算整个frame的长度
+fn decode_rescan(data: &[u8], chunk: usize) -> RespFrame {
+    let mut buf = BytesMut::new();
+    for part in data.chunks(chunk) {
+        buf.extend_from_slice(part);
+        match RespFrame::decode(&mut buf) {
+            Ok(frame) => return frame,
+            Err(RespError::NotCompleteFrame) => continue,
+            Err(e) => panic!("{e}"),
+        }
+    }
+    panic!("frame is not complete")
+}
+
+fn decode_incremental(data: &[u8], chunk: usize) -> RespFrame {
+    let mut parser = RespParser::default();
+    let mut buf = BytesMut::new();
+    for part in data.chunks(chunk) {
+        buf.extend_from_slice(part);
+        if let Some((frame, _)) = parser.parse(&mut buf, None).unwrap() {
+            return frame;
+        }
+    }
+    panic!("frame is not complete")
+}
+
+fn fragmented(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fragmented");
+    for n in [1_000, 10_000] {
+        let data = request(n);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        // 和一次TCP读的大小差不多
+        let chunk = 1024;
+        group.bench_with_input(BenchmarkId::new("rescan", n), &data, |b, data| {
+            b.iter(|| decode_rescan(data, chunk))
+        });
+        group.bench_with_input(BenchmarkId::new("incremental", n), &data, |b, data| {
+            b.iter(|| decode_incremental(data, chunk))
+        });
+    }
+    group.finish();
+}
+
+fn whole(c: &mut Criterion) {
+    let mut group = c.benchmark_group("whole");
+    let data = request(1_000);
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("rescan", |b| b.iter(|| decode_rescan(&data, data.len())));
+    group.bench_function("incremental", |b| {
+        b.iter(|| decode_incremental(&data, data.len()))
+    });
+    group.finish();
+}
+
+/// 直接写入发送缓存和先生成Vec再复制
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    let value = bytes::Bytes::from(vec![b'x'; 1 << 20]);
+    group.throughput(Throughput::Bytes(value.len() as u64));
+    group.bench_function("to_vec", |b| {
+        b.iter(|| {
+            let mut dst = BytesMut::new();
+            let frame: RespFrame = BulkString::from(value.clone()).into();
+            dst.extend_from_slice(&frame.encode());
+            dst
+        })
+    });
+    group.bench_function("encode_to", |b| {
+        b.iter(|| {
+            let mut dst = BytesMut::new();
+            let frame: RespFrame = BulkString::from(value.clone()).into();
+            frame.encode_to(&mut dst);
+            dst
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, fragmented, whole, encode);
+criterion_main!(benches);
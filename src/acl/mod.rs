@@ -0,0 +1,373 @@
+//! ACL: 用户, 命令/key/channel的权限检查和ACL LOG
+//!
+//! 用户保存在内存中, 配置了aclfile时启动时从文件加载, ACL SAVE 写回文件。
+//! requirepass 等价于设置default用户的密码
+
+mod user;
+
+pub use user::{Denied, RuleError, Selector, User, CATEGORIES};
+
+use crate::{backend::now_ms, cmd::Command};
+use ring::digest::{digest, SHA256};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+use thiserror::Error;
+
+/// ACL中的密码只保存sha256, 和redis一样用小写的16进制表示
+pub fn sha256_hex(data: &[u8]) -> String {
+    digest(&SHA256, data)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// 同一个客户端重复触发的记录在这段时间内合并成一条
+const LOG_GROUPING_MS: u64 = 60_000;
+
+#[derive(Debug, Error)]
+pub enum AclError {
+    #[error("This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration.")]
+    NoAclFile,
+    #[error("{file}:{line}: {reason}")]
+    File {
+        file: String,
+        line: usize,
+        reason: String,
+    },
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// ACL LOG 中的一条记录
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    pub id: u64,
+    pub count: u64,
+    pub reason: &'static str,
+    /// 目前只有toplevel, 没有MULTI和脚本
+    pub context: &'static str,
+    pub object: String,
+    pub username: String,
+    pub client_info: String,
+    pub created: u64,
+    pub updated: u64,
+}
+
+#[derive(Debug, Default)]
+struct AclLog {
+    /// 最新的在前面
+    entries: VecDeque<LogEntry>,
+    next_id: u64,
+}
+
+#[derive(Debug)]
+pub struct AclState {
+    file: Option<PathBuf>,
+    users: RwLock<BTreeMap<String, Arc<User>>>,
+    log: Mutex<AclLog>,
+}
+
+impl Default for AclState {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+fn default_users() -> BTreeMap<String, Arc<User>> {
+    BTreeMap::from([("default".to_string(), Arc::new(User::default_user()))])
+}
+
+impl AclState {
+    pub fn new(file: Option<PathBuf>) -> Self {
+        Self {
+            file,
+            users: RwLock::new(default_users()),
+            log: Mutex::new(AclLog::default()),
+        }
+    }
+
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    pub fn user(&self, name: &str) -> Option<Arc<User>> {
+        self.users
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(name)
+            .cloned()
+    }
+
+    /// 按名字排序
+    pub fn users(&self) -> Vec<Arc<User>> {
+        self.users
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// ACL SETUSER, 用户不存在时创建。所有规则都合法时才会修改
+    pub fn set_user<S: AsRef<str>>(&self, name: &str, rules: &[S]) -> Result<(), RuleError> {
+        let mut users = self.users.write().unwrap_or_else(|e| e.into_inner());
+        let mut user = match users.get(name) {
+            Some(user) => User::clone(user),
+            None => User::new(name),
+        };
+        user.apply_rules(rules)?;
+        users.insert(name.to_string(), Arc::new(user));
+        Ok(())
+    }
+
+    /// ACL DELUSER, 返回删除的用户数量。default用户由调用者检查
+    pub fn del_users(&self, names: &[String]) -> usize {
+        let mut users = self.users.write().unwrap_or_else(|e| e.into_inner());
+        names
+            .iter()
+            .filter(|name| users.remove(name.as_str()).is_some())
+            .count()
+    }
+
+    /// requirepass 设置default用户的密码, 为空时default用户不需要密码
+    pub fn set_requirepass(&self, password: &str) {
+        let rules = match password {
+            "" => vec!["nopass".to_string()],
+            password => vec!["resetpass".to_string(), format!(">{}", password)],
+        };
+        let _ = self.set_user("default", &rules);
+    }
+
+    /// default用户需要密码或者被关闭时, 新连接需要先认证
+    pub fn requires_auth(&self) -> bool {
+        !self
+            .user("default")
+            .is_some_and(|u| u.is_enabled() && u.is_nopass())
+    }
+
+    pub fn authenticate(&self, username: &str, password: &[u8]) -> bool {
+        self.user(username)
+            .is_some_and(|u| u.is_enabled() && u.check_password(password))
+    }
+
+    /// 检查用户能否执行命令以及访问命令中的key, AUTH和HELLO总是可以执行
+    pub fn check(&self, username: &str, command: &Command) -> Result<(), Denied> {
+        if command.is_no_auth() {
+            return Ok(());
+        }
+        let Some(user) = self.user(username) else {
+            return Err(Denied::Command(command.name().to_string()));
+        };
+        user.check_command(
+            command.name(),
+            command.subcommand(),
+            command.categories(),
+            &command.keys(),
+            command.key_access(),
+        )
+    }
+
+    /// 记录一次权限检查或者认证失败, 最多保留max_len条
+    pub fn log(&self, denied: &Denied, username: &str, client_info: String, max_len: usize) {
+        let now = now_ms();
+        let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        let similar = log.entries.iter_mut().find(|e| {
+            e.reason == denied.reason()
+                && e.object == denied.object()
+                && e.username == username
+                && now.saturating_sub(e.updated) < LOG_GROUPING_MS
+        });
+        if let Some(entry) = similar {
+            entry.count += 1;
+            entry.updated = now;
+            entry.client_info = client_info;
+            return;
+        }
+        let id = log.next_id;
+        log.next_id += 1;
+        log.entries.push_front(LogEntry {
+            id,
+            count: 1,
+            reason: denied.reason(),
+            context: "toplevel",
+            object: denied.object().to_string(),
+            username: username.to_string(),
+            client_info,
+            created: now,
+            updated: now,
+        });
+        log.entries.truncate(max_len);
+    }
+
+    /// 最新的count条记录
+    pub fn log_entries(&self, count: usize) -> Vec<LogEntry> {
+        let log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        log.entries.iter().take(count).cloned().collect()
+    }
+
+    pub fn reset_log(&self) {
+        self.log
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entries
+            .clear();
+    }
+
+    /// ACL LOAD, 文件中有任何错误时保持原来的用户不变。文件中没有default用户时使用默认的default用户
+    pub fn load(&self) -> Result<(), AclError> {
+        let file = self.file.as_ref().ok_or(AclError::NoAclFile)?;
+        let content = std::fs::read_to_string(file)?;
+        let error = |line: usize, reason: String| AclError::File {
+            file: file.display().to_string(),
+            line,
+            reason,
+        };
+        let mut users = BTreeMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let (Some("user"), Some(name)) = (words.next(), words.next()) else {
+                return Err(error(i + 1, "should start with user keyword".to_string()));
+            };
+            if users.contains_key(name) {
+                return Err(error(i + 1, format!("Duplicate user '{}' found", name)));
+            }
+            let mut user = User::new(name);
+            let rules: Vec<&str> = words.collect();
+            user.apply_rules(&rules)
+                .map_err(|e| error(i + 1, e.to_string()))?;
+            users.insert(name.to_string(), Arc::new(user));
+        }
+        if !users.contains_key("default") {
+            users.extend(default_users());
+        }
+        *self.users.write().unwrap_or_else(|e| e.into_inner()) = users;
+        Ok(())
+    }
+
+    /// ACL SAVE, 先写临时文件再rename
+    pub fn save(&self) -> Result<(), AclError> {
+        let file = self.file.as_ref().ok_or(AclError::NoAclFile)?;
+        let mut content: String = self
+            .users()
+            .iter()
+            .map(|u| u.describe())
+            .collect::<Vec<_>>()
+            .join("\n");
+        content.push('\n');
+        let tmp = file.with_extension("acl.tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, file)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_requirepass() {
+        let acl = AclState::default();
+        assert!(!acl.requires_auth());
+        acl.set_requirepass("secret");
+        assert!(acl.requires_auth());
+        assert!(acl.authenticate("default", b"secret"));
+        assert!(!acl.authenticate("default", b"wrong"));
+        acl.set_requirepass("");
+        assert!(!acl.requires_auth());
+    }
+
+    #[test]
+    fn test_set_user_atomic() -> Result<()> {
+        let acl = AclState::default();
+        acl.set_user("alice", &["on", ">pw", "+get"])?;
+        assert!(acl.authenticate("alice", b"pw"));
+        assert!(acl.set_user("alice", &["off", "+bogus"]).is_err());
+        assert!(acl.user("alice").is_some_and(|u| u.is_enabled()));
+
+        acl.set_user("alice", &["off"])?;
+        assert!(!acl.authenticate("alice", b"pw"));
+        assert_eq!(acl.del_users(&["alice".to_string(), "bob".to_string()]), 1);
+        assert!(acl.user("alice").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_grouping() {
+        let acl = AclState::default();
+        let denied = Denied::Command("get".to_string());
+        acl.log(&denied, "alice", "id=1".to_string(), 10);
+        acl.log(&denied, "alice", "id=2".to_string(), 10);
+        acl.log(
+            &Denied::Key("k".to_string()),
+            "alice",
+            "id=2".to_string(),
+            10,
+        );
+        let entries = acl.log_entries(10);
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].reason, entries[0].count), ("key", 1));
+        assert_eq!((entries[1].reason, entries[1].count), ("command", 2));
+        assert_eq!(entries[1].client_info, "id=2");
+
+        for i in 0..5 {
+            acl.log(&Denied::Key(i.to_string()), "bob", String::new(), 3);
+        }
+        assert_eq!(acl.log_entries(10).len(), 3);
+        acl.reset_log();
+        assert!(acl.log_entries(10).is_empty());
+    }
+
+    #[test]
+    fn test_load_save() -> Result<()> {
+        let file = std::env::temp_dir().join(format!("redis-acl-test-{}.acl", std::process::id()));
+        std::fs::write(
+            &file,
+            "# users\nuser alice on >pw ~app:* +@read\nuser default on nopass ~* &* +@all\n",
+        )?;
+        let acl = AclState::new(Some(file.clone()));
+        acl.load()?;
+        assert!(acl.authenticate("alice", b"pw"));
+
+        acl.set_user("bob", &["on", "nopass", "+get"])?;
+        acl.save()?;
+        let acl = AclState::new(Some(file.clone()));
+        acl.load()?;
+        assert!(acl.user("bob").is_some());
+
+        std::fs::write(&file, "user alice on +bogus\n")?;
+        let err = acl.load().unwrap_err().to_string();
+        assert!(err.ends_with(
+            ":1: Error in ACL SETUSER modifier '+bogus': Unknown command or category name in ACL"
+        ));
+        assert!(acl.user("bob").is_some());
+        std::fs::remove_file(&file)?;
+
+        assert!(matches!(
+            AclState::default().load(),
+            Err(AclError::NoAclFile)
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,656 @@
+//! ACL用户和规则, 规则的写法和redis的ACL SETUSER一致:
+//!
+//! on/off, nopass, >password, <password, #hash, !hash, resetpass, reset,
+//! +command, -command, +command|subcommand, +@category, -@category, allcommands, nocommands,
+//! ~pattern, %R~pattern, %W~pattern, %RW~pattern, allkeys, resetkeys,
+//! &pattern, allchannels, resetchannels, (selector中的规则), clearselectors
+
+use super::sha256_hex;
+use crate::{cmd::COMMAND_TABLE, glob::glob_match};
+use std::fmt;
+use thiserror::Error;
+
+/// ACL CAT 列出的分类
+pub const CATEGORIES: &[&str] = &[
+    "keyspace",
+    "read",
+    "write",
+    "set",
+    "sortedset",
+    "list",
+    "hash",
+    "string",
+    "bitmap",
+    "hyperloglog",
+    "geo",
+    "stream",
+    "pubsub",
+    "admin",
+    "fast",
+    "slow",
+    "blocking",
+    "dangerous",
+    "connection",
+    "transaction",
+    "scripting",
+];
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Error in ACL SETUSER modifier '{rule}': {reason}")]
+pub struct RuleError {
+    pub rule: String,
+    pub reason: &'static str,
+}
+
+/// 权限检查失败的原因, 也是ACL LOG中的reason和object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denied {
+    /// 命令名, 子命令用"|"连接
+    Command(String),
+    Key(String),
+    Channel(String),
+    /// AUTH失败, 不会出现在权限检查中
+    Auth,
+}
+
+impl Denied {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Denied::Command(_) => "command",
+            Denied::Key(_) => "key",
+            Denied::Channel(_) => "channel",
+            Denied::Auth => "auth",
+        }
+    }
+
+    pub fn object(&self) -> &str {
+        match self {
+            Denied::Command(name) => name,
+            Denied::Key(key) | Denied::Channel(key) => key,
+            Denied::Auth => "AUTH",
+        }
+    }
+
+    /// 返回给客户端的NOPERM错误
+    pub fn error(&self, username: &str) -> String {
+        match self {
+            Denied::Command(name) => format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                username, name
+            ),
+            Denied::Key(_) => "NOPERM No permissions to access a key".to_string(),
+            Denied::Channel(_) => "NOPERM No permissions to access a channel".to_string(),
+            Denied::Auth => {
+                "WRONGPASS invalid username-password pair or user is disabled.".to_string()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CommandTarget {
+    All,
+    Category(String),
+    /// 命令名或者 命令|子命令
+    Command(String),
+}
+
+impl fmt::Display for CommandTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandTarget::All => write!(f, "@all"),
+            CommandTarget::Category(category) => write!(f, "@{}", category),
+            CommandTarget::Command(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeyPattern {
+    pattern: String,
+    read: bool,
+    write: bool,
+}
+
+impl fmt::Display for KeyPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.read, self.write) {
+            (true, true) => write!(f, "~{}", self.pattern),
+            (true, false) => write!(f, "%R~{}", self.pattern),
+            _ => write!(f, "%W~{}", self.pattern),
+        }
+    }
+}
+
+/// 一组命令, key和channel的权限。用户有一个root selector和任意个额外的selector,
+/// 只要有一个selector允许, 命令就可以执行
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selector {
+    /// 按顺序生效, 后面的规则覆盖前面的, 为空时等价于-@all
+    commands: Vec<(bool, CommandTarget)>,
+    keys: Vec<KeyPattern>,
+    channels: Vec<String>,
+}
+
+fn command_exists(name: &str) -> bool {
+    COMMAND_TABLE
+        .iter()
+        .any(|(n, _)| n.split('|').next() == Some(name) || *n == name)
+}
+
+impl Selector {
+    fn apply(&mut self, rule: &str) -> Result<(), &'static str> {
+        match rule.to_ascii_lowercase().as_str() {
+            "allcommands" => return self.apply("+@all"),
+            "nocommands" => return self.apply("-@all"),
+            "allkeys" => return self.apply("~*"),
+            "resetkeys" => {
+                self.keys.clear();
+                return Ok(());
+            }
+            "allchannels" => return self.apply("&*"),
+            "resetchannels" => {
+                self.channels.clear();
+                return Ok(());
+            }
+            _ => {}
+        }
+        match rule.as_bytes().first() {
+            Some(b'+') => self.apply_command(true, &rule[1..]),
+            Some(b'-') => self.apply_command(false, &rule[1..]),
+            Some(b'~') => self.add_key(&rule[1..], true, true),
+            Some(b'%') => {
+                let (flags, pattern) = rule[1..].split_once('~').ok_or("Syntax error")?;
+                if flags.is_empty() {
+                    return Err("Syntax error");
+                }
+                let (mut read, mut write) = (false, false);
+                for flag in flags.chars() {
+                    match flag.to_ascii_uppercase() {
+                        'R' => read = true,
+                        'W' => write = true,
+                        _ => return Err("Syntax error"),
+                    }
+                }
+                self.add_key(pattern, read, write)
+            }
+            Some(b'&') => {
+                if self.channels.iter().any(|c| c == "*") {
+                    return Err("Adding a pattern after the * pattern (or the 'allchannels' flag) is not valid and does not have any effect. Try 'resetchannels' to start with an empty list of channels");
+                }
+                let channel = rule[1..].to_string();
+                if !self.channels.contains(&channel) {
+                    self.channels.push(channel);
+                }
+                Ok(())
+            }
+            _ => Err("Syntax error"),
+        }
+    }
+
+    fn apply_command(&mut self, allow: bool, name: &str) -> Result<(), &'static str> {
+        let name = name.to_ascii_lowercase();
+        let target = match name.strip_prefix('@') {
+            Some("all") => {
+                // @all 覆盖之前所有的命令规则, 空的规则列表就是-@all
+                self.commands.clear();
+                if !allow {
+                    return Ok(());
+                }
+                CommandTarget::All
+            }
+            Some(category) if CATEGORIES.contains(&category) => {
+                CommandTarget::Category(category.to_string())
+            }
+            Some(_) => return Err("Unknown command or category name in ACL"),
+            None => {
+                let (command, subcommand) = match name.split_once('|') {
+                    Some((command, subcommand)) => (command, Some(subcommand)),
+                    None => (name.as_str(), None),
+                };
+                if !command_exists(command) || subcommand.is_some_and(str::is_empty) {
+                    return Err("Unknown command or category name in ACL");
+                }
+                CommandTarget::Command(name.clone())
+            }
+        };
+        self.commands.retain(|(_, t)| *t != target);
+        self.commands.push((allow, target));
+        Ok(())
+    }
+
+    fn add_key(&mut self, pattern: &str, read: bool, write: bool) -> Result<(), &'static str> {
+        if self
+            .keys
+            .iter()
+            .any(|k| k.pattern == "*" && k.read && k.write)
+        {
+            return Err("Adding a pattern after the * pattern (or the 'allkeys' flag) is not valid and does not have any effect. Try 'resetkeys' to start with an empty list of patterns");
+        }
+        let key = KeyPattern {
+            pattern: pattern.to_string(),
+            read,
+            write,
+        };
+        if !self.keys.contains(&key) {
+            self.keys.push(key);
+        }
+        Ok(())
+    }
+
+    pub fn can_run(&self, name: &str, subcommand: Option<&str>, categories: &[&str]) -> bool {
+        let full = subcommand.map(|sub| format!("{}|{}", name, sub));
+        self.commands
+            .iter()
+            .fold(false, |allowed, (allow, target)| {
+                let matched = match target {
+                    CommandTarget::All => true,
+                    CommandTarget::Category(category) => categories.contains(&category.as_str()),
+                    CommandTarget::Command(command) => {
+                        command == name || Some(command) == full.as_ref()
+                    }
+                };
+                if matched {
+                    *allow
+                } else {
+                    allowed
+                }
+            })
+    }
+
+    /// 需要同一个pattern同时满足读写要求, 和redis一致
+    pub fn can_access_key(&self, key: &[u8], read: bool, write: bool) -> bool {
+        self.keys.iter().any(|k| {
+            (k.read || !read) && (k.write || !write) && glob_match(k.pattern.as_bytes(), key, false)
+        })
+    }
+
+    pub fn can_access_channel(&self, channel: &[u8]) -> bool {
+        self.channels
+            .iter()
+            .any(|c| glob_match(c.as_bytes(), channel, false))
+    }
+
+    fn check(
+        &self,
+        name: &str,
+        subcommand: Option<&str>,
+        categories: &[&str],
+        keys: &[&[u8]],
+        (read, write): (bool, bool),
+    ) -> Result<(), Denied> {
+        if !self.can_run(name, subcommand, categories) {
+            let full = match subcommand {
+                Some(sub) => format!("{}|{}", name, sub),
+                None => name.to_string(),
+            };
+            return Err(Denied::Command(full));
+        }
+        match keys
+            .iter()
+            .find(|key| !self.can_access_key(key, read, write))
+        {
+            Some(key) => Err(Denied::Key(String::from_utf8_lossy(key).into_owned())),
+            None => Ok(()),
+        }
+    }
+
+    /// 和ACL SETUSER中的写法一样, 例如 "-@all +get"
+    pub fn commands(&self) -> String {
+        let mut rules = Vec::new();
+        if !matches!(self.commands.first(), Some((true, CommandTarget::All))) {
+            rules.push("-@all".to_string());
+        }
+        for (allow, target) in &self.commands {
+            rules.push(format!("{}{}", if *allow { '+' } else { '-' }, target));
+        }
+        rules.join(" ")
+    }
+
+    pub fn keys(&self) -> String {
+        let keys: Vec<String> = self.keys.iter().map(|k| k.to_string()).collect();
+        keys.join(" ")
+    }
+
+    pub fn channels(&self) -> String {
+        let channels: Vec<String> = self.channels.iter().map(|c| format!("&{}", c)).collect();
+        channels.join(" ")
+    }
+
+    /// 可以重新用ACL SETUSER应用的规则
+    fn describe(&self) -> String {
+        let channels = match self.channels() {
+            channels if channels.is_empty() => "resetchannels".to_string(),
+            channels => channels,
+        };
+        [self.keys(), channels, self.commands()]
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    name: String,
+    enabled: bool,
+    nopass: bool,
+    /// 密码的sha256
+    passwords: Vec<String>,
+    root: Selector,
+    selectors: Vec<Selector>,
+}
+
+fn check_hash(hash: &str) -> Result<(), &'static str> {
+    match hash.len() == 64 && hash.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f')) {
+        true => Ok(()),
+        false => Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters"),
+    }
+}
+
+impl User {
+    /// 新用户没有任何权限, 并且是关闭的
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+            root: Selector::default(),
+            selectors: Vec::new(),
+        }
+    }
+
+    /// 没有配置时的default用户, 可以执行所有命令
+    pub fn default_user() -> Self {
+        let mut user = Self::new("default");
+        for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+            let _ = user.apply(rule);
+        }
+        user
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn is_nopass(&self) -> bool {
+        self.nopass
+    }
+
+    pub fn passwords(&self) -> &[String] {
+        &self.passwords
+    }
+
+    pub fn root(&self) -> &Selector {
+        &self.root
+    }
+
+    pub fn selectors(&self) -> &[Selector] {
+        &self.selectors
+    }
+
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    /// 按顺序应用规则, 出错时已经应用的规则不会回滚, 调用者需要在副本上修改
+    pub fn apply_rules<S: AsRef<str>>(&mut self, rules: &[S]) -> Result<(), RuleError> {
+        let mut rules = rules.iter().map(|r| r.as_ref());
+        while let Some(rule) = rules.next() {
+            // 参数按空格拆开时把selector重新拼起来
+            let mut rule = rule.to_string();
+            while rule.starts_with('(') && !rule.ends_with(')') {
+                match rules.next() {
+                    Some(next) => {
+                        rule.push(' ');
+                        rule.push_str(next);
+                    }
+                    None => {
+                        return Err(RuleError {
+                            rule,
+                            reason: "Unmatched parenthesis in acl selector starting at '('.",
+                        })
+                    }
+                }
+            }
+            self.apply(&rule)
+                .map_err(|reason| RuleError { rule, reason })?;
+        }
+        Ok(())
+    }
+
+    pub fn apply(&mut self, rule: &str) -> Result<(), &'static str> {
+        match rule.to_ascii_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "reset" => *self = User::new(std::mem::take(&mut self.name)),
+            "clearselectors" => self.selectors.clear(),
+            _ => match rule.as_bytes().first() {
+                Some(b'>') => self.add_password(sha256_hex(&rule.as_bytes()[1..])),
+                Some(b'#') => {
+                    check_hash(&rule[1..])?;
+                    self.add_password(rule[1..].to_string());
+                }
+                Some(b'<') => self.remove_password(&sha256_hex(&rule.as_bytes()[1..]))?,
+                Some(b'!') => {
+                    check_hash(&rule[1..])?;
+                    self.remove_password(&rule[1..])?;
+                }
+                Some(b'(') => {
+                    let inner = rule[1..].strip_suffix(')').ok_or("Syntax error")?;
+                    let mut selector = Selector::default();
+                    for rule in inner.split_whitespace() {
+                        selector.apply(rule)?;
+                    }
+                    self.selectors.push(selector);
+                }
+                _ => self.root.apply(rule)?,
+            },
+        }
+        Ok(())
+    }
+
+    fn add_password(&mut self, hash: String) {
+        self.nopass = false;
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+    }
+
+    fn remove_password(&mut self, hash: &str) -> Result<(), &'static str> {
+        let len = self.passwords.len();
+        self.passwords.retain(|h| h != hash);
+        match self.passwords.len() < len {
+            true => Ok(()),
+            false => Err("The password you are trying to remove from the user does not exist"),
+        }
+    }
+
+    /// 只比较sha256, 比较的时间和密码内容无关
+    pub fn check_password(&self, password: &[u8]) -> bool {
+        if self.nopass {
+            return true;
+        }
+        let hash = sha256_hex(password);
+        self.passwords.iter().any(|h| {
+            h.bytes()
+                .zip(hash.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+        })
+    }
+
+    /// 依次检查root和其他selector, 都不允许时优先返回key的错误
+    pub fn check_command(
+        &self,
+        name: &str,
+        subcommand: Option<&str>,
+        categories: &[&str],
+        keys: &[&[u8]],
+        access: (bool, bool),
+    ) -> Result<(), Denied> {
+        let mut denied = None;
+        for selector in std::iter::once(&self.root).chain(&self.selectors) {
+            match selector.check(name, subcommand, categories, keys, access) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    if denied.is_none() || matches!(e, Denied::Key(_)) {
+                        denied = Some(e);
+                    }
+                }
+            }
+        }
+        Err(denied.unwrap_or_else(|| Denied::Command(name.to_string())))
+    }
+
+    /// 任意一个selector允许访问channel
+    pub fn can_access_channel(&self, channel: &[u8]) -> bool {
+        std::iter::once(&self.root)
+            .chain(&self.selectors)
+            .any(|s| s.can_access_channel(channel))
+    }
+
+    /// ACL LIST 和ACL文件中的格式: user <name> <flags> <passwords> <rules> (<selector>)
+    pub fn describe(&self) -> String {
+        let mut parts = vec!["user".to_string(), self.name.clone()];
+        parts.extend(self.flags().into_iter().map(String::from));
+        parts.extend(self.passwords.iter().map(|h| format!("#{}", h)));
+        parts.push(self.root.describe());
+        parts.extend(self.selectors.iter().map(|s| format!("({})", s.describe())));
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn user(rules: &[&str]) -> Result<User, RuleError> {
+        let mut user = User::new("alice");
+        user.apply_rules(rules)?;
+        Ok(user)
+    }
+
+    #[test]
+    fn test_command_rules() -> Result<(), RuleError> {
+        let u = user(&["on", "+@read", "-hgetall", "+config|get"])?;
+        assert!(u.root.can_run("get", None, &["read", "string"]));
+        assert!(!u.root.can_run("hgetall", None, &["read", "hash"]));
+        assert!(!u.root.can_run("set", None, &["write"]));
+        assert!(u.root.can_run("config", Some("get"), &["admin"]));
+        assert!(!u.root.can_run("config", Some("set"), &["admin"]));
+        assert_eq!(u.root.commands(), "-@all +@read -hgetall +config|get");
+
+        let u = user(&["+get", "allcommands", "-@dangerous"])?;
+        assert_eq!(u.root.commands(), "+@all -@dangerous");
+        assert!(!u.root.can_run("flushall", None, &["write", "dangerous"]));
+
+        assert_eq!(
+            user(&["+nosuchcommand"]),
+            Err(RuleError {
+                rule: "+nosuchcommand".to_string(),
+                reason: "Unknown command or category name in ACL",
+            })
+        );
+        assert!(user(&["+@nosuchcategory"]).is_err());
+        assert!(user(&["bogus"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_and_channel_rules() -> Result<(), RuleError> {
+        let u = user(&["~app:*", "%R~shared:*", "%W~log:*", "&news.*"])?;
+        assert!(u.root.can_access_key(b"app:1", true, true));
+        assert!(u.root.can_access_key(b"shared:1", true, false));
+        assert!(!u.root.can_access_key(b"shared:1", true, true));
+        assert!(u.root.can_access_key(b"log:1", false, true));
+        assert!(!u.root.can_access_key(b"other", true, false));
+        assert!(u.can_access_channel(b"news.tech"));
+        assert!(!u.can_access_channel(b"sports"));
+        assert_eq!(u.root.keys(), "~app:* %R~shared:* %W~log:*");
+
+        assert!(user(&["allkeys", "~foo"]).is_err());
+        assert!(user(&["allkeys", "resetkeys", "~foo"]).is_ok());
+        assert!(user(&["%X~foo"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_passwords() -> Result<(), RuleError> {
+        let mut u = user(&["on", ">secret", ">other"])?;
+        assert!(u.check_password(b"secret") && u.check_password(b"other"));
+        assert!(!u.check_password(b"wrong"));
+        u.apply_rules(&["<other"])?;
+        assert!(!u.check_password(b"other"));
+        assert!(u.apply_rules(&["<other"]).is_err());
+
+        let hash = sha256_hex(b"hashed");
+        u.apply_rules(&[format!("#{}", hash)])?;
+        assert!(u.check_password(b"hashed"));
+        assert!(u.apply_rules(&["#abc"]).is_err());
+
+        u.apply_rules(&["nopass"])?;
+        assert!(u.check_password(b"anything") && u.passwords().is_empty());
+        u.apply_rules(&["resetpass"])?;
+        assert!(!u.check_password(b"anything"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_selectors() -> Result<(), RuleError> {
+        let u = user(&["on", "+get", "~app:*", "(+set", "~tmp:*)"])?;
+        let check = |name, categories, key: &[u8], access| {
+            u.check_command(name, None, categories, &[key], access)
+        };
+        assert!(check("get", &["read"], b"app:1", (true, false)).is_ok());
+        assert!(check("set", &["write"], b"tmp:1", (true, true)).is_ok());
+        assert_eq!(
+            check("set", &["write"], b"app:1", (true, true)),
+            Err(Denied::Key("app:1".to_string()))
+        );
+        assert_eq!(
+            check("hset", &["write"], b"app:1", (true, true)),
+            Err(Denied::Command("hset".to_string()))
+        );
+        assert_eq!(
+            u.describe(),
+            "user alice on ~app:* resetchannels -@all +get (~tmp:* resetchannels -@all +set)"
+        );
+
+        let mut copy = User::new("alice");
+        let description = u.describe();
+        let rules: Vec<&str> = description.split(' ').skip(2).collect();
+        copy.apply_rules(&rules)?;
+        assert_eq!(copy, u);
+
+        assert!(user(&["(+get"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_user() {
+        let u = User::default_user();
+        assert_eq!(u.describe(), "user default on nopass ~* &* +@all");
+        assert!(u
+            .check_command("flushall", None, &[], &[b"k"], (true, true))
+            .is_ok());
+    }
+}
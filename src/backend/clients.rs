@@ -0,0 +1,588 @@
+//! 所有连接的客户端, CLIENT LIST / KILL / PAUSE 使用
+//!
+//! 每个连接注册一个ClientHandle, 连接在每个命令执行完之后把自己的状态同步过来
+
+use super::{now_ms, parse_memory, TrackingOptions};
+use crate::resp::{frame::RespFrame, RespEncode};
+use dashmap::DashMap;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, watch, Notify};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientType {
+    Normal,
+    Replica,
+    Master,
+    PubSub,
+}
+
+impl FromStr for ClientType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "normal" => Ok(ClientType::Normal),
+            "replica" | "slave" => Ok(ClientType::Replica),
+            "master" => Ok(ClientType::Master),
+            "pubsub" => Ok(ClientType::PubSub),
+            _ => Err(format!("Unknown client type '{}'", s)),
+        }
+    }
+}
+
+/// 连接读取push消息的一端, 取出之后不再计入ClientHandle的pending_pushes
+#[derive(Debug)]
+pub struct PushReceiver {
+    rx: mpsc::UnboundedReceiver<(RespFrame, u64)>,
+    pending: Arc<AtomicU64>,
+}
+
+impl PushReceiver {
+    pub async fn recv(&mut self) -> Option<RespFrame> {
+        let (frame, len) = self.rx.recv().await?;
+        self.pending.fetch_sub(len, Ordering::Relaxed);
+        Some(frame)
+    }
+
+    pub fn try_recv(&mut self) -> Result<RespFrame, mpsc::error::TryRecvError> {
+        let (frame, len) = self.rx.try_recv()?;
+        self.pending.fetch_sub(len, Ordering::Relaxed);
+        Ok(frame)
+    }
+}
+
+/// client-output-buffer-limit 中一个class的限制, 0表示不限制
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputBufferLimit {
+    pub hard: u64,
+    pub soft: u64,
+    /// 连续超过soft limit这么多秒之后断开
+    pub soft_seconds: u64,
+}
+
+impl OutputBufferLimit {
+    /// pending是还没有发送出去的字节数, soft_since记录开始超过soft limit的时间
+    pub fn exceeded(&self, pending: u64, soft_since: &mut Option<Instant>) -> bool {
+        if self.hard > 0 && pending >= self.hard {
+            return true;
+        }
+        if self.soft > 0 && pending >= self.soft {
+            let since = *soft_since.get_or_insert_with(Instant::now);
+            return since.elapsed() >= Duration::from_secs(self.soft_seconds);
+        }
+        *soft_since = None;
+        false
+    }
+}
+
+/// normal, replica 和 pubsub 三类连接的输出缓存限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputBufferLimits {
+    pub normal: OutputBufferLimit,
+    pub replica: OutputBufferLimit,
+    pub pubsub: OutputBufferLimit,
+}
+
+impl Default for OutputBufferLimits {
+    fn default() -> Self {
+        Self {
+            normal: OutputBufferLimit::default(),
+            replica: OutputBufferLimit {
+                hard: 256 << 20,
+                soft: 64 << 20,
+                soft_seconds: 60,
+            },
+            pubsub: OutputBufferLimit {
+                hard: 32 << 20,
+                soft: 8 << 20,
+                soft_seconds: 60,
+            },
+        }
+    }
+}
+
+impl OutputBufferLimits {
+    /// master的连接不限制
+    pub fn get(&self, client_type: ClientType) -> OutputBufferLimit {
+        match client_type {
+            ClientType::Normal => self.normal,
+            ClientType::Replica => self.replica,
+            ClientType::PubSub => self.pubsub,
+            ClientType::Master => OutputBufferLimit::default(),
+        }
+    }
+
+    /// 和redis一样每次可以只设置一部分class: <class> <hard> <soft> <soft seconds> ...
+    pub fn update(mut self, value: &str) -> Result<Self, String> {
+        let args: Vec<&str> = value.split_whitespace().collect();
+        if args.is_empty() || !args.len().is_multiple_of(4) {
+            return Err("Wrong number of arguments in buffer limit configuration.".to_string());
+        }
+        for chunk in args.chunks(4) {
+            let limit = match chunk[0].parse::<ClientType>() {
+                Ok(ClientType::Normal) => &mut self.normal,
+                Ok(ClientType::Replica) => &mut self.replica,
+                Ok(ClientType::PubSub) => &mut self.pubsub,
+                _ => {
+                    return Err(
+                        "Invalid client class specified in buffer limit configuration.".to_string(),
+                    )
+                }
+            };
+            let error = || {
+                "Error in hard, soft or soft_seconds setting in buffer limit configuration."
+                    .to_string()
+            };
+            *limit = OutputBufferLimit {
+                hard: parse_memory(chunk[1]).map_err(|_| error())?,
+                soft: parse_memory(chunk[2]).map_err(|_| error())?,
+                soft_seconds: chunk[3].parse().map_err(|_| error())?,
+            };
+        }
+        Ok(self)
+    }
+}
+
+impl FromStr for OutputBufferLimits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::default().update(s)
+    }
+}
+
+impl fmt::Display for OutputBufferLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let classes = [
+            ("normal", self.normal),
+            ("replica", self.replica),
+            ("pubsub", self.pubsub),
+        ];
+        let classes: Vec<String> = classes
+            .iter()
+            .map(|(name, l)| format!("{} {} {} {}", name, l.hard, l.soft, l.soft_seconds))
+            .collect();
+        write!(f, "{}", classes.join(" "))
+    }
+}
+
+/// 连接状态的快照
+#[derive(Debug, Clone, Default)]
+pub struct ClientState {
+    pub name: Option<String>,
+    pub db: usize,
+    pub user: String,
+    pub resp: u8,
+    /// 最后执行的命令, 带子命令时为 "client|list" 的形式
+    pub last_cmd: String,
+    /// 发送过REPLCONF的连接
+    pub replica: bool,
+    pub no_evict: bool,
+    pub no_touch: bool,
+    /// CLIENT TRACKING on 时的选项
+    pub tracking: Option<TrackingOptions>,
+}
+
+#[derive(Debug)]
+pub struct ClientHandle {
+    id: u64,
+    addr: String,
+    laddr: String,
+    /// 连接建立的时间, unix毫秒
+    created: u64,
+    /// 最后一次收到命令的时间, unix毫秒
+    last_interaction: AtomicU64,
+    state: Mutex<ClientState>,
+    killed: AtomicBool,
+    kill: Notify,
+    /// 发给连接的push消息, 比如 CLIENT TRACKING 的invalidate
+    pushes: mpsc::UnboundedSender<(RespFrame, u64)>,
+    /// 还在队列中的push消息的字节数, 计入输出缓存
+    pending_pushes: Arc<AtomicU64>,
+}
+
+impl ClientHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn laddr(&self) -> &str {
+        &self.laddr
+    }
+
+    pub fn state(&self) -> ClientState {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut ClientState)) {
+        f(&mut self.state.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    /// 收到一个命令时调用
+    pub fn touch(&self) {
+        self.last_interaction.store(now_ms(), Ordering::Relaxed);
+    }
+
+    /// 连接建立了多少秒
+    pub fn age(&self) -> u64 {
+        now_ms().saturating_sub(self.created) / 1000
+    }
+
+    /// 多少秒没有收到命令
+    pub fn idle(&self) -> u64 {
+        now_ms().saturating_sub(self.last_interaction.load(Ordering::Relaxed)) / 1000
+    }
+
+    pub fn client_type(&self) -> ClientType {
+        match self.state().replica {
+            true => ClientType::Replica,
+            false => ClientType::Normal,
+        }
+    }
+
+    /// 当前命令回复之后关闭连接
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::Relaxed);
+        self.kill.notify_one();
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Relaxed)
+    }
+
+    /// 被CLIENT KILL时返回
+    pub async fn killed(&self) {
+        self.kill.notified().await
+    }
+
+    /// 连接已经断开时丢弃
+    pub fn push(&self, frame: RespFrame) {
+        let len = frame.clone().encode().len() as u64;
+        self.pending_pushes.fetch_add(len, Ordering::Relaxed);
+        if self.pushes.send((frame, len)).is_err() {
+            self.pending_pushes.fetch_sub(len, Ordering::Relaxed);
+        }
+    }
+
+    pub fn pending_pushes(&self) -> u64 {
+        self.pending_pushes.load(Ordering::Relaxed)
+    }
+
+    /// CLIENT LIST 和 CLIENT INFO 中的一行
+    pub fn info(&self) -> String {
+        let state = self.state();
+        let mut flags = String::new();
+        if state.replica {
+            flags.push('S');
+        }
+        if state.no_evict {
+            flags.push('e');
+        }
+        if state.no_touch {
+            flags.push('T');
+        }
+        if let Some(tracking) = &state.tracking {
+            flags.push('t');
+            if tracking.bcast {
+                flags.push('B');
+            }
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        let redir = match &state.tracking {
+            Some(tracking) => tracking.redirect.map_or(0, |id| id as i64),
+            None => -1,
+        };
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db={} sub=0 psub=0 multi=-1 cmd={} user={} redir={} resp={}",
+            self.id,
+            self.addr,
+            self.laddr,
+            state.name.as_deref().unwrap_or_default(),
+            self.age(),
+            self.idle(),
+            flags,
+            state.db,
+            if state.last_cmd.is_empty() { "NULL" } else { &state.last_cmd },
+            state.user,
+            redir,
+            state.resp,
+        )
+    }
+}
+
+/// CLIENT KILL 的过滤条件, 所有条件都满足的连接才会被关闭
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct KillFilter {
+    pub id: Option<u64>,
+    pub addr: Option<String>,
+    pub laddr: Option<String>,
+    pub user: Option<String>,
+    pub client_type: Option<ClientType>,
+    /// 连接时间超过这么多秒
+    pub maxage: Option<u64>,
+    /// 为true时不关闭发出命令的连接
+    pub skipme: bool,
+}
+
+impl KillFilter {
+    fn matches(&self, client: &ClientHandle, me: u64) -> bool {
+        !(self.skipme && client.id == me)
+            && self.id.is_none_or(|id| id == client.id)
+            && self.addr.as_ref().is_none_or(|addr| *addr == client.addr)
+            && self.laddr.as_ref().is_none_or(|addr| *addr == client.laddr)
+            && self
+                .user
+                .as_ref()
+                .is_none_or(|user| *user == client.state().user)
+            && self.client_type.is_none_or(|t| t == client.client_type())
+            && self.maxage.is_none_or(|age| client.age() >= age)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pause {
+    /// unix毫秒
+    until: u64,
+    /// false时只暂停写命令
+    all: bool,
+}
+
+#[derive(Debug)]
+pub struct ClientRegistry {
+    next_id: AtomicU64,
+    clients: DashMap<u64, Arc<ClientHandle>>,
+    pause: watch::Sender<Option<Pause>>,
+    output_limits: Mutex<OutputBufferLimits>,
+    /// 超过client-output-buffer-limit被断开的连接数
+    output_limit_disconnections: AtomicU64,
+}
+
+impl Default for ClientRegistry {
+    fn default() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            clients: DashMap::new(),
+            pause: watch::Sender::new(None),
+            output_limits: Mutex::new(OutputBufferLimits::default()),
+            output_limit_disconnections: AtomicU64::new(0),
+        }
+    }
+}
+
+impl ClientRegistry {
+    /// 新连接注册, 分配一个递增的id, 连接从返回的receiver中读取push消息
+    pub fn register(
+        &self,
+        addr: impl Into<String>,
+        laddr: impl Into<String>,
+    ) -> (Arc<ClientHandle>, PushReceiver) {
+        let now = now_ms();
+        let (pushes, rx) = mpsc::unbounded_channel();
+        let pending = Arc::new(AtomicU64::new(0));
+        let client = Arc::new(ClientHandle {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            addr: addr.into(),
+            laddr: laddr.into(),
+            created: now,
+            last_interaction: AtomicU64::new(now),
+            state: Mutex::new(ClientState::default()),
+            killed: AtomicBool::new(false),
+            kill: Notify::new(),
+            pushes,
+            pending_pushes: pending.clone(),
+        });
+        self.clients.insert(client.id, client.clone());
+        (client, PushReceiver { rx, pending })
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.clients.remove(&id);
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<ClientHandle>> {
+        self.clients.get(&id).map(|c| c.clone())
+    }
+
+    /// 按id排序
+    pub fn list(&self) -> Vec<Arc<ClientHandle>> {
+        let mut clients: Vec<_> = self.clients.iter().map(|c| c.clone()).collect();
+        clients.sort_by_key(|c| c.id);
+        clients
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// me是发出CLIENT KILL的连接, 返回关闭的连接数量
+    pub fn kill(&self, filter: &KillFilter, me: u64) -> usize {
+        let killed: Vec<_> = self
+            .list()
+            .into_iter()
+            .filter(|c| filter.matches(c, me))
+            .collect();
+        killed.iter().for_each(|c| c.kill());
+        killed.len()
+    }
+
+    pub fn set_output_limits(&self, limits: OutputBufferLimits) {
+        *self.output_limits.lock().unwrap_or_else(|e| e.into_inner()) = limits;
+    }
+
+    /// 按照连接的类型返回client-output-buffer-limit
+    pub fn output_limit(&self, client: &ClientHandle) -> OutputBufferLimit {
+        self.output_limits
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(client.client_type())
+    }
+
+    pub fn record_output_limit_disconnection(&self) {
+        self.output_limit_disconnections
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn output_limit_disconnections(&self) -> u64 {
+        self.output_limit_disconnections.load(Ordering::Relaxed)
+    }
+
+    /// 暂停到until(unix毫秒)。已经在暂停时取更晚的结束时间和更严格的模式
+    pub fn pause(&self, until: u64, all: bool) {
+        let now = now_ms();
+        self.pause.send_modify(|pause| {
+            let (until, all) = match pause {
+                Some(old) if old.until > now => (old.until.max(until), old.all || all),
+                _ => (until, all),
+            };
+            *pause = Some(Pause { until, all });
+        });
+    }
+
+    pub fn unpause(&self) {
+        self.pause.send_replace(None);
+    }
+
+    /// 暂停期间等待, write表示命令会修改数据
+    pub async fn wait_unpaused(&self, write: bool) {
+        let mut rx = self.pause.subscribe();
+        loop {
+            let pause = *rx.borrow_and_update();
+            let wait = match pause {
+                Some(pause) if pause.all || write => pause.until.saturating_sub(now_ms()),
+                _ => return,
+            };
+            if wait == 0 {
+                return;
+            }
+            tokio::select! {
+                _ = rx.changed() => {}
+                _ = tokio::time::sleep(Duration::from_millis(wait)) => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_register_and_kill() {
+        let clients = ClientRegistry::default();
+        let (a, _) = clients.register("127.0.0.1:1000", "127.0.0.1:6379");
+        let (b, _) = clients.register("127.0.0.1:1001", "127.0.0.1:6379");
+        b.update(|s| s.user = "alice".to_string());
+        assert_eq!(
+            clients.list().iter().map(|c| c.id()).collect::<Vec<_>>(),
+            [1, 2]
+        );
+
+        let filter = KillFilter {
+            laddr: Some("127.0.0.1:6379".to_string()),
+            skipme: true,
+            ..Default::default()
+        };
+        assert_eq!(clients.kill(&filter, a.id()), 1);
+        assert!(!a.is_killed() && b.is_killed());
+
+        let filter = KillFilter {
+            user: Some("alice".to_string()),
+            client_type: Some(ClientType::Replica),
+            ..Default::default()
+        };
+        assert_eq!(clients.kill(&filter, a.id()), 0);
+
+        clients.unregister(b.id());
+        assert_eq!(clients.len(), 1);
+        assert!(a.info().starts_with(
+            "id=1 addr=127.0.0.1:1000 laddr=127.0.0.1:6379 name= age=0 idle=0 flags=N db=0"
+        ));
+    }
+
+    #[test]
+    fn test_output_buffer_limits() {
+        let limits: OutputBufferLimits = "normal 1mb 512kb 0".parse().unwrap();
+        assert_eq!(
+            limits.to_string(),
+            "normal 1048576 524288 0 replica 268435456 67108864 60 pubsub 33554432 8388608 60"
+        );
+        let limits = limits.update("slave 0 0 0 pubsub 100 10 60").unwrap();
+        assert_eq!(
+            limits.get(ClientType::Replica),
+            OutputBufferLimit::default()
+        );
+        assert!(limits.update("normal 0 0").is_err());
+        assert!(limits.update("master 0 0 0").is_err());
+
+        let mut since = None;
+        let pubsub = limits.get(ClientType::PubSub);
+        assert!(pubsub.exceeded(100, &mut since));
+        assert!(!pubsub.exceeded(50, &mut since));
+        assert!(since.is_some());
+        assert!(!pubsub.exceeded(5, &mut since));
+        assert!(since.is_none());
+        // soft_seconds为0时超过soft limit立刻断开
+        assert!(limits.normal.exceeded(524288, &mut since));
+    }
+
+    #[tokio::test]
+    async fn test_pause() {
+        let clients = Arc::new(ClientRegistry::default());
+        clients.pause(now_ms() + 10_000, false);
+        // 只暂停写命令时其他命令不受影响
+        clients.wait_unpaused(false).await;
+
+        let waiting = {
+            let clients = clients.clone();
+            tokio::spawn(async move { clients.wait_unpaused(true).await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        clients.unpause();
+        waiting.await.unwrap();
+
+        let start = Instant::now();
+        clients.pause(now_ms() + 100, true);
+        clients.wait_unpaused(false).await;
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
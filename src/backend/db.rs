@@ -0,0 +1,658 @@
+use super::{
+    encoding::{bytes_to_int, ELEMENT_OVERHEAD},
+    now_ms, DumpValue, EncodingConfig, EvictionPolicy, HashValue, KeyAccess, SetValue,
+};
+use crate::cluster::key_hash_slot;
+use bytes::Bytes;
+use dashmap::DashMap;
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// 估算内存时每个key的固定开销
+const KEY_OVERHEAD: usize = 64;
+const EMBSTR_SIZE_LIMIT: usize = 44;
+
+/// 一个逻辑数据库, SELECT 切换的就是它
+#[derive(Debug, Default)]
+pub struct Db {
+    map: DashMap<Bytes, Bytes>,
+    hmap: DashMap<Bytes, HashValue>,
+    set: DashMap<Bytes, SetValue>,
+    /// key -> 过期时间(unix毫秒), 访问时惰性删除
+    expires: DashMap<Bytes, u64>,
+    /// key -> 访问信息, LRU/LFU淘汰使用
+    access: DashMap<Bytes, KeyAccess>,
+    /// slot -> 这个slot中的key, 和redis一样在增删key时维护, 集群命令不需要遍历所有key
+    slots: DashMap<u16, BTreeSet<Bytes>>,
+    /// 和slot索引一起维护的key数量, DBSIZE不需要遍历所有key
+    key_count: AtomicUsize,
+    /// 估算的内存占用(字节)
+    used_memory: AtomicUsize,
+    /// 所有db共享的紧凑编码阈值
+    config: Arc<EncodingConfig>,
+}
+
+/// MOVE 使用, 从一个db中取出key的所有信息再放到另一个db中
+#[derive(Debug)]
+pub(super) struct Entry {
+    value: EntryValue,
+    expire_at: Option<u64>,
+    access: Option<KeyAccess>,
+}
+
+#[derive(Debug)]
+enum EntryValue {
+    String(Bytes),
+    Hash(HashValue),
+    Set(SetValue),
+}
+
+/// 估算一个字符串值占用的内存
+fn value_size(value: &Bytes) -> usize {
+    value.len() + ELEMENT_OVERHEAD
+}
+
+impl EntryValue {
+    fn size(&self, key: &[u8]) -> usize {
+        let value = match self {
+            EntryValue::String(v) => value_size(v),
+            EntryValue::Hash(v) => v.size(),
+            EntryValue::Set(v) => v.size(),
+        };
+        KEY_OVERHEAD + key.len() + value
+    }
+}
+
+impl Db {
+    pub fn new(config: Arc<EncodingConfig>) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    fn add_delta(&self, delta: isize) {
+        if delta >= 0 {
+            self.add_memory(delta as usize);
+        } else {
+            self.sub_memory(delta.unsigned_abs());
+        }
+    }
+
+    fn add_memory(&self, n: usize) {
+        self.used_memory.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn sub_memory(&self, n: usize) {
+        let _ = self
+            .used_memory
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(n))
+            });
+    }
+
+    pub fn used_memory(&self) -> usize {
+        self.used_memory.load(Ordering::Relaxed)
+    }
+
+    /// 记录一次访问, 只对存在的key有效
+    fn touch(&self, key: &[u8]) {
+        let now = now_ms();
+        match self.access.get_mut(key) {
+            Some(mut access) => access.touch(now),
+            None => {
+                self.access
+                    .insert(Bytes::copy_from_slice(key), KeyAccess::default());
+            }
+        }
+    }
+
+    fn index_key(&self, key: &Bytes) {
+        let added = self
+            .slots
+            .entry(key_hash_slot(key))
+            .or_default()
+            .insert(key.clone());
+        if added {
+            self.key_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// key在所有类型中都不存在之后从slot索引中删除
+    fn unindex_key(&self, key: &[u8]) {
+        if self.map.contains_key(key) || self.hmap.contains_key(key) || self.set.contains_key(key) {
+            return;
+        }
+        let slot = key_hash_slot(key);
+        let mut removed = false;
+        self.slots.remove_if_mut(&slot, |_, keys| {
+            removed = keys.remove(key);
+            keys.is_empty()
+        });
+        if removed {
+            self.key_count.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// key已经过期时删除它, 返回是否删除了
+    fn expire_if_needed(&self, key: &[u8]) -> bool {
+        let expired = matches!(self.expires.get(key), Some(at) if *at <= now_ms());
+        if expired {
+            self.del(key);
+        }
+        expired
+    }
+
+    /// 所有类型的key, 按字典序排列
+    pub fn keys(&self) -> Vec<Bytes> {
+        let mut keys = BTreeSet::new();
+        keys.extend(self.map.iter().map(|e| e.key().clone()));
+        keys.extend(self.hmap.iter().map(|e| e.key().clone()));
+        keys.extend(self.set.iter().map(|e| e.key().clone()));
+        keys.into_iter()
+            .filter(|k| !self.expire_if_needed(k))
+            .collect()
+    }
+
+    pub fn exists(&self, key: &[u8]) -> bool {
+        self.expire_if_needed(key);
+        self.map.contains_key(key) || self.hmap.contains_key(key) || self.set.contains_key(key)
+    }
+
+    /// 删除所有类型中的key, 返回key是否存在
+    pub fn del(&self, key: &[u8]) -> bool {
+        self.expires.remove(key);
+        self.access.remove(key);
+        let mut removed = false;
+        if let Some((_, v)) = self.map.remove(key) {
+            self.sub_memory(EntryValue::String(v).size(key));
+            removed = true;
+        }
+        if let Some((_, v)) = self.hmap.remove(key) {
+            self.sub_memory(EntryValue::Hash(v).size(key));
+            removed = true;
+        }
+        if let Some((_, v)) = self.set.remove(key) {
+            self.sub_memory(EntryValue::Set(v).size(key));
+            removed = true;
+        }
+        if removed {
+            self.unindex_key(key);
+        }
+        removed
+    }
+    /// 设置key的过期时间(unix毫秒), key不存在时返回false
+    pub fn expire_at(&self, key: &[u8], at: u64) -> bool {
+        if !self.exists(key) {
+            return false;
+        }
+        self.expires.insert(Bytes::copy_from_slice(key), at);
+        true
+    }
+
+    /// key剩余的存活时间(毫秒), key不存在时返回None, 没有过期时间时返回Some(-1)
+    pub fn pttl(&self, key: &[u8]) -> Option<i64> {
+        if !self.exists(key) {
+            return None;
+        }
+        match self.expires.get(key) {
+            Some(at) => Some(at.saturating_sub(now_ms()) as i64),
+            None => Some(-1),
+        }
+    }
+
+    pub fn key_access(&self, key: &[u8]) -> Option<KeyAccess> {
+        self.access.get(key).map(|a| *a)
+    }
+
+    pub fn set_key_access(&self, key: &[u8], access: KeyAccess) {
+        if self.exists(key) {
+            self.access.insert(Bytes::copy_from_slice(key), access);
+        }
+    }
+
+    /// DUMP使用, key不存在时返回None
+    pub fn dump(&self, key: &[u8]) -> Option<DumpValue> {
+        self.expire_if_needed(key);
+        if let Some(value) = self.map.get(key) {
+            return Some(DumpValue::String(value.to_vec()));
+        }
+        if let Some(hash) = self.hmap.get(key) {
+            let mut fields = hash
+                .entries()
+                .into_iter()
+                .map(|(f, v)| (f.to_vec(), v.to_vec()))
+                .collect::<Vec<_>>();
+            fields.sort();
+            return Some(DumpValue::Hash(fields));
+        }
+        if let Some(set) = self.set.get(key) {
+            let members = set.members().iter().map(|m| m.to_vec()).collect();
+            return Some(DumpValue::Set(members));
+        }
+        None
+    }
+
+    /// RESTORE使用, 会覆盖已经存在的key
+    pub fn restore(&self, key: &[u8], value: DumpValue) {
+        let value = match value {
+            DumpValue::String(s) => EntryValue::String(s.into()),
+            DumpValue::Set(members) => EntryValue::Set(SetValue::from_members(
+                members.into_iter().map(Bytes::from).collect(),
+                &self.config,
+            )),
+            DumpValue::Hash(fields) => EntryValue::Hash(HashValue::from_entries(
+                fields
+                    .into_iter()
+                    .map(|(f, v)| (f.into(), v.into()))
+                    .collect(),
+                &self.config,
+            )),
+        };
+        self.put(
+            key,
+            Entry {
+                value,
+                expire_at: None,
+                access: None,
+            },
+        );
+    }
+
+    /// slot中最多count个没有过期的key
+    pub fn keys_in_slot(&self, slot: u16, count: usize) -> Vec<Bytes> {
+        let Some(keys) = self.slots.get(&slot) else {
+            return Vec::new();
+        };
+        let now = now_ms();
+        // 持有slots的锁时不能删除key, 过期的key只跳过, 留给之后的访问删除
+        keys.iter()
+            .filter(|k| !matches!(self.expires.get(k.as_ref()), Some(at) if *at <= now))
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    /// 和redis一样, 还没有被删除的过期key也计入
+    pub fn count_keys_in_slot(&self, slot: u16) -> usize {
+        self.slots.get(&slot).map_or(0, |keys| keys.len())
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        self.expire_if_needed(key);
+        let value = self.map.get(key).map(|v| v.value().clone());
+        if value.is_some() {
+            self.touch(key);
+        }
+        value
+    }
+
+    /// 和redis一样, SET会清除key的过期时间
+    pub fn set(&self, key: &[u8], value: Bytes) -> Option<Bytes> {
+        self.expires.remove(key);
+        let size = value_size(&value);
+        let owned = Bytes::copy_from_slice(key);
+        let old = self.map.insert(owned.clone(), value);
+        match &old {
+            Some(old) => self.sub_memory(value_size(old)),
+            None => {
+                self.add_memory(KEY_OVERHEAD + key.len());
+                self.index_key(&owned);
+            }
+        }
+        self.add_memory(size);
+        self.touch(key);
+        old
+    }
+
+    pub fn hget(&self, key: &[u8], field: &[u8]) -> Option<Bytes> {
+        self.expire_if_needed(key);
+        let value = self.hmap.get(key).and_then(|hash| hash.get(field));
+        if self.hmap.contains_key(key) {
+            self.touch(key);
+        }
+        value
+    }
+
+    pub fn hget_all(&self, key: &[u8]) -> Option<Vec<(Bytes, Bytes)>> {
+        self.expire_if_needed(key);
+        let value = self.hmap.get(key).map(|hash| hash.entries());
+        if value.is_some() {
+            self.touch(key);
+        }
+        value
+    }
+
+    pub fn hset(&self, key: &[u8], field: &[u8], value: Bytes) -> Option<Bytes> {
+        self.expire_if_needed(key);
+        let (old, delta) = {
+            let owned = Bytes::copy_from_slice(key);
+            let mut hash = self.hmap.entry(owned.clone()).or_insert_with(|| {
+                let value = HashValue::default();
+                self.add_memory(KEY_OVERHEAD + key.len() + value.size());
+                self.index_key(&owned);
+                value
+            });
+            hash.insert(Bytes::copy_from_slice(field), value, &self.config)
+        };
+        self.add_delta(delta);
+        self.touch(key);
+        old
+    }
+
+    /// 返回新增的成员数量
+    pub fn sadd(&self, key: &[u8], members: Vec<Bytes>) -> usize {
+        self.expire_if_needed(key);
+        let (added, delta) = {
+            let owned = Bytes::copy_from_slice(key);
+            let mut set = self.set.entry(owned.clone()).or_insert_with(|| {
+                let value = SetValue::default();
+                self.add_memory(KEY_OVERHEAD + key.len() + value.size());
+                self.index_key(&owned);
+                value
+            });
+            set.insert(members, &self.config)
+        };
+        self.add_delta(delta);
+        self.touch(key);
+        added
+    }
+
+    pub fn sismembers(&self, key: &[u8], field: &[u8]) -> bool {
+        self.expire_if_needed(key);
+        let ret = match self.set.get(key) {
+            Some(set) => set.contains(field),
+            None => return false,
+        };
+        self.touch(key);
+        ret
+    }
+
+    /// OBJECT ENCODING, 返回值实际的存储方式
+    pub fn encoding(&self, key: &[u8]) -> Option<&'static str> {
+        self.expire_if_needed(key);
+        if let Some(value) = self.map.get(key) {
+            // 和redis一样, 44字节以内的字符串是embstr
+            return Some(if bytes_to_int(value.value()).is_some() {
+                "int"
+            } else if value.len() <= EMBSTR_SIZE_LIMIT {
+                "embstr"
+            } else {
+                "raw"
+            });
+        }
+        if let Some(hash) = self.hmap.get(key) {
+            return Some(hash.encoding());
+        }
+        self.set.get(key).map(|set| set.encoding())
+    }
+
+    /// MEMORY USAGE, 估算的key和值占用的内存
+    pub fn memory_usage(&self, key: &[u8]) -> Option<usize> {
+        self.expire_if_needed(key);
+        let value = if let Some(v) = self.map.get(key) {
+            value_size(v.value())
+        } else if let Some(hash) = self.hmap.get(key) {
+            hash.size()
+        } else if let Some(set) = self.set.get(key) {
+            set.size()
+        } else {
+            return None;
+        };
+        Some(KEY_OVERHEAD + key.len() + value)
+    }
+
+    /// 设置了过期时间的key的数量
+    pub fn expires_len(&self) -> usize {
+        self.expires.len()
+    }
+
+    /// key的数量, 和redis的DBSIZE一样, 还没有被删除的过期key也计入
+    pub fn len(&self) -> usize {
+        self.key_count.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(super) fn take(&self, key: &[u8]) -> Option<Entry> {
+        self.expire_if_needed(key);
+        let value = if let Some((_, v)) = self.map.remove(key) {
+            EntryValue::String(v)
+        } else if let Some((_, v)) = self.hmap.remove(key) {
+            EntryValue::Hash(v)
+        } else if let Some((_, v)) = self.set.remove(key) {
+            EntryValue::Set(v)
+        } else {
+            return None;
+        };
+        self.sub_memory(value.size(key));
+        self.unindex_key(key);
+        Some(Entry {
+            value,
+            expire_at: self.expires.remove(key).map(|(_, v)| v),
+            access: self.access.remove(key).map(|(_, v)| v),
+        })
+    }
+
+    pub(super) fn put(&self, key: &[u8], entry: Entry) {
+        self.del(key);
+        self.add_memory(entry.value.size(key));
+        let key = Bytes::copy_from_slice(key);
+        self.index_key(&key);
+        match entry.value {
+            EntryValue::String(v) => {
+                self.map.insert(key.clone(), v);
+            }
+            EntryValue::Hash(v) => {
+                self.hmap.insert(key.clone(), v);
+            }
+            EntryValue::Set(v) => {
+                self.set.insert(key.clone(), v);
+            }
+        }
+        if let Some(at) = entry.expire_at {
+            self.expires.insert(key.clone(), at);
+        }
+        self.access.insert(key, entry.access.unwrap_or_default());
+    }
+
+    /// 按照淘汰策略采样, 返回(分数, key), 分数越大越应该被淘汰
+    pub(super) fn eviction_candidate(
+        &self,
+        policy: EvictionPolicy,
+        samples: usize,
+    ) -> Option<(u64, Bytes)> {
+        let keys = if policy.is_volatile() {
+            sample_keys(&self.expires, samples)
+        } else {
+            // 按照每种类型key的数量决定从哪个map中采样
+            let lens = [self.map.len(), self.hmap.len(), self.set.len()];
+            let total: usize = lens.iter().sum();
+            if total == 0 {
+                return None;
+            }
+            let mut pick = rand::random::<usize>() % total;
+            let index = lens
+                .iter()
+                .position(|len| {
+                    let found = pick < *len;
+                    pick = pick.saturating_sub(*len);
+                    found
+                })
+                .unwrap_or(0);
+            match index {
+                0 => sample_keys(&self.map, samples),
+                1 => sample_keys(&self.hmap, samples),
+                _ => sample_keys(&self.set, samples),
+            }
+        };
+        let now = now_ms();
+        keys.into_iter()
+            .map(|key| {
+                let access = self.key_access(&key).unwrap_or_default();
+                let score = match policy {
+                    EvictionPolicy::AllKeysLru | EvictionPolicy::VolatileLru => access.idle_ms(now),
+                    EvictionPolicy::AllKeysLfu | EvictionPolicy::VolatileLfu => {
+                        255 - access.decayed_freq(now) as u64
+                    }
+                    EvictionPolicy::VolatileTtl => {
+                        u64::MAX - self.expires.get(&key).map(|at| *at).unwrap_or(u64::MAX)
+                    }
+                    _ => rand::random(),
+                };
+                (score, key)
+            })
+            .max()
+    }
+}
+
+/// 从随机的shard和随机的位置开始取最多count个key, 不够时继续从下一个shard取
+fn sample_keys<V>(map: &DashMap<Bytes, V>, count: usize) -> Vec<Bytes> {
+    let shards = map.shards();
+    let start = rand::random::<usize>() % shards.len();
+    let mut keys = Vec::with_capacity(count);
+    for i in 0..shards.len() {
+        let shard = shards[(start + i) % shards.len()].read();
+        if shard.is_empty() {
+            continue;
+        }
+        let skip = rand::random::<usize>() % shard.len();
+        let take = (count - keys.len()).min(shard.len());
+        keys.extend(shard.keys().cycle().skip(skip).take(take).cloned());
+        if keys.len() >= count {
+            break;
+        }
+    }
+    keys
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expire() {
+        let db = Db::default();
+        db.set(b"key", "value".into());
+        assert_eq!(db.pttl(b"key"), Some(-1));
+        assert!(db.expire_at(b"key", now_ms() + 10_000));
+        assert!(db.pttl(b"key").unwrap() > 9_000);
+
+        assert!(db.expire_at(b"key", now_ms() - 1));
+        assert_eq!(db.get(b"key"), None);
+        assert_eq!(db.pttl(b"key"), None);
+        assert!(!db.expire_at(b"key", now_ms() + 10_000));
+    }
+
+    #[test]
+    fn test_take_put() {
+        let db = Db::default();
+        db.sadd(b"key", vec!["a".into()]);
+        db.expire_at(b"key", now_ms() + 10_000);
+        let other = Db::default();
+        other.put(b"key", db.take(b"key").unwrap());
+        assert!(!db.exists(b"key"));
+        assert!(other.sismembers(b"key", b"a"));
+        assert!(other.pttl(b"key").unwrap() > 9_000);
+        assert!(db.take(b"key").is_none());
+    }
+
+    #[test]
+    fn test_keys_in_slot() {
+        let db = Db::default();
+        let slot = key_hash_slot(b"{user}");
+        db.set(b"{user}.name", "a".into());
+        db.hset(b"{user}.hash", b"f", "v".into());
+        db.sadd(b"{user}.set", vec!["m".into()]);
+        db.set(b"other", "b".into());
+        assert_eq!(db.count_keys_in_slot(slot), 3);
+        assert_eq!(db.keys_in_slot(slot, 2).len(), 2);
+        assert_eq!(db.keys_in_slot(slot, 10).len(), 3);
+
+        db.del(b"{user}.name");
+        let other = Db::default();
+        other.put(b"{user}.set", db.take(b"{user}.set").unwrap());
+        assert_eq!(db.keys_in_slot(slot, 10), vec![Bytes::from("{user}.hash")]);
+        assert_eq!(other.count_keys_in_slot(slot), 1);
+        assert_eq!((db.len(), other.len()), (2, 1));
+
+        // 过期的key不会返回
+        db.expire_at(b"{user}.hash", now_ms() - 1);
+        assert!(db.keys_in_slot(slot, 10).is_empty());
+        assert!(!db.exists(b"{user}.hash"));
+        assert_eq!(db.count_keys_in_slot(slot), 0);
+        assert_eq!(db.len(), 1);
+    }
+
+    #[test]
+    fn test_used_memory() {
+        let db = Db::default();
+        db.set(b"key", "value".into());
+        let used = db.used_memory();
+        assert!(used > 0);
+        db.set(b"key", "a longer value".into());
+        assert_eq!(db.used_memory(), used + 9);
+
+        db.hset(b"hash", b"f", "v".into());
+        db.sadd(b"set", vec!["a".into(), "b".into()]);
+        db.sadd(b"set", vec!["a".into()]);
+        let other = Db::default();
+        other.put(b"set", db.take(b"set").unwrap());
+        assert!(other.used_memory() > 0);
+
+        db.del(b"key");
+        db.del(b"hash");
+        assert_eq!(db.used_memory(), 0);
+    }
+
+    #[test]
+    fn test_encoding_memory_usage() {
+        let db = Db::default();
+        db.set(b"int", "1".into());
+        db.set(b"str", "value".into());
+        db.hset(b"hash", b"f", "v".into());
+        assert_eq!(db.encoding(b"int"), Some("int"));
+        assert_eq!(db.encoding(b"str"), Some("embstr"));
+        db.set(b"long", "x".repeat(45).into());
+        assert_eq!(db.encoding(b"long"), Some("raw"));
+        db.del(b"long");
+        assert_eq!(db.encoding(b"hash"), Some("listpack"));
+        assert_eq!(db.encoding(b"missing"), None);
+
+        let usage = ["int", "str", "hash"]
+            .iter()
+            .map(|k| db.memory_usage(k.as_bytes()).unwrap())
+            .sum::<usize>();
+        assert_eq!(usage, db.used_memory());
+        assert_eq!(db.memory_usage(b"missing"), None);
+    }
+
+    #[test]
+    fn test_eviction_candidate() {
+        let db = Db::default();
+        assert_eq!(db.eviction_candidate(EvictionPolicy::AllKeysLru, 5), None);
+        db.set(b"old", "value".into());
+        db.set(b"new", "value".into());
+        db.access.get_mut(b"old".as_slice()).unwrap().last_access -= 10_000;
+        // 只有两个key, 采样一定会包含全部的key
+        let (_, key) = db
+            .eviction_candidate(EvictionPolicy::AllKeysLru, 5)
+            .unwrap();
+        assert_eq!(key, "old");
+
+        assert_eq!(db.eviction_candidate(EvictionPolicy::VolatileTtl, 5), None);
+        db.expire_at(b"new", now_ms() + 10_000);
+        db.expire_at(b"old", now_ms() + 20_000);
+        let (_, key) = db
+            .eviction_candidate(EvictionPolicy::VolatileTtl, 5)
+            .unwrap();
+        assert_eq!(key, "new");
+    }
+}
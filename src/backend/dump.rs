@@ -0,0 +1,494 @@
+//! DUMP / RESTORE 使用的序列化格式, 和redis的 RDB value 格式一致:
+//! <type><value><rdb version: 2 bytes LE><crc64: 8 bytes LE>
+//! crc64覆盖前面所有的字节(包括version)
+
+use thiserror::Error;
+
+/// 和redis 7.2一致
+pub const RDB_VERSION: u16 = 11;
+
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_SET: u8 = 2;
+const RDB_TYPE_HASH: u8 = 4;
+const RDB_TYPE_SET_INTSET: u8 = 11;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_SET_LISTPACK: u8 = 20;
+
+const RDB_6BITLEN: u8 = 0;
+const RDB_14BITLEN: u8 = 1;
+const RDB_32BITLEN: u8 = 0x80;
+const RDB_64BITLEN: u8 = 0x81;
+/// 长度的前两位是11时, 后面6位表示字符串的特殊编码
+const RDB_ENCVAL: u8 = 3;
+const RDB_ENC_INT8: u8 = 0;
+const RDB_ENC_INT16: u8 = 1;
+const RDB_ENC_INT32: u8 = 2;
+const RDB_ENC_LZF: u8 = 3;
+
+/// lzf的一个回引用最多用3个字节表示264个字节, 解压后的长度不可能超过压缩长度的88倍
+const LZF_MAX_EXPANSION: usize = 88;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DumpValue {
+    String(Vec<u8>),
+    Set(Vec<Vec<u8>>),
+    Hash(Vec<(Vec<u8>, Vec<u8>)>),
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DumpError {
+    #[error("DUMP payload version or checksum are wrong")]
+    BadPayload,
+    #[error("Bad data format")]
+    BadFormat,
+}
+
+/// CRC64 Jones (reflected), 和redis的 crc64.c 一致
+const CRC64_TABLE: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x95ac_9329_ac4b_c9b5
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc64(crc: u64, buf: &[u8]) -> u64 {
+    buf.iter().fold(crc, |crc, &b| {
+        CRC64_TABLE[((crc ^ b as u64) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn write_len(buf: &mut Vec<u8>, len: usize) {
+    if len < 1 << 6 {
+        buf.push((RDB_6BITLEN << 6) | len as u8);
+    } else if len < 1 << 14 {
+        buf.push((RDB_14BITLEN << 6) | (len >> 8) as u8);
+        buf.push(len as u8);
+    } else if len <= u32::MAX as usize {
+        buf.push(RDB_32BITLEN);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        buf.push(RDB_64BITLEN);
+        buf.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &[u8]) {
+    write_len(buf, s.len());
+    buf.extend_from_slice(s);
+}
+
+pub fn serialize(value: &DumpValue) -> Vec<u8> {
+    let mut buf = Vec::new();
+    match value {
+        DumpValue::String(s) => {
+            buf.push(RDB_TYPE_STRING);
+            write_string(&mut buf, s);
+        }
+        DumpValue::Set(members) => {
+            buf.push(RDB_TYPE_SET);
+            write_len(&mut buf, members.len());
+            for member in members {
+                write_string(&mut buf, member);
+            }
+        }
+        DumpValue::Hash(fields) => {
+            buf.push(RDB_TYPE_HASH);
+            write_len(&mut buf, fields.len());
+            for (field, value) in fields {
+                write_string(&mut buf, field);
+                write_string(&mut buf, value);
+            }
+        }
+    }
+    buf.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(0, &buf);
+    buf.extend_from_slice(&crc.to_le_bytes());
+    buf
+}
+
+/// 检查version和crc64, 返回去掉footer之后的数据
+fn verify_payload(payload: &[u8]) -> Result<&[u8], DumpError> {
+    if payload.len() < 10 {
+        return Err(DumpError::BadPayload);
+    }
+    let (data, footer) = payload.split_at(payload.len() - 10);
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    if version > RDB_VERSION {
+        return Err(DumpError::BadPayload);
+    }
+    let mut crc = [0u8; 8];
+    crc.copy_from_slice(&footer[2..]);
+    if u64::from_le_bytes(crc) != crc64(0, &payload[..payload.len() - 8]) {
+        return Err(DumpError::BadPayload);
+    }
+    Ok(data)
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], DumpError> {
+        if self.buf.len() < n {
+            return Err(DumpError::BadFormat);
+        }
+        let (data, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(data)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DumpError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_le<const N: usize>(&mut self) -> Result<[u8; N], DumpError> {
+        let mut buf = [0u8; N];
+        buf.copy_from_slice(self.read_bytes(N)?);
+        Ok(buf)
+    }
+
+    /// 返回长度, 以及它是否是字符串的特殊编码(RDB_ENCVAL)
+    fn read_len_or_enc(&mut self) -> Result<(usize, bool), DumpError> {
+        let first = self.read_u8()?;
+        match first >> 6 {
+            RDB_6BITLEN => Ok(((first & 0x3f) as usize, false)),
+            RDB_14BITLEN => Ok((
+                (((first & 0x3f) as usize) << 8) | self.read_u8()? as usize,
+                false,
+            )),
+            RDB_ENCVAL => Ok(((first & 0x3f) as usize, true)),
+            _ => match first {
+                RDB_32BITLEN => Ok((u32::from_be_bytes(self.read_le()?) as usize, false)),
+                RDB_64BITLEN => usize::try_from(u64::from_be_bytes(self.read_le()?))
+                    .map(|len| (len, false))
+                    .map_err(|_| DumpError::BadFormat),
+                _ => Err(DumpError::BadFormat),
+            },
+        }
+    }
+
+    fn read_len(&mut self) -> Result<usize, DumpError> {
+        match self.read_len_or_enc()? {
+            (len, false) => Ok(len),
+            (_, true) => Err(DumpError::BadFormat),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Vec<u8>, DumpError> {
+        let (len, encoded) = self.read_len_or_enc()?;
+        if !encoded {
+            return Ok(self.read_bytes(len)?.to_vec());
+        }
+        let value = match len as u8 {
+            RDB_ENC_INT8 => self.read_u8()? as i8 as i64,
+            RDB_ENC_INT16 => i16::from_le_bytes(self.read_le()?) as i64,
+            RDB_ENC_INT32 => i32::from_le_bytes(self.read_le()?) as i64,
+            RDB_ENC_LZF => {
+                let clen = self.read_len()?;
+                let len = self.read_len()?;
+                return lzf_decompress(self.read_bytes(clen)?, len);
+            }
+            _ => return Err(DumpError::BadFormat),
+        };
+        Ok(value.to_string().into_bytes())
+    }
+}
+
+/// redis的lzf_d.c, 压缩过的字符串在RESTORE时需要解压
+///
+/// len来自payload, 不能相信它, 输出随着解压增长而不是按len预先分配
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>, DumpError> {
+    if len > input.len().saturating_mul(LZF_MAX_EXPANSION) {
+        return Err(DumpError::BadFormat);
+    }
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 1 << 5 {
+            // 字面量, 长度为ctrl + 1
+            let end = i + ctrl + 1;
+            out.extend_from_slice(input.get(i..end).ok_or(DumpError::BadFormat)?);
+            i = end;
+        } else {
+            // 回引用, 长度为ctrl >> 5 (+ 额外的一个字节) + 2
+            let mut n = ctrl >> 5;
+            if n == 7 {
+                n += *input.get(i).ok_or(DumpError::BadFormat)? as usize;
+                i += 1;
+            }
+            let offset = ((ctrl & 0x1f) << 8) + *input.get(i).ok_or(DumpError::BadFormat)? as usize;
+            i += 1;
+            let start = out
+                .len()
+                .checked_sub(offset + 1)
+                .ok_or(DumpError::BadFormat)?;
+            // 引用的区间可能和正在写入的区间重叠, 只能逐个字节复制
+            for k in 0..n + 2 {
+                out.push(out[start + k]);
+            }
+        }
+        if out.len() > len {
+            return Err(DumpError::BadFormat);
+        }
+    }
+    if out.len() != len {
+        return Err(DumpError::BadFormat);
+    }
+    Ok(out)
+}
+
+/// intset: <encoding: u32 LE><length: u32 LE><整数 LE...>
+fn read_intset(data: &[u8]) -> Result<Vec<Vec<u8>>, DumpError> {
+    let mut reader = Reader { buf: data };
+    let encoding = u32::from_le_bytes(reader.read_le()?) as usize;
+    let len = u32::from_le_bytes(reader.read_le()?) as usize;
+    if !matches!(encoding, 2 | 4 | 8) || reader.buf.len() != len * encoding {
+        return Err(DumpError::BadFormat);
+    }
+    Ok(reader
+        .buf
+        .chunks(encoding)
+        .map(|c| {
+            let value = match encoding {
+                2 => i16::from_le_bytes([c[0], c[1]]) as i64,
+                4 => i32::from_le_bytes([c[0], c[1], c[2], c[3]]) as i64,
+                _ => i64::from_le_bytes([c[0], c[1], c[2], c[3], c[4], c[5], c[6], c[7]]),
+            };
+            value.to_string().into_bytes()
+        })
+        .collect())
+}
+
+/// listpack: <total bytes: u32 LE><num elements: u16 LE><entry...><0xFF>
+/// entry: <encoding+data><backlen>, 整数会被转换成十进制字符串
+fn read_listpack(data: &[u8]) -> Result<Vec<Vec<u8>>, DumpError> {
+    let mut reader = Reader { buf: data };
+    let total = u32::from_le_bytes(reader.read_le()?) as usize;
+    if total != data.len() {
+        return Err(DumpError::BadFormat);
+    }
+    reader.read_bytes(2)?;
+    let mut entries = Vec::new();
+    loop {
+        let first = reader.read_u8()?;
+        if first == 0xff {
+            break;
+        }
+        let (entry, size) = if first & 0x80 == 0 {
+            // 7 bit uint
+            (((first & 0x7f) as i64).to_string().into_bytes(), 1)
+        } else if first & 0xc0 == 0x80 {
+            // 6 bit str
+            let len = (first & 0x3f) as usize;
+            (reader.read_bytes(len)?.to_vec(), 1 + len)
+        } else if first & 0xe0 == 0xc0 {
+            // 13 bit int
+            let value = (((first & 0x1f) as u16) << 8) | reader.read_u8()? as u16;
+            let value = ((value << 3) as i16 >> 3) as i64;
+            (value.to_string().into_bytes(), 2)
+        } else if first & 0xf0 == 0xe0 {
+            // 12 bit str
+            let len = (((first & 0x0f) as usize) << 8) | reader.read_u8()? as usize;
+            (reader.read_bytes(len)?.to_vec(), 2 + len)
+        } else {
+            let (value, size) = match first {
+                0xf0 => {
+                    let len = u32::from_le_bytes(reader.read_le()?) as usize;
+                    let entry = reader.read_bytes(len)?.to_vec();
+                    entries.push(entry);
+                    skip_backlen(&mut reader, 5 + len)?;
+                    continue;
+                }
+                0xf1 => (i16::from_le_bytes(reader.read_le()?) as i64, 3),
+                0xf2 => {
+                    let b: [u8; 3] = reader.read_le()?;
+                    ((i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as i64, 4)
+                }
+                0xf3 => (i32::from_le_bytes(reader.read_le()?) as i64, 5),
+                0xf4 => (i64::from_le_bytes(reader.read_le()?), 9),
+                _ => return Err(DumpError::BadFormat),
+            };
+            (value.to_string().into_bytes(), size)
+        };
+        entries.push(entry);
+        skip_backlen(&mut reader, size)?;
+    }
+    if !reader.buf.is_empty() {
+        return Err(DumpError::BadFormat);
+    }
+    Ok(entries)
+}
+
+/// backlen是entry长度的变长编码, 每个字节7位
+fn skip_backlen(reader: &mut Reader, size: usize) -> Result<(), DumpError> {
+    let n = match size {
+        0..=127 => 1,
+        128..=16383 => 2,
+        16384..=2097151 => 3,
+        2097152..=268435455 => 4,
+        _ => 5,
+    };
+    reader.read_bytes(n)?;
+    Ok(())
+}
+
+pub fn deserialize(payload: &[u8]) -> Result<DumpValue, DumpError> {
+    let mut reader = Reader {
+        buf: verify_payload(payload)?,
+    };
+    let value = match reader.read_u8()? {
+        RDB_TYPE_STRING => DumpValue::String(reader.read_string()?),
+        RDB_TYPE_SET => {
+            let len = reader.read_len()?;
+            let mut members = Vec::with_capacity(len.min(reader.buf.len()));
+            for _ in 0..len {
+                members.push(reader.read_string()?);
+            }
+            DumpValue::Set(members)
+        }
+        RDB_TYPE_HASH => {
+            let len = reader.read_len()?;
+            let mut fields = Vec::with_capacity(len.min(reader.buf.len()));
+            for _ in 0..len {
+                fields.push((reader.read_string()?, reader.read_string()?));
+            }
+            DumpValue::Hash(fields)
+        }
+        RDB_TYPE_SET_INTSET => DumpValue::Set(read_intset(&reader.read_string()?)?),
+        RDB_TYPE_SET_LISTPACK => DumpValue::Set(read_listpack(&reader.read_string()?)?),
+        RDB_TYPE_HASH_LISTPACK => {
+            let entries = read_listpack(&reader.read_string()?)?;
+            if entries.len() % 2 != 0 {
+                return Err(DumpError::BadFormat);
+            }
+            let mut entries = entries.into_iter();
+            let mut fields = Vec::with_capacity(entries.len() / 2);
+            while let (Some(field), Some(value)) = (entries.next(), entries.next()) {
+                fields.push((field, value));
+            }
+            DumpValue::Hash(fields)
+        }
+        _ => return Err(DumpError::BadFormat),
+    };
+    if !reader.buf.is_empty() {
+        return Err(DumpError::BadFormat);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc64() {
+        // redis crc64.c 中的测试向量
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn test_serialize_string() {
+        // redis-cli: SET foo bar; DUMP foo
+        let payload = serialize(&DumpValue::String(b"bar".to_vec()));
+        assert_eq!(&payload[..6], b"\x00\x03bar\x0b");
+        assert_eq!(
+            deserialize(&payload),
+            Ok(DumpValue::String(b"bar".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() {
+        let long = vec![b'x'; 20000];
+        let values = vec![
+            DumpValue::String(long.clone()),
+            DumpValue::Set(vec![b"a".to_vec(), vec![b'b'; 100]]),
+            DumpValue::Hash(vec![(b"field".to_vec(), long)]),
+        ];
+        for value in values {
+            assert_eq!(deserialize(&serialize(&value)), Ok(value));
+        }
+    }
+
+    /// 给payload加上version和crc64
+    fn with_footer(mut data: Vec<u8>) -> Vec<u8> {
+        data.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        let crc = crc64(0, &data);
+        data.extend_from_slice(&crc.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_deserialize_redis_encodings() {
+        // SET foo 12345; DUMP foo (int16编码)
+        let payload = with_footer(b"\x00\xc1\x39\x30".to_vec());
+        assert_eq!(
+            deserialize(&payload),
+            Ok(DumpValue::String(b"12345".to_vec()))
+        );
+
+        // SET foo aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa; DUMP foo (lzf)
+        let payload = with_footer(b"\x00\xc3\x05\x28\x00\x61\xe0\x1e\x00".to_vec());
+        assert_eq!(deserialize(&payload), Ok(DumpValue::String(vec![b'a'; 40])));
+
+        // SADD s 1 2; DUMP s (intset)
+        let payload =
+            with_footer(b"\x0b\x0c\x02\x00\x00\x00\x02\x00\x00\x00\x01\x00\x02\x00".to_vec());
+        assert_eq!(
+            deserialize(&payload),
+            Ok(DumpValue::Set(vec![b"1".to_vec(), b"2".to_vec()]))
+        );
+
+        // HSET h f v n 1000; DUMP h (listpack)
+        let payload = with_footer(
+            b"\x10\x13\x13\x00\x00\x00\x04\x00\x81f\x02\x81v\x02\x81n\x02\xc3\xe8\x02\xff".to_vec(),
+        );
+        assert_eq!(
+            deserialize(&payload),
+            Ok(DumpValue::Hash(vec![
+                (b"f".to_vec(), b"v".to_vec()),
+                (b"n".to_vec(), b"1000".to_vec())
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_deserialize_bad_payload() {
+        let mut payload = serialize(&DumpValue::String(b"bar".to_vec()));
+        let last = payload.len() - 1;
+        payload[last] ^= 0xff;
+        assert_eq!(deserialize(&payload), Err(DumpError::BadPayload));
+        assert_eq!(deserialize(b"\x00"), Err(DumpError::BadPayload));
+
+        // crc为0时也要校验
+        let mut payload = serialize(&DumpValue::String(b"bar".to_vec()));
+        payload[last - 7..].fill(0);
+        assert_eq!(deserialize(&payload), Err(DumpError::BadPayload));
+    }
+
+    #[test]
+    fn test_deserialize_huge_lzf_len() {
+        // 5字节的压缩数据声明解压后有4GB
+        let payload = with_footer(b"\x00\xc3\x05\x80\xff\xff\xff\xff\x00\x61\xe0\x1e\x00".to_vec());
+        assert_eq!(deserialize(&payload), Err(DumpError::BadFormat));
+        // 声明的长度在范围内但是和实际不一致
+        let payload = with_footer(b"\x00\xc3\x05\x29\x00\x61\xe0\x1e\x00".to_vec());
+        assert_eq!(deserialize(&payload), Err(DumpError::BadFormat));
+    }
+}
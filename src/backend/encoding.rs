@@ -0,0 +1,398 @@
+//! 小集合的紧凑编码
+//!
+//! 和redis一样, 元素少并且都比较短的hash和set用一个数组(listpack)保存, 只包含整数的set用
+//! 有序的整数数组(intset)保存。超过阈值后转换成哈希表, 之后不会再转换回来。
+
+use bytes::Bytes;
+use dashmap::{DashMap, DashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// 哈希表中每个元素的固定开销
+pub(super) const ELEMENT_OVERHEAD: usize = 32;
+/// listpack中每个元素的固定开销(encoding + backlen)
+const LISTPACK_ENTRY_OVERHEAD: usize = 2;
+/// listpack的header和结尾
+const LISTPACK_HEADER: usize = 7;
+
+/// 紧凑编码的阈值, 可以在运行时修改, 只影响之后写入的key
+#[derive(Debug)]
+pub struct EncodingConfig {
+    hash_max_listpack_entries: AtomicUsize,
+    hash_max_listpack_value: AtomicUsize,
+    set_max_intset_entries: AtomicUsize,
+    set_max_listpack_entries: AtomicUsize,
+    set_max_listpack_value: AtomicUsize,
+}
+
+impl Default for EncodingConfig {
+    /// 和redis 7.2的默认值一致
+    fn default() -> Self {
+        Self {
+            hash_max_listpack_entries: AtomicUsize::new(128),
+            hash_max_listpack_value: AtomicUsize::new(64),
+            set_max_intset_entries: AtomicUsize::new(512),
+            set_max_listpack_entries: AtomicUsize::new(128),
+            set_max_listpack_value: AtomicUsize::new(64),
+        }
+    }
+}
+
+macro_rules! config_accessor {
+    ($($name:ident, $setter:ident;)*) => {
+        impl EncodingConfig {
+            $(
+                pub fn $name(&self) -> usize {
+                    self.$name.load(Ordering::Relaxed)
+                }
+
+                pub fn $setter(&self, value: usize) {
+                    self.$name.store(value, Ordering::Relaxed);
+                }
+            )*
+        }
+    };
+}
+
+config_accessor! {
+    hash_max_listpack_entries, set_hash_max_listpack_entries;
+    hash_max_listpack_value, set_hash_max_listpack_value;
+    set_max_intset_entries, set_set_max_intset_entries;
+    set_max_listpack_entries, set_set_max_listpack_entries;
+    set_max_listpack_value, set_set_max_listpack_value;
+}
+
+/// 和redis的string2ll一致, 只接受规范的十进制写法
+pub(super) fn bytes_to_int(value: &[u8]) -> Option<i64> {
+    let s = std::str::from_utf8(value).ok()?;
+    let value = s.parse::<i64>().ok()?;
+    (value.to_string() == s).then_some(value)
+}
+
+#[derive(Debug, Clone)]
+pub enum HashValue {
+    Listpack(Vec<(Bytes, Bytes)>),
+    Table(DashMap<Bytes, Bytes>),
+}
+
+#[derive(Debug, Clone)]
+pub enum SetValue {
+    /// 有序, 不重复
+    IntSet(Vec<i64>),
+    Listpack(Vec<Bytes>),
+    Table(DashSet<Bytes>),
+}
+
+impl Default for HashValue {
+    fn default() -> Self {
+        HashValue::Listpack(Vec::new())
+    }
+}
+
+impl Default for SetValue {
+    fn default() -> Self {
+        SetValue::IntSet(Vec::new())
+    }
+}
+
+impl HashValue {
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            HashValue::Listpack(_) => "listpack",
+            HashValue::Table(_) => "hashtable",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            HashValue::Listpack(entries) => entries.len(),
+            HashValue::Table(map) => map.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 估算的内存占用
+    pub fn size(&self) -> usize {
+        match self {
+            HashValue::Listpack(entries) => listpack_hash_size(entries),
+            HashValue::Table(map) => map
+                .iter()
+                .map(|e| table_field_size(e.key(), e.value()))
+                .sum(),
+        }
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<Bytes> {
+        match self {
+            HashValue::Listpack(entries) => entries
+                .iter()
+                .find(|(f, _)| f == field)
+                .map(|(_, v)| v.clone()),
+            HashValue::Table(map) => map.get(field).map(|v| v.value().clone()),
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(Bytes, Bytes)> {
+        match self {
+            HashValue::Listpack(entries) => entries.clone(),
+            HashValue::Table(map) => map
+                .iter()
+                .map(|e| (e.key().clone(), e.value().clone()))
+                .collect(),
+        }
+    }
+
+    /// 返回旧的值和内存占用的变化
+    pub fn insert(
+        &mut self,
+        field: Bytes,
+        value: Bytes,
+        config: &EncodingConfig,
+    ) -> (Option<Bytes>, isize) {
+        let entries = match self {
+            // 哈希表计算整体的size太慢, 只计算变化的部分
+            HashValue::Table(map) => {
+                let size = table_field_size(&field, &value) as isize;
+                let old = map.insert(field.clone(), value);
+                let delta = size - old.as_ref().map_or(0, |v| table_field_size(&field, v)) as isize;
+                return (old, delta);
+            }
+            HashValue::Listpack(entries) => entries,
+        };
+        let before = listpack_hash_size(entries);
+        let too_long = field.len() > config.hash_max_listpack_value()
+            || value.len() > config.hash_max_listpack_value();
+        let old = match entries.iter_mut().find(|(f, _)| *f == field) {
+            Some((_, v)) => Some(std::mem::replace(v, value)),
+            None => {
+                entries.push((field, value));
+                None
+            }
+        };
+        if too_long || entries.len() > config.hash_max_listpack_entries() {
+            let table = std::mem::take(entries).into_iter().collect();
+            *self = HashValue::Table(table);
+        }
+        (old, self.size() as isize - before as isize)
+    }
+
+    /// 按照阈值选择编码, RESTORE使用
+    pub fn from_entries(entries: Vec<(Bytes, Bytes)>, config: &EncodingConfig) -> Self {
+        let mut value = HashValue::default();
+        for (field, v) in entries {
+            value.insert(field, v, config);
+        }
+        value
+    }
+}
+
+fn listpack_hash_size(entries: &[(Bytes, Bytes)]) -> usize {
+    LISTPACK_HEADER
+        + entries
+            .iter()
+            .map(|(f, v)| f.len() + v.len() + 2 * LISTPACK_ENTRY_OVERHEAD)
+            .sum::<usize>()
+}
+
+fn table_field_size(field: &[u8], value: &Bytes) -> usize {
+    field.len() + value.len() + ELEMENT_OVERHEAD
+}
+
+fn intset_width(values: &[i64]) -> usize {
+    values
+        .iter()
+        .map(|v| {
+            if i16::try_from(*v).is_ok() {
+                2
+            } else if i32::try_from(*v).is_ok() {
+                4
+            } else {
+                8
+            }
+        })
+        .max()
+        .unwrap_or(2)
+}
+
+impl SetValue {
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            SetValue::IntSet(_) => "intset",
+            SetValue::Listpack(_) => "listpack",
+            SetValue::Table(_) => "hashtable",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            SetValue::IntSet(values) => values.len(),
+            SetValue::Listpack(members) => members.len(),
+            SetValue::Table(set) => set.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            SetValue::IntSet(values) => 8 + values.len() * intset_width(values),
+            SetValue::Listpack(members) => {
+                LISTPACK_HEADER
+                    + members
+                        .iter()
+                        .map(|m| m.len() + LISTPACK_ENTRY_OVERHEAD)
+                        .sum::<usize>()
+            }
+            SetValue::Table(set) => set.iter().map(|m| m.len() + ELEMENT_OVERHEAD).sum(),
+        }
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            SetValue::IntSet(values) => {
+                bytes_to_int(member).is_some_and(|v| values.binary_search(&v).is_ok())
+            }
+            SetValue::Listpack(members) => members.iter().any(|m| m == member),
+            SetValue::Table(set) => set.contains(member),
+        }
+    }
+
+    pub fn members(&self) -> Vec<Bytes> {
+        match self {
+            SetValue::IntSet(values) => values.iter().map(|v| Bytes::from(v.to_string())).collect(),
+            SetValue::Listpack(members) => members.clone(),
+            SetValue::Table(set) => set.iter().map(|m| m.key().clone()).collect(),
+        }
+    }
+
+    /// 返回新增的成员数量和内存占用的变化
+    pub fn insert(&mut self, members: Vec<Bytes>, config: &EncodingConfig) -> (usize, isize) {
+        if let SetValue::Table(set) = self {
+            let mut added = 0;
+            let mut delta = 0;
+            for member in members {
+                let size = (member.len() + ELEMENT_OVERHEAD) as isize;
+                if set.insert(member) {
+                    added += 1;
+                    delta += size;
+                }
+            }
+            return (added, delta);
+        }
+        let before = self.size();
+        let mut added = 0;
+        for member in members {
+            if self.contains(&member) {
+                continue;
+            }
+            added += 1;
+            let member_len = member.len();
+            match self {
+                SetValue::IntSet(values) => match bytes_to_int(&member) {
+                    Some(v) if values.len() < config.set_max_intset_entries() => {
+                        let pos = values.binary_search(&v).unwrap_or_else(|p| p);
+                        values.insert(pos, v);
+                    }
+                    // 不是整数或者超过了intset的阈值, 先转换成listpack
+                    _ => {
+                        let mut list = values
+                            .iter()
+                            .map(|v| Bytes::from(v.to_string()))
+                            .collect::<Vec<Bytes>>();
+                        list.push(member);
+                        *self = SetValue::Listpack(list);
+                    }
+                },
+                SetValue::Listpack(list) => list.push(member),
+                SetValue::Table(set) => {
+                    set.insert(member);
+                }
+            }
+            if let SetValue::Listpack(list) = self {
+                if member_len > config.set_max_listpack_value()
+                    || list.len() > config.set_max_listpack_entries()
+                {
+                    *self = SetValue::Table(std::mem::take(list).into_iter().collect());
+                }
+            }
+        }
+        (added, self.size() as isize - before as isize)
+    }
+
+    pub fn from_members(members: Vec<Bytes>, config: &EncodingConfig) -> Self {
+        let mut value = SetValue::default();
+        value.insert(members, config);
+        value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bulk(s: &str) -> Bytes {
+        Bytes::copy_from_slice(s.as_bytes())
+    }
+
+    #[test]
+    fn test_hash_encoding() {
+        let config = EncodingConfig::default();
+        config.set_hash_max_listpack_entries(2);
+        let mut hash = HashValue::default();
+        hash.insert(bulk("a"), bulk("1"), &config);
+        let (old, _) = hash.insert(bulk("a"), bulk("2"), &config);
+        assert_eq!(old, Some(bulk("1")));
+        hash.insert(bulk("b"), bulk("2"), &config);
+        assert_eq!(hash.encoding(), "listpack");
+
+        hash.insert(bulk("c"), bulk("3"), &config);
+        assert_eq!(hash.encoding(), "hashtable");
+        assert_eq!(hash.get(b"a"), Some(bulk("2")));
+        assert_eq!(hash.len(), 3);
+
+        let long = "x".repeat(65);
+        let hash = HashValue::from_entries(vec![(bulk("f"), bulk(&long))], &config);
+        assert_eq!(hash.encoding(), "hashtable");
+    }
+
+    #[test]
+    fn test_set_encoding() {
+        let config = EncodingConfig::default();
+        config.set_set_max_intset_entries(3);
+        let mut set = SetValue::default();
+        let (added, _) = set.insert(vec![bulk("3"), bulk("1"), bulk("3")], &config);
+        assert_eq!(added, 2);
+        assert_eq!(set.encoding(), "intset");
+        assert!(set.contains(&bulk("1")) && !set.contains(&bulk("01")));
+
+        set.insert(vec![bulk("2"), bulk("4")], &config);
+        assert_eq!(set.encoding(), "listpack");
+        assert_eq!(set.len(), 4);
+        assert!(set.contains(&bulk("4")));
+
+        set.insert(vec![bulk(&"x".repeat(65))], &config);
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(set.len(), 5);
+
+        let set = SetValue::from_members(vec![bulk("a"), bulk("1")], &config);
+        assert_eq!(set.encoding(), "listpack");
+    }
+
+    #[test]
+    fn test_size_delta() {
+        let config = EncodingConfig::default();
+        config.set_set_max_listpack_entries(2);
+        let mut set = SetValue::default();
+        let mut size = set.size() as isize;
+        for m in ["a", "b", "c", "d"] {
+            let (_, delta) = set.insert(vec![bulk(m)], &config);
+            size += delta;
+        }
+        assert_eq!(set.encoding(), "hashtable");
+        assert_eq!(size as usize, set.size());
+    }
+}
@@ -0,0 +1,246 @@
+//! maxmemory 和淘汰策略
+//!
+//! 内存是按key估算的近似值, 超过maxmemory时在执行会占用内存的命令之前淘汰key。
+//! 和redis一样每次只采样 maxmemory-samples 个key, 从中选出最适合淘汰的一个。
+
+use super::now_ms;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+};
+
+/// LFU计数器的初始值, 新的key不会马上被淘汰
+pub const LFU_INIT_VAL: u8 = 5;
+/// 计数器增长的对数因子, 越大增长越慢
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// 每过多少毫秒计数器减一
+const LFU_DECAY_TIME: u64 = 60_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    NoEviction,
+    AllKeysLru,
+    AllKeysLfu,
+    AllKeysRandom,
+    VolatileLru,
+    VolatileLfu,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+const POLICIES: [(EvictionPolicy, &str); 8] = [
+    (EvictionPolicy::NoEviction, "noeviction"),
+    (EvictionPolicy::AllKeysLru, "allkeys-lru"),
+    (EvictionPolicy::AllKeysLfu, "allkeys-lfu"),
+    (EvictionPolicy::AllKeysRandom, "allkeys-random"),
+    (EvictionPolicy::VolatileLru, "volatile-lru"),
+    (EvictionPolicy::VolatileLfu, "volatile-lfu"),
+    (EvictionPolicy::VolatileRandom, "volatile-random"),
+    (EvictionPolicy::VolatileTtl, "volatile-ttl"),
+];
+
+impl EvictionPolicy {
+    /// volatile-* 只淘汰设置了过期时间的key
+    pub fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            EvictionPolicy::VolatileLru
+                | EvictionPolicy::VolatileLfu
+                | EvictionPolicy::VolatileRandom
+                | EvictionPolicy::VolatileTtl
+        )
+    }
+
+    fn index(&self) -> u8 {
+        POLICIES.iter().position(|(p, _)| p == self).unwrap_or(0) as u8
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        POLICIES
+            .iter()
+            .find(|(_, name)| name.eq_ignore_ascii_case(s))
+            .map(|(p, _)| *p)
+            .ok_or_else(|| format!("invalid maxmemory-policy: {}", s))
+    }
+}
+
+impl fmt::Display for EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(POLICIES[self.index() as usize].1)
+    }
+}
+
+/// 支持redis配置文件的写法: 100, 1k, 1kb, 1m, 1mb, 1g, 1gb
+pub fn parse_memory(s: &str) -> Result<u64, String> {
+    let lower = s.to_ascii_lowercase();
+    let units: [(&str, u64); 6] = [
+        ("kb", 1024),
+        ("k", 1000),
+        ("mb", 1024 * 1024),
+        ("m", 1000 * 1000),
+        ("gb", 1024 * 1024 * 1024),
+        ("g", 1000 * 1000 * 1000),
+    ];
+    let (num, unit) = units
+        .iter()
+        .find_map(|(suffix, unit)| lower.strip_suffix(suffix).map(|n| (n, *unit)))
+        .unwrap_or((lower.as_str(), 1));
+    num.parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("invalid memory value: {}", s))
+}
+
+/// maxmemory相关的配置和统计, 可以在运行时修改
+#[derive(Debug)]
+pub struct MaxMemory {
+    /// 0表示不限制
+    maxmemory: AtomicU64,
+    policy: AtomicU8,
+    samples: AtomicUsize,
+    evicted_keys: AtomicU64,
+}
+
+impl Default for MaxMemory {
+    fn default() -> Self {
+        Self {
+            maxmemory: AtomicU64::new(0),
+            policy: AtomicU8::new(EvictionPolicy::NoEviction.index()),
+            samples: AtomicUsize::new(5),
+            evicted_keys: AtomicU64::new(0),
+        }
+    }
+}
+
+impl MaxMemory {
+    pub fn maxmemory(&self) -> u64 {
+        self.maxmemory.load(Ordering::Relaxed)
+    }
+
+    pub fn set_maxmemory(&self, maxmemory: u64) {
+        self.maxmemory.store(maxmemory, Ordering::Relaxed);
+    }
+
+    pub fn policy(&self) -> EvictionPolicy {
+        POLICIES[self.policy.load(Ordering::Relaxed) as usize].0
+    }
+
+    pub fn set_policy(&self, policy: EvictionPolicy) {
+        self.policy.store(policy.index(), Ordering::Relaxed);
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples.load(Ordering::Relaxed)
+    }
+
+    pub fn set_samples(&self, samples: usize) {
+        self.samples.store(samples.max(1), Ordering::Relaxed);
+    }
+
+    pub fn evicted_keys(&self) -> u64 {
+        self.evicted_keys.load(Ordering::Relaxed)
+    }
+
+    /// CONFIG RESETSTAT
+    pub fn reset_stats(&self) {
+        self.evicted_keys.store(0, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_eviction(&self) {
+        self.evicted_keys.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// key的LRU/LFU信息, 每次访问key时更新
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyAccess {
+    /// 最后一次访问的时间(unix毫秒)
+    pub last_access: u64,
+    /// 对数计数器, 和redis的LFU计数器一致
+    pub freq: u8,
+}
+
+impl Default for KeyAccess {
+    fn default() -> Self {
+        Self {
+            last_access: now_ms(),
+            freq: LFU_INIT_VAL,
+        }
+    }
+}
+
+impl KeyAccess {
+    pub fn idle_ms(&self, now: u64) -> u64 {
+        now.saturating_sub(self.last_access)
+    }
+
+    /// 距离上次访问每过 LFU_DECAY_TIME 计数器减一
+    pub fn decayed_freq(&self, now: u64) -> u8 {
+        let periods = self.idle_ms(now) / LFU_DECAY_TIME;
+        self.freq.saturating_sub(periods.min(255) as u8)
+    }
+
+    pub fn touch(&mut self, now: u64) {
+        let mut freq = self.decayed_freq(now);
+        if freq < 255 {
+            let base = freq.saturating_sub(LFU_INIT_VAL) as f64;
+            if rand::random::<f64>() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+                freq += 1;
+            }
+        }
+        self.freq = freq;
+        self.last_access = now;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        for (policy, name) in POLICIES {
+            assert_eq!(name.parse::<EvictionPolicy>(), Ok(policy));
+            assert_eq!(policy.to_string(), name);
+        }
+        assert!("allkeys".parse::<EvictionPolicy>().is_err());
+        assert!(EvictionPolicy::VolatileTtl.is_volatile());
+
+        let config = MaxMemory::default();
+        assert_eq!(config.policy(), EvictionPolicy::NoEviction);
+        config.set_policy(EvictionPolicy::AllKeysLfu);
+        assert_eq!(config.policy(), EvictionPolicy::AllKeysLfu);
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(parse_memory("100"), Ok(100));
+        assert_eq!(parse_memory("1kb"), Ok(1024));
+        assert_eq!(parse_memory("2M"), Ok(2_000_000));
+        assert_eq!(parse_memory("1gb"), Ok(1 << 30));
+        assert!(parse_memory("1tb").is_err());
+    }
+
+    #[test]
+    fn test_lfu() {
+        let now = now_ms();
+        let mut access = KeyAccess {
+            last_access: now,
+            freq: LFU_INIT_VAL,
+        };
+        // 计数器较小时几乎每次都会增长
+        for _ in 0..100 {
+            access.touch(now);
+        }
+        assert!(access.freq > LFU_INIT_VAL && access.freq < 255);
+        assert_eq!(
+            access.decayed_freq(now + 3 * LFU_DECAY_TIME),
+            access.freq - 3
+        );
+    }
+}
@@ -1,27 +1,88 @@
-use crate::resp::frame::RespFrame;
-use dashmap::{DashMap, DashSet};
-use std::{ops::Deref, sync::Arc};
+mod clients;
+mod db;
+mod dump;
+mod encoding;
+mod evict;
+mod replication;
+mod shutdown;
+mod tracking;
 
+use crate::{
+    acl::{AclError, AclState, Denied},
+    cluster::ClusterState,
+    config::Config,
+    tls::TlsContext,
+};
+use bytes::Bytes;
+pub use clients::{
+    ClientHandle, ClientRegistry, ClientState, ClientType, KillFilter, OutputBufferLimit,
+    OutputBufferLimits, PushReceiver,
+};
+pub use db::Db;
+pub use dump::{crc64, deserialize, serialize, DumpError, DumpValue, RDB_VERSION};
+pub use encoding::{EncodingConfig, HashValue, SetValue};
+pub use evict::{parse_memory, EvictionPolicy, KeyAccess, MaxMemory, LFU_INIT_VAL};
+pub use replication::{ReplicaAck, ReplicationState};
+pub use shutdown::{ShutdownError, ShutdownFlags, ShutdownState};
+use std::{
+    ops::Deref,
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+pub use tracking::{TrackingOptions, TrackingTable, INVALIDATE_CHANNEL};
+
+/// 和redis一样默认16个db
+pub const DEFAULT_DATABASES: usize = 16;
+
+/// Backend 绑定了一个db, 读写key的方法都作用在这个db上, 用select切换
 #[derive(Debug, Clone)]
-pub struct Backend(Arc<BackendInner>);
+pub struct Backend {
+    inner: Arc<BackendInner>,
+    index: usize,
+    /// CLIENT NO-TOUCH, 访问key时不更新LRU/LFU
+    no_touch: bool,
+}
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct BackendInner {
-    map: DashMap<String, RespFrame>,
-    hmap: DashMap<String, DashMap<String, RespFrame>>,
-    set: DashMap<String, DashSet<RespFrame>>,
+    /// SWAPDB 和 FLUSHDB 直接替换Arc, 不需要逐个key操作
+    dbs: Vec<RwLock<Arc<Db>>>,
+    replication: ReplicationState,
+    shutdown: ShutdownState,
+    clients: ClientRegistry,
+    tracking: TrackingTable,
+    acl: AclState,
+    cluster: ClusterState,
+    maxmemory: MaxMemory,
+    encoding: Arc<EncodingConfig>,
+    tls: TlsContext,
+    config: Config,
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
 }
 
 impl Deref for Backend {
     type Target = BackendInner;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.inner
     }
 }
 
 impl Default for Backend {
     fn default() -> Self {
-        Self(Arc::new(BackendInner::default()))
+        Self::with_databases(DEFAULT_DATABASES)
+    }
+}
+
+/// FLUSHDB ASYNC 在后台线程释放内存, 不阻塞当前请求
+fn drop_dbs(dbs: Vec<Arc<Db>>, lazy: bool) {
+    if lazy {
+        std::thread::spawn(move || drop(dbs));
     }
 }
 
@@ -30,41 +91,474 @@ impl Backend {
         Self::default()
     }
 
-    pub fn get(&self, key: &str) -> Option<RespFrame> {
-        let value = self.map.get(key).map(|v| v.value().clone());
-        value
+    /// databases 至少为1
+    pub fn with_databases(databases: usize) -> Self {
+        let databases = databases.max(1).to_string();
+        Self::build(
+            Config::load(None, &[("databases".to_string(), databases)]).unwrap_or_default(),
+            TlsContext::default(),
+        )
+    }
+
+    /// 开启cluster模式, ip和port是本节点对外公布的地址
+    pub fn new_cluster(ip: impl Into<String>, port: u16) -> Self {
+        let overrides = [
+            ("cluster-enabled", "yes".to_string()),
+            ("cluster-announce-ip", ip.into()),
+            ("port", port.to_string()),
+        ]
+        .map(|(k, v)| (k.to_string(), v));
+        Self::build(
+            Config::load(None, &overrides).unwrap_or_default(),
+            TlsContext::default(),
+        )
+    }
+
+    /// 按照配置创建, 不使用TLS
+    pub fn from_config(config: Config) -> Result<Self, AclError> {
+        Self::with_tls(config, TlsContext::default())
+    }
+
+    /// 按照配置创建, 配置了aclfile时从文件中读取用户, 文件不合法时启动失败。
+    /// TLS证书读取失败时启动失败, 所以由调用者先创建TlsContext
+    pub fn with_tls(config: Config, tls: TlsContext) -> Result<Self, AclError> {
+        let backend = Self::build(config, tls);
+        if backend.acl.file().is_some() {
+            backend.acl.load()?;
+            // requirepass 优先于aclfile中default用户的密码
+            let requirepass = backend.config.get("requirepass").unwrap_or_default();
+            if !requirepass.is_empty() {
+                backend.acl.set_requirepass(&requirepass);
+            }
+        }
+        Ok(backend)
+    }
+
+    /// cluster模式下没有设置cluster-announce-ip时使用bind的地址
+    fn build(config: Config, tls: TlsContext) -> Self {
+        let cluster = if config.get_bool("cluster-enabled") {
+            let ip = match config.get("cluster-announce-ip").unwrap_or_default() {
+                ip if !ip.is_empty() => ip,
+                _ => match config.get("bind").unwrap_or_default().as_str() {
+                    "0.0.0.0" | "" => "127.0.0.1".to_string(),
+                    ip => ip.to_string(),
+                },
+            };
+            ClusterState::new(ip, config.get_parsed("port"))
+        } else {
+            ClusterState::default()
+        };
+        let acl = match config.get("aclfile").unwrap_or_default() {
+            file if file.is_empty() => AclState::default(),
+            file => AclState::new(Some(file.into())),
+        };
+        let requirepass = config.get("requirepass").unwrap_or_default();
+        if !requirepass.is_empty() {
+            acl.set_requirepass(&requirepass);
+        }
+        let encoding = Arc::new(EncodingConfig::default());
+        let dbs = (0..config.get_parsed::<usize>("databases").max(1))
+            .map(|_| RwLock::new(Arc::new(Db::new(encoding.clone()))))
+            .collect();
+        let backend = Self {
+            inner: Arc::new(BackendInner {
+                dbs,
+                replication: ReplicationState::default(),
+                shutdown: ShutdownState::default(),
+                clients: ClientRegistry::default(),
+                tracking: TrackingTable::default(),
+                acl,
+                cluster,
+                maxmemory: MaxMemory::default(),
+                encoding,
+                tls,
+                config,
+            }),
+            index: 0,
+            no_touch: false,
+        };
+        // hook由config持有, 只能持有Weak, 否则BackendInner永远不会释放
+        let inner = Arc::downgrade(&backend.inner);
+        backend.config.subscribe("requirepass", move |password| {
+            if let Some(inner) = inner.upgrade() {
+                inner.acl.set_requirepass(password);
+            }
+        });
+        backend.apply_config();
+        backend
+    }
+
+    /// 把可以在运行时修改的配置同步到各个模块, 启动和CONFIG SET之后调用
+    pub fn apply_config(&self) {
+        let config = &self.config;
+        self.maxmemory.set_maxmemory(config.get_parsed("maxmemory"));
+        if let Ok(policy) = config.get("maxmemory-policy").unwrap_or_default().parse() {
+            self.maxmemory.set_policy(policy);
+        }
+        self.maxmemory
+            .set_samples(config.get_parsed("maxmemory-samples"));
+        self.clients
+            .set_output_limits(config.get_parsed("client-output-buffer-limit"));
+        let encoding = &self.encoding;
+        encoding.set_hash_max_listpack_entries(config.get_parsed("hash-max-listpack-entries"));
+        encoding.set_hash_max_listpack_value(config.get_parsed("hash-max-listpack-value"));
+        encoding.set_set_max_intset_entries(config.get_parsed("set-max-intset-entries"));
+        encoding.set_set_max_listpack_entries(config.get_parsed("set-max-listpack-entries"));
+        encoding.set_set_max_listpack_value(config.get_parsed("set-max-listpack-value"));
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// default用户需要密码时新连接需要先AUTH
+    pub fn requires_auth(&self) -> bool {
+        self.acl.requires_auth()
+    }
+
+    pub fn acl(&self) -> &AclState {
+        &self.acl
+    }
+
+    pub fn tls(&self) -> &TlsContext {
+        &self.tls
+    }
+
+    /// 记录到ACL LOG, 长度由acllog-max-len限制
+    pub fn acl_log(&self, denied: &Denied, username: &str, client_info: String) {
+        let max_len = self.config.get_parsed("acllog-max-len");
+        self.acl.log(denied, username, client_info, max_len);
+    }
+
+    pub fn replication(&self) -> &ReplicationState {
+        &self.replication
     }
 
-    pub fn set(&self, key: &str, value: RespFrame) -> Option<RespFrame> {
-        self.map.insert(key.to_string(), value)
+    pub fn shutdown(&self) -> &ShutdownState {
+        &self.shutdown
     }
 
-    pub fn hget(&self, key: &str, field: &str) -> Option<RespFrame> {
-        let value = self
-            .hmap
-            .get(key)
-            .and_then(|m| m.get(field).map(|v| v.value().clone()));
-        value
+    pub fn clients(&self) -> &ClientRegistry {
+        &self.clients
     }
 
-    pub fn hget_all(&self, key: &str) -> Option<DashMap<String, RespFrame>> {
-        self.hmap.get(key).map(|m| m.clone())
+    pub fn tracking(&self) -> &TrackingTable {
+        &self.tracking
     }
 
-    pub fn hset(&self, key: &str, field: &str, value: RespFrame) -> Option<RespFrame> {
-        let map = self.hmap.entry(key.to_string()).or_default();
-        map.insert(field.to_string(), value)
+    /// key被修改, 通知读过这些key的连接, by是修改key的连接
+    pub fn invalidate(&self, keys: &[Bytes], by: u64) {
+        self.tracking.invalidate(&self.clients, keys, by);
     }
 
-    pub fn sadd(&self, key: &str, members: Vec<RespFrame>) {
-        let mut set = self.set.entry(key.to_string()).or_default();
-        set.extend(members);
+    /// FLUSHDB / FLUSHALL 之后通知所有开启了tracking的连接
+    pub fn invalidate_all(&self) {
+        self.tracking.invalidate_all(&self.clients);
     }
 
-    pub fn sismembers(&self, key: &str, field: &RespFrame) -> bool {
-        match self.set.get(key) {
-            Some(set) => set.contains(field),
+    pub fn cluster(&self) -> &ClusterState {
+        &self.cluster
+    }
+
+    pub fn encoding_config(&self) -> &EncodingConfig {
+        &self.encoding
+    }
+
+    pub fn maxmemory(&self) -> &MaxMemory {
+        &self.maxmemory
+    }
+
+    /// 所有db估算的内存占用之和
+    pub fn used_memory(&self) -> u64 {
+        (0..self.databases())
+            .map(|i| self.db_at(i).used_memory() as u64)
+            .sum()
+    }
+
+    /// 内存超过maxmemory时按照淘汰策略删除key, 无法释放足够内存时返回false
+    pub fn evict_if_needed(&self) -> bool {
+        let maxmemory = self.maxmemory.maxmemory();
+        if maxmemory == 0 {
+            return true;
+        }
+        let (policy, samples) = (self.maxmemory.policy(), self.maxmemory.samples());
+        while self.used_memory() > maxmemory {
+            if policy == EvictionPolicy::NoEviction {
+                return false;
+            }
+            // 每个db采样一次, 淘汰分数最高的key
+            let victim = (0..self.databases())
+                .filter_map(|i| {
+                    let (score, key) = self.db_at(i).eviction_candidate(policy, samples)?;
+                    Some((score, i, key))
+                })
+                .max();
+            match victim {
+                Some((_, i, key)) => {
+                    self.db_at(i).del(&key);
+                    self.maxmemory.record_eviction();
+                    self.invalidate(&[key], 0);
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
+    pub fn databases(&self) -> usize {
+        self.dbs.len()
+    }
+
+    /// 当前绑定的db
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// 返回绑定到另一个db的Backend, index超出范围时返回None
+    pub fn select(&self, index: usize) -> Option<Backend> {
+        (index < self.databases()).then(|| Backend {
+            inner: self.inner.clone(),
+            index,
+            no_touch: self.no_touch,
+        })
+    }
+
+    pub fn with_no_touch(mut self, no_touch: bool) -> Self {
+        self.no_touch = no_touch;
+        self
+    }
+
+    /// NO-TOUCH 时恢复key原来的访问信息, 新建的key不受影响
+    fn untouched<T>(&self, key: &[u8], f: impl FnOnce(&Db) -> T) -> T {
+        let db = self.db();
+        if !self.no_touch {
+            return f(&db);
+        }
+        let access = db.key_access(key);
+        let ret = f(&db);
+        if let Some(access) = access {
+            db.set_key_access(key, access);
+        }
+        ret
+    }
+
+    pub fn db(&self) -> Arc<Db> {
+        self.db_at(self.index)
+    }
+
+    fn db_at(&self, index: usize) -> Arc<Db> {
+        self.dbs[index]
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// SWAPDB, 连接到其中一个db的客户端会立刻看到另一个db的数据
+    pub fn swap_db(&self, a: usize, b: usize) -> bool {
+        if a >= self.databases() || b >= self.databases() {
+            return false;
+        }
+        if a != b {
+            let (first, second) = (a.min(b), a.max(b));
+            let mut first = self.dbs[first].write().unwrap_or_else(|e| e.into_inner());
+            let mut second = self.dbs[second].write().unwrap_or_else(|e| e.into_inner());
+            std::mem::swap(&mut *first, &mut *second);
+        }
+        true
+    }
+
+    /// FLUSHDB, lazy为true时在后台释放旧的数据
+    pub fn flush_db(&self, lazy: bool) {
+        let old = self.replace_db(self.index);
+        drop_dbs(vec![old], lazy);
+    }
+
+    /// FLUSHALL
+    pub fn flush_all(&self, lazy: bool) {
+        let old = (0..self.databases()).map(|i| self.replace_db(i)).collect();
+        drop_dbs(old, lazy);
+    }
+
+    fn replace_db(&self, index: usize) -> Arc<Db> {
+        let mut db = self.dbs[index].write().unwrap_or_else(|e| e.into_inner());
+        std::mem::replace(&mut *db, Arc::new(Db::new(self.encoding.clone())))
+    }
+
+    /// MOVE, 目标db中已经存在key或者当前db中没有key时返回false
+    pub fn move_key(&self, key: &[u8], to: usize) -> bool {
+        let (from, to) = (self.db(), self.db_at(to));
+        if to.exists(key) {
+            return false;
+        }
+        match from.take(key) {
+            Some(entry) => {
+                to.put(key, entry);
+                true
+            }
             None => false,
         }
     }
+
+    pub fn keys(&self) -> Vec<Bytes> {
+        self.db().keys()
+    }
+
+    pub fn exists(&self, key: &[u8]) -> bool {
+        self.db().exists(key)
+    }
+
+    pub fn del(&self, key: &[u8]) -> bool {
+        self.db().del(key)
+    }
+
+    pub fn expire_at(&self, key: &[u8], at: u64) -> bool {
+        self.db().expire_at(key, at)
+    }
+
+    pub fn pttl(&self, key: &[u8]) -> Option<i64> {
+        self.db().pttl(key)
+    }
+
+    pub fn key_access(&self, key: &[u8]) -> Option<KeyAccess> {
+        self.db().key_access(key)
+    }
+
+    pub fn set_key_access(&self, key: &[u8], access: KeyAccess) {
+        self.db().set_key_access(key, access)
+    }
+
+    pub fn encoding(&self, key: &[u8]) -> Option<&'static str> {
+        self.db().encoding(key)
+    }
+
+    pub fn memory_usage(&self, key: &[u8]) -> Option<usize> {
+        self.db().memory_usage(key)
+    }
+
+    pub fn dump(&self, key: &[u8]) -> Option<DumpValue> {
+        self.db().dump(key)
+    }
+
+    pub fn restore(&self, key: &[u8], value: DumpValue) {
+        self.db().restore(key, value)
+    }
+
+    pub fn keys_in_slot(&self, slot: u16, count: usize) -> Vec<Bytes> {
+        self.db().keys_in_slot(slot, count)
+    }
+
+    pub fn count_keys_in_slot(&self, slot: u16) -> usize {
+        self.db().count_keys_in_slot(slot)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
+        self.untouched(key, |db| db.get(key))
+    }
+
+    pub fn set(&self, key: &[u8], value: Bytes) -> Option<Bytes> {
+        self.db().set(key, value)
+    }
+
+    pub fn hget(&self, key: &[u8], field: &[u8]) -> Option<Bytes> {
+        self.untouched(key, |db| db.hget(key, field))
+    }
+
+    pub fn hget_all(&self, key: &[u8]) -> Option<Vec<(Bytes, Bytes)>> {
+        self.untouched(key, |db| db.hget_all(key))
+    }
+
+    pub fn hset(&self, key: &[u8], field: &[u8], value: Bytes) -> Option<Bytes> {
+        self.untouched(key, |db| db.hset(key, field, value))
+    }
+
+    pub fn sadd(&self, key: &[u8], members: Vec<Bytes>) -> usize {
+        self.untouched(key, |db| db.sadd(key, members))
+    }
+
+    pub fn sismembers(&self, key: &[u8], field: &[u8]) -> bool {
+        self.untouched(key, |db| db.sismembers(key, field))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_select_swap_move() {
+        let backend = Backend::with_databases(4);
+        assert!(backend.select(4).is_none());
+        let db1 = backend.select(1).unwrap();
+        db1.set(b"key", "v1".into());
+        assert!(!backend.exists(b"key"));
+
+        assert!(backend.swap_db(0, 1));
+        assert_eq!(backend.get(b"key"), Some("v1".into()));
+        assert!(!db1.exists(b"key"));
+        assert!(!backend.swap_db(0, 4));
+
+        assert!(backend.move_key(b"key", 2));
+        assert!(!backend.exists(b"key"));
+        let db2 = backend.select(2).unwrap();
+        assert_eq!(db2.get(b"key"), Some("v1".into()));
+        assert!(!backend.move_key(b"key", 2));
+
+        backend.set(b"key", "v0".into());
+        assert!(!backend.move_key(b"key", 2));
+        assert!(backend.exists(b"key"));
+    }
+
+    #[test]
+    fn test_evict() {
+        let backend = Backend::new();
+        for i in 0..100 {
+            backend.set(format!("key{}", i).as_bytes(), "value".into());
+        }
+        let used = backend.used_memory();
+        backend.maxmemory().set_maxmemory(used / 2);
+        assert!(!backend.evict_if_needed());
+
+        backend.maxmemory().set_policy(EvictionPolicy::VolatileLru);
+        assert!(!backend.evict_if_needed());
+
+        backend.maxmemory().set_policy(EvictionPolicy::AllKeysLru);
+        assert!(backend.evict_if_needed());
+        assert!(backend.used_memory() <= used / 2);
+        assert!(backend.maxmemory().evicted_keys() >= 50);
+    }
+
+    #[test]
+    fn test_flush() {
+        let backend = Backend::new();
+        let db1 = backend.select(1).unwrap();
+        backend.set(b"a", "1".into());
+        db1.set(b"b", "2".into());
+
+        db1.flush_db(true);
+        assert!(!db1.exists(b"b"));
+        assert!(backend.exists(b"a"));
+
+        db1.set(b"b", "2".into());
+        backend.flush_all(false);
+        assert!(backend.keys().is_empty() && db1.keys().is_empty());
+    }
+
+    #[test]
+    fn test_aclfile_on_startup() -> anyhow::Result<()> {
+        let file = std::env::temp_dir().join(format!("redis-backend-{}.acl", std::process::id()));
+        let config = || {
+            let aclfile = ("aclfile".to_string(), file.display().to_string());
+            Config::load(None, &[aclfile])
+        };
+        std::fs::write(&file, "user alice on >pw ~* +@all\n")?;
+        let backend = Backend::from_config(config()?)?;
+        assert!(backend.acl().authenticate("alice", b"pw"));
+        // 文件中没有default用户时使用默认的default用户
+        assert!(backend.acl().user("default").is_some());
+
+        std::fs::write(&file, "user alice on +bogus\n")?;
+        assert!(Backend::from_config(config()?).is_err());
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,146 @@
+//! 记录master的复制offset和各个replica通过REPLCONF ACK上报的offset,
+//! WAIT / WAITAOF 依赖这里的数据来判断有多少replica已经确认了写入
+
+use dashmap::DashMap;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tokio::{sync::Notify, time::Instant};
+
+#[derive(Debug, Default)]
+pub struct ReplicationState {
+    /// 已经写入(可以被复制)的字节数
+    master_offset: AtomicU64,
+    /// connection id -> replica上报的ack
+    replicas: DashMap<u64, ReplicaAck>,
+    /// 每次收到ack时唤醒所有在WAIT的连接
+    ack_notify: Notify,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReplicaAck {
+    /// REPLCONF ACK <offset>
+    pub offset: u64,
+    /// REPLCONF ACK <offset> FACK <aofoffset>
+    pub aof_offset: u64,
+}
+
+impl ReplicationState {
+    pub fn master_offset(&self) -> u64 {
+        self.master_offset.load(Ordering::SeqCst)
+    }
+
+    /// 一个写命令执行完之后调用, 返回新的master offset
+    pub fn propagate(&self, len: usize) -> u64 {
+        self.master_offset.fetch_add(len as u64, Ordering::SeqCst) + len as u64
+    }
+
+    pub fn ack(&self, conn_id: u64, ack: ReplicaAck) {
+        self.replicas.insert(conn_id, ack);
+        self.ack_notify.notify_waiters();
+    }
+
+    /// replica断开连接时调用
+    pub fn remove_replica(&self, conn_id: u64) {
+        if self.replicas.remove(&conn_id).is_some() {
+            self.ack_notify.notify_waiters();
+        }
+    }
+
+    pub fn replica_count(&self) -> usize {
+        self.replicas.len()
+    }
+
+    /// 统计ack的offset >= offset的replica数量, aof为true时比较FACK上报的offset
+    pub fn acked(&self, offset: u64, aof: bool) -> usize {
+        self.replicas
+            .iter()
+            .filter(|r| {
+                let acked = if aof { r.aof_offset } else { r.offset };
+                acked >= offset
+            })
+            .count()
+    }
+
+    /// 一直等到至少numreplicas个replica确认了offset, 或者超时。
+    /// timeout为0时永远等待, 返回值是最后确认了的replica数量。
+    /// 只会挂起调用WAIT的那个连接的task
+    pub async fn wait_for_acks(
+        &self,
+        offset: u64,
+        numreplicas: usize,
+        timeout: Duration,
+        aof: bool,
+    ) -> usize {
+        let deadline = (!timeout.is_zero()).then(|| Instant::now() + timeout);
+        loop {
+            // 先注册notified再检查, 否则检查和等待之间来的ack会丢失
+            let notified = self.ack_notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let acked = self.acked(offset, aof);
+            if acked >= numreplicas {
+                return acked;
+            }
+            match deadline {
+                Some(deadline) => {
+                    if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                        return self.acked(offset, aof);
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_wait_for_acks_timeout() {
+        let state = ReplicationState::default();
+        let offset = state.propagate(10);
+        state.ack(
+            1,
+            ReplicaAck {
+                offset: 5,
+                aof_offset: 0,
+            },
+        );
+        let acked = state
+            .wait_for_acks(offset, 1, Duration::from_millis(20), false)
+            .await;
+        assert_eq!(acked, 0);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_acks_wakeup() {
+        let state = Arc::new(ReplicationState::default());
+        let offset = state.propagate(10);
+        let waiter = {
+            let state = state.clone();
+            tokio::spawn(async move { state.wait_for_acks(offset, 2, Duration::ZERO, false).await })
+        };
+        state.ack(
+            1,
+            ReplicaAck {
+                offset: 10,
+                aof_offset: 0,
+            },
+        );
+        state.ack(
+            2,
+            ReplicaAck {
+                offset: 12,
+                aof_offset: 0,
+            },
+        );
+        assert_eq!(waiter.await.unwrap(), 2);
+        assert_eq!(state.acked(offset, true), 0);
+    }
+}
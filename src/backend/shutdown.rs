@@ -0,0 +1,224 @@
+//! 优雅退出, SHUTDOWN 命令和 SIGINT/SIGTERM 都走这里
+//!
+//! 1. 有replica时等待它们确认当前的master offset, 最多shutdown-timeout秒(NOW跳过),
+//!    这期间照常处理请求, SHUTDOWN ABORT 可以取消
+//! 2. 按需保存数据
+//! 3. 进入关闭阶段: 停止accept, 每个连接回复完正在处理的请求之后断开,
+//!    主循环最多再等待shutdown-timeout秒后退出
+
+use super::ReplicationState;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::sync::{watch, Notify};
+use tracing::{error, info, warn};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownFlags {
+    /// Some(true)为SAVE, Some(false)为NOSAVE, None时按照配置(目前没有save规则, 不保存)
+    pub save: Option<bool>,
+    /// 不等待replica
+    pub now: bool,
+    /// 忽略保存失败之类的错误, 强制退出
+    pub force: bool,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ShutdownError {
+    #[error("Errors trying to SHUTDOWN. Check logs.")]
+    Failed,
+    #[error("No shutdown in progress.")]
+    NotInProgress,
+}
+
+#[derive(Debug)]
+pub struct ShutdownState {
+    /// 正在等待replica, SHUTDOWN ABORT 只能取消这个阶段
+    pending: AtomicBool,
+    abort: Notify,
+    /// 进入关闭阶段后变成true, accept循环和所有连接都订阅它
+    closing: watch::Sender<bool>,
+    /// 当前的连接数, 关闭阶段等它变成0
+    connections: watch::Sender<usize>,
+}
+
+impl Default for ShutdownState {
+    fn default() -> Self {
+        Self {
+            pending: AtomicBool::new(false),
+            abort: Notify::new(),
+            closing: watch::Sender::new(false),
+            connections: watch::Sender::new(0),
+        }
+    }
+}
+
+impl ShutdownState {
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    pub fn is_closing(&self) -> bool {
+        *self.closing.borrow()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.closing.subscribe()
+    }
+
+    pub fn connection_opened(&self) {
+        self.connections.send_modify(|n| *n += 1);
+    }
+
+    pub fn connection_closed(&self) {
+        self.connections.send_modify(|n| *n = n.saturating_sub(1));
+    }
+
+    pub fn connections(&self) -> usize {
+        *self.connections.borrow()
+    }
+
+    /// 完整的shutdown流程, 成功时已经进入关闭阶段。
+    /// 只挂起发起shutdown的连接, 等待replica期间被ABORT时返回错误
+    pub async fn shutdown(
+        &self,
+        repl: &ReplicationState,
+        timeout: Duration,
+        flags: ShutdownFlags,
+    ) -> Result<(), ShutdownError> {
+        if self.is_closing() {
+            return Ok(());
+        }
+        let replicas = repl.replica_count();
+        if !flags.now && replicas > 0 && !timeout.is_zero() {
+            // 先注册notified再标记pending, 否则中间来的ABORT会丢失
+            let aborted = self.abort.notified();
+            tokio::pin!(aborted);
+            aborted.as_mut().enable();
+            self.pending.store(true, Ordering::SeqCst);
+            info!(
+                "Waiting for {} replicas to sync before shutting down",
+                replicas
+            );
+            tokio::select! {
+                acked = repl.wait_for_acks(repl.master_offset(), replicas, timeout, false) => {
+                    if acked < replicas {
+                        warn!("{} replicas are lagging behind, shutting down anyway", replicas - acked);
+                    }
+                }
+                _ = aborted => {
+                    warn!("Shutdown aborted");
+                    return Err(ShutdownError::Failed);
+                }
+            }
+            // 同时有其他连接ABORT时以ABORT为准
+            if !self.pending.swap(false, Ordering::SeqCst) {
+                warn!("Shutdown aborted");
+                return Err(ShutdownError::Failed);
+            }
+        }
+        self.finish(flags)
+    }
+
+    /// 保存数据并进入关闭阶段, 不等待replica
+    pub fn finish(&self, flags: ShutdownFlags) -> Result<(), ShutdownError> {
+        if flags.save == Some(true) {
+            // 还没有RDB/AOF持久化, SAVE总是失败
+            error!("Error trying to save the DB: persistence is not supported");
+            if !flags.force {
+                return Err(ShutdownError::Failed);
+            }
+            warn!("Error trying to save the DB, exiting anyway (FORCE)");
+        }
+        info!("User requested shutdown...");
+        self.closing.send_replace(true);
+        Ok(())
+    }
+
+    /// SHUTDOWN ABORT, 唤醒所有正在等待replica的shutdown
+    pub fn abort(&self) -> Result<(), ShutdownError> {
+        if !self.pending.swap(false, Ordering::SeqCst) {
+            return Err(ShutdownError::NotInProgress);
+        }
+        self.abort.notify_waiters();
+        Ok(())
+    }
+
+    /// 关闭阶段等待所有连接断开, 超时返回false
+    pub async fn wait_drained(&self, timeout: Duration) -> bool {
+        let mut connections = self.connections.subscribe();
+        let drained = tokio::time::timeout(timeout, connections.wait_for(|n| *n == 0)).await;
+        drained.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ReplicaAck;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_shutdown_without_replicas() {
+        let state = ShutdownState::default();
+        let repl = ReplicationState::default();
+        let save = ShutdownFlags {
+            save: Some(true),
+            ..Default::default()
+        };
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            state.shutdown(&repl, timeout, save).await,
+            Err(ShutdownError::Failed)
+        );
+        assert!(!state.is_closing());
+        assert_eq!(state.abort(), Err(ShutdownError::NotInProgress));
+
+        let mut closing = state.subscribe();
+        let force = ShutdownFlags {
+            force: true,
+            ..save
+        };
+        assert_eq!(state.shutdown(&repl, timeout, force).await, Ok(()));
+        assert!(state.is_closing() && *closing.borrow_and_update());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_abort() {
+        let state = Arc::new(ShutdownState::default());
+        let repl = Arc::new(ReplicationState::default());
+        repl.propagate(10);
+        repl.ack(1, ReplicaAck::default());
+        let shutdown = {
+            let (state, repl) = (state.clone(), repl.clone());
+            tokio::spawn(async move {
+                let flags = ShutdownFlags::default();
+                state.shutdown(&repl, Duration::from_secs(10), flags).await
+            })
+        };
+        while !state.is_pending() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(state.abort(), Ok(()));
+        assert_eq!(shutdown.await.unwrap(), Err(ShutdownError::Failed));
+        assert!(!state.is_closing());
+
+        // 没有确认的replica等到超时后照样关闭
+        let flags = ShutdownFlags::default();
+        let timeout = Duration::from_millis(20);
+        assert_eq!(state.shutdown(&repl, timeout, flags).await, Ok(()));
+        assert!(state.is_closing());
+    }
+
+    #[tokio::test]
+    async fn test_wait_drained() {
+        let state = ShutdownState::default();
+        state.connection_opened();
+        assert!(!state.wait_drained(Duration::from_millis(10)).await);
+        state.connection_closed();
+        assert!(state.wait_drained(Duration::from_millis(10)).await);
+        assert_eq!(state.connections(), 0);
+    }
+}
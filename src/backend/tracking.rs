@@ -0,0 +1,271 @@
+//! CLIENT TRACKING: 记录连接读过的key, key被修改时给连接发送invalidate消息
+//!
+//! 默认模式下只记录连接读过的key, 发送一次invalidate之后就不再记录, 直到连接再次读取。
+//! BCAST模式不记录key, 所有匹配前缀的key被修改时都会通知
+
+use super::{ClientRegistry, ClientState};
+use crate::resp::{frame::RespFrame, BulkString, RespArray, RespPush};
+use bytes::Bytes;
+use dashmap::DashMap;
+use std::collections::{BTreeMap, HashSet};
+
+/// RESP2 的连接通过pub/sub消息收到invalidate时使用的channel
+pub const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackingOptions {
+    /// 把invalidate消息发给另一个连接
+    pub redirect: Option<u64>,
+    pub bcast: bool,
+    /// BCAST模式下的key前缀, 为空时匹配所有key
+    pub prefixes: Vec<Bytes>,
+    /// 只记录CLIENT CACHING yes之后的命令读取的key
+    pub optin: bool,
+    /// 不记录CLIENT CACHING no之后的命令读取的key
+    pub optout: bool,
+    /// 不通知连接自己修改的key
+    pub noloop: bool,
+}
+
+impl TrackingOptions {
+    /// 读取key之后是否记录, caching是紧接着的CLIENT CACHING的参数
+    pub fn should_track(&self, caching: Option<bool>) -> bool {
+        !self.bcast
+            && match (self.optin, self.optout) {
+                (true, _) => caching == Some(true),
+                (_, true) => caching != Some(false),
+                _ => true,
+            }
+    }
+
+    fn matches_prefix(&self, key: &[u8]) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|p| key.starts_with(p))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TrackingTable {
+    /// key -> 读过这个key的连接id
+    keys: DashMap<Bytes, HashSet<u64>>,
+    /// 开启了tracking的连接
+    clients: DashMap<u64, TrackingOptions>,
+}
+
+impl TrackingTable {
+    pub fn enable(&self, id: u64, options: TrackingOptions) {
+        self.clients.insert(id, options);
+    }
+
+    /// 关闭tracking或者连接断开时调用, 同时删除这个连接记录过的key
+    pub fn disable(&self, id: u64) {
+        if self.clients.remove(&id).is_some() {
+            self.keys.retain(|_, ids| {
+                ids.remove(&id);
+                !ids.is_empty()
+            });
+        }
+    }
+
+    /// 没有任何连接开启tracking时不需要收集key
+    pub fn is_active(&self) -> bool {
+        !self.clients.is_empty()
+    }
+
+    pub fn remember(&self, id: u64, keys: &[Bytes]) {
+        for key in keys {
+            self.keys.entry(key.clone()).or_default().insert(id);
+        }
+    }
+
+    /// key被修改, by是修改key的连接, 后台淘汰时为0
+    pub fn invalidate(&self, clients: &ClientRegistry, keys: &[Bytes], by: u64) {
+        if !self.is_active() {
+            return;
+        }
+        // 每个连接只发送一条消息
+        let mut pending: BTreeMap<u64, Vec<Bytes>> = BTreeMap::new();
+        for key in keys {
+            if let Some((_, ids)) = self.keys.remove(key) {
+                for id in ids {
+                    pending.entry(id).or_default().push(key.clone());
+                }
+            }
+            for client in self.clients.iter().filter(|c| c.bcast) {
+                if client.matches_prefix(key) {
+                    pending.entry(*client.key()).or_default().push(key.clone());
+                }
+            }
+        }
+        for (id, mut keys) in pending {
+            let Some(options) = self.clients.get(&id).map(|o| o.clone()) else {
+                continue;
+            };
+            if options.noloop && id == by {
+                continue;
+            }
+            keys.dedup();
+            self.send(clients, id, &options, Some(keys));
+        }
+    }
+
+    /// FLUSHDB / FLUSHALL 之后通知所有连接清空缓存
+    pub fn invalidate_all(&self, clients: &ClientRegistry) {
+        self.keys.clear();
+        let tracking: Vec<_> = self
+            .clients
+            .iter()
+            .map(|c| (*c.key(), c.value().clone()))
+            .collect();
+        for (id, options) in tracking {
+            self.send(clients, id, &options, None);
+        }
+    }
+
+    /// keys为None时表示所有key
+    fn send(
+        &self,
+        clients: &ClientRegistry,
+        id: u64,
+        options: &TrackingOptions,
+        keys: Option<Vec<Bytes>>,
+    ) {
+        let keys: RespFrame = match keys {
+            Some(keys) => RespArray::new(
+                keys.into_iter()
+                    .map(|k| BulkString::from(k).into())
+                    .collect::<Vec<RespFrame>>(),
+            )
+            .into(),
+            None => RespArray(None).into(),
+        };
+        let target = options.redirect.unwrap_or(id);
+        let Some(client) = clients.get(target) else {
+            // 重定向的连接已经断开, RESP3 的连接会收到通知
+            if let Some(client) = clients.get(id).filter(|c| c.state().resp == 3) {
+                client.push(
+                    RespPush::new(vec![
+                        BulkString::new("tracking-redir-broken").into(),
+                        RespFrame::Integer(target as i64),
+                    ])
+                    .into(),
+                );
+            }
+            return;
+        };
+        let frame = match (options.redirect, client.state()) {
+            (None, ClientState { resp: 3, .. }) => {
+                RespPush::new(vec![BulkString::new("invalidate").into(), keys]).into()
+            }
+            // RESP2 没有push, 只能通过重定向收到消息
+            (None, _) => return,
+            // 没有SUBSCRIBE, RESP2 的连接不可能订阅了__redis__:invalidate, 发过去会打乱回复的顺序
+            (Some(_), ClientState { resp: 3, .. }) => RespPush::new(vec![
+                BulkString::new("message").into(),
+                BulkString::new(INVALIDATE_CHANNEL).into(),
+                keys,
+            ])
+            .into(),
+            (Some(_), _) => return,
+        };
+        client.push(frame);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn invalidate(keys: &[&str]) -> RespFrame {
+        RespPush::new(vec![
+            BulkString::new("invalidate").into(),
+            RespArray::new(
+                keys.iter()
+                    .map(|k| BulkString::new(*k).into())
+                    .collect::<Vec<RespFrame>>(),
+            )
+            .into(),
+        ])
+        .into()
+    }
+
+    #[test]
+    fn test_default_tracking() {
+        let clients = ClientRegistry::default();
+        let (a, mut a_rx) = clients.register("a:1", "s:1");
+        let (b, mut b_rx) = clients.register("b:1", "s:1");
+        a.update(|s| s.resp = 3);
+        let table = TrackingTable::default();
+        table.enable(a.id(), TrackingOptions::default());
+        table.remember(a.id(), &["foo".into(), "bar".into()]);
+
+        table.invalidate(&clients, &["foo".into(), "other".into()], b.id());
+        assert_eq!(a_rx.try_recv().ok(), Some(invalidate(&["foo"])));
+        // 发送一次之后不再记录
+        table.invalidate(&clients, &["foo".into()], b.id());
+        assert!(a_rx.try_recv().is_err());
+
+        // 重定向到 RESP2 的连接时丢弃消息, 不能混进普通的回复里
+        table.enable(
+            a.id(),
+            TrackingOptions {
+                redirect: Some(b.id()),
+                ..Default::default()
+            },
+        );
+        table.invalidate_all(&clients);
+        assert!(b_rx.try_recv().is_err());
+        // RESP3 的连接收到push形式的message
+        b.update(|s| s.resp = 3);
+        table.invalidate_all(&clients);
+        let message = RespPush::new(vec![
+            BulkString::new("message").into(),
+            BulkString::new(INVALIDATE_CHANNEL).into(),
+            RespArray(None).into(),
+        ]);
+        assert_eq!(b_rx.try_recv().ok(), Some(message.into()));
+
+        table.remember(a.id(), &["foo".into()]);
+        table.remember(b.id(), &["foo".into(), "bar".into()]);
+        table.disable(a.id());
+        assert!(!table.is_active());
+        assert_eq!(table.keys.len(), 2);
+        assert!(table.keys.iter().all(|ids| !ids.contains(&a.id())));
+    }
+
+    #[test]
+    fn test_bcast_noloop() {
+        let clients = ClientRegistry::default();
+        let (a, mut a_rx) = clients.register("a:1", "s:1");
+        a.update(|s| s.resp = 3);
+        let table = TrackingTable::default();
+        table.enable(
+            a.id(),
+            TrackingOptions {
+                bcast: true,
+                prefixes: vec!["user:".into()],
+                noloop: true,
+                ..Default::default()
+            },
+        );
+        table.invalidate(&clients, &["user:1".into(), "order:1".into()], 0);
+        assert_eq!(a_rx.try_recv().ok(), Some(invalidate(&["user:1"])));
+        table.invalidate(&clients, &["user:2".into()], a.id());
+        assert!(a_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_should_track() {
+        let optin = TrackingOptions {
+            optin: true,
+            ..Default::default()
+        };
+        assert!(!optin.should_track(None));
+        assert!(optin.should_track(Some(true)));
+        let optout = TrackingOptions {
+            optout: true,
+            ..Default::default()
+        };
+        assert!(optout.should_track(None));
+        assert!(!optout.should_track(Some(false)));
+    }
+}
@@ -0,0 +1,425 @@
+//! cluster模式下的slot -> node映射和请求重定向
+//!
+//! 没有实现cluster bus(gossip), 节点之间的拓扑通过 CLUSTER MEET / ADDSLOTS 等命令在每个节点上配置
+
+mod slot;
+
+pub use slot::{crc16, key_hash_slot, CLUSTER_SLOTS};
+
+use crate::resp::{frame::RespFrame, SimpleError};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClusterNode {
+    pub id: String,
+    pub ip: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Default)]
+pub struct ClusterState {
+    enabled: bool,
+    myself: String,
+    inner: RwLock<ClusterNodes>,
+}
+
+#[derive(Debug)]
+struct ClusterNodes {
+    nodes: BTreeMap<String, ClusterNode>,
+    /// slot -> 负责这个slot的node id
+    slots: Vec<Option<String>>,
+    /// 正在从本节点迁出的slot -> 目标node id
+    migrating: HashMap<u16, String>,
+    /// 正在迁入本节点的slot -> 源node id
+    importing: HashMap<u16, String>,
+}
+
+/// 请求不能在本节点执行时返回给客户端的错误
+#[derive(Debug, PartialEq, Eq)]
+pub enum Redirect {
+    Moved(u16, String),
+    Ask(u16, String),
+    CrossSlot,
+    TryAgain,
+    Unbound,
+}
+
+impl Default for ClusterNodes {
+    fn default() -> Self {
+        Self {
+            nodes: BTreeMap::new(),
+            slots: vec![None; CLUSTER_SLOTS],
+            migrating: HashMap::new(),
+            importing: HashMap::new(),
+        }
+    }
+}
+
+impl ClusterNode {
+    pub fn addr(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+}
+
+impl From<Redirect> for RespFrame {
+    fn from(redirect: Redirect) -> Self {
+        let msg = match redirect {
+            Redirect::Moved(slot, addr) => format!("MOVED {} {}", slot, addr),
+            Redirect::Ask(slot, addr) => format!("ASK {} {}", slot, addr),
+            Redirect::CrossSlot => "CROSSSLOT Keys in request don't hash to the same slot".into(),
+            Redirect::TryAgain => "TRYAGAIN Multiple keys request during rehashing of slot".into(),
+            Redirect::Unbound => "CLUSTERDOWN Hash slot not served".into(),
+        };
+        SimpleError::new(msg).into()
+    }
+}
+
+/// 随机生成40个字符的node id
+pub fn random_node_id() -> String {
+    let bytes: [u8; 20] = rand::random();
+    bytes.iter().fold(String::with_capacity(40), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+impl ClusterState {
+    /// 开启cluster模式, ip和port是本节点对外公布的地址
+    pub fn new(ip: impl Into<String>, port: u16) -> Self {
+        let myself = ClusterNode {
+            id: random_node_id(),
+            ip: ip.into(),
+            port,
+        };
+        let mut inner = ClusterNodes::default();
+        inner.nodes.insert(myself.id.clone(), myself.clone());
+        Self {
+            enabled: true,
+            myself: myself.id,
+            inner: RwLock::new(inner),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn myself(&self) -> &str {
+        &self.myself
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, ClusterNodes> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, ClusterNodes> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn nodes(&self) -> Vec<ClusterNode> {
+        self.read().nodes.values().cloned().collect()
+    }
+
+    pub fn node(&self, id: &str) -> Option<ClusterNode> {
+        self.read().nodes.get(id).cloned()
+    }
+
+    pub fn add_node(&self, node: ClusterNode) {
+        self.write().nodes.insert(node.id.clone(), node);
+    }
+
+    pub fn slot_owner(&self, slot: u16) -> Option<ClusterNode> {
+        let inner = self.read();
+        inner.slots[slot as usize]
+            .as_ref()
+            .and_then(|id| inner.nodes.get(id).cloned())
+    }
+
+    pub fn slots_assigned(&self) -> usize {
+        self.read().slots.iter().filter(|s| s.is_some()).count()
+    }
+
+    /// CLUSTER ADDSLOTS, 任意一个slot已经被分配时整个命令失败
+    pub fn add_slots(&self, slots: &[u16]) -> Result<(), String> {
+        let mut inner = self.write();
+        if let Some(slot) = slots.iter().find(|&&s| inner.slots[s as usize].is_some()) {
+            return Err(format!("ERR Slot {} is already busy", slot));
+        }
+        for &slot in slots {
+            inner.slots[slot as usize] = Some(self.myself.clone());
+            inner.importing.remove(&slot);
+        }
+        Ok(())
+    }
+
+    /// CLUSTER DELSLOTS, 任意一个slot没有被分配时整个命令失败
+    pub fn del_slots(&self, slots: &[u16]) -> Result<(), String> {
+        let mut inner = self.write();
+        if let Some(slot) = slots.iter().find(|&&s| inner.slots[s as usize].is_none()) {
+            return Err(format!("ERR Slot {} is already unassigned", slot));
+        }
+        for &slot in slots {
+            inner.slots[slot as usize] = None;
+            inner.migrating.remove(&slot);
+            inner.importing.remove(&slot);
+        }
+        Ok(())
+    }
+
+    /// 把slot分配给一个已知的node, 不管之前属于谁
+    pub fn assign_slot(&self, slot: u16, node_id: &str) -> Result<(), String> {
+        let mut inner = self.write();
+        if !inner.nodes.contains_key(node_id) {
+            return Err(format!("ERR Unknown node {}", node_id));
+        }
+        inner.slots[slot as usize] = Some(node_id.to_string());
+        inner.migrating.remove(&slot);
+        inner.importing.remove(&slot);
+        Ok(())
+    }
+
+    /// 把本节点的slot标记为正在迁往node_id
+    pub fn set_migrating(&self, slot: u16, node_id: &str) -> Result<(), String> {
+        let mut inner = self.write();
+        if inner.slots[slot as usize].as_deref() != Some(self.myself.as_str()) {
+            return Err(format!("ERR I'm not the owner of hash slot {}", slot));
+        }
+        if !inner.nodes.contains_key(node_id) {
+            return Err(format!("ERR I don't know about node {}", node_id));
+        }
+        inner.migrating.insert(slot, node_id.to_string());
+        Ok(())
+    }
+
+    /// 把slot标记为正在从node_id迁入本节点
+    pub fn set_importing(&self, slot: u16, node_id: &str) -> Result<(), String> {
+        let mut inner = self.write();
+        if inner.slots[slot as usize].as_deref() == Some(self.myself.as_str()) {
+            return Err(format!("ERR I'm already the owner of hash slot {}", slot));
+        }
+        if !inner.nodes.contains_key(node_id) {
+            return Err(format!("ERR I don't know about node {}", node_id));
+        }
+        inner.importing.insert(slot, node_id.to_string());
+        Ok(())
+    }
+
+    /// CLUSTER SETSLOT <slot> STABLE
+    pub fn set_stable(&self, slot: u16) {
+        let mut inner = self.write();
+        inner.migrating.remove(&slot);
+        inner.importing.remove(&slot);
+    }
+
+    pub fn migrating(&self, slot: u16) -> Option<String> {
+        self.read().migrating.get(&slot).cloned()
+    }
+
+    pub fn importing(&self, slot: u16) -> Option<String> {
+        self.read().importing.get(&slot).cloned()
+    }
+
+    /// 把连续的slot合并成区间, 返回 (start, end, owner)
+    pub fn slot_ranges(&self) -> Vec<(u16, u16, ClusterNode)> {
+        let inner = self.read();
+        let mut ranges: Vec<(u16, u16, ClusterNode)> = Vec::new();
+        for (slot, owner) in inner.slots.iter().enumerate() {
+            let Some(node) = owner.as_ref().and_then(|id| inner.nodes.get(id)) else {
+                continue;
+            };
+            match ranges.last_mut() {
+                Some((_, end, last)) if *end as usize + 1 == slot && last.id == node.id => {
+                    *end = slot as u16;
+                }
+                _ => ranges.push((slot as u16, slot as u16, node.clone())),
+            }
+        }
+        ranges
+    }
+
+    /// CLUSTER NODES 的输出, 每个节点一行
+    pub fn nodes_description(&self) -> String {
+        let ranges = self.slot_ranges();
+        let inner = self.read();
+        let mut out = String::new();
+        for node in inner.nodes.values() {
+            let flags = if node.id == self.myself {
+                "myself,master"
+            } else {
+                "master"
+            };
+            let _ = write!(
+                out,
+                "{} {}:{}@{} {} - 0 0 0 connected",
+                node.id,
+                node.ip,
+                node.port,
+                node.port as u32 + 10000,
+                flags
+            );
+            for (start, end, _) in ranges.iter().filter(|(_, _, n)| n.id == node.id) {
+                if start == end {
+                    let _ = write!(out, " {}", start);
+                } else {
+                    let _ = write!(out, " {}-{}", start, end);
+                }
+            }
+            if node.id == self.myself {
+                let mut migrating = inner.migrating.iter().collect::<Vec<_>>();
+                migrating.sort();
+                for (slot, target) in migrating {
+                    let _ = write!(out, " [{}->-{}]", slot, target);
+                }
+                let mut importing = inner.importing.iter().collect::<Vec<_>>();
+                importing.sort();
+                for (slot, source) in importing {
+                    let _ = write!(out, " [{}-<-{}]", slot, source);
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// 判断访问这些key的请求能不能在本节点执行, 不能时返回重定向错误。
+    /// asking表示这个连接在这个命令之前发送了ASKING, exists用来判断key在本节点是否存在
+    pub fn route(
+        &self,
+        keys: &[&[u8]],
+        asking: bool,
+        exists: impl Fn(&[u8]) -> bool,
+    ) -> Option<Redirect> {
+        if !self.enabled || keys.is_empty() {
+            return None;
+        }
+        let slot = key_hash_slot(keys[0]);
+        if keys[1..].iter().any(|k| key_hash_slot(k) != slot) {
+            return Some(Redirect::CrossSlot);
+        }
+
+        let inner = self.read();
+        let importing = inner.importing.contains_key(&slot);
+        let owner = match &inner.slots[slot as usize] {
+            Some(owner) => owner,
+            None if importing && asking => return None,
+            None => return Some(Redirect::Unbound),
+        };
+
+        if *owner == self.myself {
+            let target = inner.migrating.get(&slot)?;
+            // 迁移中的slot, key还在本节点时直接执行, 否则让客户端去目标节点问
+            let missing = keys.iter().filter(|k| !exists(k)).count();
+            return match (missing, inner.nodes.get(target)) {
+                (0, _) => None,
+                (n, Some(node)) if n == keys.len() => Some(Redirect::Ask(slot, node.addr())),
+                _ => Some(Redirect::TryAgain),
+            };
+        }
+
+        if importing && asking {
+            return None;
+        }
+        inner
+            .nodes
+            .get(owner)
+            .map(|node| Redirect::Moved(slot, node.addr()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(id: &str, port: u16) -> ClusterNode {
+        ClusterNode {
+            id: id.to_string(),
+            ip: "127.0.0.1".to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn test_route() {
+        let state = ClusterState::new("127.0.0.1", 7000);
+        state.add_node(node("other", 7001));
+        // foo -> 12182, bar -> 5061
+        assert_eq!(
+            state.route(&[b"foo".as_slice()], false, |_| true),
+            Some(Redirect::Unbound)
+        );
+
+        state.add_slots(&[12182]).unwrap();
+        state.assign_slot(5061, "other").unwrap();
+        assert_eq!(state.route(&[b"foo".as_slice()], false, |_| true), None);
+        assert_eq!(
+            state.route(&[b"bar".as_slice()], false, |_| true),
+            Some(Redirect::Moved(5061, "127.0.0.1:7001".to_string()))
+        );
+        assert_eq!(
+            state.route(&[b"foo".as_slice(), b"bar"], false, |_| true),
+            Some(Redirect::CrossSlot)
+        );
+        assert_eq!(state.route(&[], false, |_| true), None);
+        assert!(state.add_slots(&[12182]).is_err());
+    }
+
+    #[test]
+    fn test_route_ask() {
+        let source = ClusterState::new("127.0.0.1", 7000);
+        source.add_node(node("target", 7001));
+        source.add_slots(&[12182]).unwrap();
+        source.set_migrating(12182, "target").unwrap();
+        assert_eq!(source.route(&[b"foo".as_slice()], false, |_| true), None);
+        assert_eq!(
+            source.route(&[b"foo".as_slice()], false, |_| false),
+            Some(Redirect::Ask(12182, "127.0.0.1:7001".to_string()))
+        );
+        assert_eq!(
+            source.route(&[b"{foo}a".as_slice(), b"{foo}b"], false, |k| k
+                == b"{foo}a"),
+            Some(Redirect::TryAgain)
+        );
+
+        let target = ClusterState::new("127.0.0.1", 7001);
+        target.add_node(node("source", 7000));
+        target.assign_slot(12182, "source").unwrap();
+        target.set_importing(12182, "source").unwrap();
+        assert_eq!(
+            target.route(&[b"foo".as_slice()], false, |_| false),
+            Some(Redirect::Moved(12182, "127.0.0.1:7000".to_string()))
+        );
+        assert_eq!(target.route(&[b"foo".as_slice()], true, |_| false), None);
+    }
+
+    #[test]
+    fn test_slot_ranges_and_nodes() {
+        let state = ClusterState::new("127.0.0.1", 7000);
+        state.add_node(node("other", 7001));
+        state.add_slots(&[0, 1, 2, 5]).unwrap();
+        state.assign_slot(3, "other").unwrap();
+        let ranges = state
+            .slot_ranges()
+            .into_iter()
+            .map(|(s, e, n)| (s, e, n.port))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, vec![(0, 2, 7000), (3, 3, 7001), (5, 5, 7000)]);
+
+        let nodes = state.nodes_description();
+        assert!(nodes.contains(&format!(
+            "{} 127.0.0.1:7000@17000 myself,master - 0 0 0 connected 0-2 5\n",
+            state.myself()
+        )));
+        assert!(nodes.contains("other 127.0.0.1:7001@17001 master - 0 0 0 connected 3\n"));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let state = ClusterState::default();
+        assert!(!state.is_enabled());
+        assert_eq!(state.route(&[b"foo".as_slice()], false, |_| false), None);
+    }
+}
@@ -0,0 +1,71 @@
+//! hash slot的计算, 和redis的 keyHashSlot 一致:
+//! slot = CRC16(key) mod 16384, 如果key中有 {...} 且花括号里不为空, 只对花括号中的内容计算
+
+pub const CLUSTER_SLOTS: usize = 16384;
+
+/// CRC16-CCITT (XMODEM), poly = 0x1021, init = 0
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc16(buf: &[u8]) -> u16 {
+    buf.iter().fold(0u16, |crc, &b| {
+        (crc << 8) ^ CRC16_TABLE[(((crc >> 8) as u8) ^ b) as usize]
+    })
+}
+
+/// 只对第一个 { 和它后面第一个 } 之间的内容计算, 比如 {user1000}.following 和 {user1000}.followers 在同一个slot
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let hash_key = match key.iter().position(|&b| b == b'{') {
+        Some(start) => match key[start + 1..].iter().position(|&b| b == b'}') {
+            // {} 里为空时, 还是对整个key计算
+            Some(0) | None => key,
+            Some(len) => &key[start + 1..start + 1 + len],
+        },
+        None => key,
+    };
+    crc16(hash_key) & (CLUSTER_SLOTS as u16 - 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc16() {
+        // redis crc16.c 中的测试向量
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+    }
+
+    #[test]
+    fn test_key_hash_slot() {
+        assert_eq!(key_hash_slot(b"foo"), 12182);
+        assert_eq!(key_hash_slot(b"bar"), 5061);
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"{user1000}.followers")
+        );
+        assert_eq!(
+            key_hash_slot(b"{user1000}.following"),
+            key_hash_slot(b"user1000")
+        );
+        assert_ne!(key_hash_slot(b"foo{}{bar}"), key_hash_slot(b"bar"));
+        assert_eq!(key_hash_slot(b"foo{{bar}}zap"), key_hash_slot(b"{bar"));
+    }
+}
@@ -0,0 +1,440 @@
+//! support ACL command
+//!
+//! ACL SETUSER username [rule ...]：创建或修改用户, 所有规则都合法时才会生效
+//! ACL GETUSER username | DELUSER username [username ...] | USERS | LIST：查看和删除用户
+//! ACL WHOAMI：当前连接使用的用户
+//! ACL CAT [category]：列出所有分类, 或者分类中的命令
+//! ACL LOG [count | RESET]：最近被拒绝的命令和失败的认证
+//! ACL DRYRUN username command [arg ...]：只检查用户能否执行命令, 不会真正执行
+//! ACL LOAD | SAVE：从aclfile重新加载用户, 或者把用户保存到aclfile
+//! ACL GENPASS [bits]：生成随机密码
+
+use super::{
+    extract_args, parse_integer, parse_string, Command, CommandError, CommandExecuter,
+    COMMAND_TABLE, REDACTED, RESP_OK,
+};
+use crate::{
+    acl::{Selector, CATEGORIES},
+    backend::{now_ms, Backend},
+    network::Connection,
+    resp::{frame::RespFrame, BulkString, RespArray, SimpleError},
+};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Acl {
+    SetUser(String, Rules),
+    GetUser(String),
+    DelUser(Vec<String>),
+    Users,
+    List,
+    WhoAmI,
+    Cat(Option<String>),
+    Log(usize),
+    LogReset,
+    /// 用户名, 命令名和解析好的命令
+    DryRun(String, String, Box<Command>),
+    Load,
+    Save,
+    GenPass(usize),
+    Help,
+}
+
+/// ACL SETUSER 的规则, 写进日志时隐藏密码和密码的hash
+pub struct Rules(Vec<String>);
+
+impl fmt::Debug for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rules = self.0.iter().map(|rule| {
+            if rule.starts_with(['>', '<', '#', '!']) {
+                REDACTED
+            } else {
+                rule.as_str()
+            }
+        });
+        f.debug_list().entries(rules).finish()
+    }
+}
+
+const ACL_HELP: &[&str] = &[
+    "ACL <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "CAT [<category>]",
+    "    List all commands that belong to <category>, or all command categories",
+    "    when no category is specified.",
+    "DELUSER <username> [<username> ...]",
+    "    Delete a list of users.",
+    "DRYRUN <username> <command> [<arg> ...]",
+    "    Returns whether the user can execute the given command without executing the command.",
+    "GETUSER <username>",
+    "    Get the user's details.",
+    "GENPASS [<bits>]",
+    "    Generate a secure 256-bit user password. The optional `bits` argument can",
+    "    be used to specify a different size.",
+    "LIST",
+    "    Show users details in config file format.",
+    "LOAD",
+    "    Reload users from the ACL file.",
+    "LOG [<count> | RESET]",
+    "    Show the ACL log entries.",
+    "SAVE",
+    "    Save the current config to the ACL file.",
+    "SETUSER <username> <attribute> [<attribute> ...]",
+    "    Create or modify a user with the specified attributes.",
+    "USERS",
+    "    List all the registered usernames.",
+    "WHOAMI",
+    "    Return the current connection username.",
+];
+
+/// ACL LOG 默认返回的条数
+const DEFAULT_LOG_COUNT: usize = 10;
+
+fn bulk_array<S: AsRef<[u8]>>(items: impl IntoIterator<Item = S>) -> RespFrame {
+    RespArray::new(
+        items
+            .into_iter()
+            .map(|item| BulkString::new(item.as_ref()).into())
+            .collect::<Vec<RespFrame>>(),
+    )
+    .into()
+}
+
+fn selector_frame(selector: &Selector) -> Vec<RespFrame> {
+    vec![
+        BulkString::new("commands").into(),
+        BulkString::new(selector.commands()).into(),
+        BulkString::new("keys").into(),
+        BulkString::new(selector.keys()).into(),
+        BulkString::new("channels").into(),
+        BulkString::new(selector.channels()).into(),
+    ]
+}
+
+impl Acl {
+    pub(super) fn subcommand(&self) -> &'static str {
+        match self {
+            Acl::SetUser(..) => "setuser",
+            Acl::GetUser(_) => "getuser",
+            Acl::DelUser(_) => "deluser",
+            Acl::Users => "users",
+            Acl::List => "list",
+            Acl::WhoAmI => "whoami",
+            Acl::Cat(_) => "cat",
+            Acl::Log(_) | Acl::LogReset => "log",
+            Acl::DryRun(..) => "dryrun",
+            Acl::Load => "load",
+            Acl::Save => "save",
+            Acl::GenPass(_) => "genpass",
+            Acl::Help => "help",
+        }
+    }
+
+    /// 在连接中执行, WHOAMI 需要连接的用户
+    pub fn apply(self, backend: &Backend, conn: &Connection) -> RespFrame {
+        match self {
+            Acl::WhoAmI => BulkString::new(conn.user.as_str()).into(),
+            acl => acl.execute(backend.clone()),
+        }
+    }
+}
+
+impl CommandExecuter for Acl {
+    fn execute(self, backend: Backend) -> RespFrame {
+        let acl = backend.acl();
+        match self {
+            Acl::SetUser(name, rules) => match acl.set_user(&name, &rules.0) {
+                Ok(()) => RESP_OK.clone(),
+                Err(e) => SimpleError::new(format!("ERR {}", e)).into(),
+            },
+            Acl::GetUser(name) => {
+                let Some(user) = acl.user(&name) else {
+                    return BulkString(None).into();
+                };
+                let mut reply = vec![
+                    BulkString::new("flags").into(),
+                    bulk_array(user.flags()),
+                    BulkString::new("passwords").into(),
+                    bulk_array(user.passwords()),
+                ];
+                reply.extend(selector_frame(user.root()));
+                reply.push(BulkString::new("selectors").into());
+                reply.push(
+                    RespArray::new(
+                        user.selectors()
+                            .iter()
+                            .map(|s| RespArray::new(selector_frame(s)).into())
+                            .collect::<Vec<RespFrame>>(),
+                    )
+                    .into(),
+                );
+                RespArray::new(reply).into()
+            }
+            Acl::DelUser(names) => {
+                if names.iter().any(|name| name == "default") {
+                    return SimpleError::new("ERR The 'default' user cannot be removed").into();
+                }
+                RespFrame::Integer(acl.del_users(&names) as i64)
+            }
+            Acl::Users => bulk_array(acl.users().iter().map(|u| u.name().to_string())),
+            Acl::List => bulk_array(acl.users().iter().map(|u| u.describe())),
+            Acl::WhoAmI => BulkString::new("default").into(),
+            Acl::Cat(None) => bulk_array(CATEGORIES),
+            Acl::Cat(Some(category)) => {
+                let category = category.to_ascii_lowercase();
+                if !CATEGORIES.contains(&category.as_str()) {
+                    return SimpleError::new(format!("ERR Unknown category '{}'", category)).into();
+                }
+                bulk_array(
+                    COMMAND_TABLE
+                        .iter()
+                        .filter(|(_, categories)| categories.contains(&category.as_str()))
+                        .map(|(name, _)| *name),
+                )
+            }
+            Acl::Log(count) => {
+                let now = now_ms();
+                let entries = acl
+                    .log_entries(count)
+                    .into_iter()
+                    .map(|e| {
+                        let age = now.saturating_sub(e.created) as f64 / 1000.0;
+                        RespArray::new(vec![
+                            BulkString::new("count").into(),
+                            RespFrame::Integer(e.count as i64),
+                            BulkString::new("reason").into(),
+                            BulkString::new(e.reason).into(),
+                            BulkString::new("context").into(),
+                            BulkString::new(e.context).into(),
+                            BulkString::new("object").into(),
+                            BulkString::new(e.object).into(),
+                            BulkString::new("username").into(),
+                            BulkString::new(e.username).into(),
+                            BulkString::new("age-seconds").into(),
+                            BulkString::new(format!("{:.3}", age)).into(),
+                            BulkString::new("client-info").into(),
+                            BulkString::new(e.client_info).into(),
+                            BulkString::new("entry-id").into(),
+                            RespFrame::Integer(e.id as i64),
+                            BulkString::new("timestamp-created").into(),
+                            RespFrame::Integer(e.created as i64),
+                            BulkString::new("timestamp-last-updated").into(),
+                            RespFrame::Integer(e.updated as i64),
+                        ])
+                        .into()
+                    })
+                    .collect::<Vec<RespFrame>>();
+                RespArray::new(entries).into()
+            }
+            Acl::LogReset => {
+                acl.reset_log();
+                RESP_OK.clone()
+            }
+            Acl::DryRun(username, name, command) => {
+                if acl.user(&username).is_none() {
+                    return SimpleError::new(format!("ERR User '{}' not found", username)).into();
+                }
+                if matches!(*command, Command::Unrecongnized(_)) {
+                    return SimpleError::new(format!("ERR Command '{}' not found", name)).into();
+                }
+                match acl.check(&username, &command) {
+                    Ok(()) => RESP_OK.clone(),
+                    Err(denied) => {
+                        let error = denied.error(&username);
+                        BulkString::new(error.trim_start_matches("NOPERM ")).into()
+                    }
+                }
+            }
+            Acl::Load => match acl.load() {
+                Ok(()) => RESP_OK.clone(),
+                Err(e) => SimpleError::new(format!("ERR {}", e)).into(),
+            },
+            Acl::Save => match acl.save() {
+                Ok(()) => RESP_OK.clone(),
+                Err(e) => SimpleError::new(format!("ERR {}", e)).into(),
+            },
+            Acl::GenPass(bits) => {
+                let password: String = (0..bits.div_ceil(4))
+                    .map(|_| format!("{:x}", rand::random::<u8>() & 0xf))
+                    .collect();
+                BulkString::new(password).into()
+            }
+            Acl::Help => bulk_array(ACL_HELP),
+        }
+    }
+}
+
+fn syntax_error(subcommand: &str) -> CommandError {
+    CommandError::InvalidArgument(format!(
+        "unknown subcommand or wrong number of arguments for '{}'. Try ACL HELP.",
+        subcommand
+    ))
+}
+
+fn parse_strings(args: Vec<RespFrame>) -> Result<Vec<String>, CommandError> {
+    args.into_iter().map(parse_string).collect()
+}
+
+impl TryFrom<RespArray> for Acl {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let mut args = extract_args(value, 1)?;
+        if args.is_empty() {
+            return Err(syntax_error(""));
+        }
+        let subcommand = parse_string(args.remove(0))?;
+        match (subcommand.to_ascii_lowercase().as_str(), args.len()) {
+            ("setuser", 1..) => {
+                let mut args = parse_strings(args)?;
+                let name = args.remove(0);
+                Ok(Acl::SetUser(name, Rules(args)))
+            }
+            ("getuser", 1) => Ok(Acl::GetUser(parse_strings(args)?.remove(0))),
+            ("deluser", 1..) => Ok(Acl::DelUser(parse_strings(args)?)),
+            ("users", 0) => Ok(Acl::Users),
+            ("list", 0) => Ok(Acl::List),
+            ("whoami", 0) => Ok(Acl::WhoAmI),
+            ("cat", 0) => Ok(Acl::Cat(None)),
+            ("cat", 1) => Ok(Acl::Cat(parse_strings(args)?.pop())),
+            ("log", 0) => Ok(Acl::Log(DEFAULT_LOG_COUNT)),
+            ("log", 1) => {
+                let arg = args.remove(0);
+                match parse_string(arg.clone())?.eq_ignore_ascii_case("reset") {
+                    true => Ok(Acl::LogReset),
+                    false => Ok(Acl::Log(parse_integer(arg)?)),
+                }
+            }
+            ("dryrun", 2..) => {
+                let username = parse_string(args.remove(0))?;
+                let name = parse_string(args[0].clone())?;
+                let command = Command::try_from(RespArray::new(args))?;
+                Ok(Acl::DryRun(username, name, Box::new(command)))
+            }
+            ("load", 0) => Ok(Acl::Load),
+            ("save", 0) => Ok(Acl::Save),
+            ("genpass", 0) => Ok(Acl::GenPass(256)),
+            ("genpass", 1) => match parse_integer(args.remove(0))? {
+                bits @ 1..=4096 => Ok(Acl::GenPass(bits)),
+                _ => Err(CommandError::InvalidArgument(
+                    "ACL GENPASS argument must be the number of bits for the output password, \
+                     a positive number up to 4096"
+                        .to_string(),
+                )),
+            },
+            ("help", 0) => Ok(Acl::Help),
+            _ => Err(syntax_error(&subcommand)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    fn array(args: &[&str]) -> RespArray {
+        RespArray::new(
+            args.iter()
+                .map(|a| BulkString::new(*a).into())
+                .collect::<Vec<RespFrame>>(),
+        )
+    }
+
+    fn acl(backend: &Backend, args: &[&str]) -> Result<RespFrame> {
+        let mut full = vec!["acl"];
+        full.extend_from_slice(args);
+        Ok(Acl::try_from(array(&full))?.execute(backend.clone()))
+    }
+
+    #[test]
+    fn test_setuser_debug_redacts_password() -> Result<()> {
+        let cmd = Acl::try_from(array(&[
+            "acl", "setuser", "alice", "on", ">secret", "<old", "#abcd", "+get",
+        ]))?;
+        let debug = format!("{:?}", cmd);
+        assert!(debug.contains("alice") && debug.contains("+get"));
+        assert!(!debug.contains("secret") && !debug.contains("old") && !debug.contains("abcd"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_acl_setuser_getuser() -> Result<()> {
+        let backend = Backend::new();
+        let reply = acl(
+            &backend,
+            &["setuser", "alice", "on", ">pw", "~app:*", "+@read"],
+        )?;
+        assert_eq!(reply, RESP_OK.clone());
+        let RespFrame::Array(RespArray(Some(reply))) = acl(&backend, &["getuser", "alice"])? else {
+            panic!("GETUSER should return an array");
+        };
+        assert_eq!(reply[0], BulkString::new("flags").into());
+        assert_eq!(reply[1], bulk_array(["on"]));
+        assert_eq!(reply[5], BulkString::new("-@all +@read").into());
+        assert_eq!(reply[7], BulkString::new("~app:*").into());
+
+        let reply = acl(&backend, &["setuser", "alice", "+nosuch"])?;
+        assert!(matches!(reply, RespFrame::SimpleError(_)));
+        assert_eq!(acl(&backend, &["getuser", "bob"])?, BulkString(None).into());
+        assert_eq!(acl(&backend, &["users"])?, bulk_array(["alice", "default"]));
+        assert_eq!(
+            acl(&backend, &["list"])?,
+            bulk_array([
+                "user alice on #30c952fab122c3f9759f02a6d95c3758b246b4fee239957b2d4fee46e26170c4 ~app:* resetchannels -@all +@read",
+                "user default on nopass ~* &* +@all",
+            ])
+        );
+        assert_eq!(
+            acl(&backend, &["deluser", "default"])?,
+            SimpleError::new("ERR The 'default' user cannot be removed").into()
+        );
+        assert_eq!(
+            acl(&backend, &["deluser", "alice", "bob"])?,
+            RespFrame::Integer(1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_acl_dryrun_cat_log() -> Result<()> {
+        let backend = Backend::new();
+        acl(&backend, &["setuser", "alice", "on", "~app:*", "+get"])?;
+        assert_eq!(
+            acl(&backend, &["dryrun", "alice", "get", "app:1"])?,
+            RESP_OK.clone()
+        );
+        assert_eq!(
+            acl(&backend, &["dryrun", "alice", "set", "app:1", "v"])?,
+            BulkString::new("User alice has no permissions to run the 'set' command").into()
+        );
+        assert_eq!(
+            acl(&backend, &["dryrun", "alice", "get", "other"])?,
+            BulkString::new("No permissions to access a key").into()
+        );
+        assert!(matches!(
+            acl(&backend, &["dryrun", "alice", "nosuch"])?,
+            RespFrame::SimpleError(_)
+        ));
+        // DRYRUN 不会记录到ACL LOG
+        assert_eq!(acl(&backend, &["log"])?, RespArray::new(vec![]).into());
+
+        let RespFrame::Array(RespArray(Some(commands))) = acl(&backend, &["cat", "dangerous"])?
+        else {
+            panic!("CAT should return an array");
+        };
+        assert!(commands.contains(&BulkString::new("flushall").into()));
+        assert!(!commands.contains(&BulkString::new("get").into()));
+        assert!(matches!(
+            acl(&backend, &["cat", "nosuch"])?,
+            RespFrame::SimpleError(_)
+        ));
+
+        let RespFrame::BulkString(password) = acl(&backend, &["genpass", "64"])? else {
+            panic!("GENPASS should return a bulk string");
+        };
+        assert_eq!(password.as_deref().map(|p| p.len()), Some(16));
+        assert!(Acl::try_from(array(&["acl", "genpass", "0"])).is_err());
+        assert!(Acl::try_from(array(&["acl", "whoami", "x"])).is_err());
+        Ok(())
+    }
+}
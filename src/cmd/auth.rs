@@ -0,0 +1,368 @@
+//! support AUTH and HELLO command
+//!
+//! AUTH [username] password：认证当前连接并切换到这个ACL用户, 没有用户名时使用default用户
+//! HELLO [protover [AUTH username password] [SETNAME clientname]]：切换协议版本,
+//! 可以同时认证和设置连接的名字, 返回服务器的信息
+
+use super::{
+    client::{invalid_client_name, valid_client_name},
+    extract_args, parse_bytes, parse_string, CommandError, CommandExecuter, REDACTED, RESP_OK,
+};
+use crate::{
+    acl::Denied,
+    backend::Backend,
+    network::Connection,
+    resp::{frame::RespFrame, BulkString, RespArray, RespMap, SimpleError},
+};
+use bytes::Bytes;
+use std::fmt;
+
+/// HELLO 返回的redis版本, 客户端按照它判断支持的功能
+const REDIS_VERSION: &str = "7.2.0";
+
+pub struct Auth {
+    username: Option<String>,
+    password: Bytes,
+}
+
+pub struct Hello {
+    protover: Option<i64>,
+    auth: Option<(String, Bytes)>,
+    setname: Option<String>,
+}
+
+/// 命令会被写进日志, 不能输出密码
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Auth")
+            .field("username", &self.username)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
+impl fmt::Debug for Hello {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hello")
+            .field("protover", &self.protover)
+            .field(
+                "auth",
+                &self.auth.as_ref().map(|(user, _)| (user, REDACTED)),
+            )
+            .field("setname", &self.setname)
+            .finish()
+    }
+}
+
+fn wrong_pass() -> RespFrame {
+    SimpleError::new(Denied::Auth.error("")).into()
+}
+
+/// 检查用户名和密码, 成功时返回用户名。没有用户名时使用default用户
+fn check_password(
+    backend: &Backend,
+    username: Option<&str>,
+    password: &[u8],
+) -> Result<String, RespFrame> {
+    let acl = backend.acl();
+    let username = match username {
+        Some(username) => username,
+        None if acl.user("default").is_some_and(|u| u.is_nopass()) => {
+            return Err(SimpleError::new(
+                "ERR AUTH <password> called without any password configured for the default user. \
+                 Are you sure your configuration is correct?",
+            )
+            .into())
+        }
+        None => "default",
+    };
+    match acl.authenticate(username, password) {
+        true => Ok(username.to_string()),
+        false => Err(wrong_pass()),
+    }
+}
+
+/// 认证成功时切换连接的用户, 失败时记录到ACL LOG
+fn authenticate(
+    backend: &Backend,
+    conn: &mut Connection,
+    username: Option<&str>,
+    password: &[u8],
+) -> Result<(), RespFrame> {
+    match check_password(backend, username, password) {
+        Ok(username) => {
+            conn.user = username;
+            conn.authenticated = true;
+            Ok(())
+        }
+        Err(e) => {
+            let username = username.unwrap_or("default");
+            backend.acl_log(&Denied::Auth, username, conn.client_info());
+            Err(e)
+        }
+    }
+}
+
+impl Auth {
+    /// 在连接中执行, 成功时把连接标记为已认证
+    pub fn apply(self, backend: &Backend, conn: &mut Connection) -> RespFrame {
+        match authenticate(backend, conn, self.username.as_deref(), &self.password) {
+            Ok(()) => RESP_OK.clone(),
+            Err(e) => e,
+        }
+    }
+}
+
+impl CommandExecuter for Auth {
+    fn execute(self, backend: Backend) -> RespFrame {
+        match check_password(&backend, self.username.as_deref(), &self.password) {
+            Ok(_) => RESP_OK.clone(),
+            Err(e) => e,
+        }
+    }
+}
+
+impl Hello {
+    /// 按照redis的顺序: 检查协议版本, 认证, 设置名字, 最后切换协议
+    pub fn apply(self, backend: &Backend, conn: &mut Connection) -> RespFrame {
+        let protover = match self.protover {
+            Some(v @ 2..=3) => v as u8,
+            Some(_) => return SimpleError::new("NOPROTO unsupported protocol version").into(),
+            None => conn.protover,
+        };
+        match self.auth {
+            Some((username, password)) => {
+                if let Err(e) = authenticate(backend, conn, Some(&username), &password) {
+                    return e;
+                }
+            }
+            None if !conn.authenticated && backend.requires_auth() => {
+                return SimpleError::new(
+                    "NOAUTH HELLO must be called with the client already authenticated, \
+                     otherwise the HELLO <proto> AUTH <user> <pass> option can be used to \
+                     authenticate the client and select the RESP protocol version at the same time",
+                )
+                .into();
+            }
+            None => {}
+        }
+        if let Some(name) = self.setname {
+            if !valid_client_name(&name) {
+                return invalid_client_name();
+            }
+            conn.name = (!name.is_empty()).then_some(name);
+        }
+        conn.protover = protover;
+        hello_reply(backend, conn.id, protover)
+    }
+}
+
+/// 不在连接中执行时不能切换协议, 按照RESP2回复
+impl CommandExecuter for Hello {
+    fn execute(self, backend: Backend) -> RespFrame {
+        match self.protover {
+            Some(2..=3) | None => hello_reply(&backend, 0, 2),
+            Some(_) => SimpleError::new("NOPROTO unsupported protocol version").into(),
+        }
+    }
+}
+
+fn hello_reply(backend: &Backend, id: u64, protover: u8) -> RespFrame {
+    let mode = match backend.cluster().is_enabled() {
+        true => "cluster",
+        false => "standalone",
+    };
+    let fields: [(&str, RespFrame); 7] = [
+        ("server", BulkString::new("redis").into()),
+        ("version", BulkString::new(REDIS_VERSION).into()),
+        ("proto", RespFrame::Integer(protover as i64)),
+        ("id", RespFrame::Integer(id as i64)),
+        ("mode", BulkString::new(mode).into()),
+        ("role", BulkString::new("master").into()),
+        ("modules", RespArray::new(Vec::<RespFrame>::new()).into()),
+    ];
+    fields_reply(fields, protover)
+}
+
+/// RESP3返回map, RESP2返回平铺的数组
+pub(super) fn fields_reply(
+    fields: impl IntoIterator<Item = (&'static str, RespFrame)>,
+    protover: u8,
+) -> RespFrame {
+    match protover {
+        3 => {
+            let mut map = RespMap::new();
+            for (k, v) in fields {
+                map.insert(k.to_string(), v);
+            }
+            map.into()
+        }
+        _ => RespArray::new(
+            fields
+                .into_iter()
+                .flat_map(|(k, v)| [BulkString::new(k).into(), v])
+                .collect::<Vec<RespFrame>>(),
+        )
+        .into(),
+    }
+}
+
+impl TryFrom<RespArray> for Auth {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let mut args = extract_args(value, 1)?.into_iter();
+        match (args.next(), args.next(), args.next()) {
+            (Some(password), None, None) => Ok(Auth {
+                username: None,
+                password: parse_bytes(password)?,
+            }),
+            (Some(username), Some(password), None) => Ok(Auth {
+                username: Some(parse_string(username)?),
+                password: parse_bytes(password)?,
+            }),
+            _ => Err(CommandError::InvalidArgument(
+                "AUTH [username] password".to_string(),
+            )),
+        }
+    }
+}
+
+impl TryFrom<RespArray> for Hello {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let mut args = extract_args(value, 1)?.into_iter();
+        let mut hello = Hello {
+            protover: None,
+            auth: None,
+            setname: None,
+        };
+        let Some(protover) = args.next() else {
+            return Ok(hello);
+        };
+        hello.protover = Some(parse_string(protover)?.parse().map_err(|_| {
+            CommandError::InvalidArgument(
+                "Protocol version is not an integer or out of range".to_string(),
+            )
+        })?);
+        while let Some(arg) = args.next() {
+            let option = parse_string(arg)?;
+            match option.to_ascii_lowercase().as_str() {
+                "auth" => {
+                    if let (Some(username), Some(password)) = (args.next(), args.next()) {
+                        hello.auth = Some((parse_string(username)?, parse_bytes(password)?));
+                        continue;
+                    }
+                }
+                "setname" => {
+                    if let Some(name) = args.next() {
+                        hello.setname = Some(parse_string(name)?);
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            return Err(CommandError::InvalidArgument(format!(
+                "Syntax error in HELLO option '{}'",
+                option
+            )));
+        }
+        Ok(hello)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    fn array(args: &[&str]) -> RespArray {
+        RespArray::new(
+            args.iter()
+                .map(|a| BulkString::new(*a).into())
+                .collect::<Vec<RespFrame>>(),
+        )
+    }
+
+    fn set_requirepass(backend: &Backend, password: &str) -> Result<()> {
+        let pairs = [("requirepass".to_string(), password.to_string())];
+        backend.config().set(&pairs)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_redacts_password() -> Result<()> {
+        let auth = Auth::try_from(array(&["auth", "alice", "secret"]))?;
+        let hello = Hello::try_from(array(&["hello", "3", "AUTH", "alice", "secret"]))?;
+        for debug in [format!("{:?}", auth), format!("{:?}", hello)] {
+            assert!(debug.contains("alice") && debug.contains(REDACTED));
+            assert!(!debug.contains("secret"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_auth() -> Result<()> {
+        let backend = Backend::new();
+        let mut conn = Connection::new(&backend, "127.0.0.1:1000", "127.0.0.1:6379");
+        assert!(conn.authenticated);
+        let cmd = Auth::try_from(array(&["auth", "secret"]))?;
+        assert!(matches!(
+            cmd.apply(&backend, &mut conn),
+            RespFrame::SimpleError(_)
+        ));
+
+        set_requirepass(&backend, "secret")?;
+        let mut conn = Connection::new(&backend, "127.0.0.1:1000", "127.0.0.1:6379");
+        assert!(!conn.authenticated);
+        for args in [&["auth", "wrong"][..], &["auth", "admin", "secret"]] {
+            let cmd = Auth::try_from(array(args))?;
+            assert_eq!(cmd.apply(&backend, &mut conn), wrong_pass());
+            assert!(!conn.authenticated);
+        }
+        let cmd = Auth::try_from(array(&["auth", "default", "secret"]))?;
+        assert_eq!(cmd.apply(&backend, &mut conn), RESP_OK.clone());
+        assert!(conn.authenticated);
+
+        assert!(Auth::try_from(array(&["auth"])).is_err());
+        assert!(Auth::try_from(array(&["auth", "a", "b", "c"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hello() -> Result<()> {
+        let backend = Backend::new();
+        set_requirepass(&backend, "secret")?;
+        let mut conn = Connection::new(&backend, "127.0.0.1:1000", "127.0.0.1:6379");
+
+        let cmd = Hello::try_from(array(&["hello", "3"]))?;
+        let reply = cmd.apply(&backend, &mut conn);
+        assert!(matches!(reply, RespFrame::SimpleError(e) if e.starts_with("NOAUTH")));
+        assert_eq!(conn.protover, 2);
+
+        let cmd = Hello::try_from(array(&["hello", "4", "auth", "default", "secret"]))?;
+        assert_eq!(
+            cmd.apply(&backend, &mut conn),
+            SimpleError::new("NOPROTO unsupported protocol version").into()
+        );
+        assert!(!conn.authenticated);
+
+        let cmd = Hello::try_from(array(&[
+            "hello", "3", "AUTH", "default", "secret", "SETNAME", "myconn",
+        ]))?;
+        let reply = cmd.apply(&backend, &mut conn);
+        assert!(matches!(&reply, RespFrame::Map(map) if map["proto"] == RespFrame::Integer(3)));
+        assert!(conn.authenticated);
+        assert_eq!(conn.protover, 3);
+        assert_eq!(conn.name.as_deref(), Some("myconn"));
+
+        let cmd = Hello::try_from(array(&["hello", "2"]))?;
+        let reply = cmd.apply(&backend, &mut conn);
+        assert!(matches!(reply, RespFrame::Array(array) if array.len() == 14));
+
+        assert!(Hello::try_from(array(&["hello", "x"])).is_err());
+        assert!(Hello::try_from(array(&["hello", "3", "auth", "default"])).is_err());
+        Ok(())
+    }
+}
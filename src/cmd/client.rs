@@ -0,0 +1,644 @@
+//! support CLIENT command
+//!
+//! CLIENT LIST [TYPE type] [ID id ...] | INFO：查看所有连接或当前连接
+//! CLIENT ID | SETNAME name | GETNAME：当前连接的id和名字
+//! CLIENT KILL ip:port | KILL [ID id] [ADDR ip:port] [LADDR ip:port] [USER username]
+//!     [TYPE type] [MAXAGE seconds] [SKIPME yes|no]：关闭连接
+//! CLIENT PAUSE timeout [WRITE|ALL] | UNPAUSE：暂停所有连接或者只暂停写命令
+//! CLIENT NO-EVICT on|off | NO-TOUCH on|off：没有客户端淘汰, NO-EVICT只记录标志;
+//!     NO-TOUCH 时这个连接访问key不更新LRU/LFU
+//! CLIENT TRACKING on|off [REDIRECT id] [PREFIX prefix ...] [BCAST] [OPTIN] [OPTOUT] [NOLOOP]：
+//!     客户端缓存, 读过的key被修改时发送invalidate消息。没有pub/sub, 只有RESP3的连接能作为REDIRECT的目标
+//! CLIENT CACHING yes|no | GETREDIR | TRACKINGINFO
+
+use super::{
+    auth::fields_reply, extract_args, parse_integer, parse_string, CommandError, CommandExecuter,
+    RESP_OK,
+};
+use crate::{
+    backend::{now_ms, Backend, ClientType, KillFilter, TrackingOptions},
+    network::Connection,
+    resp::{frame::RespFrame, BulkString, RespArray, SimpleError},
+};
+
+#[derive(Debug)]
+pub enum Client {
+    List {
+        client_type: Option<ClientType>,
+        ids: Vec<u64>,
+    },
+    Info,
+    Id,
+    SetName(String),
+    GetName,
+    /// 旧的写法 CLIENT KILL ip:port
+    KillAddr(String),
+    Kill(KillFilter),
+    /// 毫秒, 是否暂停所有命令
+    Pause(u64, bool),
+    Unpause,
+    NoEvict(bool),
+    NoTouch(bool),
+    /// None 表示 CLIENT TRACKING off
+    Tracking(Option<TrackingOptions>),
+    Caching(bool),
+    GetRedir,
+    TrackingInfo,
+    Help,
+}
+
+const CLIENT_HELP: &[&str] = &[
+    "CLIENT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "GETNAME",
+    "    Return the name of the current connection.",
+    "ID",
+    "    Return the ID of the current connection.",
+    "INFO",
+    "    Return information about the current client connection.",
+    "KILL <ip:port>",
+    "    Kill connection made from <ip:port>.",
+    "KILL <option> <value> [<option> <value> [...]]",
+    "    Kill connections. Options are:",
+    "    * ADDR (<ip:port>|<unixsocket>:0)",
+    "      Kill connections made from the specified address",
+    "    * LADDR (<ip:port>|<unixsocket>:0)",
+    "      Kill connections made to specified local address",
+    "    * TYPE (NORMAL|MASTER|REPLICA|PUBSUB)",
+    "      Kill connections by type.",
+    "    * USER <username>",
+    "      Kill connections authenticated by <username>.",
+    "    * SKIPME (YES|NO)",
+    "      Skip killing current connection (default: yes).",
+    "    * ID <client-id>",
+    "      Kill connections by client id.",
+    "    * MAXAGE <maxage>",
+    "      Kill connections older than the specified age.",
+    "LIST [options ...]",
+    "    Return information about client connections. Options:",
+    "    * TYPE (NORMAL|MASTER|REPLICA|PUBSUB)",
+    "      Return clients of specified type.",
+    "UNPAUSE",
+    "    Stop the current client pause, resuming traffic.",
+    "PAUSE <timeout> [WRITE|ALL]",
+    "    Suspend all, or just write, clients for <timeout> milliseconds.",
+    "SETNAME <name>",
+    "    Assign the name <name> to the current connection.",
+    "NO-EVICT (ON|OFF)",
+    "    Protect current client connection from eviction.",
+    "NO-TOUCH (ON|OFF)",
+    "    Will not touch LRU/LFU stats when this mode is on.",
+    "TRACKING (ON|OFF) [REDIRECT <id>] [BCAST] [PREFIX <prefix> [...]]",
+    "         [OPTIN] [OPTOUT] [NOLOOP]",
+    "    Control server assisted client side caching.",
+    "CACHING (YES|NO)",
+    "    Enable/disable tracking of the keys for next command in OPTIN/OPTOUT modes.",
+    "GETREDIR",
+    "    Return the client ID we are redirecting to when tracking is enabled.",
+    "TRACKINGINFO",
+    "    Report tracking status for the current connection.",
+];
+
+/// 和HELLO SETNAME的规则一样, 只能是可见的ascii字符
+pub(super) fn valid_client_name(name: &str) -> bool {
+    name.bytes().all(|c| (b'!'..=b'~').contains(&c))
+}
+
+pub(super) fn invalid_client_name() -> RespFrame {
+    SimpleError::new("ERR Client names cannot contain spaces, newlines or special characters.")
+        .into()
+}
+
+impl Client {
+    pub(super) fn subcommand(&self) -> &'static str {
+        match self {
+            Client::List { .. } => "list",
+            Client::Info => "info",
+            Client::Id => "id",
+            Client::SetName(_) => "setname",
+            Client::GetName => "getname",
+            Client::KillAddr(_) | Client::Kill(_) => "kill",
+            Client::Pause(..) => "pause",
+            Client::Unpause => "unpause",
+            Client::NoEvict(_) => "no-evict",
+            Client::NoTouch(_) => "no-touch",
+            Client::Tracking(_) => "tracking",
+            Client::Caching(_) => "caching",
+            Client::GetRedir => "getredir",
+            Client::TrackingInfo => "trackinginfo",
+            Client::Help => "help",
+        }
+    }
+
+    /// 在连接中执行, 修改连接的名字和标志
+    pub fn apply(self, backend: &Backend, conn: &mut Connection) -> RespFrame {
+        match self {
+            Client::Info => BulkString::new(format!("{}\n", conn.client_info())).into(),
+            Client::Id => RespFrame::Integer(conn.id as i64),
+            Client::SetName(name) => {
+                if !valid_client_name(&name) {
+                    return invalid_client_name();
+                }
+                conn.name = (!name.is_empty()).then_some(name);
+                RESP_OK.clone()
+            }
+            Client::GetName => match &conn.name {
+                Some(name) => BulkString::new(name.as_str()).into(),
+                None => BulkString(None).into(),
+            },
+            Client::NoEvict(on) => {
+                conn.no_evict = on;
+                RESP_OK.clone()
+            }
+            Client::NoTouch(on) => {
+                conn.no_touch = on;
+                RESP_OK.clone()
+            }
+            Client::Tracking(Some(options)) => match enable_tracking(backend, conn, options) {
+                Ok(()) => RESP_OK.clone(),
+                Err(e) => SimpleError::new(e).into(),
+            },
+            Client::Tracking(None) => {
+                conn.tracking = None;
+                conn.caching = None;
+                backend.tracking().disable(conn.id);
+                RESP_OK.clone()
+            }
+            Client::Caching(yes) => {
+                let Some(tracking) = conn.tracking.as_ref().filter(|t| t.optin || t.optout) else {
+                    return SimpleError::new("ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled").into();
+                };
+                match (yes, tracking.optin) {
+                    (true, false) => SimpleError::new(
+                        "ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode.",
+                    )
+                    .into(),
+                    (false, true) => SimpleError::new(
+                        "ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.",
+                    )
+                    .into(),
+                    _ => {
+                        conn.caching = Some(yes);
+                        RESP_OK.clone()
+                    }
+                }
+            }
+            Client::GetRedir => RespFrame::Integer(match &conn.tracking {
+                Some(tracking) => tracking.redirect.map_or(0, |id| id as i64),
+                None => -1,
+            }),
+            Client::TrackingInfo => tracking_info(backend, conn),
+            Client::KillAddr(addr) => {
+                let filter = KillFilter {
+                    addr: Some(addr),
+                    ..Default::default()
+                };
+                match backend.clients().kill(&filter, conn.id) {
+                    0 => SimpleError::new("ERR No such client").into(),
+                    _ => RESP_OK.clone(),
+                }
+            }
+            Client::Kill(filter) => {
+                RespFrame::Integer(backend.clients().kill(&filter, conn.id) as i64)
+            }
+            // LIST 中当前连接的信息要是最新的
+            client @ Client::List { .. } => {
+                conn.sync();
+                client.execute(backend.clone())
+            }
+            client => client.execute(backend.clone()),
+        }
+    }
+}
+
+/// 已经开启时只能追加BCAST的前缀, 不能切换模式
+fn enable_tracking(
+    backend: &Backend,
+    conn: &mut Connection,
+    mut options: TrackingOptions,
+) -> Result<(), String> {
+    if let Some(id) = options.redirect {
+        if backend.clients().get(id).is_none() {
+            return Err("ERR The client ID you want redirect to does not exist".to_string());
+        }
+    }
+    if let Some(old) = &conn.tracking {
+        if old.bcast != options.bcast {
+            return Err("ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode.".to_string());
+        }
+        if (old.optin, old.optout) != (options.optin, options.optout) {
+            return Err("ERR You can't switch OPTIN/OPTOUT mode before disabling tracking for this client, and then re-enabling it with a different mode.".to_string());
+        }
+        let mut prefixes = old.prefixes.clone();
+        prefixes.extend(
+            options
+                .prefixes
+                .into_iter()
+                .filter(|p| !old.prefixes.contains(p)),
+        );
+        options.prefixes = prefixes;
+    }
+    for (i, a) in options.prefixes.iter().enumerate() {
+        for b in &options.prefixes[i + 1..] {
+            if a.starts_with(b) || b.starts_with(a) {
+                let (a, b) = (String::from_utf8_lossy(a), String::from_utf8_lossy(b));
+                return Err(format!("ERR Prefix '{}' overlaps with an existing prefix '{}'. Prefixes for a single client must not overlap.", b, a));
+            }
+        }
+    }
+    backend.tracking().enable(conn.id, options.clone());
+    conn.tracking = Some(options);
+    Ok(())
+}
+
+fn tracking_info(backend: &Backend, conn: &Connection) -> RespFrame {
+    let Some(tracking) = &conn.tracking else {
+        return fields_reply(
+            [
+                (
+                    "flags",
+                    RespArray::new(vec![BulkString::new("off").into()]).into(),
+                ),
+                ("redirect", RespFrame::Integer(-1)),
+                ("prefixes", RespArray::new(Vec::<RespFrame>::new()).into()),
+            ],
+            conn.protover,
+        );
+    };
+    let mut flags = vec!["on"];
+    for (on, flag) in [
+        (tracking.bcast, "bcast"),
+        (tracking.optin, "optin"),
+        (tracking.optout, "optout"),
+        (conn.caching == Some(true), "caching-yes"),
+        (conn.caching == Some(false), "caching-no"),
+        (tracking.noloop, "noloop"),
+    ] {
+        if on {
+            flags.push(flag);
+        }
+    }
+    let redirect = match tracking.redirect {
+        Some(id) => {
+            if backend.clients().get(id).is_none() {
+                flags.push("broken_redirect");
+            }
+            id as i64
+        }
+        None => 0,
+    };
+    let prefixes = tracking
+        .prefixes
+        .iter()
+        .map(|p| BulkString::from(p.clone()).into())
+        .collect::<Vec<RespFrame>>();
+    fields_reply(
+        [
+            (
+                "flags",
+                RespArray::new(
+                    flags
+                        .into_iter()
+                        .map(|f| BulkString::new(f).into())
+                        .collect::<Vec<RespFrame>>(),
+                )
+                .into(),
+            ),
+            ("redirect", RespFrame::Integer(redirect)),
+            ("prefixes", RespArray::new(prefixes).into()),
+        ],
+        conn.protover,
+    )
+}
+
+impl CommandExecuter for Client {
+    fn execute(self, backend: Backend) -> RespFrame {
+        let clients = backend.clients();
+        match self {
+            Client::List { client_type, ids } => {
+                let list: String = clients
+                    .list()
+                    .into_iter()
+                    .filter(|c| client_type.is_none_or(|t| t == c.client_type()))
+                    .filter(|c| ids.is_empty() || ids.contains(&c.id()))
+                    .map(|c| format!("{}\n", c.info()))
+                    .collect();
+                BulkString::new(list).into()
+            }
+            Client::Kill(filter) => RespFrame::Integer(clients.kill(&filter, 0) as i64),
+            Client::Pause(timeout, all) => {
+                clients.pause(now_ms() + timeout, all);
+                RESP_OK.clone()
+            }
+            Client::Unpause => {
+                clients.unpause();
+                RESP_OK.clone()
+            }
+            Client::Help => RespArray::new(
+                CLIENT_HELP
+                    .iter()
+                    .map(|line| BulkString::new(*line).into())
+                    .collect::<Vec<RespFrame>>(),
+            )
+            .into(),
+            client => SimpleError::new(format!(
+                "ERR CLIENT {} can only be used in a connection",
+                client.subcommand().to_ascii_uppercase()
+            ))
+            .into(),
+        }
+    }
+}
+
+fn syntax_error(subcommand: &str) -> CommandError {
+    CommandError::InvalidArgument(format!(
+        "unknown subcommand or wrong number of arguments for '{}'. Try CLIENT HELP.",
+        subcommand
+    ))
+}
+
+fn invalid(message: &str) -> CommandError {
+    CommandError::InvalidArgument(message.to_string())
+}
+
+fn parse_on_off(frame: RespFrame) -> Result<bool, CommandError> {
+    match parse_string(frame)?.to_ascii_lowercase().as_str() {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(invalid("syntax error")),
+    }
+}
+
+fn parse_type(frame: RespFrame) -> Result<ClientType, CommandError> {
+    parse_string(frame)?
+        .parse()
+        .map_err(|e: String| CommandError::InvalidArgument(e))
+}
+
+/// CLIENT TRACKING on 之后的选项
+fn parse_tracking(args: Vec<RespFrame>) -> Result<TrackingOptions, CommandError> {
+    let mut options = TrackingOptions::default();
+    let mut args = args.into_iter();
+    while let Some(option) = args.next() {
+        match parse_string(option)?.to_ascii_lowercase().as_str() {
+            "redirect" => {
+                let id = args.next().ok_or_else(|| invalid("syntax error"))?;
+                options.redirect = Some(parse_integer(id)?);
+            }
+            "prefix" => {
+                let prefix = args.next().ok_or_else(|| invalid("syntax error"))?;
+                options.prefixes.push(parse_string(prefix)?.into());
+            }
+            "bcast" => options.bcast = true,
+            "optin" => options.optin = true,
+            "optout" => options.optout = true,
+            "noloop" => options.noloop = true,
+            _ => return Err(invalid("syntax error")),
+        }
+    }
+    if !options.bcast && !options.prefixes.is_empty() {
+        return Err(invalid("PREFIX option requires BCAST mode to be enabled"));
+    }
+    if options.optin && options.optout {
+        return Err(invalid("You can't use both OPTIN and OPTOUT"));
+    }
+    if options.bcast && (options.optin || options.optout) {
+        return Err(invalid("OPTIN and OPTOUT are not compatible with BCAST"));
+    }
+    Ok(options)
+}
+
+/// CLIENT KILL 新的写法, 参数是成对的
+fn parse_kill_filter(args: Vec<RespFrame>) -> Result<KillFilter, CommandError> {
+    if !args.len().is_multiple_of(2) {
+        return Err(invalid("syntax error"));
+    }
+    let mut filter = KillFilter {
+        skipme: true,
+        ..Default::default()
+    };
+    let mut args = args.into_iter();
+    while let (Some(name), Some(value)) = (args.next(), args.next()) {
+        match parse_string(name)?.to_ascii_lowercase().as_str() {
+            "id" => {
+                filter.id = Some(
+                    parse_integer(value)
+                        .map_err(|_| invalid("client-id should be greater than 0"))?,
+                )
+            }
+            "addr" => filter.addr = Some(parse_string(value)?),
+            "laddr" => filter.laddr = Some(parse_string(value)?),
+            "user" => filter.user = Some(parse_string(value)?),
+            "type" => filter.client_type = Some(parse_type(value)?),
+            "maxage" => filter.maxage = Some(parse_integer(value)?),
+            "skipme" => {
+                filter.skipme = match parse_string(value)?.to_ascii_lowercase().as_str() {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err(invalid("syntax error")),
+                }
+            }
+            _ => return Err(invalid("syntax error")),
+        }
+    }
+    Ok(filter)
+}
+
+impl TryFrom<RespArray> for Client {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let mut args = extract_args(value, 1)?;
+        if args.is_empty() {
+            return Err(syntax_error(""));
+        }
+        let subcommand = parse_string(args.remove(0))?;
+        match (subcommand.to_ascii_lowercase().as_str(), args.len()) {
+            ("list", _) => {
+                let (mut client_type, mut ids) = (None, Vec::new());
+                let mut args = args.into_iter();
+                while let Some(option) = args.next() {
+                    match parse_string(option)?.to_ascii_lowercase().as_str() {
+                        "type" => {
+                            let value = args.next().ok_or_else(|| invalid("syntax error"))?;
+                            client_type = Some(parse_type(value)?);
+                        }
+                        "id" => {
+                            for id in args.by_ref() {
+                                ids.push(
+                                    parse_integer(id).map_err(|_| invalid("Invalid client ID"))?,
+                                );
+                            }
+                            if ids.is_empty() {
+                                return Err(invalid("syntax error"));
+                            }
+                        }
+                        _ => return Err(invalid("syntax error")),
+                    }
+                }
+                Ok(Client::List { client_type, ids })
+            }
+            ("info", 0) => Ok(Client::Info),
+            ("id", 0) => Ok(Client::Id),
+            ("setname", 1) => Ok(Client::SetName(parse_string(args.remove(0))?)),
+            ("getname", 0) => Ok(Client::GetName),
+            ("kill", 1) => Ok(Client::KillAddr(parse_string(args.remove(0))?)),
+            ("kill", 2..) => Ok(Client::Kill(parse_kill_filter(args)?)),
+            ("pause", 1..=2) => {
+                let timeout = parse_integer::<i64>(args.remove(0))
+                    .ok()
+                    .filter(|t| *t >= 0)
+                    .ok_or_else(|| invalid("timeout is not an integer or out of range"))?;
+                let all = match args.pop() {
+                    None => true,
+                    Some(mode) => match parse_string(mode)?.to_ascii_lowercase().as_str() {
+                        "all" => true,
+                        "write" => false,
+                        _ => return Err(invalid("syntax error")),
+                    },
+                };
+                Ok(Client::Pause(timeout as u64, all))
+            }
+            ("unpause", 0) => Ok(Client::Unpause),
+            ("no-evict", 1) => Ok(Client::NoEvict(parse_on_off(args.remove(0))?)),
+            ("no-touch", 1) => Ok(Client::NoTouch(parse_on_off(args.remove(0))?)),
+            ("tracking", 1..) => match parse_on_off(args.remove(0))? {
+                true => Ok(Client::Tracking(Some(parse_tracking(args)?))),
+                false => Ok(Client::Tracking(None)),
+            },
+            ("caching", 1) => match parse_string(args.remove(0))?.to_ascii_lowercase().as_str() {
+                "yes" => Ok(Client::Caching(true)),
+                "no" => Ok(Client::Caching(false)),
+                _ => Err(invalid("syntax error")),
+            },
+            ("getredir", 0) => Ok(Client::GetRedir),
+            ("trackinginfo", 0) => Ok(Client::TrackingInfo),
+            ("help", 0) => Ok(Client::Help),
+            _ => Err(syntax_error(&subcommand)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    fn client(args: &[&str]) -> Result<Client> {
+        let mut full = vec!["client"];
+        full.extend_from_slice(args);
+        Ok(Client::try_from(RespArray::new(
+            full.iter()
+                .map(|a| BulkString::new(*a).into())
+                .collect::<Vec<RespFrame>>(),
+        ))?)
+    }
+
+    #[test]
+    fn test_client_parse() -> Result<()> {
+        let Client::Kill(filter) = client(&["kill", "user", "alice", "skipme", "no"])? else {
+            panic!("expected CLIENT KILL filter");
+        };
+        assert_eq!(filter.user.as_deref(), Some("alice"));
+        assert!(!filter.skipme);
+        assert!(matches!(
+            client(&["kill", "127.0.0.1:1234"])?,
+            Client::KillAddr(_)
+        ));
+        assert!(client(&["kill", "id", "1", "user"]).is_err());
+        assert!(matches!(
+            client(&["pause", "100"])?,
+            Client::Pause(100, true)
+        ));
+        assert!(matches!(
+            client(&["pause", "100", "write"])?,
+            Client::Pause(100, false)
+        ));
+        assert!(client(&["pause", "-1"]).is_err());
+        assert!(matches!(
+            client(&["list", "type", "replica", "id", "1", "2"])?,
+            Client::List {
+                client_type: Some(ClientType::Replica),
+                ref ids,
+            } if ids == &[1, 2]
+        ));
+        assert!(client(&["no-touch", "maybe"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_commands() -> Result<()> {
+        let backend = Backend::new();
+        let mut conn = Connection::new(&backend, "127.0.0.1:1000", "127.0.0.1:6379");
+        let mut other = Connection::new(&backend, "127.0.0.1:1001", "127.0.0.1:6379");
+
+        let reply = client(&["setname", "worker"])?.apply(&backend, &mut conn);
+        assert_eq!(reply, RESP_OK.clone());
+        let reply = client(&["getname"])?.apply(&backend, &mut conn);
+        assert_eq!(reply, BulkString::new("worker").into());
+        let reply = client(&["setname", "bad name"])?.apply(&backend, &mut conn);
+        assert_eq!(reply, invalid_client_name());
+
+        client(&["no-touch", "on"])?.apply(&backend, &mut conn);
+        let RespFrame::BulkString(BulkString(Some(list))) =
+            client(&["list"])?.apply(&backend, &mut conn)
+        else {
+            panic!("expected bulk string");
+        };
+        let list = String::from_utf8(list.into())?;
+        let lines: Vec<&str> = list.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(" name=worker ") && lines[0].contains(" flags=T "));
+
+        let reply = client(&["kill", "127.0.0.1:1001"])?.apply(&backend, &mut conn);
+        assert_eq!(reply, RESP_OK.clone());
+        assert!(other.client.is_killed());
+        let reply = client(&["kill", "127.0.0.1:9999"])?.apply(&backend, &mut conn);
+        assert_eq!(reply, SimpleError::new("ERR No such client").into());
+        // 默认SKIPME yes
+        let reply = client(&["kill", "laddr", "127.0.0.1:6379"])?.apply(&backend, &mut other);
+        assert_eq!(reply, RespFrame::Integer(1));
+        assert!(conn.client.is_killed());
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_tracking() -> Result<()> {
+        assert!(client(&["tracking", "on", "prefix", "a"]).is_err());
+        assert!(client(&["tracking", "on", "bcast", "optin"]).is_err());
+        assert!(client(&["tracking", "on", "optin", "optout"]).is_err());
+
+        let backend = Backend::new();
+        let mut conn = Connection::new(&backend, "127.0.0.1:1000", "127.0.0.1:6379");
+        let reply = client(&["getredir"])?.apply(&backend, &mut conn);
+        assert_eq!(reply, RespFrame::Integer(-1));
+        let reply = client(&["tracking", "on", "redirect", "99"])?.apply(&backend, &mut conn);
+        assert_eq!(
+            reply,
+            SimpleError::new("ERR The client ID you want redirect to does not exist").into()
+        );
+        let reply = client(&["caching", "yes"])?.apply(&backend, &mut conn);
+        assert!(matches!(reply, RespFrame::SimpleError(_)));
+
+        conn.protover = 3;
+        conn.sync();
+        let reply = client(&["tracking", "on", "optin"])?.apply(&backend, &mut conn);
+        assert_eq!(reply, RESP_OK.clone());
+        let reply = client(&["caching", "no"])?.apply(&backend, &mut conn);
+        assert!(matches!(reply, RespFrame::SimpleError(_)));
+        let reply = client(&["caching", "yes"])?.apply(&backend, &mut conn);
+        assert_eq!(reply, RESP_OK.clone());
+        assert_eq!(conn.caching, Some(true));
+        let reply = client(&["tracking", "on", "bcast"])?.apply(&backend, &mut conn);
+        assert!(matches!(reply, RespFrame::SimpleError(_)));
+
+        backend.tracking().remember(conn.id, &["foo".into()]);
+        backend.invalidate(&["foo".into()], 0);
+        assert!(matches!(conn.pushes.try_recv(), Ok(RespFrame::Push(_))));
+        assert!(conn.client_info().contains(" flags=t "));
+
+        let reply = client(&["tracking", "off"])?.apply(&backend, &mut conn);
+        assert_eq!(reply, RESP_OK.clone());
+        assert!(!backend.tracking().is_active());
+        Ok(())
+    }
+}
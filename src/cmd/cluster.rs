@@ -0,0 +1,572 @@
+//! support CLUSTER and ASKING command
+//!
+//! CLUSTER MYID | INFO | NODES | SLOTS | SHARDS
+//! CLUSTER KEYSLOT key
+//! CLUSTER COUNTKEYSINSLOT slot
+//! CLUSTER ADDSLOTS slot [slot ...] / ADDSLOTSRANGE start end [start end ...]
+//! CLUSTER DELSLOTS slot [slot ...] / DELSLOTSRANGE start end [start end ...]
+//! CLUSTER MEET ip port
+//! CLUSTER SETSLOT slot IMPORTING node-id | MIGRATING node-id | STABLE | NODE node-id
+//! CLUSTER GETKEYSINSLOT slot count
+
+use super::{
+    extract_args, parse_bytes, parse_integer, parse_string, CommandError, CommandExecuter, RESP_OK,
+};
+use crate::{
+    backend::Backend,
+    cluster::{key_hash_slot, ClusterNode, CLUSTER_SLOTS},
+    network::RespClient,
+    resp::{frame::RespFrame, BulkString, RespArray, SimpleError},
+};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use std::collections::BTreeMap;
+
+#[derive(Debug)]
+pub enum Cluster {
+    MyId,
+    Info,
+    Nodes,
+    Slots,
+    Shards,
+    KeySlot(Bytes),
+    CountKeysInSlot(u16),
+    AddSlots(Vec<u16>),
+    DelSlots(Vec<u16>),
+    /// 需要连接到对方节点, 在network中异步执行
+    Meet(String, u16),
+    SetSlot(u16, SetSlot),
+    GetKeysInSlot(u16, usize),
+}
+
+#[derive(Debug)]
+pub enum SetSlot {
+    Importing(String),
+    Migrating(String),
+    Stable,
+    Node(String),
+}
+
+/// ASKING: 下一个命令访问的slot如果正在迁入本节点, 不返回MOVED
+#[derive(Debug)]
+pub struct Asking;
+
+impl CommandExecuter for Asking {
+    fn execute(self, _backend: Backend) -> RespFrame {
+        RESP_OK.clone()
+    }
+}
+
+fn cluster_disabled() -> RespFrame {
+    SimpleError::new("ERR This instance has cluster support disabled").into()
+}
+
+fn node_frame(node: &ClusterNode) -> RespFrame {
+    RespArray::new(vec![
+        BulkString::new(node.ip.as_str()).into(),
+        RespFrame::Integer(node.port as i64),
+        BulkString::new(node.id.as_str()).into(),
+    ])
+    .into()
+}
+
+impl CommandExecuter for Cluster {
+    fn execute(self, backend: Backend) -> RespFrame {
+        let cluster = backend.cluster();
+        if !cluster.is_enabled() {
+            return cluster_disabled();
+        }
+        match self {
+            Cluster::MyId => BulkString::new(cluster.myself()).into(),
+            Cluster::Info => {
+                let assigned = cluster.slots_assigned();
+                let state = if assigned == CLUSTER_SLOTS {
+                    "ok"
+                } else {
+                    "fail"
+                };
+                let size = cluster
+                    .slot_ranges()
+                    .into_iter()
+                    .map(|(_, _, n)| n.id)
+                    .collect::<std::collections::BTreeSet<_>>()
+                    .len();
+                let info = format!(
+                    "cluster_enabled:1\r\ncluster_state:{}\r\ncluster_slots_assigned:{}\r\n\
+                     cluster_slots_ok:{}\r\ncluster_slots_pfail:0\r\ncluster_slots_fail:0\r\n\
+                     cluster_known_nodes:{}\r\ncluster_size:{}\r\n",
+                    state,
+                    assigned,
+                    assigned,
+                    cluster.nodes().len(),
+                    size
+                );
+                BulkString::new(info).into()
+            }
+            Cluster::Nodes => BulkString::new(cluster.nodes_description()).into(),
+            // [start, end, [ip, port, id]]
+            Cluster::Slots => {
+                let slots = cluster
+                    .slot_ranges()
+                    .into_iter()
+                    .map(|(start, end, node)| {
+                        RespArray::new(vec![
+                            RespFrame::Integer(start as i64),
+                            RespFrame::Integer(end as i64),
+                            node_frame(&node),
+                        ])
+                        .into()
+                    })
+                    .collect::<Vec<RespFrame>>();
+                RespArray::new(slots).into()
+            }
+            // [slots, [start, end, ...], nodes, [[id, .., port, .., ip, ..]]]
+            Cluster::Shards => {
+                let mut shards: BTreeMap<String, Vec<RespFrame>> = cluster
+                    .nodes()
+                    .into_iter()
+                    .map(|n| (n.id, Vec::new()))
+                    .collect();
+                for (start, end, node) in cluster.slot_ranges() {
+                    if let Some(slots) = shards.get_mut(&node.id) {
+                        slots.push(RespFrame::Integer(start as i64));
+                        slots.push(RespFrame::Integer(end as i64));
+                    }
+                }
+                let shards = shards
+                    .into_iter()
+                    .filter_map(|(id, slots)| {
+                        let node = cluster.node(&id)?;
+                        let node: RespFrame = RespArray::new(vec![
+                            BulkString::new("id").into(),
+                            BulkString::new(node.id.as_str()).into(),
+                            BulkString::new("port").into(),
+                            RespFrame::Integer(node.port as i64),
+                            BulkString::new("ip").into(),
+                            BulkString::new(node.ip.as_str()).into(),
+                            BulkString::new("endpoint").into(),
+                            BulkString::new(node.ip.as_str()).into(),
+                            BulkString::new("role").into(),
+                            BulkString::new("master").into(),
+                            BulkString::new("replication-offset").into(),
+                            RespFrame::Integer(backend.replication().master_offset() as i64),
+                            BulkString::new("health").into(),
+                            BulkString::new("online").into(),
+                        ])
+                        .into();
+                        Some(
+                            RespArray::new(vec![
+                                BulkString::new("slots").into(),
+                                RespArray::new(slots).into(),
+                                BulkString::new("nodes").into(),
+                                RespArray::new(vec![node]).into(),
+                            ])
+                            .into(),
+                        )
+                    })
+                    .collect::<Vec<RespFrame>>();
+                RespArray::new(shards).into()
+            }
+            Cluster::KeySlot(key) => RespFrame::Integer(key_hash_slot(&key) as i64),
+            Cluster::CountKeysInSlot(slot) => {
+                RespFrame::Integer(backend.count_keys_in_slot(slot) as i64)
+            }
+            Cluster::AddSlots(slots) => match cluster.add_slots(&slots) {
+                Ok(_) => RESP_OK.clone(),
+                Err(e) => SimpleError::new(e).into(),
+            },
+            Cluster::DelSlots(slots) => match cluster.del_slots(&slots) {
+                Ok(_) => RESP_OK.clone(),
+                Err(e) => SimpleError::new(e).into(),
+            },
+            Cluster::SetSlot(slot, action) => {
+                let ret = match action {
+                    SetSlot::Importing(id) => cluster.set_importing(slot, &id),
+                    SetSlot::Migrating(id) => cluster.set_migrating(slot, &id),
+                    SetSlot::Stable => {
+                        cluster.set_stable(slot);
+                        Ok(())
+                    }
+                    SetSlot::Node(id) => {
+                        let owner = cluster.slot_owner(slot).map(|n| n.id);
+                        if owner.as_deref() == Some(cluster.myself())
+                            && id != cluster.myself()
+                            && backend.count_keys_in_slot(slot) > 0
+                        {
+                            Err(format!(
+                                "ERR Can't assign hashslot {} to a different node while I still hold keys for this hash slot.",
+                                slot
+                            ))
+                        } else {
+                            cluster.assign_slot(slot, &id)
+                        }
+                    }
+                };
+                match ret {
+                    Ok(_) => RESP_OK.clone(),
+                    Err(e) => SimpleError::new(e).into(),
+                }
+            }
+            Cluster::GetKeysInSlot(slot, count) => {
+                let keys = backend
+                    .keys_in_slot(slot, count)
+                    .into_iter()
+                    .map(|k| BulkString::new(k).into())
+                    .collect::<Vec<RespFrame>>();
+                RespArray::new(keys).into()
+            }
+            Cluster::Meet(_, _) => {
+                SimpleError::new("ERR CLUSTER MEET must be called from a client connection").into()
+            }
+        }
+    }
+}
+
+impl Cluster {
+    pub(super) fn subcommand(&self) -> &'static str {
+        match self {
+            Cluster::MyId => "myid",
+            Cluster::Info => "info",
+            Cluster::Nodes => "nodes",
+            Cluster::Slots => "slots",
+            Cluster::Shards => "shards",
+            Cluster::KeySlot(_) => "keyslot",
+            Cluster::CountKeysInSlot(_) => "countkeysinslot",
+            Cluster::AddSlots(_) => "addslots",
+            Cluster::DelSlots(_) => "delslots",
+            Cluster::Meet(..) => "meet",
+            Cluster::SetSlot(..) => "setslot",
+            Cluster::GetKeysInSlot(..) => "getkeysinslot",
+        }
+    }
+
+    /// 连接到对方节点, 获取它的node id和它负责的slot。
+    /// 没有cluster bus, 对方不会因此知道本节点, 需要在对方节点上也执行MEET
+    pub async fn meet(backend: Backend, ip: String, port: u16) -> RespFrame {
+        if !backend.cluster().is_enabled() {
+            return cluster_disabled();
+        }
+        match meet_node(&backend, ip, port).await {
+            Ok(_) => RESP_OK.clone(),
+            Err(e) => SimpleError::new(format!("ERR {}", e)).into(),
+        }
+    }
+}
+
+async fn meet_node(backend: &Backend, ip: String, port: u16) -> Result<()> {
+    let cluster = backend.cluster();
+    let mut client = RespClient::connect(&format!("{}:{}", ip, port), backend.tls()).await?;
+    let id = match client.call(["cluster", "myid"]).await? {
+        RespFrame::BulkString(id) => id.to_string(),
+        other => return Err(anyhow!("unexpected CLUSTER MYID reply: {:?}", other)),
+    };
+    if id == cluster.myself() {
+        return Ok(());
+    }
+    cluster.add_node(ClusterNode {
+        id: id.clone(),
+        ip,
+        port,
+    });
+
+    let RespFrame::Array(RespArray(Some(ranges))) = client.call(["cluster", "slots"]).await? else {
+        return Err(anyhow!("unexpected CLUSTER SLOTS reply"));
+    };
+    for range in ranges {
+        let RespFrame::Array(RespArray(Some(range))) = range else {
+            continue;
+        };
+        let (Some(RespFrame::Integer(start)), Some(RespFrame::Integer(end))) =
+            (range.first(), range.get(1))
+        else {
+            continue;
+        };
+        let owner = match range.get(2) {
+            Some(RespFrame::Array(RespArray(Some(node)))) => node.get(2).cloned(),
+            _ => None,
+        };
+        if owner != Some(BulkString::new(id.as_str()).into()) {
+            continue;
+        }
+        // 本节点自己负责的slot不会被覆盖
+        for slot in *start..=*end {
+            let slot = slot as u16;
+            if cluster.slot_owner(slot).map(|n| n.id).as_deref() != Some(cluster.myself()) {
+                cluster.assign_slot(slot, &id).map_err(|e| anyhow!(e))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_slot(frame: RespFrame) -> Result<u16, CommandError> {
+    match parse_integer::<i64>(frame) {
+        Ok(slot) if (0..CLUSTER_SLOTS as i64).contains(&slot) => Ok(slot as u16),
+        _ => Err(CommandError::InvalidArgument(
+            "Invalid or out of range slot".to_string(),
+        )),
+    }
+}
+
+/// ADDSLOTSRANGE / DELSLOTSRANGE 的 start end 对展开成slot列表
+fn parse_slot_ranges(args: impl Iterator<Item = RespFrame>) -> Result<Vec<u16>, CommandError> {
+    let args = args.collect::<Vec<_>>();
+    if args.is_empty() || args.len() % 2 != 0 {
+        return Err(CommandError::InvalidArgument(
+            "slot ranges must be start end pairs".to_string(),
+        ));
+    }
+    let mut slots = Vec::new();
+    let mut args = args.into_iter();
+    while let (Some(start), Some(end)) = (args.next(), args.next()) {
+        let (start, end) = (parse_slot(start)?, parse_slot(end)?);
+        if start > end {
+            return Err(CommandError::InvalidArgument(format!(
+                "start slot number {} is greater than end slot number {}",
+                start, end
+            )));
+        }
+        slots.extend(start..=end);
+    }
+    Ok(slots)
+}
+
+impl TryFrom<RespArray> for Cluster {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let mut args = extract_args(value, 1)?.into_iter();
+        let subcommand = match args.next() {
+            Some(frame) => parse_string(frame)?.to_ascii_lowercase(),
+            None => {
+                return Err(CommandError::InvalidArgument(
+                    "CLUSTER subcommand is required".to_string(),
+                ))
+            }
+        };
+        let cmd = match subcommand.as_str() {
+            "myid" => Cluster::MyId,
+            "info" => Cluster::Info,
+            "nodes" => Cluster::Nodes,
+            "slots" => Cluster::Slots,
+            "shards" => Cluster::Shards,
+            "keyslot" => match args.next() {
+                Some(key) => Cluster::KeySlot(parse_bytes(key)?),
+                None => {
+                    return Err(CommandError::InvalidArgument(
+                        "CLUSTER KEYSLOT key".to_string(),
+                    ))
+                }
+            },
+            "countkeysinslot" => match args.next() {
+                Some(slot) => Cluster::CountKeysInSlot(parse_slot(slot)?),
+                None => {
+                    return Err(CommandError::InvalidArgument(
+                        "CLUSTER COUNTKEYSINSLOT slot".to_string(),
+                    ))
+                }
+            },
+            "addslots" | "delslots" => {
+                let slots = args
+                    .by_ref()
+                    .map(parse_slot)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if slots.is_empty() {
+                    return Err(CommandError::InvalidArgument(format!(
+                        "CLUSTER {} slot [slot ...]",
+                        subcommand.to_uppercase()
+                    )));
+                }
+                if subcommand == "addslots" {
+                    Cluster::AddSlots(slots)
+                } else {
+                    Cluster::DelSlots(slots)
+                }
+            }
+            "addslotsrange" => Cluster::AddSlots(parse_slot_ranges(args.by_ref())?),
+            "delslotsrange" => Cluster::DelSlots(parse_slot_ranges(args.by_ref())?),
+            "meet" => match (args.next(), args.next()) {
+                (Some(ip), Some(port)) => Cluster::Meet(
+                    parse_string(ip)?,
+                    parse_integer(port).map_err(|_| {
+                        CommandError::InvalidArgument("Invalid base port specified".to_string())
+                    })?,
+                ),
+                _ => {
+                    return Err(CommandError::InvalidArgument(
+                        "CLUSTER MEET ip port".to_string(),
+                    ))
+                }
+            },
+            "setslot" => {
+                let slot = parse_slot(args.next().ok_or_else(|| {
+                    CommandError::InvalidArgument("CLUSTER SETSLOT slot action".to_string())
+                })?)?;
+                let action = match args.next() {
+                    Some(action) => parse_string(action)?.to_ascii_lowercase(),
+                    None => String::new(),
+                };
+                let action = match (action.as_str(), args.next()) {
+                    ("importing", Some(id)) => SetSlot::Importing(parse_string(id)?),
+                    ("migrating", Some(id)) => SetSlot::Migrating(parse_string(id)?),
+                    ("node", Some(id)) => SetSlot::Node(parse_string(id)?),
+                    ("stable", None) => SetSlot::Stable,
+                    _ => {
+                        return Err(CommandError::InvalidArgument(
+                            "Invalid CLUSTER SETSLOT action or number of arguments".to_string(),
+                        ))
+                    }
+                };
+                Cluster::SetSlot(slot, action)
+            }
+            "getkeysinslot" => match (args.next(), args.next()) {
+                (Some(slot), Some(count)) => {
+                    let slot = parse_slot(slot)?;
+                    let count = parse_integer::<usize>(count).map_err(|_| {
+                        CommandError::InvalidArgument("Invalid number of keys".to_string())
+                    })?;
+                    Cluster::GetKeysInSlot(slot, count)
+                }
+                _ => {
+                    return Err(CommandError::InvalidArgument(
+                        "CLUSTER GETKEYSINSLOT slot count".to_string(),
+                    ))
+                }
+            },
+            _ => {
+                return Err(CommandError::InvalidCommand(format!(
+                    "unknown subcommand '{}'",
+                    subcommand
+                )))
+            }
+        };
+        if args.next().is_some() {
+            return Err(CommandError::InvalidArgument(format!(
+                "wrong number of arguments for CLUSTER {}",
+                subcommand.to_uppercase()
+            )));
+        }
+        Ok(cmd)
+    }
+}
+
+impl TryFrom<RespArray> for Asking {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        super::validate_command(&value, &["asking"], 0)?;
+        Ok(Asking)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resp::RespDecode;
+    use anyhow::Result;
+    use bytes::BytesMut;
+
+    fn cluster_cmd(args: &[&str]) -> Result<Cluster> {
+        let frames = args
+            .iter()
+            .map(|a| BulkString::new(*a).into())
+            .collect::<Vec<RespFrame>>();
+        Ok(Cluster::try_from(RespArray::new(frames))?)
+    }
+
+    #[test]
+    fn test_cluster_try_from_resp_array() -> Result<()> {
+        let mut buf = BytesMut::from("*3\r\n$7\r\ncluster\r\n$7\r\nkeyslot\r\n$3\r\nfoo\r\n");
+        let cmd = Cluster::try_from(RespArray::decode(&mut buf)?)?;
+        assert!(matches!(cmd, Cluster::KeySlot(k) if k == "foo"));
+
+        let cmd = cluster_cmd(&["cluster", "addslotsrange", "0", "2", "10", "10"])?;
+        assert!(matches!(cmd, Cluster::AddSlots(s) if s == vec![0, 1, 2, 10]));
+        assert!(cluster_cmd(&["cluster", "addslots", "16384"]).is_err());
+        assert!(cluster_cmd(&["cluster", "addslotsrange", "5", "1"]).is_err());
+        assert!(cluster_cmd(&["cluster", "foo"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cluster_execute() -> Result<()> {
+        let backend = Backend::new();
+        assert_eq!(
+            cluster_cmd(&["cluster", "myid"])?.execute(backend),
+            cluster_disabled()
+        );
+
+        let backend = Backend::new_cluster("127.0.0.1", 7000);
+        backend.set(b"foo", "bar".into());
+        let resp =
+            cluster_cmd(&["cluster", "addslotsrange", "0", "16383"])?.execute(backend.clone());
+        assert_eq!(resp, RESP_OK.clone());
+
+        let resp = cluster_cmd(&["cluster", "keyslot", "foo"])?.execute(backend.clone());
+        assert_eq!(resp, RespFrame::Integer(12182));
+        let resp = cluster_cmd(&["cluster", "countkeysinslot", "12182"])?.execute(backend.clone());
+        assert_eq!(resp, RespFrame::Integer(1));
+
+        let resp =
+            cluster_cmd(&["cluster", "getkeysinslot", "12182", "10"])?.execute(backend.clone());
+        assert_eq!(
+            resp,
+            RespArray::new(vec![BulkString::new("foo").into()]).into()
+        );
+
+        let resp = cluster_cmd(&["cluster", "slots"])?.execute(backend.clone());
+        let node = ClusterNode {
+            id: backend.cluster().myself().to_string(),
+            ip: "127.0.0.1".to_string(),
+            port: 7000,
+        };
+        assert_eq!(
+            resp,
+            RespArray::new(vec![RespArray::new(vec![
+                RespFrame::Integer(0),
+                RespFrame::Integer(16383),
+                node_frame(&node),
+            ])
+            .into()])
+            .into()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cluster_setslot() -> Result<()> {
+        let backend = Backend::new_cluster("127.0.0.1", 7000);
+        backend.cluster().add_node(ClusterNode {
+            id: "other".to_string(),
+            ip: "127.0.0.1".to_string(),
+            port: 7001,
+        });
+        cluster_cmd(&["cluster", "addslots", "12182"])?.execute(backend.clone());
+        backend.set(b"foo", "bar".into());
+
+        let resp = cluster_cmd(&["cluster", "setslot", "12182", "migrating", "other"])?
+            .execute(backend.clone());
+        assert_eq!(resp, RESP_OK.clone());
+        assert_eq!(
+            backend.cluster().migrating(12182),
+            Some("other".to_string())
+        );
+
+        let resp = cluster_cmd(&["cluster", "setslot", "12182", "node", "other"])?
+            .execute(backend.clone());
+        assert!(matches!(resp, RespFrame::SimpleError(_)));
+
+        backend.del(b"foo");
+        let resp = cluster_cmd(&["cluster", "setslot", "12182", "node", "other"])?
+            .execute(backend.clone());
+        assert_eq!(resp, RESP_OK.clone());
+        assert_eq!(backend.cluster().migrating(12182), None);
+        assert_eq!(
+            backend.cluster().slot_owner(12182).map(|n| n.port),
+            Some(7001)
+        );
+
+        assert!(cluster_cmd(&["cluster", "setslot", "12182", "stable", "x"]).is_err());
+        Ok(())
+    }
+}
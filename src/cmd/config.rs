@@ -0,0 +1,222 @@
+//! support CONFIG GET, SET, RESETSTAT and REWRITE command
+//!
+//! CONFIG GET parameter [parameter ...]：参数名支持glob, 返回 名字 值 的平铺数组
+//! CONFIG SET parameter value [parameter value ...]：所有参数都合法时才会修改
+//! CONFIG RESETSTAT：重置统计信息
+//! CONFIG REWRITE：把当前的配置写回启动时使用的配置文件
+
+use super::{extract_args, parse_string, CommandError, CommandExecuter, REDACTED, RESP_OK};
+use crate::{
+    backend::Backend,
+    resp::{frame::RespFrame, BulkString, RespArray, SimpleError},
+};
+use std::fmt;
+
+/// 值是密码的参数, CONFIG SET 写进日志时隐藏它们的值
+const SENSITIVE_PARAMS: &[&str] = &["requirepass", "masterauth"];
+
+pub enum Config {
+    Get(Vec<String>),
+    Set(Vec<(String, String)>),
+    ResetStat,
+    Rewrite,
+    Help,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Config::Get(patterns) => f.debug_tuple("Get").field(patterns).finish(),
+            Config::Set(pairs) => {
+                let pairs = pairs
+                    .iter()
+                    .map(|(name, value)| {
+                        let sensitive = SENSITIVE_PARAMS
+                            .iter()
+                            .any(|param| name.eq_ignore_ascii_case(param));
+                        (name, if sensitive { REDACTED } else { value.as_str() })
+                    })
+                    .collect::<Vec<_>>();
+                f.debug_tuple("Set").field(&pairs).finish()
+            }
+            Config::ResetStat => f.write_str("ResetStat"),
+            Config::Rewrite => f.write_str("Rewrite"),
+            Config::Help => f.write_str("Help"),
+        }
+    }
+}
+
+const CONFIG_HELP: &[&str] = &[
+    "CONFIG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "GET <pattern>",
+    "    Return parameters matching the glob-like <pattern> and their values.",
+    "SET <directive> <value>",
+    "    Set the configuration <directive> to <value>.",
+    "RESETSTAT",
+    "    Reset statistics reported by the INFO command.",
+    "REWRITE",
+    "    Rewrite the configuration file.",
+];
+
+impl Config {
+    pub(super) fn subcommand(&self) -> &'static str {
+        match self {
+            Config::Get(_) => "get",
+            Config::Set(_) => "set",
+            Config::ResetStat => "resetstat",
+            Config::Rewrite => "rewrite",
+            Config::Help => "help",
+        }
+    }
+}
+
+impl CommandExecuter for Config {
+    fn execute(self, backend: Backend) -> RespFrame {
+        match self {
+            Config::Get(patterns) => {
+                let reply = backend
+                    .config()
+                    .matching(&patterns)
+                    .into_iter()
+                    .flat_map(|(name, value)| {
+                        [BulkString::new(name).into(), BulkString::new(value).into()]
+                    })
+                    .collect::<Vec<RespFrame>>();
+                RespArray::new(reply).into()
+            }
+            Config::Set(pairs) => match backend.config().set(&pairs) {
+                Ok(()) => {
+                    backend.apply_config();
+                    RESP_OK.clone()
+                }
+                Err(e) => SimpleError::new(format!("ERR {}", e)).into(),
+            },
+            Config::ResetStat => {
+                backend.maxmemory().reset_stats();
+                RESP_OK.clone()
+            }
+            Config::Rewrite => match backend.config().rewrite() {
+                Ok(()) => RESP_OK.clone(),
+                Err(e) => SimpleError::new(format!("ERR {}", e)).into(),
+            },
+            Config::Help => RespArray::new(
+                CONFIG_HELP
+                    .iter()
+                    .map(|l| BulkString::new(*l).into())
+                    .collect::<Vec<RespFrame>>(),
+            )
+            .into(),
+        }
+    }
+}
+
+fn syntax_error(subcommand: &str) -> CommandError {
+    CommandError::InvalidArgument(format!(
+        "unknown subcommand or wrong number of arguments for '{}'. Try CONFIG HELP.",
+        subcommand
+    ))
+}
+
+impl TryFrom<RespArray> for Config {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let args = extract_args(value, 1)?
+            .into_iter()
+            .map(parse_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        let Some((subcommand, args)) = args.split_first() else {
+            return Err(syntax_error(""));
+        };
+        match (subcommand.to_ascii_lowercase().as_str(), args.len()) {
+            ("get", n) if n > 0 => Ok(Config::Get(args.to_vec())),
+            ("set", n) if n > 0 && n % 2 == 0 => Ok(Config::Set(
+                args.chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect(),
+            )),
+            ("resetstat", 0) => Ok(Config::ResetStat),
+            ("rewrite", 0) => Ok(Config::Rewrite),
+            ("help", 0) => Ok(Config::Help),
+            _ => Err(syntax_error(subcommand)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    fn array(args: &[&str]) -> RespArray {
+        RespArray::new(
+            args.iter()
+                .map(|a| BulkString::new(*a).into())
+                .collect::<Vec<RespFrame>>(),
+        )
+    }
+
+    fn bulk_array(items: &[&str]) -> RespFrame {
+        array(items).into()
+    }
+
+    #[test]
+    fn test_debug_redacts_password() -> Result<()> {
+        let cmd = Config::try_from(array(&[
+            "config",
+            "set",
+            "REQUIREPASS",
+            "secret",
+            "maxmemory",
+            "100mb",
+        ]))?;
+        let debug = format!("{:?}", cmd);
+        assert!(debug.contains(REDACTED) && debug.contains("100mb"));
+        assert!(!debug.contains("secret"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_get_set() -> Result<()> {
+        let backend = Backend::new();
+        let cmd = Config::try_from(array(&[
+            "config",
+            "set",
+            "maxmemory",
+            "1mb",
+            "maxmemory-policy",
+            "allkeys-lru",
+        ]))?;
+        assert_eq!(cmd.execute(backend.clone()), RESP_OK.clone());
+        assert_eq!(backend.maxmemory().maxmemory(), 1024 * 1024);
+
+        let cmd = Config::try_from(array(&["config", "get", "maxmemory", "maxmemory-p*"]))?;
+        assert_eq!(
+            cmd.execute(backend.clone()),
+            bulk_array(&["maxmemory", "1048576", "maxmemory-policy", "allkeys-lru"])
+        );
+
+        let cmd = Config::try_from(array(&["config", "set", "hash-max-listpack-entries", "1"]))?;
+        assert_eq!(cmd.execute(backend.clone()), RESP_OK.clone());
+        assert_eq!(backend.encoding_config().hash_max_listpack_entries(), 1);
+
+        let cmd = Config::try_from(array(&["config", "set", "port", "1"]))?;
+        assert_eq!(
+            cmd.execute(backend.clone()),
+            SimpleError::new(
+                "ERR CONFIG SET failed (possibly related to argument 'port') - can't set immutable config"
+            )
+            .into()
+        );
+        let cmd = Config::try_from(array(&["config", "rewrite"]))?;
+        assert_eq!(
+            cmd.execute(backend),
+            SimpleError::new("ERR The server is running without a config file").into()
+        );
+
+        assert!(Config::try_from(array(&["config", "set", "maxmemory"])).is_err());
+        assert!(Config::try_from(array(&["config", "get"])).is_err());
+        assert!(Config::try_from(array(&["config", "resetstat", "x"])).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+//! support SELECT, SWAPDB, MOVE, DBSIZE, FLUSHDB and FLUSHALL command
+//!
+//! SELECT index：切换当前连接使用的db, 只影响这个连接
+//! SWAPDB index1 index2：交换两个db的数据, 所有连接立刻可见
+//! MOVE key db：把key(连同过期时间)移动到另一个db, 目标db已经存在key时不移动
+//! FLUSHDB [ASYNC|SYNC] / FLUSHALL [ASYNC|SYNC]：ASYNC在后台线程释放旧的数据
+
+use super::{
+    extract_args, parse_bytes, parse_integer, parse_string, validate_command, CommandError,
+    CommandExecuter, RESP_OK,
+};
+use crate::{
+    backend::Backend,
+    resp::{frame::RespFrame, RespArray, SimpleError},
+};
+use bytes::Bytes;
+
+#[derive(Debug)]
+pub struct Select {
+    index: i64,
+}
+
+#[derive(Debug)]
+pub struct SwapDb {
+    a: i64,
+    b: i64,
+}
+
+#[derive(Debug)]
+pub struct Move {
+    pub(super) key: Bytes,
+    db: i64,
+}
+
+#[derive(Debug)]
+pub struct DbSize;
+
+#[derive(Debug)]
+pub struct FlushDb {
+    lazy: bool,
+}
+
+#[derive(Debug)]
+pub struct FlushAll {
+    lazy: bool,
+}
+
+fn out_of_range() -> RespFrame {
+    SimpleError::new("ERR DB index is out of range").into()
+}
+
+/// index合法时返回usize
+fn check_index(backend: &Backend, index: i64) -> Option<usize> {
+    usize::try_from(index)
+        .ok()
+        .filter(|i| *i < backend.databases())
+}
+
+impl Select {
+    /// 在连接中执行, 成功时修改连接当前的db
+    pub fn apply(self, backend: &Backend, db: &mut usize) -> RespFrame {
+        if backend.cluster().is_enabled() && self.index != 0 {
+            return SimpleError::new("ERR SELECT is not allowed in cluster mode").into();
+        }
+        match check_index(backend, self.index) {
+            Some(index) => {
+                *db = index;
+                RESP_OK.clone()
+            }
+            None => out_of_range(),
+        }
+    }
+}
+
+/// 不在连接中执行时没有可以切换的状态, 只检查index
+impl CommandExecuter for Select {
+    fn execute(self, backend: Backend) -> RespFrame {
+        let mut db = backend.index();
+        self.apply(&backend, &mut db)
+    }
+}
+
+impl CommandExecuter for SwapDb {
+    fn execute(self, backend: Backend) -> RespFrame {
+        if backend.cluster().is_enabled() {
+            return SimpleError::new("ERR SWAPDB is not allowed in cluster mode").into();
+        }
+        match (check_index(&backend, self.a), check_index(&backend, self.b)) {
+            (Some(a), Some(b)) => {
+                backend.swap_db(a, b);
+                RESP_OK.clone()
+            }
+            _ => SimpleError::new("ERR invalid DB index").into(),
+        }
+    }
+}
+
+impl CommandExecuter for Move {
+    fn execute(self, backend: Backend) -> RespFrame {
+        if backend.cluster().is_enabled() {
+            return SimpleError::new("ERR MOVE is not allowed in cluster mode").into();
+        }
+        let db = match check_index(&backend, self.db) {
+            Some(db) => db,
+            None => return out_of_range(),
+        };
+        if db == backend.index() {
+            return SimpleError::new("ERR source and destination objects are the same").into();
+        }
+        RespFrame::Integer(backend.move_key(&self.key, db) as i64)
+    }
+}
+
+impl CommandExecuter for DbSize {
+    fn execute(self, backend: Backend) -> RespFrame {
+        RespFrame::Integer(backend.db().len() as i64)
+    }
+}
+
+impl CommandExecuter for FlushDb {
+    fn execute(self, backend: Backend) -> RespFrame {
+        backend.flush_db(self.lazy);
+        RESP_OK.clone()
+    }
+}
+
+impl CommandExecuter for FlushAll {
+    fn execute(self, backend: Backend) -> RespFrame {
+        backend.flush_all(self.lazy);
+        RESP_OK.clone()
+    }
+}
+
+impl TryFrom<RespArray> for Select {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        validate_command(&value, &["select"], 1)?;
+        let mut args = extract_args(value, 1)?.into_iter();
+        match args.next() {
+            Some(index) => Ok(Select {
+                index: parse_integer(index)?,
+            }),
+            None => Err(CommandError::InvalidArgument("SELECT index".to_string())),
+        }
+    }
+}
+
+impl TryFrom<RespArray> for SwapDb {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        validate_command(&value, &["swapdb"], 2)?;
+        let mut args = extract_args(value, 1)?.into_iter();
+        match (args.next(), args.next()) {
+            (Some(a), Some(b)) => Ok(SwapDb {
+                a: parse_integer(a)?,
+                b: parse_integer(b)?,
+            }),
+            _ => Err(CommandError::InvalidArgument(
+                "SWAPDB index1 index2".to_string(),
+            )),
+        }
+    }
+}
+
+impl TryFrom<RespArray> for Move {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        validate_command(&value, &["move"], 2)?;
+        let mut args = extract_args(value, 1)?.into_iter();
+        match (args.next(), args.next()) {
+            (Some(key), Some(db)) => Ok(Move {
+                key: parse_bytes(key)?,
+                db: parse_integer(db)?,
+            }),
+            _ => Err(CommandError::InvalidArgument("MOVE key db".to_string())),
+        }
+    }
+}
+
+impl TryFrom<RespArray> for DbSize {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        validate_command(&value, &["dbsize"], 0)?;
+        Ok(DbSize)
+    }
+}
+
+/// FLUSHDB 和 FLUSHALL 的 [ASYNC|SYNC] 参数
+fn parse_flush_mode(value: RespArray) -> Result<bool, CommandError> {
+    let args = extract_args(value, 1)?;
+    if args.len() > 1 {
+        return Err(CommandError::InvalidArgument("syntax error".to_string()));
+    }
+    match args.into_iter().next() {
+        Some(arg) => match parse_string(arg)?.to_ascii_lowercase().as_str() {
+            "async" => Ok(true),
+            "sync" => Ok(false),
+            _ => Err(CommandError::InvalidArgument("syntax error".to_string())),
+        },
+        None => Ok(false),
+    }
+}
+
+impl TryFrom<RespArray> for FlushDb {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        Ok(FlushDb {
+            lazy: parse_flush_mode(value)?,
+        })
+    }
+}
+
+impl TryFrom<RespArray> for FlushAll {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        Ok(FlushAll {
+            lazy: parse_flush_mode(value)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resp::BulkString;
+    use anyhow::Result;
+
+    fn array(args: &[&str]) -> RespArray {
+        RespArray::new(
+            args.iter()
+                .map(|a| BulkString::new(*a).into())
+                .collect::<Vec<RespFrame>>(),
+        )
+    }
+
+    #[test]
+    fn test_select() -> Result<()> {
+        let backend = Backend::new();
+        let mut db = 0;
+        let select = Select::try_from(array(&["select", "3"]))?;
+        assert_eq!(select.apply(&backend, &mut db), RESP_OK.clone());
+        assert_eq!(db, 3);
+
+        for index in ["16", "-1"] {
+            let select = Select::try_from(array(&["select", index]))?;
+            assert_eq!(select.apply(&backend, &mut db), out_of_range());
+            assert_eq!(db, 3);
+        }
+        assert!(Select::try_from(array(&["select", "a"])).is_err());
+
+        let backend = Backend::new_cluster("127.0.0.1", 7000);
+        let select = Select::try_from(array(&["select", "1"]))?;
+        assert_eq!(
+            select.apply(&backend, &mut db),
+            SimpleError::new("ERR SELECT is not allowed in cluster mode").into()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_dbsize_flush() -> Result<()> {
+        let backend = Backend::new();
+        backend.set(b"key", "value".into());
+        let db1 = backend.select(1).unwrap();
+
+        let cmd = Move::try_from(array(&["move", "key", "0"]))?;
+        assert_eq!(
+            cmd.execute(backend.clone()),
+            SimpleError::new("ERR source and destination objects are the same").into()
+        );
+        let cmd = Move::try_from(array(&["move", "key", "1"]))?;
+        assert_eq!(cmd.execute(backend.clone()), RespFrame::Integer(1));
+
+        let dbsize = || DbSize::try_from(array(&["dbsize"]));
+        assert_eq!(dbsize()?.execute(backend.clone()), RespFrame::Integer(0));
+        assert_eq!(dbsize()?.execute(db1.clone()), RespFrame::Integer(1));
+
+        let cmd = SwapDb::try_from(array(&["swapdb", "0", "1"]))?;
+        assert_eq!(cmd.execute(backend.clone()), RESP_OK.clone());
+        assert_eq!(dbsize()?.execute(backend.clone()), RespFrame::Integer(1));
+
+        let cmd = SwapDb::try_from(array(&["swapdb", "0", "16"]))?;
+        assert_eq!(
+            cmd.execute(backend.clone()),
+            SimpleError::new("ERR invalid DB index").into()
+        );
+
+        db1.set(b"other", "value".into());
+        let cmd = FlushDb::try_from(array(&["flushdb", "ASYNC"]))?;
+        assert_eq!(cmd.execute(backend.clone()), RESP_OK.clone());
+        assert_eq!(dbsize()?.execute(backend.clone()), RespFrame::Integer(0));
+        assert_eq!(dbsize()?.execute(db1.clone()), RespFrame::Integer(1));
+
+        let cmd = FlushAll::try_from(array(&["flushall"]))?;
+        assert_eq!(cmd.execute(backend.clone()), RESP_OK.clone());
+        assert_eq!(dbsize()?.execute(db1), RespFrame::Integer(0));
+
+        assert!(FlushAll::try_from(array(&["flushall", "LAZY"])).is_err());
+        Ok(())
+    }
+}
@@ -1,20 +1,22 @@
 use crate::{
     backend::Backend,
-    resp::{frame::RespFrame, RespArray, RespError, SimpleString},
+    resp::{frame::RespFrame, BulkString, RespArray, RespError},
 };
+use bytes::Bytes;
 
 use super::CommandExecuter;
 
+/// 参数可以是任意字节, 原样用bulk string返回
 #[derive(Debug)]
-pub struct Echo(pub String);
+pub struct Echo(pub Bytes);
 
 impl CommandExecuter for Echo {
     fn execute(self, _backend: Backend) -> RespFrame {
-        SimpleString::new(self.0).into()
+        BulkString::from(self.0).into()
     }
 }
 impl Echo {
-    fn new(data: impl Into<String>) -> Self {
+    fn new(data: impl Into<Bytes>) -> Self {
         Echo(data.into())
     }
 }
@@ -29,7 +31,7 @@ impl TryFrom<RespArray> for Echo {
                 Some(v) => match v.into_iter().nth(1) {
                     Some(v) => match v {
                         RespFrame::BulkString(s) => match s.0 {
-                            Some(v) => Ok(Echo::new(String::from_utf8(v)?)),
+                            Some(v) => Ok(Echo::new(v)),
                             None => Err(RespError::InvalidFrame(
                                 "Expect a BulkString, but got a Null BulkString".into(),
                             )),
@@ -59,7 +61,19 @@ mod test {
         let echo = Echo::new("hello");
         let resp = echo.execute(Backend::default());
         let encoded = resp.encode();
-        assert_eq!(encoded, b"+hello\r\n");
+        assert_eq!(encoded, b"$5\r\nhello\r\n");
+    }
+
+    #[test]
+    fn test_echo_binary() -> Result<()> {
+        let mut buf = BytesMut::from(&b"*2\r\n$4\r\necho\r\n$6\r\na\r\n\xff\x00b\r\n"[..]);
+        let echo = Echo::try_from(RespArray::decode(&mut buf)?)?;
+        // CRLF和非UTF-8的字节不会破坏回复
+        assert_eq!(
+            echo.execute(Backend::default()).encode(),
+            b"$6\r\na\r\n\xff\x00b\r\n"
+        );
+        Ok(())
     }
 
     #[test]
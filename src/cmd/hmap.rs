@@ -4,22 +4,25 @@ use crate::{
 };
 
 use super::{
-    extract_args, validate_command, CommandError, CommandExecuter, HGet, HGetAll, HSet, RESP_OK,
+    extract_args, parse_bytes, validate_command, CommandError, CommandExecuter, HGet, HGetAll,
+    HSet, RESP_OK,
 };
 
 impl CommandExecuter for HGet {
     fn execute(self, backend: Backend) -> RespFrame {
-        backend
-            .hget(&self.key, &self.field)
-            .unwrap_or(RespFrame::BulkString(BulkString::new_null_string()))
+        match backend.hget(&self.key, &self.field) {
+            Some(value) => BulkString::from(value).into(),
+            None => BulkString::new_null_string().into(),
+        }
     }
 }
 
 impl CommandExecuter for HSet {
     fn execute(self, backend: Backend) -> RespFrame {
-        backend
-            .hset(&self.key, &self.field, self.value)
-            .unwrap_or(RESP_OK.clone())
+        match backend.hset(&self.key, &self.field, self.value) {
+            Some(old) => BulkString::from(old).into(),
+            None => RESP_OK.clone(),
+        }
     }
 }
 impl CommandExecuter for HGetAll {
@@ -27,18 +30,12 @@ impl CommandExecuter for HGetAll {
         let map = backend.hget_all(&self.key);
 
         match map {
-            Some(hmap) => {
-                let mut data = Vec::with_capacity(hmap.len());
-                for v in hmap.iter() {
-                    let key = v.key().to_owned();
-                    data.push((key, v.value().clone()));
-                }
-
+            Some(mut data) => {
                 data.sort_by(|a, b| a.0.cmp(&b.0));
 
                 let ret = data
                     .into_iter()
-                    .flat_map(|(k, v)| vec![BulkString::new(k).into(), v])
+                    .flat_map(|(k, v)| [BulkString::from(k).into(), BulkString::from(v).into()])
                     .collect::<Vec<RespFrame>>();
 
                 RespArray::new(ret).into()
@@ -55,9 +52,9 @@ impl TryFrom<RespArray> for HGet {
         validate_command(&value, &["hget"], 2)?;
         let mut args = extract_args(value, 1)?.into_iter();
         match (args.next(), args.next()) {
-            (Some(RespFrame::BulkString(key)), Some(RespFrame::BulkString(field))) => Ok(HGet {
-                key: String::from_utf8(key.0.unwrap())?,
-                field: String::from_utf8(field.0.unwrap())?,
+            (Some(key), Some(field)) => Ok(HGet {
+                key: parse_bytes(key)?,
+                field: parse_bytes(field)?,
             }),
             _ => Err(CommandError::InvalidArgument(
                 "HGet Command, key and field must be bulk string".to_string(),
@@ -73,8 +70,8 @@ impl TryFrom<RespArray> for HGetAll {
         validate_command(&value, &["hgetall"], 1)?;
         let mut args = extract_args(value, 1)?.into_iter();
         match args.next() {
-            Some(RespFrame::BulkString(key)) => Ok(HGetAll {
-                key: String::from_utf8(key.0.unwrap())?,
+            Some(key) => Ok(HGetAll {
+                key: parse_bytes(key)?,
             }),
             _ => Err(CommandError::InvalidArgument(
                 "HGetAll Command, key must be bulk string".to_string(),
@@ -90,13 +87,11 @@ impl TryFrom<RespArray> for HSet {
         validate_command(&value, &["hset"], 3)?;
         let mut args = extract_args(value, 1)?.into_iter();
         match (args.next(), args.next(), args.next()) {
-            (Some(RespFrame::BulkString(key)), Some(RespFrame::BulkString(field)), Some(value)) => {
-                Ok(HSet {
-                    key: String::from_utf8(key.0.unwrap())?,
-                    field: String::from_utf8(field.0.unwrap())?,
-                    value,
-                })
-            }
+            (Some(key), Some(field), Some(value)) => Ok(HSet {
+                key: parse_bytes(key)?,
+                field: parse_bytes(field)?,
+                value: parse_bytes(value)?,
+            }),
             _ => Err(CommandError::InvalidArgument(
                 "HSet Command, key, field and value must be bulk string".to_string(),
             )),
@@ -112,7 +107,7 @@ mod test {
         resp::{array::RespArray, bulk_string::BulkString, frame::RespFrame, RespDecode},
     };
     use anyhow::Result;
-    use bytes::BytesMut;
+    use bytes::{Bytes, BytesMut};
 
     #[test]
     fn test_hget_try_from_resp_array() -> Result<()> {
@@ -140,10 +135,11 @@ mod test {
         let hset_cmd = HSet::try_from(frame)?;
         assert_eq!(hset_cmd.key, "key");
         assert_eq!(hset_cmd.field, "field");
-        assert_eq!(
-            hset_cmd.value,
-            RespFrame::BulkString(BulkString::new("value"))
-        );
+        assert_eq!(hset_cmd.value, "value");
+
+        let mut buf = BytesMut::from("*4\r\n$4\r\nhset\r\n$3\r\nkey\r\n$5\r\nfield\r\n:1\r\n");
+        let frame = RespArray::decode(&mut buf)?;
+        assert!(HSet::try_from(frame).is_err());
         Ok(())
     }
 
@@ -184,4 +180,28 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_binary_key_and_field() -> Result<()> {
+        let backend = Backend::new();
+        let mut buf = BytesMut::from(
+            &b"*4\r\n$4\r\nhset\r\n$2\r\n\xff\x00\r\n$2\r\n\xc3\x28\r\n$1\r\nv\r\n"[..],
+        );
+        let hset = HSet::try_from(RespArray::decode(&mut buf)?)?;
+        assert_eq!(hset.execute(backend.clone()), RESP_OK.clone());
+        assert_eq!(backend.hget(b"\xff\x00", b"\xc3\x28"), Some("v".into()));
+
+        let hgetall = HGetAll {
+            key: Bytes::from_static(b"\xff\x00"),
+        };
+        assert_eq!(
+            hgetall.execute(backend),
+            RespArray::new(vec![
+                BulkString::new(b"\xc3\x28".to_vec()).into(),
+                BulkString::new("v").into(),
+            ])
+            .into()
+        );
+        Ok(())
+    }
 }
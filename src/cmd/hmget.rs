@@ -2,12 +2,13 @@ use crate::{
     backend::Backend,
     resp::{frame::RespFrame, RespArray, RespError},
 };
+use bytes::Bytes;
 
 use super::{CommandExecuter, HGet};
 
 #[derive(Debug)]
 pub struct HmGet {
-    key: String,
+    pub(super) key: Bytes,
     fields: Vec<HGet>,
 }
 
@@ -37,9 +38,9 @@ impl TryFrom<RespArray> for HmGet {
 
         let key = match args.next() {
             Some(v) => match v {
-                RespFrame::BulkString(k) => String::from_utf8(
-                    k.0.ok_or(RespError::InvalidFrame("hmset key cannot be Null".into()))?,
-                )?,
+                RespFrame::BulkString(k) => {
+                    k.0.ok_or(RespError::InvalidFrame("hmset key cannot be Null".into()))?
+                }
                 _ => {
                     return Err(RespError::InvalidFrame(
                         "hmset key must be a bulk string".to_string(),
@@ -56,9 +57,8 @@ impl TryFrom<RespArray> for HmGet {
         for frame in args {
             match frame {
                 RespFrame::BulkString(f) => {
-                    let field = String::from_utf8(
-                        f.0.ok_or(RespError::InvalidFrame("hmset field cannot be Null".into()))?,
-                    )?;
+                    let field =
+                        f.0.ok_or(RespError::InvalidFrame("hmset field cannot be Null".into()))?;
                     fields.push(HGet {
                         key: key.clone(),
                         field,
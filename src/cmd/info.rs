@@ -0,0 +1,118 @@
+//! support INFO command
+//!
+//! INFO [section ...]：只有 clients, memory 和 stats 三个部分, 没有参数或者 all/default/everything 时全部返回
+
+use super::{extract_args, parse_string, CommandError, CommandExecuter};
+use crate::{
+    backend::Backend,
+    resp::{frame::RespFrame, BulkString, RespArray},
+};
+
+const SECTIONS: &[&str] = &["clients", "memory", "stats"];
+
+#[derive(Debug)]
+pub struct Info {
+    /// 小写的section名字
+    sections: Vec<String>,
+}
+
+fn section(backend: &Backend, name: &str) -> Vec<(&'static str, String)> {
+    let clients = backend.clients();
+    let maxmemory = backend.maxmemory();
+    match name {
+        "clients" => vec![
+            ("connected_clients", clients.len().to_string()),
+            (
+                "maxclients",
+                backend.config().get("maxclients").unwrap_or_default(),
+            ),
+        ],
+        "memory" => vec![
+            ("used_memory", backend.used_memory().to_string()),
+            ("maxmemory", maxmemory.maxmemory().to_string()),
+            ("maxmemory_policy", maxmemory.policy().to_string()),
+        ],
+        "stats" => vec![
+            ("evicted_keys", maxmemory.evicted_keys().to_string()),
+            (
+                "client_output_buffer_limit_disconnections",
+                clients.output_limit_disconnections().to_string(),
+            ),
+        ],
+        _ => vec![],
+    }
+}
+
+impl CommandExecuter for Info {
+    fn execute(self, backend: Backend) -> RespFrame {
+        let all = self.sections.is_empty()
+            || self
+                .sections
+                .iter()
+                .any(|s| matches!(s.as_str(), "all" | "default" | "everything"));
+        let info: Vec<String> = SECTIONS
+            .iter()
+            .filter(|name| all || self.sections.iter().any(|s| s == *name))
+            .map(|name| {
+                let mut title = name.to_string();
+                title[..1].make_ascii_uppercase();
+                let fields: String = section(&backend, name)
+                    .into_iter()
+                    .map(|(k, v)| format!("{}:{}\r\n", k, v))
+                    .collect();
+                format!("# {}\r\n{}", title, fields)
+            })
+            .collect();
+        BulkString::new(info.join("\r\n")).into()
+    }
+}
+
+impl TryFrom<RespArray> for Info {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let sections = extract_args(value, 1)?
+            .into_iter()
+            .map(|frame| parse_string(frame).map(|s| s.to_ascii_lowercase()))
+            .collect::<Result<_, _>>()?;
+        Ok(Info { sections })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    fn info(backend: &Backend, args: &[&str]) -> Result<String> {
+        let mut full = vec!["info"];
+        full.extend_from_slice(args);
+        let frame = Info::try_from(RespArray::new(
+            full.iter()
+                .map(|a| BulkString::new(*a).into())
+                .collect::<Vec<RespFrame>>(),
+        ))?
+        .execute(backend.clone());
+        let RespFrame::BulkString(BulkString(Some(info))) = frame else {
+            panic!("expected bulk string");
+        };
+        Ok(String::from_utf8(info.into())?)
+    }
+
+    #[test]
+    fn test_info() -> Result<()> {
+        let backend = Backend::new();
+        let all = info(&backend, &[])?;
+        assert!(all.starts_with("# Clients\r\nconnected_clients:0\r\nmaxclients:10000\r\n"));
+        assert!(all.contains("\r\n# Stats\r\nevicted_keys:0\r\n"));
+
+        backend.clients().record_output_limit_disconnection();
+        let stats = info(&backend, &["STATS"])?;
+        assert_eq!(
+            stats,
+            "# Stats\r\nevicted_keys:0\r\nclient_output_buffer_limit_disconnections:1\r\n"
+        );
+        assert_eq!(info(&backend, &["nope"])?, "");
+        Ok(())
+    }
+}
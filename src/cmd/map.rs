@@ -1,22 +1,26 @@
 use crate::{
     backend::Backend,
     cmd::{CommandError, Get},
-    resp::{array::RespArray, frame::RespFrame, null::RespNull},
+    resp::{array::RespArray, frame::RespFrame, BulkString},
 };
 
-use super::{extract_args, validate_command, CommandExecuter, Set, RESP_OK};
+use super::{extract_args, parse_bytes, validate_command, CommandExecuter, Set, RESP_OK};
 
 impl CommandExecuter for Get {
     fn execute(self, backend: Backend) -> RespFrame {
-        backend.get(&self.key).unwrap_or(RespFrame::Null(RespNull))
+        match backend.get(&self.key) {
+            Some(value) => BulkString::from(value).into(),
+            None => BulkString::new_null_string().into(),
+        }
     }
 }
 
 impl CommandExecuter for Set {
     fn execute(self, backend: Backend) -> RespFrame {
-        backend
-            .set(&self.key, self.value)
-            .unwrap_or(RESP_OK.clone())
+        match backend.set(&self.key, self.value) {
+            Some(old) => BulkString::from(old).into(),
+            None => RESP_OK.clone(),
+        }
     }
 }
 
@@ -26,8 +30,8 @@ impl TryFrom<RespArray> for Get {
         validate_command(&value, &["get"], 1)?;
         let mut args = extract_args(value, 1)?.into_iter();
         let key = match args.next() {
-            Some(RespFrame::BulkString(key)) => String::from_utf8(key.0.unwrap())?,
-            _ => {
+            Some(key) => parse_bytes(key)?,
+            None => {
                 return Err(CommandError::InvalidArgument(
                     "key must be a bulk string".to_string(),
                 ))
@@ -46,9 +50,7 @@ impl TryFrom<RespArray> for Set {
         validate_command(&value, &["set"], 2)?;
         let mut args = extract_args(value, 1)?.into_iter();
         let (key, val) = match (args.next(), args.next()) {
-            (Some(RespFrame::BulkString(key)), Some(value)) => {
-                (String::from_utf8(key.0.unwrap())?, value)
-            }
+            (Some(key), Some(value)) => (parse_bytes(key)?, parse_bytes(value)?),
             _ => {
                 return Err(CommandError::InvalidArgument(
                     "key must be a bulk string".to_string(),
@@ -62,8 +64,9 @@ impl TryFrom<RespArray> for Set {
 #[cfg(test)]
 mod test {
     use crate::{
-        cmd::{Get, Set},
-        resp::{array::RespArray, bulk_string::BulkString, frame::RespFrame, RespDecode},
+        backend::Backend,
+        cmd::{CommandExecuter, Get, Set, RESP_OK},
+        resp::{array::RespArray, bulk_string::BulkString, RespDecode},
     };
     use bytes::BytesMut;
     use std::vec;
@@ -71,8 +74,8 @@ mod test {
     #[test]
     fn test_get_try_from_resp_array() {
         let frame = RespArray::new(vec![
-            BulkString(Some(b"get".to_vec())).into(),
-            BulkString(Some(b"key".to_vec())).into(),
+            BulkString::from(b"get").into(),
+            BulkString::from(b"key").into(),
         ]);
         let get_cmd = Get::try_from(frame).unwrap();
         assert_eq!(get_cmd.key, "key");
@@ -84,10 +87,32 @@ mod test {
         let frame = RespArray::decode(&mut buf).unwrap();
         let set_cmd = Set::try_from(frame)?;
         assert_eq!(set_cmd.key, "key");
+        assert_eq!(set_cmd.value, "value");
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_get() -> anyhow::Result<()> {
+        let backend = Backend::new();
+        let mut buf = BytesMut::from("*3\r\n$3\r\nset\r\n$3\r\nkey\r\n+value\r\n");
+        let frame = RespArray::decode(&mut buf)?;
+        assert!(Set::try_from(frame).is_err());
+
+        let mut buf = BytesMut::from("*3\r\n$3\r\nset\r\n$3\r\nkey\r\n*1\r\n$1\r\na\r\n");
+        let frame = RespArray::decode(&mut buf)?;
+        assert!(Set::try_from(frame).is_err());
+
+        let mut buf = BytesMut::from("*3\r\n$3\r\nset\r\n$3\r\nkey\r\n$3\r\na\x00b\r\n");
+        let set_cmd = Set::try_from(RespArray::decode(&mut buf)?)?;
+        assert_eq!(set_cmd.execute(backend.clone()), RESP_OK.clone());
+
+        let get = || Get { key: "key".into() };
         assert_eq!(
-            set_cmd.value,
-            RespFrame::BulkString(BulkString(Some(b"value".to_vec())))
+            get().execute(backend.clone()),
+            BulkString::new(b"a\x00b".to_vec()).into()
         );
+        backend.del(b"key");
+        assert_eq!(get().execute(backend), BulkString::new_null_string().into());
         Ok(())
     }
 }
@@ -0,0 +1,519 @@
+//! support DUMP, MIGRATE, RESTORE and RESTORE-ASKING command
+//!
+//! DUMP key
+//! 返回redis RDB value格式的序列化结果, 带有RDB version和CRC64
+//!
+//! MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE] [AUTH password]
+//!     [AUTH2 username password] [KEYS key [key ...]]
+//! 把key用DUMP的格式序列化, 在目标节点上执行RESTORE(cluster模式下是RESTORE-ASKING), 成功后删除本地的key
+//!
+//! RESTORE key ttl serialized-value [REPLACE] [ABSTTL] [IDLETIME seconds] [FREQ frequency]
+
+use super::{
+    extract_args, parse_bytes, parse_integer, parse_string, validate_command, CommandError,
+    CommandExecuter, RESP_OK,
+};
+use crate::{
+    backend::{deserialize, now_ms, serialize, Backend, KeyAccess},
+    network::RespClient,
+    resp::{frame::RespFrame, BulkString, RespArray, SimpleError, SimpleString},
+};
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::time::timeout;
+
+#[derive(Debug)]
+pub struct Migrate {
+    host: String,
+    port: u16,
+    pub(super) keys: Vec<Bytes>,
+    db: u64,
+    timeout: Duration,
+    copy: bool,
+    replace: bool,
+    /// (username, password)
+    auth: Option<(Option<String>, String)>,
+}
+
+#[derive(Debug)]
+pub struct Dump {
+    pub(super) key: Bytes,
+}
+
+#[derive(Debug)]
+pub struct Restore {
+    pub(super) key: Bytes,
+    /// 毫秒, 0表示不过期
+    ttl: u64,
+    payload: Bytes,
+    replace: bool,
+    /// ttl是unix时间戳(毫秒)而不是相对时间
+    absttl: bool,
+    /// 秒
+    idletime: Option<u64>,
+    freq: Option<u8>,
+    /// RESTORE-ASKING, 迁移过程中目标节点还不是slot的owner
+    pub(super) asking: bool,
+}
+
+impl CommandExecuter for Dump {
+    fn execute(self, backend: Backend) -> RespFrame {
+        match backend.dump(&self.key) {
+            Some(value) => BulkString::new(serialize(&value)).into(),
+            None => BulkString::new_null_string().into(),
+        }
+    }
+}
+
+impl CommandExecuter for Restore {
+    fn execute(self, backend: Backend) -> RespFrame {
+        if !self.replace && backend.exists(&self.key) {
+            return SimpleError::new("BUSYKEY Target key name already exists.").into();
+        }
+        let value = match deserialize(&self.payload) {
+            Ok(value) => value,
+            Err(e) => return SimpleError::new(format!("ERR {}", e)).into(),
+        };
+        let now = now_ms();
+        let expire_at = match (self.ttl, self.absttl) {
+            (0, _) => None,
+            (ttl, true) => Some(ttl),
+            (ttl, false) => Some(now.saturating_add(ttl)),
+        };
+        // 和redis一样, 已经过期的key不会被创建, 但REPLACE仍然会删除旧的key
+        if matches!(expire_at, Some(at) if at <= now) {
+            backend.del(&self.key);
+            return RESP_OK.clone();
+        }
+        backend.restore(&self.key, value);
+        if let Some(at) = expire_at {
+            backend.expire_at(&self.key, at);
+        }
+        if self.idletime.is_some() || self.freq.is_some() {
+            let access = KeyAccess {
+                last_access: now
+                    .saturating_sub(self.idletime.unwrap_or_default().saturating_mul(1000)),
+                freq: self.freq.unwrap_or_default(),
+            };
+            backend.set_key_access(&self.key, access);
+        }
+        RESP_OK.clone()
+    }
+}
+
+/// MIGRATE 需要连接目标节点, 只能在network中异步执行
+impl CommandExecuter for Migrate {
+    fn execute(self, _backend: Backend) -> RespFrame {
+        SimpleError::new("ERR MIGRATE must be called from a client connection").into()
+    }
+}
+
+impl Migrate {
+    pub async fn migrate(self, backend: Backend) -> RespFrame {
+        let mut payloads = Vec::with_capacity(self.keys.len());
+        for key in &self.keys {
+            if let Some(value) = backend.dump(key) {
+                payloads.push((key, serialize(&value)));
+            }
+        }
+        if payloads.is_empty() {
+            return SimpleString::new("NOKEY").into();
+        }
+
+        let addr = format!("{}:{}", self.host, self.port);
+        let mut client = match timeout(self.timeout, RespClient::connect(&addr, backend.tls()))
+            .await
+        {
+            Ok(Ok(client)) => client,
+            _ => return SimpleError::new("IOERR error or timeout connecting to the client").into(),
+        };
+
+        let mut prelude: Vec<Vec<Vec<u8>>> = Vec::new();
+        match &self.auth {
+            Some((Some(username), password)) => prelude.push(vec![
+                b"AUTH".to_vec(),
+                username.as_bytes().to_vec(),
+                password.as_bytes().to_vec(),
+            ]),
+            Some((None, password)) => {
+                prelude.push(vec![b"AUTH".to_vec(), password.as_bytes().to_vec()])
+            }
+            None => {}
+        }
+        if self.db != 0 {
+            prelude.push(vec![b"SELECT".to_vec(), self.db.to_string().into_bytes()]);
+        }
+        for args in prelude {
+            if let Err(e) = self.call(&mut client, args).await {
+                return e;
+            }
+        }
+
+        let restore: &[u8] = if backend.cluster().is_enabled() {
+            b"RESTORE-ASKING"
+        } else {
+            b"RESTORE"
+        };
+        for (key, payload) in payloads {
+            // 带上剩余的ttl, 没有过期时间时为0
+            let ttl = backend.pttl(key).unwrap_or(-1).max(0);
+            let mut args = vec![
+                restore.to_vec(),
+                key.to_vec(),
+                ttl.to_string().into_bytes(),
+                payload,
+            ];
+            if self.replace {
+                args.push(b"REPLACE".to_vec());
+            }
+            if let Err(e) = self.call(&mut client, args).await {
+                return e;
+            }
+            if !self.copy {
+                backend.del(key);
+            }
+        }
+        RESP_OK.clone()
+    }
+
+    /// 执行一个命令, 超时或者目标节点返回错误时返回要回复给客户端的错误
+    async fn call(&self, client: &mut RespClient, args: Vec<Vec<u8>>) -> Result<(), RespFrame> {
+        match timeout(self.timeout, client.call(args)).await {
+            Ok(Ok(RespFrame::SimpleError(e))) => Err(SimpleError::new(format!(
+                "ERR Target instance replied with error: {}",
+                e.0
+            ))
+            .into()),
+            Ok(Ok(_)) => Ok(()),
+            _ => Err(SimpleError::new("IOERR error or timeout reading to target instance").into()),
+        }
+    }
+}
+
+impl TryFrom<RespArray> for Dump {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        validate_command(&value, &["dump"], 1)?;
+        let mut args = extract_args(value, 1)?.into_iter();
+        match args.next() {
+            Some(key) => Ok(Dump {
+                key: parse_bytes(key)?,
+            }),
+            _ => Err(CommandError::InvalidArgument("Invalid key".to_string())),
+        }
+    }
+}
+
+impl TryFrom<RespArray> for Restore {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let mut args = extract_args(value, 0)?.into_iter();
+        let asking = match args.next() {
+            Some(name) => parse_string(name)?.eq_ignore_ascii_case("restore-asking"),
+            None => false,
+        };
+        let (key, ttl, payload) = match (args.next(), args.next(), args.next()) {
+            (Some(key), Some(ttl), Some(payload)) => {
+                let ttl: i64 = parse_integer(ttl)?;
+                if ttl < 0 {
+                    return Err(CommandError::InvalidArgument(
+                        "Invalid TTL value, must be >= 0".to_string(),
+                    ));
+                }
+                (parse_bytes(key)?, ttl as u64, parse_bytes(payload)?)
+            }
+            _ => {
+                return Err(CommandError::InvalidArgument(
+                    "RESTORE key ttl serialized-value [REPLACE] [ABSTTL] [IDLETIME seconds] \
+                         [FREQ frequency]"
+                        .to_string(),
+                ))
+            }
+        };
+        let mut restore = Restore {
+            key,
+            ttl,
+            payload,
+            replace: false,
+            absttl: false,
+            idletime: None,
+            freq: None,
+            asking,
+        };
+        let syntax_error = || CommandError::InvalidArgument("syntax error".to_string());
+        while let Some(arg) = args.next() {
+            match parse_string(arg)?.to_ascii_lowercase().as_str() {
+                "replace" => restore.replace = true,
+                "absttl" => restore.absttl = true,
+                "idletime" if restore.freq.is_none() => {
+                    let idletime: i64 = parse_integer(args.next().ok_or_else(syntax_error)?)?;
+                    if idletime < 0 {
+                        return Err(CommandError::InvalidArgument(
+                            "Invalid IDLETIME value, must be >= 0".to_string(),
+                        ));
+                    }
+                    restore.idletime = Some(idletime as u64);
+                }
+                "freq" if restore.idletime.is_none() => {
+                    let freq: i64 = parse_integer(args.next().ok_or_else(syntax_error)?)?;
+                    if !(0..=255).contains(&freq) {
+                        return Err(CommandError::InvalidArgument(
+                            "Invalid FREQ value, must be >= 0 and <= 255".to_string(),
+                        ));
+                    }
+                    restore.freq = Some(freq as u8);
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+        Ok(restore)
+    }
+}
+
+impl TryFrom<RespArray> for Migrate {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let mut args = extract_args(value, 1)?.into_iter();
+        let (host, port, key, db, timeout) = match (
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+        ) {
+            (Some(host), Some(port), Some(key), Some(db), Some(timeout)) => (
+                parse_string(host)?,
+                parse_integer::<u16>(port)?,
+                parse_bytes(key)?,
+                parse_integer::<u64>(db)?,
+                parse_integer::<i64>(timeout)?,
+            ),
+            _ => {
+                return Err(CommandError::InvalidArgument(
+                    "MIGRATE host port key|\"\" destination-db timeout [COPY] [REPLACE] \
+                         [AUTH password] [AUTH2 username password] [KEYS key [key ...]]"
+                        .to_string(),
+                ))
+            }
+        };
+        let mut migrate = Migrate {
+            host,
+            port,
+            keys: vec![],
+            db,
+            // 和redis一样, timeout <= 0 时使用1秒
+            timeout: Duration::from_millis(if timeout <= 0 { 1000 } else { timeout as u64 }),
+            copy: false,
+            replace: false,
+            auth: None,
+        };
+        let syntax_error = || CommandError::InvalidArgument("syntax error".to_string());
+        while let Some(arg) = args.next() {
+            match parse_string(arg)?.to_ascii_lowercase().as_str() {
+                "copy" => migrate.copy = true,
+                "replace" => migrate.replace = true,
+                "auth" => {
+                    let password = parse_string(args.next().ok_or_else(syntax_error)?)?;
+                    migrate.auth = Some((None, password));
+                }
+                "auth2" => {
+                    let username = parse_string(args.next().ok_or_else(syntax_error)?)?;
+                    let password = parse_string(args.next().ok_or_else(syntax_error)?)?;
+                    migrate.auth = Some((Some(username), password));
+                }
+                "keys" => {
+                    if !key.is_empty() {
+                        return Err(CommandError::InvalidArgument(
+                            "When using MIGRATE KEYS option, the key argument must be set to the empty string"
+                                .to_string(),
+                        ));
+                    }
+                    migrate.keys = args.by_ref().map(parse_bytes).collect::<Result<_, _>>()?;
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+        if !key.is_empty() {
+            migrate.keys.push(key);
+        }
+        Ok(migrate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backend::{crc64, DumpValue, RDB_VERSION},
+        resp::{BulkString, RespArray},
+    };
+    use anyhow::Result;
+
+    fn array(args: &[&[u8]]) -> RespArray {
+        RespArray::new(
+            args.iter()
+                .map(|a| BulkString::new(*a).into())
+                .collect::<Vec<RespFrame>>(),
+        )
+    }
+
+    #[test]
+    fn test_migrate_try_from_resp_array() -> Result<()> {
+        let migrate = Migrate::try_from(array(&[
+            b"migrate",
+            b"127.0.0.1",
+            b"7001",
+            b"",
+            b"0",
+            b"5000",
+            b"COPY",
+            b"AUTH2",
+            b"user",
+            b"pass",
+            b"KEYS",
+            b"a",
+            b"b",
+        ]))?;
+        assert_eq!(migrate.keys, vec!["a", "b"]);
+        assert_eq!(migrate.port, 7001);
+        assert!(migrate.copy && !migrate.replace);
+        assert_eq!(
+            migrate.auth,
+            Some((Some("user".to_string()), "pass".to_string()))
+        );
+
+        let migrate = Migrate::try_from(array(&[
+            b"migrate",
+            b"127.0.0.1",
+            b"7001",
+            b"foo",
+            b"0",
+            b"0",
+        ]))?;
+        assert_eq!(migrate.keys, vec!["foo"]);
+        assert_eq!(migrate.timeout, Duration::from_millis(1000));
+
+        assert!(Migrate::try_from(array(&[
+            b"migrate",
+            b"127.0.0.1",
+            b"7001",
+            b"foo",
+            b"0",
+            b"0",
+            b"KEYS",
+            b"a",
+        ]))
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore() -> Result<()> {
+        let backend = Backend::new();
+        let payload = serialize(&DumpValue::Set(vec![b"a".to_vec(), b"b".to_vec()]));
+        let restore = Restore::try_from(array(&[b"restore", b"key", b"0", &payload]))?;
+        assert!(!restore.asking);
+        assert_eq!(restore.execute(backend.clone()), RESP_OK.clone());
+        assert!(backend.sismembers(b"key", b"a"));
+
+        let restore = Restore::try_from(array(&[b"restore-asking", b"key", b"0", &payload]))?;
+        assert!(restore.asking);
+        assert_eq!(
+            restore.execute(backend.clone()),
+            SimpleError::new("BUSYKEY Target key name already exists.").into()
+        );
+
+        let payload = serialize(&DumpValue::String(b"v".to_vec()));
+        let restore = Restore::try_from(array(&[b"restore", b"key", b"0", &payload, b"REPLACE"]))?;
+        assert_eq!(restore.execute(backend.clone()), RESP_OK.clone());
+        assert_eq!(backend.get(b"key"), Some("v".into()));
+        assert!(!backend.sismembers(b"key", b"a"));
+
+        let restore = Restore::try_from(array(&[b"restore", b"key", b"0", b"bad", b"REPLACE"]))?;
+        assert_eq!(
+            restore.execute(backend),
+            SimpleError::new("ERR DUMP payload version or checksum are wrong").into()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_restore_options() -> Result<()> {
+        let backend = Backend::new();
+        backend.hset(b"h", b"f", "v".into());
+        let payload = match Dump::try_from(array(&[b"dump", b"h"]))?.execute(backend.clone()) {
+            RespFrame::BulkString(BulkString(Some(payload))) => payload,
+            frame => panic!("unexpected reply: {:?}", frame),
+        };
+        assert_eq!(
+            Dump::try_from(array(&[b"dump", b"missing"]))?.execute(backend.clone()),
+            BulkString::new_null_string().into()
+        );
+
+        let restore = Restore::try_from(array(&[
+            b"restore",
+            b"h2",
+            b"10000",
+            &payload,
+            b"IDLETIME",
+            b"100",
+        ]))?;
+        assert_eq!(restore.execute(backend.clone()), RESP_OK.clone());
+        let access = backend.key_access(b"h2").unwrap();
+        assert!(now_ms() - access.last_access >= 100_000);
+        assert_eq!(backend.hget(b"h2", b"f"), Some("v".into()));
+        assert!(backend.pttl(b"h2").unwrap() > 9000);
+
+        let restore =
+            Restore::try_from(array(&[b"restore", b"h3", b"0", &payload, b"FREQ", b"42"]))?;
+        assert_eq!(restore.execute(backend.clone()), RESP_OK.clone());
+        assert_eq!(backend.key_access(b"h3").unwrap().freq, 42);
+        assert_eq!(backend.pttl(b"h3"), Some(-1));
+
+        // ABSTTL 已经过期时不会创建key, REPLACE会删除旧的key
+        let restore = Restore::try_from(array(&[
+            b"restore", b"h3", b"1", &payload, b"ABSTTL", b"REPLACE",
+        ]))?;
+        assert_eq!(restore.execute(backend.clone()), RESP_OK.clone());
+        assert!(!backend.exists(b"h3"));
+
+        let invalid: [&[&[u8]]; 4] = [
+            &[b"FREQ", b"1", b"IDLETIME", b"1"],
+            &[b"FREQ", b"256"],
+            &[b"IDLETIME", b"-1"],
+            &[b"IDLETIME"],
+        ];
+        for options in invalid {
+            let mut args: Vec<&[u8]> = vec![b"restore", b"k", b"0", &payload];
+            args.extend_from_slice(options);
+            assert!(Restore::try_from(array(&args)).is_err());
+        }
+
+        // 很大的IDLETIME不会溢出, 访问时间是最早的
+        let restore = Restore::try_from(array(&[
+            b"restore",
+            b"idle",
+            b"0",
+            &payload,
+            b"IDLETIME",
+            b"9223372036854775807",
+        ]))?;
+        assert_eq!(restore.execute(backend.clone()), RESP_OK.clone());
+        assert_eq!(backend.key_access(b"idle").unwrap().last_access, 0);
+
+        // lzf压缩的字符串声明了4GB的长度
+        let mut payload = b"\x00\xc3\x05\x80\xff\xff\xff\xff\x00\x61\xe0\x1e\x00".to_vec();
+        payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        payload.extend_from_slice(&crc64(0, &payload).to_le_bytes());
+        let restore = Restore::try_from(array(&[b"restore", b"huge", b"0", &payload]))?;
+        assert_eq!(
+            restore.execute(backend.clone()),
+            SimpleError::new("ERR Bad data format").into()
+        );
+        assert!(!backend.exists(b"huge"));
+        Ok(())
+    }
+}
@@ -1,22 +1,123 @@
 #![allow(dead_code)]
 use crate::{
     backend::Backend,
-    resp::{array::RespArray, frame::RespFrame, simple_string::SimpleString},
+    resp::{array::RespArray, frame::RespFrame, simple_string::SimpleString, BulkString},
 };
+use bytes::Bytes;
 use enum_dispatch::enum_dispatch;
 use lazy_static::lazy_static;
 use thiserror::Error;
+mod acl;
+mod auth;
+mod client;
+mod cluster;
+mod config;
+mod db;
 mod echo;
 mod hmap;
 mod hmget;
+mod info;
 mod map;
+mod migrate;
+mod object;
 mod set;
+mod shutdown;
+mod wait;
+pub use acl::Acl;
+pub use auth::{Auth, Hello};
+pub use client::Client;
+pub use cluster::{Asking, Cluster};
+use config::Config;
+pub use db::Select;
+use db::{DbSize, FlushAll, FlushDb, Move, SwapDb};
 use echo::Echo;
 use hmget::HmGet;
+use info::Info;
+pub use migrate::{Dump, Migrate, Restore};
+use object::{Memory, Object};
 use set::{SAdd, SisMember};
+pub use shutdown::Shutdown;
+use std::str::FromStr;
+use wait::{ReplConf, Wait, WaitAof};
+
+/// 所有支持的命令和它们的ACL分类, 子命令的分类和父命令不同时单独列出, 用"|"分隔
+pub const COMMAND_TABLE: &[(&str, &[&str])] = &[
+    ("get", &["read", "string", "fast"]),
+    ("set", &["write", "string", "slow"]),
+    ("hset", &["write", "hash", "fast"]),
+    ("hget", &["read", "hash", "fast"]),
+    ("hgetall", &["read", "hash", "slow"]),
+    ("hmget", &["read", "hash", "fast"]),
+    ("sadd", &["write", "set", "fast"]),
+    ("sismember", &["read", "set", "fast"]),
+    ("echo", &["fast", "connection"]),
+    ("wait", &["slow", "connection"]),
+    ("waitaof", &["slow", "connection"]),
+    ("replconf", &["admin", "slow", "dangerous"]),
+    ("cluster", &["slow"]),
+    ("cluster|addslots", &["admin", "slow", "dangerous"]),
+    ("cluster|delslots", &["admin", "slow", "dangerous"]),
+    ("cluster|meet", &["admin", "slow", "dangerous"]),
+    ("cluster|setslot", &["admin", "slow", "dangerous"]),
+    ("asking", &["fast", "connection"]),
+    ("migrate", &["keyspace", "write", "slow", "dangerous"]),
+    ("dump", &["keyspace", "read", "slow"]),
+    ("restore", &["keyspace", "write", "slow", "dangerous"]),
+    (
+        "restore-asking",
+        &["keyspace", "write", "slow", "dangerous"],
+    ),
+    ("select", &["fast", "connection"]),
+    ("swapdb", &["keyspace", "write", "fast", "dangerous"]),
+    ("move", &["keyspace", "write", "fast"]),
+    ("dbsize", &["keyspace", "read", "fast"]),
+    ("flushdb", &["keyspace", "write", "slow", "dangerous"]),
+    ("flushall", &["keyspace", "write", "slow", "dangerous"]),
+    ("object", &["keyspace", "read", "slow"]),
+    ("memory", &["slow"]),
+    ("info", &["slow", "dangerous"]),
+    ("memory|usage", &["read", "slow"]),
+    ("config", &["admin", "slow", "dangerous"]),
+    ("shutdown", &["admin", "slow", "dangerous"]),
+    ("auth", &["fast", "connection"]),
+    ("hello", &["fast", "connection"]),
+    ("acl", &["admin", "slow", "dangerous"]),
+    ("acl|cat", &["slow"]),
+    ("acl|whoami", &["slow"]),
+    ("acl|genpass", &["slow"]),
+    ("client", &["slow", "connection"]),
+    ("client|list", &["admin", "slow", "dangerous", "connection"]),
+    ("client|kill", &["admin", "slow", "dangerous", "connection"]),
+    (
+        "client|pause",
+        &["admin", "slow", "dangerous", "connection"],
+    ),
+    (
+        "client|unpause",
+        &["admin", "slow", "dangerous", "connection"],
+    ),
+    (
+        "client|no-evict",
+        &["admin", "slow", "dangerous", "connection"],
+    ),
+];
+
+/// 查找命令的ACL分类, 子命令没有单独列出时使用父命令的分类
+pub fn command_categories(name: &str, subcommand: Option<&str>) -> &'static [&'static str] {
+    let full = subcommand.map(|sub| format!("{}|{}", name, sub));
+    let find = |name: &str| COMMAND_TABLE.iter().find(|(n, _)| *n == name);
+    full.as_deref()
+        .and_then(find)
+        .or_else(|| find(name))
+        .map(|(_, categories)| *categories)
+        .unwrap_or_default()
+}
+
+/// 日志中代替密码的占位符, 和redis的redactClientCommandArgument一样
+pub(crate) const REDACTED: &str = "(redacted)";
 
 lazy_static! {
-    static ref RESP_OK: RespFrame = SimpleString::new("OK").into();
+    pub(crate) static ref RESP_OK: RespFrame = SimpleString::new("OK").into();
 }
 #[enum_dispatch]
 pub trait CommandExecuter {
@@ -35,9 +136,152 @@ pub enum Command {
     HmGet(HmGet),
     SAdd(SAdd),
     SisMember(SisMember),
+    Wait(Wait),
+    WaitAof(WaitAof),
+    ReplConf(ReplConf),
+    Cluster(Cluster),
+    Asking(Asking),
+    Migrate(Migrate),
+    Dump(Dump),
+    Restore(Restore),
+    Select(Select),
+    SwapDb(SwapDb),
+    Move(Move),
+    DbSize(DbSize),
+    FlushDb(FlushDb),
+    FlushAll(FlushAll),
+    Object(Object),
+    Memory(Memory),
+    Config(Config),
+    Shutdown(Shutdown),
+    Auth(Auth),
+    Hello(Hello),
+    Acl(Acl),
+    Client(Client),
+    Info(Info),
     Unrecongnized(Unrecongnized),
 }
 
+impl Command {
+    /// 会修改数据的命令, 执行后需要推进复制的offset
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Set(_)
+                | Command::HSet(_)
+                | Command::SAdd(_)
+                | Command::Migrate(_)
+                | Command::Restore(_)
+                | Command::SwapDb(_)
+                | Command::Move(_)
+                | Command::FlushDb(_)
+                | Command::FlushAll(_)
+        )
+    }
+
+    /// 会占用更多内存的命令, 超过maxmemory并且无法淘汰时拒绝执行
+    pub fn is_denyoom(&self) -> bool {
+        matches!(
+            self,
+            Command::Set(_) | Command::HSet(_) | Command::SAdd(_) | Command::Restore(_)
+        )
+    }
+
+    /// 没有认证的连接也可以执行的命令
+    pub fn is_no_auth(&self) -> bool {
+        matches!(self, Command::Auth(_) | Command::Hello(_))
+    }
+
+    /// 小写的命令名, 和COMMAND_TABLE中的一致
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Get(_) => "get",
+            Command::Set(_) => "set",
+            Command::HSet(_) => "hset",
+            Command::HGet(_) => "hget",
+            Command::HGetAll(_) => "hgetall",
+            Command::Echo(_) => "echo",
+            Command::HmGet(_) => "hmget",
+            Command::SAdd(_) => "sadd",
+            Command::SisMember(_) => "sismember",
+            Command::Wait(_) => "wait",
+            Command::WaitAof(_) => "waitaof",
+            Command::ReplConf(_) => "replconf",
+            Command::Cluster(_) => "cluster",
+            Command::Asking(_) => "asking",
+            Command::Migrate(_) => "migrate",
+            Command::Dump(_) => "dump",
+            Command::Restore(cmd) if cmd.asking => "restore-asking",
+            Command::Restore(_) => "restore",
+            Command::Select(_) => "select",
+            Command::SwapDb(_) => "swapdb",
+            Command::Move(_) => "move",
+            Command::DbSize(_) => "dbsize",
+            Command::FlushDb(_) => "flushdb",
+            Command::FlushAll(_) => "flushall",
+            Command::Object(_) => "object",
+            Command::Memory(_) => "memory",
+            Command::Config(_) => "config",
+            Command::Shutdown(_) => "shutdown",
+            Command::Auth(_) => "auth",
+            Command::Hello(_) => "hello",
+            Command::Acl(_) => "acl",
+            Command::Client(_) => "client",
+            Command::Info(_) => "info",
+            Command::Unrecongnized(_) => "unknown",
+        }
+    }
+
+    /// 带子命令的命令返回小写的子命令名
+    pub fn subcommand(&self) -> Option<&'static str> {
+        match self {
+            Command::Cluster(cmd) => Some(cmd.subcommand()),
+            Command::Object(cmd) => Some(cmd.subcommand()),
+            Command::Memory(cmd) => Some(cmd.subcommand()),
+            Command::Config(cmd) => Some(cmd.subcommand()),
+            Command::Acl(cmd) => Some(cmd.subcommand()),
+            Command::Client(cmd) => Some(cmd.subcommand()),
+            _ => None,
+        }
+    }
+
+    pub fn categories(&self) -> &'static [&'static str] {
+        command_categories(self.name(), self.subcommand())
+    }
+
+    /// ACL检查key权限时使用, 返回(是否读取key, 是否修改key)
+    pub fn key_access(&self) -> (bool, bool) {
+        let read = !matches!(self, Command::SAdd(_) | Command::Restore(_));
+        (read, self.is_write())
+    }
+
+    /// RESTORE-ASKING 和先发送ASKING效果一样
+    pub fn is_asking(&self) -> bool {
+        matches!(self, Command::Restore(cmd) if cmd.asking)
+    }
+
+    /// 命令访问的key, cluster模式下用来计算slot
+    pub fn keys(&self) -> Vec<&[u8]> {
+        match self {
+            Command::Get(cmd) => vec![&cmd.key],
+            Command::Set(cmd) => vec![&cmd.key],
+            Command::HSet(cmd) => vec![&cmd.key],
+            Command::HGet(cmd) => vec![&cmd.key],
+            Command::HGetAll(cmd) => vec![&cmd.key],
+            Command::HmGet(cmd) => vec![&cmd.key],
+            Command::SAdd(cmd) => vec![&cmd.key],
+            Command::SisMember(cmd) => vec![&cmd.key],
+            Command::Migrate(cmd) => cmd.keys.iter().map(|k| k.as_ref()).collect(),
+            Command::Dump(cmd) => vec![&cmd.key],
+            Command::Restore(cmd) => vec![&cmd.key],
+            Command::Move(cmd) => vec![&cmd.key],
+            Command::Object(cmd) => cmd.key().into_iter().collect(),
+            Command::Memory(cmd) => cmd.key().into_iter().collect(),
+            _ => vec![],
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Unrecongnized;
 impl CommandExecuter for Unrecongnized {
@@ -48,13 +292,13 @@ impl CommandExecuter for Unrecongnized {
 
 #[derive(Debug)]
 pub struct Get {
-    key: String,
+    key: Bytes,
 }
 
 #[derive(Debug)]
 pub struct Set {
-    key: String,
-    value: RespFrame,
+    key: Bytes,
+    value: Bytes,
 }
 
 /// HSET key field value：将哈希表 key 中的字段 field 的值设为 value。
@@ -62,21 +306,21 @@ pub struct Set {
 /// eg. hset key field value
 #[derive(Debug)]
 pub struct HSet {
-    key: String,
-    field: String,
-    value: RespFrame,
+    key: Bytes,
+    field: Bytes,
+    value: Bytes,
 }
 
 /// HGET key field：获取存储在哈希表 key 中指定字段 field 的值。如果 key 或 field 不存在，返回 nil。
 #[derive(Debug)]
 pub struct HGet {
-    key: String,
-    field: String,
+    key: Bytes,
+    field: Bytes,
 }
 
 #[derive(Debug)]
 pub struct HGetAll {
-    key: String,
+    key: Bytes,
 }
 
 #[derive(Debug, Error)]
@@ -89,29 +333,63 @@ pub enum CommandError {
     RespError(#[from] crate::resp::RespError),
     #[error("Utf8Error: {0}")]
     Utf8Error(#[from] std::string::FromUtf8Error),
+    #[error("value is not an integer or out of range")]
+    NotInteger,
+    #[error("ERR Protocol error: {0}")]
+    ProtocolError(String),
 }
 impl TryFrom<RespArray> for Command {
     type Error = CommandError;
 
     fn try_from(frames: RespArray) -> Result<Self, Self::Error> {
-        match frames.0.as_ref().unwrap().first() {
-            Some(RespFrame::BulkString(frame)) => {
-                match frame.0.as_ref().unwrap().to_ascii_lowercase().as_slice() {
-                    b"get" => Ok(Get::try_from(frames)?.into()),
-                    b"set" => Ok(Set::try_from(frames)?.into()),
-                    b"hset" => Ok(HSet::try_from(frames)?.into()),
-                    b"hget" => Ok(HGet::try_from(frames)?.into()),
-                    b"hgetall" => Ok(HGetAll::try_from(frames)?.into()),
-                    b"echo" => Ok(Echo::try_from(frames)?.into()),
-                    b"hmget" => Ok(HmGet::try_from(frames)?.into()),
-                    b"sadd" => Ok(SAdd::try_from(frames)?.into()),
-                    b"sismember" => Ok(SisMember::try_from(frames)?.into()),
-                    _ => Ok(Unrecongnized.into()),
-                }
+        // 命令来自客户端, 任何格式错误都要回复协议错误而不是panic
+        let name = match frames.0.as_deref() {
+            Some([RespFrame::BulkString(BulkString(Some(name))), ..]) => name.to_ascii_lowercase(),
+            Some([]) | None => {
+                return Err(CommandError::ProtocolError(
+                    "command must be a non-empty array".to_string(),
+                ))
             }
-            _ => Err(CommandError::InvalidCommand(
-                "Command must be a BulkString as the first arguments".to_string(),
-            )),
+            Some(_) => {
+                return Err(CommandError::ProtocolError(
+                    "command name must be a bulk string".to_string(),
+                ))
+            }
+        };
+        match name.as_slice() {
+            b"get" => Ok(Get::try_from(frames)?.into()),
+            b"set" => Ok(Set::try_from(frames)?.into()),
+            b"hset" => Ok(HSet::try_from(frames)?.into()),
+            b"hget" => Ok(HGet::try_from(frames)?.into()),
+            b"hgetall" => Ok(HGetAll::try_from(frames)?.into()),
+            b"echo" => Ok(Echo::try_from(frames)?.into()),
+            b"hmget" => Ok(HmGet::try_from(frames)?.into()),
+            b"sadd" => Ok(SAdd::try_from(frames)?.into()),
+            b"sismember" => Ok(SisMember::try_from(frames)?.into()),
+            b"wait" => Ok(Wait::try_from(frames)?.into()),
+            b"waitaof" => Ok(WaitAof::try_from(frames)?.into()),
+            b"replconf" => Ok(ReplConf::try_from(frames)?.into()),
+            b"cluster" => Ok(Cluster::try_from(frames)?.into()),
+            b"asking" => Ok(Asking::try_from(frames)?.into()),
+            b"migrate" => Ok(Migrate::try_from(frames)?.into()),
+            b"dump" => Ok(Dump::try_from(frames)?.into()),
+            b"restore" | b"restore-asking" => Ok(Restore::try_from(frames)?.into()),
+            b"select" => Ok(Select::try_from(frames)?.into()),
+            b"swapdb" => Ok(SwapDb::try_from(frames)?.into()),
+            b"move" => Ok(Move::try_from(frames)?.into()),
+            b"dbsize" => Ok(DbSize::try_from(frames)?.into()),
+            b"flushdb" => Ok(FlushDb::try_from(frames)?.into()),
+            b"flushall" => Ok(FlushAll::try_from(frames)?.into()),
+            b"object" => Ok(Object::try_from(frames)?.into()),
+            b"memory" => Ok(Memory::try_from(frames)?.into()),
+            b"config" => Ok(Config::try_from(frames)?.into()),
+            b"shutdown" => Ok(Shutdown::try_from(frames)?.into()),
+            b"auth" => Ok(Auth::try_from(frames)?.into()),
+            b"hello" => Ok(Hello::try_from(frames)?.into()),
+            b"acl" => Ok(Acl::try_from(frames)?.into()),
+            b"client" => Ok(Client::try_from(frames)?.into()),
+            b"info" => Ok(Info::try_from(frames)?.into()),
+            _ => Ok(Unrecongnized.into()),
         }
     }
 }
@@ -153,7 +431,10 @@ fn validate_command(
     if let Some((i, name)) = names.iter().enumerate().next() {
         match value[i] {
             RespFrame::BulkString(ref cmd) => {
-                if cmd.0.as_ref().unwrap().to_ascii_lowercase() == name.as_bytes() {
+                if cmd
+                    .as_deref()
+                    .is_some_and(|cmd| cmd.eq_ignore_ascii_case(name.as_bytes()))
+                {
                     return Ok(());
                 } else {
                     return Err(CommandError::InvalidCommand(
@@ -180,3 +461,36 @@ fn extract_args(value: RespArray, start: usize) -> Result<Vec<RespFrame>, Comman
         )),
     }
 }
+
+/// 把一个BulkString参数解析成整数
+fn parse_integer<T: FromStr>(frame: RespFrame) -> Result<T, CommandError> {
+    match frame {
+        RespFrame::BulkString(s) => match s.as_deref() {
+            Some(v) => std::str::from_utf8(v)
+                .ok()
+                .and_then(|v| v.parse::<T>().ok())
+                .ok_or(CommandError::NotInteger),
+            None => Err(CommandError::NotInteger),
+        },
+        _ => Err(CommandError::NotInteger),
+    }
+}
+
+fn parse_string(frame: RespFrame) -> Result<String, CommandError> {
+    match frame {
+        RespFrame::BulkString(BulkString(Some(s))) => Ok(String::from_utf8(s.into())?),
+        _ => Err(CommandError::InvalidArgument(
+            "argument must be a bulk string".to_string(),
+        )),
+    }
+}
+
+/// 二进制安全的参数, 不做utf8转换
+fn parse_bytes(frame: RespFrame) -> Result<Bytes, CommandError> {
+    match frame {
+        RespFrame::BulkString(BulkString(Some(s))) => Ok(s),
+        _ => Err(CommandError::InvalidArgument(
+            "argument must be a bulk string".to_string(),
+        )),
+    }
+}
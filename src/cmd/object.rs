@@ -0,0 +1,411 @@
+//! support OBJECT and MEMORY command
+//!
+//! OBJECT ENCODING|IDLETIME|FREQ|REFCOUNT key：查看key的存储方式和访问信息, 不会更新key的访问时间
+//! IDLETIME 只在非LFU策略下可用, FREQ 只在LFU策略下可用, 和redis一致
+//! MEMORY USAGE key [SAMPLES count] | MEMORY STATS | MEMORY DOCTOR：估算的内存使用情况
+
+use super::{
+    extract_args, parse_bytes, parse_integer, parse_string, CommandError, CommandExecuter,
+};
+use crate::{
+    backend::{now_ms, Backend, EvictionPolicy},
+    resp::{frame::RespFrame, BulkString, RespArray, SimpleError},
+};
+use bytes::Bytes;
+
+#[derive(Debug)]
+pub enum Object {
+    Encoding(Bytes),
+    IdleTime(Bytes),
+    Freq(Bytes),
+    RefCount(Bytes),
+    Help,
+}
+
+#[derive(Debug)]
+pub enum Memory {
+    Usage(Bytes),
+    Stats,
+    Doctor,
+    Help,
+}
+
+const OBJECT_HELP: &[&str] = &[
+    "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "ENCODING <key>",
+    "    Return the kind of internal representation used in order to store the value",
+    "    associated with a <key>.",
+    "FREQ <key>",
+    "    Return the access frequency index of the <key>. The returned integer is",
+    "    proportional to the logarithm of the recent access frequency of the key.",
+    "IDLETIME <key>",
+    "    Return the idle time of the <key>, that is the approximated number of",
+    "    seconds elapsed since the last access to the key.",
+    "REFCOUNT <key>",
+    "    Return the number of references of the value associated with the specified",
+    "    <key>.",
+];
+
+const MEMORY_HELP: &[&str] = &[
+    "MEMORY <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "DOCTOR",
+    "    Return memory problems reports.",
+    "STATS",
+    "    Return information about the memory usage of the server.",
+    "USAGE <key> [SAMPLES <count>]",
+    "    Return memory in bytes used by <key> and its value.",
+];
+
+fn help(lines: &[&str]) -> RespFrame {
+    RespArray::new(
+        lines
+            .iter()
+            .map(|l| BulkString::new(*l).into())
+            .collect::<Vec<RespFrame>>(),
+    )
+    .into()
+}
+
+impl Object {
+    pub(super) fn key(&self) -> Option<&[u8]> {
+        match self {
+            Object::Encoding(key)
+            | Object::IdleTime(key)
+            | Object::Freq(key)
+            | Object::RefCount(key) => Some(key),
+            Object::Help => None,
+        }
+    }
+
+    pub(super) fn subcommand(&self) -> &'static str {
+        match self {
+            Object::Encoding(_) => "encoding",
+            Object::IdleTime(_) => "idletime",
+            Object::Freq(_) => "freq",
+            Object::RefCount(_) => "refcount",
+            Object::Help => "help",
+        }
+    }
+}
+
+impl Memory {
+    pub(super) fn key(&self) -> Option<&[u8]> {
+        match self {
+            Memory::Usage(key) => Some(key),
+            _ => None,
+        }
+    }
+
+    pub(super) fn subcommand(&self) -> &'static str {
+        match self {
+            Memory::Usage(_) => "usage",
+            Memory::Stats => "stats",
+            Memory::Doctor => "doctor",
+            Memory::Help => "help",
+        }
+    }
+}
+
+fn is_lfu(backend: &Backend) -> bool {
+    matches!(
+        backend.maxmemory().policy(),
+        EvictionPolicy::AllKeysLfu | EvictionPolicy::VolatileLfu
+    )
+}
+
+impl CommandExecuter for Object {
+    fn execute(self, backend: Backend) -> RespFrame {
+        let key = match self.key() {
+            Some(key) => key,
+            None => return help(OBJECT_HELP),
+        };
+        if !backend.exists(key) {
+            return BulkString::new_null_string().into();
+        }
+        match self {
+            Object::Encoding(key) => match backend.encoding(&key) {
+                Some(encoding) => BulkString::new(encoding).into(),
+                None => BulkString::new_null_string().into(),
+            },
+            Object::IdleTime(key) => {
+                if is_lfu(&backend) {
+                    return SimpleError::new(
+                        "ERR An LFU maxmemory policy is selected, idle time not tracked. \
+                         Please note that when switching between policies at runtime LRU \
+                         and LFU data will take some time to adjust.",
+                    )
+                    .into();
+                }
+                let access = backend.key_access(&key).unwrap_or_default();
+                RespFrame::Integer((access.idle_ms(now_ms()) / 1000) as i64)
+            }
+            Object::Freq(key) => {
+                if !is_lfu(&backend) {
+                    return SimpleError::new(
+                        "ERR An LFU maxmemory policy is not selected, access frequency not \
+                         tracked. Please note that when switching between policies at runtime \
+                         LRU and LFU data will take some time to adjust.",
+                    )
+                    .into();
+                }
+                let access = backend.key_access(&key).unwrap_or_default();
+                RespFrame::Integer(access.decayed_freq(now_ms()) as i64)
+            }
+            // 值没有被共享, 引用计数总是1
+            Object::RefCount(_) => RespFrame::Integer(1),
+            Object::Help => help(OBJECT_HELP),
+        }
+    }
+}
+
+impl CommandExecuter for Memory {
+    fn execute(self, backend: Backend) -> RespFrame {
+        match self {
+            Memory::Usage(key) => match backend.memory_usage(&key) {
+                Some(usage) => RespFrame::Integer(usage as i64),
+                None => BulkString::new_null_string().into(),
+            },
+            Memory::Stats => memory_stats(&backend),
+            Memory::Doctor => BulkString::new(memory_doctor(&backend)).into(),
+            Memory::Help => help(MEMORY_HELP),
+        }
+    }
+}
+
+/// 和redis一样是key/value交替的数组, 只包含可以估算的部分
+fn memory_stats(backend: &Backend) -> RespFrame {
+    let used = backend.used_memory();
+    let mut keys = 0;
+    let mut stats = vec![];
+    for i in 0..backend.databases() {
+        let db = match backend.select(i) {
+            Some(db) => db.db(),
+            None => continue,
+        };
+        let (len, expires) = (db.len(), db.expires_len());
+        keys += len;
+        if len > 0 {
+            stats.push(BulkString::new(format!("db.{}", i)).into());
+            stats.push(
+                RespArray::new(vec![
+                    BulkString::new("keys").into(),
+                    RespFrame::Integer(len as i64),
+                    BulkString::new("expires").into(),
+                    RespFrame::Integer(expires as i64),
+                ])
+                .into(),
+            );
+        }
+    }
+    let mut reply: Vec<RespFrame> = vec![
+        BulkString::new("total.allocated").into(),
+        RespFrame::Integer(used as i64),
+        BulkString::new("dataset.bytes").into(),
+        RespFrame::Integer(used as i64),
+        BulkString::new("keys.count").into(),
+        RespFrame::Integer(keys as i64),
+        BulkString::new("keys.bytes-per-key").into(),
+        RespFrame::Integer(if keys == 0 { 0 } else { used / keys as u64 } as i64),
+        BulkString::new("maxmemory").into(),
+        RespFrame::Integer(backend.maxmemory().maxmemory() as i64),
+        BulkString::new("evicted.keys").into(),
+        RespFrame::Integer(backend.maxmemory().evicted_keys() as i64),
+    ];
+    reply.extend(stats);
+    RespArray::new(reply).into()
+}
+
+fn memory_doctor(backend: &Backend) -> String {
+    let used = backend.used_memory();
+    let maxmemory = backend.maxmemory().maxmemory();
+    if used < 5 * 1024 * 1024 && maxmemory == 0 {
+        return "Hi Sam, this instance is empty or is using very little memory, my issues \
+                detector can't be used in these conditions. Please, leave for your mission on \
+                Earth and fill it with some data. The new Sam and I will be back to our \
+                programming as soon as I finished rebooting."
+            .to_string();
+    }
+    let mut issues = vec![];
+    if maxmemory > 0 && used * 10 > maxmemory * 9 {
+        issues.push(format!(
+            " * High memory usage: {} of maxmemory {} bytes is used, policy is {}.",
+            used,
+            maxmemory,
+            backend.maxmemory().policy()
+        ));
+    }
+    let evicted = backend.maxmemory().evicted_keys();
+    if evicted > 0 {
+        issues.push(format!(
+            " * Evictions: {} keys have been evicted since startup.",
+            evicted
+        ));
+    }
+    if issues.is_empty() {
+        return "Hi Sam, I can't find any memory issue in your instance. I can only account \
+                for what occurs on this base."
+            .to_string();
+    }
+    format!(
+        "Sam, I detected a few issues in this Redis instance memory implants:\n\n{}\n",
+        issues.join("\n")
+    )
+}
+
+fn syntax_error(name: &str, subcommand: &str) -> CommandError {
+    CommandError::InvalidArgument(format!(
+        "unknown subcommand or wrong number of arguments for '{}'. Try {} HELP.",
+        subcommand, name
+    ))
+}
+
+impl TryFrom<RespArray> for Object {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let args = extract_args(value, 1)?;
+        let mut args = args.into_iter();
+        let subcommand = match args.next() {
+            Some(frame) => parse_string(frame)?.to_ascii_lowercase(),
+            None => return Err(syntax_error("OBJECT", "")),
+        };
+        let key = args.next().map(parse_bytes).transpose()?;
+        if args.next().is_some() {
+            return Err(syntax_error("OBJECT", &subcommand));
+        }
+        match (subcommand.as_str(), key) {
+            ("encoding", Some(key)) => Ok(Object::Encoding(key)),
+            ("idletime", Some(key)) => Ok(Object::IdleTime(key)),
+            ("freq", Some(key)) => Ok(Object::Freq(key)),
+            ("refcount", Some(key)) => Ok(Object::RefCount(key)),
+            ("help", None) => Ok(Object::Help),
+            _ => Err(syntax_error("OBJECT", &subcommand)),
+        }
+    }
+}
+
+impl TryFrom<RespArray> for Memory {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let mut args = extract_args(value, 1)?.into_iter();
+        let subcommand = match args.next() {
+            Some(frame) => parse_string(frame)?.to_ascii_lowercase(),
+            None => return Err(syntax_error("MEMORY", "")),
+        };
+        let cmd = match subcommand.as_str() {
+            "usage" => {
+                let key = match args.next() {
+                    Some(key) => parse_bytes(key)?,
+                    None => return Err(syntax_error("MEMORY", &subcommand)),
+                };
+                // 内存是精确估算的, SAMPLES 只做参数检查
+                match args.next().map(parse_string).transpose()? {
+                    Some(opt) if opt.eq_ignore_ascii_case("samples") => {
+                        let samples = args
+                            .next()
+                            .ok_or_else(|| syntax_error("MEMORY", &subcommand))?;
+                        parse_integer::<u64>(samples)?;
+                    }
+                    Some(_) => {
+                        return Err(CommandError::InvalidArgument("syntax error".to_string()))
+                    }
+                    None => {}
+                }
+                Memory::Usage(key)
+            }
+            "stats" => Memory::Stats,
+            "doctor" => Memory::Doctor,
+            "help" => Memory::Help,
+            _ => return Err(syntax_error("MEMORY", &subcommand)),
+        };
+        if args.next().is_some() {
+            return Err(syntax_error("MEMORY", &subcommand));
+        }
+        Ok(cmd)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::KeyAccess;
+    use anyhow::Result;
+
+    fn array(args: &[&str]) -> RespArray {
+        RespArray::new(
+            args.iter()
+                .map(|a| BulkString::new(*a).into())
+                .collect::<Vec<RespFrame>>(),
+        )
+    }
+
+    #[test]
+    fn test_object() -> Result<()> {
+        let backend = Backend::new();
+        backend.hset(b"hash", b"f", "v".into());
+        let now = now_ms();
+        backend.set_key_access(
+            b"hash",
+            KeyAccess {
+                last_access: now - 20_000,
+                freq: 7,
+            },
+        );
+
+        let object = |args: &[&str]| Object::try_from(array(args));
+        assert_eq!(
+            object(&["object", "encoding", "hash"])?.execute(backend.clone()),
+            BulkString::new("listpack").into()
+        );
+        assert_eq!(
+            object(&["object", "idletime", "hash"])?.execute(backend.clone()),
+            RespFrame::Integer(20)
+        );
+        assert!(matches!(
+            object(&["object", "freq", "hash"])?.execute(backend.clone()),
+            RespFrame::SimpleError(_)
+        ));
+        backend.maxmemory().set_policy(EvictionPolicy::AllKeysLfu);
+        assert_eq!(
+            object(&["object", "freq", "hash"])?.execute(backend.clone()),
+            RespFrame::Integer(7)
+        );
+        assert_eq!(
+            object(&["object", "refcount", "missing"])?.execute(backend.clone()),
+            BulkString::new_null_string().into()
+        );
+        assert!(object(&["object", "encoding"]).is_err());
+        assert!(object(&["object", "foo", "hash"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory() -> Result<()> {
+        let backend = Backend::new();
+        backend.set(b"key", "value".into());
+        let memory = |args: &[&str]| Memory::try_from(array(args));
+        assert_eq!(
+            memory(&["memory", "usage", "key", "SAMPLES", "0"])?.execute(backend.clone()),
+            RespFrame::Integer(backend.used_memory() as i64)
+        );
+        assert!(memory(&["memory", "usage", "key", "foo"]).is_err());
+
+        let stats = match memory(&["memory", "stats"])?.execute(backend.clone()) {
+            RespFrame::Array(RespArray(Some(stats))) => stats,
+            frame => panic!("unexpected reply: {:?}", frame),
+        };
+        let keys = stats
+            .iter()
+            .position(|f| f == &BulkString::new("keys.count").into())
+            .unwrap();
+        assert_eq!(stats[keys + 1], RespFrame::Integer(1));
+        assert!(stats.contains(&BulkString::new("db.0").into()));
+
+        assert!(matches!(
+            memory(&["memory", "doctor"])?.execute(backend),
+            RespFrame::BulkString(_)
+        ));
+        Ok(())
+    }
+}
@@ -3,36 +3,36 @@
 use super::CommandExecuter;
 use crate::{
     backend::Backend,
-    resp::{frame::RespFrame, RespArray, RespError},
+    resp::{frame::RespFrame, BulkString, RespArray, RespError},
 };
+use bytes::Bytes;
 
 #[derive(Debug)]
 pub struct SAdd {
-    key: String,
-    members: Vec<RespFrame>,
+    pub(super) key: Bytes,
+    members: Vec<Bytes>,
 }
 
 #[derive(Debug)]
 pub struct SisMember {
-    key: String,
-    member: RespFrame,
+    pub(super) key: Bytes,
+    member: Bytes,
 }
 
-/// 虽然我手动给RespFrame实现了hash和Eq，但是我没有搞明白hash和Eq对f64作用，所以如果接收到f64类型的数据，直接返回0
+/// key和成员必须是BulkString, 其他类型在解析时拒绝
+fn parse_bulk(frame: RespFrame) -> Result<Bytes, RespError> {
+    match frame {
+        RespFrame::BulkString(BulkString(Some(v))) => Ok(v),
+        _ => Err(RespError::InvalidFrameType(
+            "set key and member must be BulkString".into(),
+        )),
+    }
+}
+
+/// 返回新增的成员数量
 impl CommandExecuter for SAdd {
     fn execute(self, backend: Backend) -> RespFrame {
-        let len = self.members.len();
-        let members = self
-            .members
-            .into_iter()
-            .filter(|m| !matches!(m, RespFrame::Double(_)))
-            .collect::<Vec<_>>();
-
-        if members.len() != len {
-            return RespFrame::Integer(0);
-        }
-        backend.sadd(&self.key, members);
-        RespFrame::Integer(1)
+        RespFrame::Integer(backend.sadd(&self.key, self.members) as i64)
     }
 }
 
@@ -54,14 +54,10 @@ impl TryFrom<RespArray> for SAdd {
             .into_iter()
             .skip(1);
         let key = match iter.next() {
-            Some(RespFrame::BulkString(v)) => v.to_string(),
-            _ => {
-                return Err(RespError::InvalidFrameType(
-                    "sadd key must be BulkString".into(),
-                ))
-            }
+            Some(v) => parse_bulk(v)?,
+            None => return Err(RespError::InvalidFrame("sadd key cannot be Null".into())),
         };
-        let members = iter.collect();
+        let members = iter.map(parse_bulk).collect::<Result<_, _>>()?;
         Ok(SAdd { key, members })
     }
 }
@@ -80,14 +76,7 @@ impl TryFrom<RespArray> for SisMember {
             .skip(1);
 
         let key = match iter.next() {
-            Some(v) => match v {
-                RespFrame::BulkString(k) => k.to_string(),
-                _ => {
-                    return Err(RespError::InvalidFrameType(
-                        "cmd sismember key must be BulkString".into(),
-                    ))
-                }
-            },
+            Some(v) => parse_bulk(v)?,
             None => {
                 return Err(RespError::InvalidFrame(
                     "cmd sismember key cannot be Null".into(),
@@ -95,7 +84,7 @@ impl TryFrom<RespArray> for SisMember {
             }
         };
         let member = match iter.next() {
-            Some(v) => v,
+            Some(v) => parse_bulk(v)?,
             None => {
                 return Err(RespError::InvalidFrame(
                     "cmd sismember member cannot be Null".into(),
@@ -112,52 +101,59 @@ mod test {
     use crate::backend::Backend;
     use crate::resp::frame::RespFrame;
     use crate::resp::{RespArray, SimpleString};
+
     #[test]
     fn test_sadd() {
         let backend = Backend::new();
         let cmd1 = SAdd {
-            key: "key1".to_string(),
-            members: vec![
-                RespFrame::SimpleString(SimpleString::new("value1")),
-                RespFrame::Integer(1),
-                RespFrame::Double(1.0),
-            ],
+            key: "key1".into(),
+            members: vec!["value1".into(), "1".into(), "1".into()],
         };
         let cmd2 = SAdd {
-            key: "key2".to_string(),
-            members: vec![
-                RespFrame::SimpleString(SimpleString::new("value2")),
-                RespFrame::Integer(2),
-                RespFrame::Array(RespArray::new_null_array()),
-            ],
+            key: "key1".into(),
+            members: vec!["value1".into(), "2".into()],
         };
         let resp = cmd1.execute(backend.clone());
-        assert_eq!(resp, RespFrame::Integer(0));
+        assert_eq!(resp, RespFrame::Integer(2));
         let resp = cmd2.execute(backend.clone());
         assert_eq!(resp, RespFrame::Integer(1));
     }
 
+    #[test]
+    fn test_sadd_reject_non_string() {
+        for member in [
+            RespFrame::SimpleString(SimpleString::new("value1")),
+            RespFrame::Integer(1),
+            RespFrame::Double(1.0),
+            RespFrame::Array(RespArray::new_null_array()),
+        ] {
+            let frame = RespArray::new(vec![
+                BulkString::new("sadd").into(),
+                BulkString::new("key").into(),
+                member,
+            ]);
+            assert!(SAdd::try_from(frame).is_err());
+        }
+    }
+
     #[test]
     fn test_sismember() {
         let backend = Backend::new();
         let cmd1 = SAdd {
-            key: "key1".to_string(),
-            members: vec![
-                RespFrame::SimpleString(SimpleString::new("value1")),
-                RespFrame::Integer(1),
-            ],
+            key: "key1".into(),
+            members: vec!["value1".into(), "1".into()],
         };
         cmd1.execute(backend.clone());
         let cmd2 = SisMember {
-            key: "key1".to_string(),
-            member: RespFrame::SimpleString(SimpleString::new("value1")),
+            key: "key1".into(),
+            member: "value1".into(),
         };
         let resp = cmd2.execute(backend.clone());
         assert_eq!(resp, RespFrame::Integer(1));
 
         let cmd2 = SisMember {
-            key: "key1".to_string(),
-            member: RespFrame::SimpleString(SimpleString::new("zack")),
+            key: "key1".into(),
+            member: "zack".into(),
         };
         let resp = cmd2.execute(backend.clone());
         assert_eq!(resp, RespFrame::Integer(0));
@@ -0,0 +1,122 @@
+//! support SHUTDOWN command
+//!
+//! SHUTDOWN [NOSAVE|SAVE] [NOW] [FORCE] [ABORT]：等待replica确认之后停止服务,
+//! NOW不等待replica, FORCE忽略保存失败, ABORT取消正在等待replica的shutdown。
+//! 成功时不回复, 连接随之关闭
+
+use super::{extract_args, parse_string, CommandError, CommandExecuter, RESP_OK};
+use crate::{
+    backend::{Backend, ShutdownError, ShutdownFlags},
+    resp::{frame::RespFrame, RespArray, SimpleError},
+};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum Shutdown {
+    Shutdown(ShutdownFlags),
+    Abort,
+}
+
+fn shutdown_error(e: ShutdownError) -> RespFrame {
+    SimpleError::new(format!("ERR {}", e)).into()
+}
+
+impl Shutdown {
+    /// 在连接中执行, 只挂起当前连接。成功时返回None
+    pub async fn shutdown(self, backend: Backend) -> Option<RespFrame> {
+        match self {
+            Shutdown::Shutdown(flags) => {
+                let timeout = backend.config().get_parsed("shutdown-timeout");
+                let result = backend
+                    .shutdown()
+                    .shutdown(backend.replication(), Duration::from_secs(timeout), flags)
+                    .await;
+                result.err().map(shutdown_error)
+            }
+            Shutdown::Abort => Some(self.execute(backend)),
+        }
+    }
+}
+
+/// 不在连接中执行时不等待replica
+impl CommandExecuter for Shutdown {
+    fn execute(self, backend: Backend) -> RespFrame {
+        let result = match self {
+            Shutdown::Shutdown(flags) => backend.shutdown().finish(flags),
+            Shutdown::Abort => backend.shutdown().abort(),
+        };
+        match result {
+            Ok(()) => RESP_OK.clone(),
+            Err(e) => shutdown_error(e),
+        }
+    }
+}
+
+impl TryFrom<RespArray> for Shutdown {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let syntax_error = || CommandError::InvalidArgument("syntax error".to_string());
+        let mut flags = ShutdownFlags::default();
+        let mut abort = false;
+        for arg in extract_args(value, 1)? {
+            match parse_string(arg)?.to_ascii_lowercase().as_str() {
+                "nosave" if flags.save.is_none() => flags.save = Some(false),
+                "save" if flags.save.is_none() => flags.save = Some(true),
+                "now" => flags.now = true,
+                "force" => flags.force = true,
+                "abort" => abort = true,
+                _ => return Err(syntax_error()),
+            }
+        }
+        match abort {
+            true if flags != ShutdownFlags::default() => Err(syntax_error()),
+            true => Ok(Shutdown::Abort),
+            false => Ok(Shutdown::Shutdown(flags)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resp::BulkString;
+    use anyhow::Result;
+
+    fn array(args: &[&str]) -> RespArray {
+        RespArray::new(
+            args.iter()
+                .map(|a| BulkString::new(*a).into())
+                .collect::<Vec<RespFrame>>(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() -> Result<()> {
+        let backend = Backend::new();
+        let cmd = Shutdown::try_from(array(&["shutdown", "abort"]))?;
+        assert_eq!(
+            cmd.shutdown(backend.clone()).await,
+            Some(SimpleError::new("ERR No shutdown in progress.").into())
+        );
+        let cmd = Shutdown::try_from(array(&["shutdown", "save"]))?;
+        assert_eq!(
+            cmd.shutdown(backend.clone()).await,
+            Some(SimpleError::new("ERR Errors trying to SHUTDOWN. Check logs.").into())
+        );
+        assert!(!backend.shutdown().is_closing());
+
+        let cmd = Shutdown::try_from(array(&["shutdown", "NOSAVE", "now"]))?;
+        assert_eq!(cmd.shutdown(backend.clone()).await, None);
+        assert!(backend.shutdown().is_closing());
+
+        for args in [
+            &["shutdown", "save", "nosave"][..],
+            &["shutdown", "abort", "now"],
+            &["shutdown", "later"],
+        ] {
+            assert!(Shutdown::try_from(array(args)).is_err());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+//! support WAIT, WAITAOF and REPLCONF command
+//!
+//! WAIT numreplicas timeout：阻塞当前连接, 直到至少numreplicas个replica确认了这个连接最后一次写入的offset,
+//! 或者timeout(毫秒, 0表示永远等待)到期。返回确认了的replica数量。
+//! replica通过 REPLCONF ACK <offset> [FACK <aofoffset>] 上报offset
+
+use super::{
+    extract_args, parse_integer, validate_command, CommandError, CommandExecuter, RESP_OK,
+};
+use crate::{
+    backend::{Backend, ReplicaAck},
+    resp::{frame::RespFrame, RespArray, SimpleError},
+};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct Wait {
+    numreplicas: usize,
+    timeout: Duration,
+}
+
+#[derive(Debug)]
+pub struct WaitAof {
+    numlocal: usize,
+    numreplicas: usize,
+    timeout: Duration,
+}
+
+#[derive(Debug)]
+pub enum ReplConf {
+    Ack(ReplicaAck),
+    /// replica握手时告诉master自己监听的端口
+    ListeningPort(u16),
+    /// capa 等其他握手参数, 直接返回OK
+    Other,
+}
+
+impl Wait {
+    /// offset是调用WAIT的连接最后一次写入后的master offset
+    pub async fn wait(self, backend: Backend, offset: u64) -> RespFrame {
+        let acked = backend
+            .replication()
+            .wait_for_acks(offset, self.numreplicas, self.timeout, false)
+            .await;
+        RespFrame::Integer(acked as i64)
+    }
+}
+
+impl WaitAof {
+    pub async fn wait(self, backend: Backend, offset: u64) -> RespFrame {
+        if self.numlocal > 0 {
+            return SimpleError::new(
+                "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.",
+            )
+            .into();
+        }
+        let acked = backend
+            .replication()
+            .wait_for_acks(offset, self.numreplicas, self.timeout, true)
+            .await;
+        RespArray::new([RespFrame::Integer(0), RespFrame::Integer(acked as i64)]).into()
+    }
+}
+
+impl ReplConf {
+    /// REPLCONF ACK 按照redis的行为不回复任何内容, 返回None
+    pub fn apply(self, backend: &Backend, conn_id: u64) -> Option<RespFrame> {
+        match self {
+            ReplConf::Ack(ack) => {
+                backend.replication().ack(conn_id, ack);
+                None
+            }
+            ReplConf::ListeningPort(_) | ReplConf::Other => Some(RESP_OK.clone()),
+        }
+    }
+
+    /// 只有真正的replica才会发送ACK和listening-port, 其他REPLCONF不改变连接的类型
+    pub fn is_replica_handshake(&self) -> bool {
+        matches!(self, ReplConf::Ack(_) | ReplConf::ListeningPort(_))
+    }
+}
+
+/// 不在连接中执行时(没有offset, 不能阻塞), 只返回当前已经确认了最新offset的replica数量
+impl CommandExecuter for Wait {
+    fn execute(self, backend: Backend) -> RespFrame {
+        let repl = backend.replication();
+        RespFrame::Integer(repl.acked(repl.master_offset(), false) as i64)
+    }
+}
+
+impl CommandExecuter for WaitAof {
+    fn execute(self, backend: Backend) -> RespFrame {
+        if self.numlocal > 0 {
+            return SimpleError::new(
+                "ERR WAITAOF cannot be used when numlocal is set but appendonly is disabled.",
+            )
+            .into();
+        }
+        let repl = backend.replication();
+        let acked = repl.acked(repl.master_offset(), true);
+        RespArray::new([RespFrame::Integer(0), RespFrame::Integer(acked as i64)]).into()
+    }
+}
+
+impl CommandExecuter for ReplConf {
+    fn execute(self, _backend: Backend) -> RespFrame {
+        RESP_OK.clone()
+    }
+}
+
+fn parse_timeout(frame: RespFrame) -> Result<Duration, CommandError> {
+    let timeout: i64 = parse_integer(frame)?;
+    if timeout < 0 {
+        return Err(CommandError::InvalidArgument(
+            "timeout is negative".to_string(),
+        ));
+    }
+    Ok(Duration::from_millis(timeout as u64))
+}
+
+impl TryFrom<RespArray> for Wait {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        validate_command(&value, &["wait"], 2)?;
+        let mut args = extract_args(value, 1)?.into_iter();
+        match (args.next(), args.next()) {
+            (Some(numreplicas), Some(timeout)) => Ok(Wait {
+                numreplicas: parse_integer(numreplicas)?,
+                timeout: parse_timeout(timeout)?,
+            }),
+            _ => Err(CommandError::InvalidArgument(
+                "WAIT numreplicas timeout".to_string(),
+            )),
+        }
+    }
+}
+
+impl TryFrom<RespArray> for WaitAof {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        validate_command(&value, &["waitaof"], 3)?;
+        let mut args = extract_args(value, 1)?.into_iter();
+        match (args.next(), args.next(), args.next()) {
+            (Some(numlocal), Some(numreplicas), Some(timeout)) => Ok(WaitAof {
+                numlocal: parse_integer(numlocal)?,
+                numreplicas: parse_integer(numreplicas)?,
+                timeout: parse_timeout(timeout)?,
+            }),
+            _ => Err(CommandError::InvalidArgument(
+                "WAITAOF numlocal numreplicas timeout".to_string(),
+            )),
+        }
+    }
+}
+
+/// REPLCONF ACK <offset> [FACK <aofoffset>]
+/// REPLCONF <option> <value> ...
+impl TryFrom<RespArray> for ReplConf {
+    type Error = CommandError;
+
+    fn try_from(value: RespArray) -> Result<Self, Self::Error> {
+        let mut args = extract_args(value, 1)?.into_iter();
+        let option = match args.next() {
+            Some(RespFrame::BulkString(option)) => option.to_string().to_ascii_lowercase(),
+            _ => {
+                return Err(CommandError::InvalidArgument(
+                    "REPLCONF option must be a bulk string".to_string(),
+                ))
+            }
+        };
+        match option.as_str() {
+            "ack" => {}
+            "listening-port" => {
+                return match args.next() {
+                    Some(port) => Ok(ReplConf::ListeningPort(parse_integer(port)?)),
+                    None => Err(CommandError::InvalidArgument(
+                        "REPLCONF listening-port <port>".to_string(),
+                    )),
+                }
+            }
+            _ => return Ok(ReplConf::Other),
+        }
+        let offset = match args.next() {
+            Some(offset) => parse_integer(offset)?,
+            None => {
+                return Err(CommandError::InvalidArgument(
+                    "REPLCONF ACK <offset>".to_string(),
+                ))
+            }
+        };
+        let aof_offset = match (args.next(), args.next()) {
+            (Some(RespFrame::BulkString(fack)), Some(aof_offset))
+                if fack.to_string().eq_ignore_ascii_case("fack") =>
+            {
+                parse_integer(aof_offset)?
+            }
+            (None, None) => 0,
+            _ => {
+                return Err(CommandError::InvalidArgument(
+                    "REPLCONF ACK <offset> [FACK <aofoffset>]".to_string(),
+                ))
+            }
+        };
+        Ok(ReplConf::Ack(ReplicaAck { offset, aof_offset }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resp::{RespDecode, SimpleError};
+    use anyhow::Result;
+    use bytes::BytesMut;
+
+    #[test]
+    fn test_wait_try_from_resp_array() -> Result<()> {
+        let mut buf = BytesMut::from("*3\r\n$4\r\nwait\r\n$1\r\n2\r\n$3\r\n100\r\n");
+        let wait = Wait::try_from(RespArray::decode(&mut buf)?)?;
+        assert_eq!(wait.numreplicas, 2);
+        assert_eq!(wait.timeout, Duration::from_millis(100));
+
+        let mut buf = BytesMut::from("*3\r\n$4\r\nwait\r\n$1\r\n2\r\n$2\r\n-1\r\n");
+        assert!(Wait::try_from(RespArray::decode(&mut buf)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replconf_ack_try_from_resp_array() -> Result<()> {
+        let mut buf = BytesMut::from(
+            "*5\r\n$8\r\nreplconf\r\n$3\r\nACK\r\n$3\r\n100\r\n$4\r\nFACK\r\n$2\r\n90\r\n",
+        );
+        let replconf = ReplConf::try_from(RespArray::decode(&mut buf)?)?;
+        assert!(matches!(
+            replconf,
+            ReplConf::Ack(ReplicaAck {
+                offset: 100,
+                aof_offset: 90
+            })
+        ));
+        assert!(replconf.is_replica_handshake());
+
+        let mut buf = BytesMut::from("*3\r\n$8\r\nreplconf\r\n$4\r\ncapa\r\n$6\r\npsync2\r\n");
+        assert!(!ReplConf::try_from(RespArray::decode(&mut buf)?)?.is_replica_handshake());
+        let mut buf =
+            BytesMut::from("*3\r\n$8\r\nreplconf\r\n$14\r\nlistening-port\r\n$4\r\n6380\r\n");
+        let replconf = ReplConf::try_from(RespArray::decode(&mut buf)?)?;
+        assert!(matches!(replconf, ReplConf::ListeningPort(6380)));
+        assert!(replconf.is_replica_handshake());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wait_with_acks() {
+        let backend = Backend::new();
+        let offset = backend.replication().propagate(32);
+
+        let wait = Wait {
+            numreplicas: 1,
+            timeout: Duration::from_millis(10),
+        };
+        assert_eq!(
+            wait.wait(backend.clone(), offset).await,
+            RespFrame::Integer(0)
+        );
+
+        let ack = ReplConf::Ack(ReplicaAck {
+            offset,
+            aof_offset: 0,
+        });
+        assert_eq!(ack.apply(&backend, 1), None);
+        let wait = Wait {
+            numreplicas: 1,
+            timeout: Duration::ZERO,
+        };
+        assert_eq!(
+            wait.wait(backend.clone(), offset).await,
+            RespFrame::Integer(1)
+        );
+
+        let waitaof = WaitAof {
+            numlocal: 1,
+            numreplicas: 0,
+            timeout: Duration::ZERO,
+        };
+        assert!(matches!(
+            waitaof.wait(backend.clone(), offset).await,
+            RespFrame::SimpleError(SimpleError(_))
+        ));
+    }
+}
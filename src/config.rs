@@ -0,0 +1,637 @@
+//! 服务器配置
+//!
+//! 支持redis.conf格式的配置文件和命令行参数(命令行覆盖配置文件), 运行时用
+//! CONFIG GET/SET/REWRITE 查看和修改。所有的值都按redis的格式保存成字符串,
+//! 写入之前先检查并转换成规范的写法, 比如 maxmemory 1kb 保存成 1024。
+
+use crate::{
+    backend::{parse_memory, EvictionPolicy, OutputBufferLimits},
+    glob::glob_match,
+    split_args::split_args,
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::RwLock,
+};
+use thiserror::Error;
+use tracing::warn;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Unknown option or number of arguments for CONFIG SET - '{0}'")]
+    UnknownOption(String),
+    #[error("CONFIG SET failed (possibly related to argument '{0}') - can't set immutable config")]
+    Immutable(String),
+    #[error("CONFIG SET failed (possibly related to argument '{0}') - {1}")]
+    InvalidValue(String, String),
+    #[error("Reading the configuration file, at line {line} >>> '{content}' {reason}")]
+    File {
+        line: usize,
+        content: String,
+        reason: String,
+    },
+    #[error("The server is running without a config file")]
+    NoConfigFile,
+    #[error("Rewriting config file: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// 参数值的类型, 决定了怎么检查和规范化
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    /// yes|no
+    Bool,
+    Int(i64, i64),
+    /// 100, 1k, 1kb, 1mb, 1gb
+    Memory,
+    Enum(&'static [&'static str]),
+    Str,
+    Custom(fn(&str) -> Result<String, String>),
+    /// 在旧的值上修改, 参数是(旧的值, 新的值)
+    Update(fn(&str, &str) -> Result<String, String>),
+}
+
+#[derive(Debug)]
+struct Param {
+    name: &'static str,
+    default: &'static str,
+    kind: Kind,
+    /// 能否用CONFIG SET修改
+    mutable: bool,
+}
+
+const LOG_LEVELS: &[&str] = &["debug", "verbose", "notice", "warning", "nothing"];
+
+const TLS_AUTH_CLIENTS: &[&str] = &["yes", "no", "optional"];
+
+const fn param(name: &'static str, default: &'static str, kind: Kind, mutable: bool) -> Param {
+    Param {
+        name,
+        default,
+        kind,
+        mutable,
+    }
+}
+
+/// 支持的参数, 按名字排序
+const PARAMS: &[Param] = &[
+    param("aclfile", "", Kind::Str, false),
+    param("acllog-max-len", "128", Kind::Int(0, i64::MAX), true),
+    param("appenddirname", "appendonlydir", Kind::Str, false),
+    param("appendfilename", "appendonly.aof", Kind::Str, false),
+    param("appendonly", "no", Kind::Bool, true),
+    param("bind", "0.0.0.0", Kind::Str, false),
+    param(
+        "client-output-buffer-limit",
+        "normal 0 0 0 replica 268435456 67108864 60 pubsub 33554432 8388608 60",
+        Kind::Update(update_output_buffer_limit),
+        true,
+    ),
+    param(
+        "client-query-buffer-limit",
+        "1073741824",
+        Kind::Memory,
+        true,
+    ),
+    param("cluster-announce-ip", "", Kind::Str, false),
+    param("cluster-enabled", "no", Kind::Bool, false),
+    param("databases", "16", Kind::Int(1, i32::MAX as i64), false),
+    param("dbfilename", "dump.rdb", Kind::Str, true),
+    param("dir", ".", Kind::Custom(check_dir), true),
+    param(
+        "hash-max-listpack-entries",
+        "128",
+        Kind::Int(0, i64::MAX),
+        true,
+    ),
+    param(
+        "hash-max-listpack-value",
+        "64",
+        Kind::Int(0, i64::MAX),
+        true,
+    ),
+    param("logfile", "", Kind::Str, false),
+    param("loglevel", "notice", Kind::Enum(LOG_LEVELS), true),
+    param("maxclients", "10000", Kind::Int(1, i32::MAX as i64), true),
+    param("maxmemory", "0", Kind::Memory, true),
+    param(
+        "maxmemory-policy",
+        "noeviction",
+        Kind::Custom(check_policy),
+        true,
+    ),
+    param("maxmemory-samples", "5", Kind::Int(1, 64), true),
+    param("port", "6379", Kind::Int(0, 65535), false),
+    param("proto-max-bulk-len", "536870912", Kind::Memory, true),
+    param("requirepass", "", Kind::Str, true),
+    param(
+        "set-max-intset-entries",
+        "512",
+        Kind::Int(0, i64::MAX),
+        true,
+    ),
+    param(
+        "set-max-listpack-entries",
+        "128",
+        Kind::Int(0, i64::MAX),
+        true,
+    ),
+    param("set-max-listpack-value", "64", Kind::Int(0, i64::MAX), true),
+    param(
+        "shutdown-timeout",
+        "10",
+        Kind::Int(0, i32::MAX as i64),
+        true,
+    ),
+    param("tcp-keepalive", "300", Kind::Int(0, i32::MAX as i64), true),
+    param("tcp-nodelay", "yes", Kind::Bool, true),
+    param("timeout", "0", Kind::Int(0, i32::MAX as i64), true),
+    param(
+        "tls-auth-clients",
+        "yes",
+        Kind::Enum(TLS_AUTH_CLIENTS),
+        false,
+    ),
+    param("tls-ca-cert-file", "", Kind::Str, false),
+    param("tls-cert-file", "", Kind::Str, false),
+    param("tls-cluster", "no", Kind::Bool, false),
+    param("tls-key-file", "", Kind::Str, false),
+    param("tls-port", "0", Kind::Int(0, 65535), false),
+    param("unixsocket", "", Kind::Str, false),
+    param("unixsocketperm", "0", Kind::Custom(check_perm), false),
+];
+
+fn find_param(name: &str) -> Option<&'static Param> {
+    PARAMS.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+}
+
+fn check_dir(value: &str) -> Result<String, String> {
+    if Path::new(value).is_dir() {
+        Ok(value.to_string())
+    } else {
+        Err(format!("No such file or directory: {}", value))
+    }
+}
+
+fn check_policy(value: &str) -> Result<String, String> {
+    EvictionPolicy::from_str(value)
+        .map(|p| p.to_string())
+        .map_err(|_| "argument(s) must be one of the following: noeviction, allkeys-lru, allkeys-lfu, allkeys-random, volatile-lru, volatile-lfu, volatile-random, volatile-ttl".to_string())
+}
+
+/// 八进制的文件权限, 例如700
+fn check_perm(value: &str) -> Result<String, String> {
+    match u32::from_str_radix(value, 8) {
+        Ok(perm) if perm <= 0o777 => Ok(format!("{:o}", perm)),
+        _ => Err("argument must be an octal file mode".to_string()),
+    }
+}
+
+/// 每次只设置出现的class, 比如 client-output-buffer-limit replica 0 0 0
+fn update_output_buffer_limit(old: &str, value: &str) -> Result<String, String> {
+    let old: OutputBufferLimits = old.parse().unwrap_or_default();
+    old.update(value).map(|limits| limits.to_string())
+}
+
+impl Kind {
+    /// 检查值是否合法, 返回规范的写法。old是参数当前的值
+    fn normalize(&self, value: &str, old: &str) -> Result<String, String> {
+        match self {
+            Kind::Bool => match value.to_ascii_lowercase().as_str() {
+                "yes" => Ok("yes".to_string()),
+                "no" => Ok("no".to_string()),
+                _ => Err("argument must be 'yes' or 'no'".to_string()),
+            },
+            Kind::Int(min, max) => match value.parse::<i64>() {
+                Ok(n) if (*min..=*max).contains(&n) => Ok(n.to_string()),
+                Ok(_) => Err(format!(
+                    "argument must be between {} and {} inclusive",
+                    min, max
+                )),
+                Err(_) => Err("argument couldn't be parsed into an integer".to_string()),
+            },
+            Kind::Memory => parse_memory(value)
+                .map(|n| n.to_string())
+                .map_err(|_| "argument must be a memory value".to_string()),
+            Kind::Enum(values) => values
+                .iter()
+                .find(|v| v.eq_ignore_ascii_case(value))
+                .map(|v| v.to_string())
+                .ok_or_else(|| {
+                    format!(
+                        "argument(s) must be one of the following: {}",
+                        values.join(", ")
+                    )
+                }),
+            Kind::Str => Ok(value.to_string()),
+            Kind::Custom(check) => check(value),
+            Kind::Update(update) => update(old, value),
+        }
+    }
+}
+
+type Hook = Box<dyn Fn(&str) + Send + Sync>;
+
+pub struct Config {
+    /// 启动时使用的配置文件, CONFIG REWRITE 写回这里
+    file: Option<PathBuf>,
+    /// 配置文件中不支持的参数, 日志初始化之后再输出警告
+    ignored: Vec<String>,
+    values: RwLock<BTreeMap<&'static str, String>>,
+    /// 参数修改之后的回调, 比如修改日志级别
+    hooks: RwLock<Vec<(&'static str, Hook)>>,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("file", &self.file)
+            .field("values", &self.values)
+            .finish()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            file: None,
+            ignored: Vec::new(),
+            values: RwLock::new(
+                PARAMS
+                    .iter()
+                    .map(|p| (p.name, p.default.to_string()))
+                    .collect(),
+            ),
+            hooks: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+impl Config {
+    /// 先读取配置文件, 再用overrides(命令行参数)覆盖
+    pub fn load(file: Option<&Path>, overrides: &[(String, String)]) -> Result<Self, ConfigError> {
+        let mut config = Config {
+            file: file.map(Path::to_path_buf),
+            ..Default::default()
+        };
+        if let Some(file) = file {
+            let content = std::fs::read_to_string(file)?;
+            for (i, line) in content.lines().enumerate() {
+                let Some((name, value)) = parse_line(line) else {
+                    continue;
+                };
+                let error = |reason: String| ConfigError::File {
+                    line: i + 1,
+                    content: line.to_string(),
+                    reason,
+                };
+                let name = name.map_err(error)?;
+                match find_param(&name) {
+                    Some(param) => {
+                        let values = config.values.get_mut().unwrap_or_else(|e| e.into_inner());
+                        let value = param
+                            .kind
+                            .normalize(&value, &values[param.name])
+                            .map_err(error)?;
+                        values.insert(param.name, value);
+                    }
+                    // 不支持的参数只给出警告, 这样可以直接使用redis的配置文件
+                    None => config.ignored.push(name),
+                }
+            }
+        }
+        for (name, value) in overrides {
+            let param = find_param(name).ok_or_else(|| ConfigError::UnknownOption(name.clone()))?;
+            let values = config.values.get_mut().unwrap_or_else(|e| e.into_inner());
+            let value = param
+                .kind
+                .normalize(value, &values[param.name])
+                .map_err(|e| ConfigError::InvalidValue(param.name.to_string(), e))?;
+            values.insert(param.name, value);
+        }
+        Ok(config)
+    }
+
+    /// 输出配置文件中被忽略的参数
+    pub fn warn_ignored(&self) {
+        for name in &self.ignored {
+            warn!("Unsupported config directive '{}' ignored", name);
+        }
+    }
+
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        let param = find_param(name)?;
+        self.values
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(param.name)
+            .cloned()
+    }
+
+    /// 读取已经检查过的值, 参数不存在或者类型不对时返回默认值
+    pub fn get_parsed<T: FromStr + Default>(&self, name: &str) -> T {
+        self.get(name)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn get_bool(&self, name: &str) -> bool {
+        self.get(name).as_deref() == Some("yes")
+    }
+
+    /// CONFIG GET, 返回名字匹配任意一个pattern的参数, 按名字排序
+    pub fn matching(&self, patterns: &[String]) -> Vec<(&'static str, String)> {
+        let values = self.values.read().unwrap_or_else(|e| e.into_inner());
+        values
+            .iter()
+            .filter(|(name, _)| {
+                patterns
+                    .iter()
+                    .any(|p| glob_match(p.as_bytes(), name.as_bytes(), true))
+            })
+            .map(|(name, value)| (*name, value.clone()))
+            .collect()
+    }
+
+    /// CONFIG SET, 所有参数都合法时才会修改
+    pub fn set(&self, pairs: &[(String, String)]) -> Result<(), ConfigError> {
+        let mut checked = Vec::with_capacity(pairs.len());
+        let mut seen = HashSet::new();
+        for (name, value) in pairs {
+            let param = find_param(name).ok_or_else(|| ConfigError::UnknownOption(name.clone()))?;
+            if !seen.insert(param.name) {
+                return Err(ConfigError::InvalidValue(
+                    param.name.to_string(),
+                    "duplicate parameter".to_string(),
+                ));
+            }
+            if !param.mutable {
+                return Err(ConfigError::Immutable(param.name.to_string()));
+            }
+            let old = self.get(param.name).unwrap_or_default();
+            let value = param
+                .kind
+                .normalize(value, &old)
+                .map_err(|e| ConfigError::InvalidValue(param.name.to_string(), e))?;
+            checked.push((param.name, value));
+        }
+        {
+            let mut values = self.values.write().unwrap_or_else(|e| e.into_inner());
+            for (name, value) in &checked {
+                values.insert(name, value.clone());
+            }
+        }
+        let hooks = self.hooks.read().unwrap_or_else(|e| e.into_inner());
+        for (name, value) in &checked {
+            hooks
+                .iter()
+                .filter(|(hook, _)| hook == name)
+                .for_each(|(_, f)| f(value));
+        }
+        Ok(())
+    }
+
+    /// 参数被CONFIG SET修改之后调用f
+    pub fn subscribe(&self, name: &'static str, f: impl Fn(&str) + Send + Sync + 'static) {
+        self.hooks
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((name, Box::new(f)));
+    }
+
+    /// CONFIG REWRITE, 保留配置文件中的注释和不认识的参数, 修改已有的参数,
+    /// 不在文件中并且和默认值不同的参数追加到文件最后
+    pub fn rewrite(&self) -> Result<(), ConfigError> {
+        let file = self.file.as_ref().ok_or(ConfigError::NoConfigFile)?;
+        let content = match std::fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let values = self
+            .values
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let mut written = HashSet::new();
+        let mut lines = Vec::new();
+        for line in content.lines() {
+            let param = match parse_line(line) {
+                Some((Ok(name), _)) => find_param(&name),
+                _ => None,
+            };
+            match param {
+                // 重复出现的参数只保留第一个
+                Some(param) if !written.insert(param.name) => {}
+                Some(param) => lines.push(format_line(param.name, &values[param.name])),
+                None => lines.push(line.to_string()),
+            }
+        }
+        let missing = PARAMS
+            .iter()
+            .filter(|p| !written.contains(p.name) && values[p.name] != p.default)
+            .map(|p| format_line(p.name, &values[p.name]))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            if !lines.iter().any(|l| l == REWRITE_SIGNATURE) {
+                lines.push(REWRITE_SIGNATURE.to_string());
+            }
+            lines.extend(missing);
+        }
+        let mut output = lines.join("\n");
+        output.push('\n');
+        // 先写临时文件再rename, 避免写到一半时文件损坏
+        let tmp = file.with_extension("rewrite.tmp");
+        std::fs::write(&tmp, output)?;
+        std::fs::rename(&tmp, file)?;
+        Ok(())
+    }
+}
+
+const REWRITE_SIGNATURE: &str = "# Generated by CONFIG REWRITE";
+
+/// 把一行配置解析成(参数名, 值), 空行和注释返回None, 多个参数用空格连接
+fn parse_line(line: &str) -> Option<(Result<String, String>, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let args = match split_args(line.as_bytes()) {
+        Some(args) => args,
+        None => {
+            return Some((
+                Err("Unbalanced quotes in configuration line".into()),
+                "".into(),
+            ))
+        }
+    };
+    let mut args = args.into_iter();
+    let name = String::from_utf8_lossy(&args.next()?).to_ascii_lowercase();
+    let value = args
+        .map(|arg| String::from_utf8_lossy(&arg).into_owned())
+        .collect::<Vec<_>>();
+    if value.is_empty() {
+        return Some((
+            Err("Bad directive or wrong number of arguments".into()),
+            "".into(),
+        ));
+    }
+    Some((Ok(name), value.join(" ")))
+}
+
+/// 生成一行配置, 值中有空格或者为空时加上引号
+fn format_line(name: &str, value: &str) -> String {
+    let simple = !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\'' || c == '\\');
+    if simple {
+        format!("{} {}", name, value)
+    } else {
+        let escaped = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        format!("{} \"{}\"", name, escaped)
+    }
+}
+
+/// redis的日志级别对应的tracing过滤规则
+pub fn log_filter(level: &str) -> &'static str {
+    match level {
+        "debug" => "trace",
+        "verbose" => "debug",
+        "warning" => "warn",
+        "nothing" => "off",
+        _ => "info",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    fn pairs(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn temp_file(name: &str, content: &str) -> Result<PathBuf> {
+        let path = std::env::temp_dir().join(format!("{}-{}.conf", name, std::process::id()));
+        std::fs::write(&path, content)?;
+        Ok(path)
+    }
+
+    #[test]
+    fn test_load() -> Result<()> {
+        let path = temp_file(
+            "test-load",
+            "# comment\nport 7000\nmaxmemory 1kb\nsave 900 1\nlogfile \"my log.txt\"\nunixsocketperm 0770\n\
+             client-output-buffer-limit normal 1mb 0 0\nclient-output-buffer-limit pubsub 0 0 0\n",
+        )?;
+        let config = Config::load(Some(&path), &pairs(&[("port", "7001")]))?;
+        assert_eq!(config.get("port").as_deref(), Some("7001"));
+        assert_eq!(config.get("maxmemory").as_deref(), Some("1024"));
+        assert_eq!(config.get("logfile").as_deref(), Some("my log.txt"));
+        assert_eq!(config.get_parsed::<u64>("maxmemory"), 1024);
+        assert_eq!(config.get("unixsocketperm").as_deref(), Some("770"));
+        // 每一行只修改一个class
+        assert_eq!(
+            config.get("client-output-buffer-limit").as_deref(),
+            Some("normal 1048576 0 0 replica 268435456 67108864 60 pubsub 0 0 0")
+        );
+
+        std::fs::write(&path, "port\n")?;
+        assert!(Config::load(Some(&path), &[]).is_err());
+        std::fs::write(&path, "maxmemory lots\n")?;
+        assert!(matches!(
+            Config::load(Some(&path), &[]),
+            Err(ConfigError::File { line: 1, .. })
+        ));
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_set() -> Result<()> {
+        let config = Config::default();
+        let found = config.matching(&["maxmemory*".to_string()]);
+        let names = found.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["maxmemory", "maxmemory-policy", "maxmemory-samples"]
+        );
+
+        config.set(&pairs(&[
+            ("maxmemory", "2mb"),
+            ("MAXMEMORY-POLICY", "ALLKEYS-LRU"),
+        ]))?;
+        assert_eq!(config.get("maxmemory").as_deref(), Some("2097152"));
+        assert_eq!(
+            config.get("maxmemory-policy").as_deref(),
+            Some("allkeys-lru")
+        );
+
+        // 有一个参数不合法时整个CONFIG SET都不生效
+        let err = config
+            .set(&pairs(&[("maxmemory", "0"), ("maxmemory-samples", "0")]))
+            .unwrap_err();
+        assert!(matches!(err, ConfigError::InvalidValue(..)));
+        assert_eq!(config.get("maxmemory").as_deref(), Some("2097152"));
+
+        assert!(matches!(
+            config.set(&pairs(&[("port", "7000")])),
+            Err(ConfigError::Immutable(_))
+        ));
+        assert!(matches!(
+            config.set(&pairs(&[("nope", "1")])),
+            Err(ConfigError::UnknownOption(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_subscribe() -> Result<()> {
+        let config = Config::default();
+        let (tx, rx) = std::sync::mpsc::channel();
+        config.subscribe("loglevel", move |v| tx.send(v.to_string()).unwrap());
+        config.set(&pairs(&[("loglevel", "WARNING")]))?;
+        assert_eq!(rx.try_recv()?, "warning");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite() -> Result<()> {
+        assert!(matches!(
+            Config::default().rewrite(),
+            Err(ConfigError::NoConfigFile)
+        ));
+        let path = temp_file(
+            "test-rewrite",
+            "# my config\nmaxmemory 1mb\nunknown-directive 1\nmaxmemory 2mb\n",
+        )?;
+        let config = Config::load(Some(&path), &[])?;
+        config.set(&pairs(&[("maxmemory", "100"), ("timeout", "30")]))?;
+        config.rewrite()?;
+        let content = std::fs::read_to_string(&path)?;
+        assert_eq!(
+            content,
+            "# my config\nmaxmemory 100\nunknown-directive 1\n# Generated by CONFIG REWRITE\ntimeout 30\n"
+        );
+        // 再次rewrite结果不变
+        config.rewrite()?;
+        assert_eq!(std::fs::read_to_string(&path)?, content);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! redis风格的glob匹配, 和 util.c 中的 stringmatchlen 一致
+//!
+//! 支持 `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` 和 `\` 转义
+
+pub fn glob_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+    let (mut p, mut s) = (0, 0);
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                // 连续的*和一个*一样
+                while p + 1 < pattern.len() && pattern[p + 1] == b'*' {
+                    p += 1;
+                }
+                if p + 1 == pattern.len() {
+                    return true;
+                }
+                return (s..=string.len())
+                    .any(|i| glob_match(&pattern[p + 1..], &string[i..], nocase));
+            }
+            b'?' => {
+                if s >= string.len() {
+                    return false;
+                }
+                s += 1;
+            }
+            b'[' => {
+                if s >= string.len() {
+                    return false;
+                }
+                p += 1;
+                let not = pattern.get(p) == Some(&b'^');
+                if not {
+                    p += 1;
+                }
+                let mut matched = false;
+                while p < pattern.len() && pattern[p] != b']' {
+                    if pattern[p] == b'\\' && p + 1 < pattern.len() {
+                        p += 1;
+                        matched |= eq(pattern[p], string[s]);
+                    } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' {
+                        let (mut start, mut end) = (pattern[p], pattern[p + 2]);
+                        if start > end {
+                            std::mem::swap(&mut start, &mut end);
+                        }
+                        let c = string[s];
+                        matched |= (start..=end).contains(&c)
+                            || (nocase
+                                && (start..=end).contains(&c.to_ascii_lowercase())
+                                && (start..=end).contains(&c.to_ascii_uppercase()));
+                        p += 2;
+                    } else {
+                        matched |= eq(pattern[p], string[s]);
+                    }
+                    p += 1;
+                }
+                // 没有]时和redis一样把结尾当作]
+                if p >= pattern.len() {
+                    p = pattern.len() - 1;
+                }
+                if matched == not {
+                    return false;
+                }
+                s += 1;
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                p += 1;
+                if s >= string.len() || !eq(pattern[p], string[s]) {
+                    return false;
+                }
+                s += 1;
+            }
+            c => {
+                if s >= string.len() || !eq(c, string[s]) {
+                    return false;
+                }
+                s += 1;
+            }
+        }
+        p += 1;
+    }
+    s == string.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        let cases: [(&str, &str, bool); 12] = [
+            ("*", "anything", true),
+            ("max*", "maxmemory", true),
+            ("max*", "port", false),
+            ("*memory*", "maxmemory-policy", true),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h[ae]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-c]llo", "hbllo", true),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("a*b*c", "axxbyyc", true),
+        ];
+        for (pattern, string, expected) in cases {
+            assert_eq!(
+                glob_match(pattern.as_bytes(), string.as_bytes(), false),
+                expected,
+                "{} {}",
+                pattern,
+                string
+            );
+        }
+        assert!(glob_match(b"MAX*", b"maxmemory", true));
+        assert!(!glob_match(b"MAX*", b"maxmemory", false));
+    }
+}
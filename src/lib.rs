@@ -1,4 +1,10 @@
+pub mod acl;
 pub mod backend;
+pub mod cluster;
 pub mod cmd;
+pub mod config;
+pub mod glob;
 pub mod network;
 pub mod resp;
+pub mod split_args;
+pub mod tls;
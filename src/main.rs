@@ -1,27 +1,346 @@
 use anyhow::Result;
-use redis::{backend::Backend, network::stream_handler};
-use tokio::net::TcpListener;
+use clap::Parser;
+use redis::{
+    backend::{Backend, ShutdownFlags},
+    config::{log_filter, Config},
+    network::{configure_tcp, stream_handler, tls_stream_handler, AsyncStream, UnixSocket},
+    tls::TlsContext,
+};
+use std::{fs::OpenOptions, net::SocketAddr, path::PathBuf, sync::Mutex, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream, UnixStream},
+    signal::unix::{signal, SignalKind},
+};
+use tokio_rustls::TlsAcceptor;
 use tracing::{info, warn};
+use tracing_subscriber::{
+    fmt::writer::BoxMakeWriter, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter,
+};
+
+/// accept失败之后等待的时间
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// 命令行参数的写法和配置文件一样, 会覆盖配置文件中的值
+#[derive(Debug, Parser)]
+#[command(about = "A simple redis server")]
+struct Args {
+    /// redis.conf格式的配置文件
+    config: Option<PathBuf>,
+    #[arg(long)]
+    bind: Option<String>,
+    #[arg(long)]
+    port: Option<String>,
+    /// debug, verbose, notice, warning 或 nothing
+    #[arg(long)]
+    loglevel: Option<String>,
+    /// 日志文件, 为空时输出到标准输出
+    #[arg(long)]
+    logfile: Option<String>,
+    /// db的数量, 可以用SELECT切换
+    #[arg(long)]
+    databases: Option<String>,
+    /// 内存上限, 支持1kb/1mb/1gb等写法, 0表示不限制
+    #[arg(long)]
+    maxmemory: Option<String>,
+    /// 超过maxmemory时的淘汰策略
+    #[arg(long)]
+    maxmemory_policy: Option<String>,
+    /// 淘汰时每次采样的key数量
+    #[arg(long)]
+    maxmemory_samples: Option<String>,
+    /// hash用listpack保存的最大元素数量
+    #[arg(long)]
+    hash_max_listpack_entries: Option<String>,
+    /// hash用listpack保存时field和value的最大长度
+    #[arg(long)]
+    hash_max_listpack_value: Option<String>,
+    /// 只包含整数的set用intset保存的最大元素数量
+    #[arg(long)]
+    set_max_intset_entries: Option<String>,
+    /// set用listpack保存的最大元素数量
+    #[arg(long)]
+    set_max_listpack_entries: Option<String>,
+    /// set用listpack保存时成员的最大长度
+    #[arg(long)]
+    set_max_listpack_value: Option<String>,
+    /// 设置之后客户端需要先AUTH
+    #[arg(long)]
+    requirepass: Option<String>,
+    /// 持久化文件所在的目录
+    #[arg(long)]
+    dir: Option<String>,
+    #[arg(long)]
+    dbfilename: Option<String>,
+    #[arg(long)]
+    appendonly: Option<String>,
+    #[arg(long)]
+    appendfilename: Option<String>,
+    /// 客户端空闲多少秒之后断开, 0表示不断开
+    #[arg(long)]
+    timeout: Option<String>,
+    /// TCP keepalive的间隔秒数, 0表示不开启
+    #[arg(long)]
+    tcp_keepalive: Option<String>,
+    #[arg(long)]
+    tcp_nodelay: Option<String>,
+    /// 同时连接的客户端数量上限
+    #[arg(long)]
+    maxclients: Option<String>,
+    /// 开启cluster模式
+    #[arg(long, num_args = 0..=1, default_missing_value = "yes")]
+    cluster_enabled: Option<String>,
+    /// cluster模式下告诉其他节点和客户端的ip, 默认和bind一致(bind 0.0.0.0时为127.0.0.1)
+    #[arg(long)]
+    cluster_announce_ip: Option<String>,
+    /// TLS端口, 0表示不开启
+    #[arg(long)]
+    tls_port: Option<String>,
+    /// PEM格式的证书和私钥
+    #[arg(long)]
+    tls_cert_file: Option<String>,
+    #[arg(long)]
+    tls_key_file: Option<String>,
+    /// 用来校验客户端证书和其他节点证书的CA
+    #[arg(long)]
+    tls_ca_cert_file: Option<String>,
+    /// yes, no 或 optional
+    #[arg(long)]
+    tls_auth_clients: Option<String>,
+    /// CLUSTER MEET 和 MIGRATE 连接其他节点时使用TLS
+    #[arg(long, num_args = 0..=1, default_missing_value = "yes")]
+    tls_cluster: Option<String>,
+    /// 同时监听的unix socket路径
+    #[arg(long)]
+    unixsocket: Option<String>,
+    /// unix socket文件的权限, 八进制, 例如700
+    #[arg(long)]
+    unixsocketperm: Option<String>,
+}
+
+impl Args {
+    fn overrides(&self) -> Vec<(String, String)> {
+        let options = [
+            ("bind", &self.bind),
+            ("port", &self.port),
+            ("loglevel", &self.loglevel),
+            ("logfile", &self.logfile),
+            ("databases", &self.databases),
+            ("maxmemory", &self.maxmemory),
+            ("maxmemory-policy", &self.maxmemory_policy),
+            ("maxmemory-samples", &self.maxmemory_samples),
+            ("hash-max-listpack-entries", &self.hash_max_listpack_entries),
+            ("hash-max-listpack-value", &self.hash_max_listpack_value),
+            ("set-max-intset-entries", &self.set_max_intset_entries),
+            ("set-max-listpack-entries", &self.set_max_listpack_entries),
+            ("set-max-listpack-value", &self.set_max_listpack_value),
+            ("requirepass", &self.requirepass),
+            ("dir", &self.dir),
+            ("dbfilename", &self.dbfilename),
+            ("appendonly", &self.appendonly),
+            ("appendfilename", &self.appendfilename),
+            ("timeout", &self.timeout),
+            ("tcp-keepalive", &self.tcp_keepalive),
+            ("tcp-nodelay", &self.tcp_nodelay),
+            ("maxclients", &self.maxclients),
+            ("cluster-enabled", &self.cluster_enabled),
+            ("cluster-announce-ip", &self.cluster_announce_ip),
+            ("tls-port", &self.tls_port),
+            ("tls-cert-file", &self.tls_cert_file),
+            ("tls-key-file", &self.tls_key_file),
+            ("tls-ca-cert-file", &self.tls_ca_cert_file),
+            ("tls-auth-clients", &self.tls_auth_clients),
+            ("tls-cluster", &self.tls_cluster),
+            ("unixsocket", &self.unixsocket),
+            ("unixsocketperm", &self.unixsocketperm),
+        ];
+        options
+            .into_iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.clone()?)))
+            .collect()
+    }
+}
+
+/// 按照loglevel和logfile初始化日志, CONFIG SET loglevel 立刻生效。设置了RUST_LOG时启动时以它为准
+fn init_tracing(config: &Config) -> Result<()> {
+    let level = config.get("loglevel").unwrap_or_default();
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(log_filter(&level)));
+    let (filter, handle) = reload::Layer::new(filter);
+    let writer = match config.get("logfile").unwrap_or_default() {
+        file if file.is_empty() => BoxMakeWriter::new(std::io::stdout),
+        file => BoxMakeWriter::new(Mutex::new(
+            OpenOptions::new().create(true).append(true).open(file)?,
+        )),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(writer))
+        .init();
+    config.subscribe("loglevel", move |level| {
+        let _ = handle.reload(EnvFilter::new(log_filter(level)));
+    });
+    Ok(())
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
-    let addr = "0.0.0.0:6379";
+    let args = Args::parse();
+    let config = Config::load(args.config.as_deref(), &args.overrides())?;
+    init_tracing(&config)?;
+    config.warn_ignored();
+    let bind = config.get("bind").unwrap_or_default();
+    let addr = format!("{}:{}", bind, config.get("port").unwrap_or_default());
     info!("Starting redis server on {}", addr);
-    let listener = TcpListener::bind(addr).await?;
-    let backend = Backend::new();
+    let listener = TcpListener::bind(&addr).await?;
+    let tls = TlsContext::from_config(&config)?;
+    let tls_listener = match tls.acceptor() {
+        Some(_) => {
+            let addr = format!("{}:{}", bind, config.get("tls-port").unwrap_or_default());
+            info!("Listening for TLS connections on {}", addr);
+            Some(TcpListener::bind(&addr).await?)
+        }
+        None => None,
+    };
+    let unix_listener = match config.get("unixsocket").unwrap_or_default().as_str() {
+        "" => None,
+        path => {
+            info!("Listening on unix socket {}", path);
+            Some(UnixSocket::bind(
+                path,
+                &config.get("unixsocketperm").unwrap_or_default(),
+            )?)
+        }
+    };
+    let backend = Backend::with_tls(config, tls)?;
+    let mut closing = backend.shutdown().subscribe();
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
     loop {
-        let (socket, remote_addr) = listener.accept().await?;
-        // backend is Arc<BackendInner>
-        let backend = backend.clone();
-        info!("Accepted connection from {}", remote_addr);
-        tokio::spawn(async move {
-            match stream_handler(socket, backend).await {
-                Ok(_) => info!("Connection from {} is exited", remote_addr),
-                Err(e) => {
-                    warn!("error processing connection: {}, error:{}", addr, e);
+        tokio::select! {
+            accepted = listener.accept() => match accepted_tcp(accepted) {
+                Ok((socket, remote_addr, local_addr)) => {
+                    if let Err(e) = configure_tcp(&socket, backend.config()) {
+                        warn!("Failed to set socket options for {}: {}", remote_addr, e);
+                    }
+                    spawn_connection(socket, remote_addr, local_addr, backend.clone(), None);
+                }
+                Err(e) => accept_failed(e).await,
+            },
+            accepted = accept_tls(tls_listener.as_ref()) => match accepted_tcp(accepted) {
+                Ok((socket, remote_addr, local_addr)) => {
+                    if let Err(e) = configure_tcp(&socket, backend.config()) {
+                        warn!("Failed to set socket options for {}: {}", remote_addr, e);
+                    }
+                    let acceptor = backend.tls().acceptor().cloned();
+                    spawn_connection(socket, remote_addr, local_addr, backend.clone(), acceptor);
                 }
+                Err(e) => accept_failed(e).await,
+            },
+            accepted = accept_unix(unix_listener.as_ref()) => match accepted {
+                Ok(socket) => {
+                    let addr = unix_listener.as_ref().map(UnixSocket::addr).unwrap_or_default();
+                    spawn_connection(socket, addr.clone(), addr, backend.clone(), None);
+                }
+                Err(e) => accept_failed(e).await,
+            },
+            _ = sigint.recv() => on_signal(&backend, "SIGINT"),
+            _ = sigterm.recv() => on_signal(&backend, "SIGTERM"),
+            _ = closing.wait_for(|closing| *closing) => break,
+        }
+    }
+    drop(listener);
+    drop(tls_listener);
+    // 删除socket文件
+    drop(unix_listener);
+    let timeout = Duration::from_secs(backend.config().get_parsed("shutdown-timeout"));
+    let connections = backend.shutdown().connections();
+    if connections > 0 {
+        info!("Waiting for {} connections to finish", connections);
+    }
+    if !backend.shutdown().wait_drained(timeout).await {
+        warn!(
+            "{} connections did not finish in time",
+            backend.shutdown().connections()
+        );
+    }
+    info!("Redis is now ready to exit, bye bye...");
+    Ok(())
+}
+
+/// 返回连接和它的远端地址, 本地地址
+fn accepted_tcp(
+    accepted: std::io::Result<(TcpStream, SocketAddr)>,
+) -> std::io::Result<(TcpStream, String, String)> {
+    let (socket, remote_addr) = accepted?;
+    let local_addr = socket.local_addr()?.to_string();
+    Ok((socket, remote_addr.to_string(), local_addr))
+}
+
+/// accept失败(例如文件描述符用完)时不退出, 等一会儿再继续接受连接
+async fn accept_failed(e: std::io::Error) {
+    warn!("Accepting client connection: {}", e);
+    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+}
+
+/// 没有开启TLS时一直等待
+async fn accept_tls(listener: Option<&TcpListener>) -> std::io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// 没有配置unixsocket时一直等待
+async fn accept_unix(listener: Option<&UnixSocket>) -> std::io::Result<UnixStream> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// acceptor不为空时先进行TLS握手
+fn spawn_connection<S: AsyncStream + 'static>(
+    socket: S,
+    remote_addr: String,
+    local_addr: String,
+    backend: Backend,
+    acceptor: Option<TlsAcceptor>,
+) {
+    info!("Accepted connection from {}", remote_addr);
+    tokio::spawn(async move {
+        let ret = match acceptor {
+            Some(acceptor) => {
+                tls_stream_handler(socket, acceptor, &remote_addr, &local_addr, backend).await
             }
-        });
+            // backend is Arc<BackendInner>
+            None => stream_handler(socket, &remote_addr, &local_addr, backend).await,
+        };
+        match ret {
+            Ok(_) => info!("Connection from {} is exited", remote_addr),
+            Err(e) => {
+                warn!("error processing connection: {}, error:{}", remote_addr, e);
+            }
+        }
+    });
+}
+
+/// 和SHUTDOWN一样在后台执行shutdown, 再次收到信号时立刻退出
+fn on_signal(backend: &Backend, signal: &str) {
+    if backend.shutdown().is_pending() || backend.shutdown().is_closing() {
+        warn!("Received {} during shutdown, exiting now", signal);
+        std::process::exit(1);
     }
+    info!("Received {} scheduling shutdown...", signal);
+    let backend = backend.clone();
+    tokio::spawn(async move {
+        let timeout = Duration::from_secs(backend.config().get_parsed("shutdown-timeout"));
+        let flags = ShutdownFlags::default();
+        let shutdown = backend.shutdown();
+        if let Err(e) = shutdown
+            .shutdown(backend.replication(), timeout, flags)
+            .await
+        {
+            warn!("Shutdown failed: {}", e);
+        }
+    });
 }
@@ -1,55 +1,357 @@
 use crate::{
-    backend::Backend,
-    cmd::{Command, CommandExecuter},
-    resp::{frame::RespFrame, simple_error::SimpleError, RespDecode, RespEncode, RespError},
+    backend::{Backend, ClientHandle, PushReceiver, TrackingOptions},
+    cmd::{Cluster, Command, CommandExecuter, RESP_OK},
+    config::Config,
+    resp::{
+        decode_inline, frame::RespFrame, simple_error::SimpleError, BulkString, DecodeLimits,
+        RespArray, RespEncode, RespError, RespParser,
+    },
+    tls::{server_name, TlsContext},
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
 use futures::SinkExt;
-use tokio::net::TcpStream;
+use socket2::{SockRef, TcpKeepalive};
+use std::{fs::Permissions, os::unix::fs::PermissionsExt, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, UnixListener, UnixStream},
+    time::{sleep_until, Instant},
+};
+use tokio_rustls::TlsAcceptor;
 use tokio_stream::StreamExt;
 use tokio_util::codec::{Decoder, Encoder, Framed};
-use tracing::{info, warn};
+use tracing::{debug, info, trace, warn};
 
 #[derive(Debug)]
 struct RedisRequest {
     frame: RespFrame,
+    /// frame编码后的字节数, 写命令用它来推进复制的offset
+    len: usize,
     backend: Backend,
 }
 struct RedisResponse {
-    frame: RespFrame,
+    /// REPLCONF ACK 这类命令不需要回复
+    frame: Option<RespFrame>,
 }
 
+/// 每个连接自己的状态
 #[derive(Debug)]
-struct RespFrameCodec;
+pub struct Connection {
+    pub id: u64,
+    /// 这个连接最后一次写入之后的master offset, WAIT等待replica确认到这个offset
+    pub write_offset: u64,
+    /// 上一个命令是ASKING, 只对紧接着的一个命令有效
+    pub asking: bool,
+    /// SELECT 选择的db
+    pub db: usize,
+    /// default用户不需要密码时连接建立就是已认证的
+    pub authenticated: bool,
+    /// 当前的ACL用户
+    pub user: String,
+    /// HELLO 选择的协议版本, 2或者3
+    pub protover: u8,
+    /// HELLO SETNAME 或者 CLIENT SETNAME 设置的名字
+    pub name: Option<String>,
+    /// 发送过REPLCONF, 不受CLIENT PAUSE影响
+    pub replica: bool,
+    /// CLIENT NO-EVICT
+    pub no_evict: bool,
+    /// CLIENT NO-TOUCH
+    pub no_touch: bool,
+    /// CLIENT TRACKING on 时的选项
+    pub tracking: Option<TrackingOptions>,
+    /// CLIENT CACHING yes/no, 只对紧接着的一个命令有效
+    pub caching: Option<bool>,
+    /// 在ClientRegistry中注册的信息, CLIENT LIST 等命令通过它查看其他连接
+    pub client: Arc<ClientHandle>,
+    /// 其他连接发来的push消息, 在等待请求时发送给客户端
+    pub pushes: PushReceiver,
+}
+
+impl Connection {
+    /// addr和laddr是对端和本地的地址, unix socket为 "path:0"
+    pub fn new(backend: &Backend, addr: &str, laddr: &str) -> Self {
+        let (client, pushes) = backend.clients().register(addr, laddr);
+        let conn = Self {
+            id: client.id(),
+            write_offset: 0,
+            asking: false,
+            db: 0,
+            authenticated: !backend.requires_auth(),
+            user: "default".to_string(),
+            protover: 2,
+            name: None,
+            replica: false,
+            no_evict: false,
+            no_touch: false,
+            tracking: None,
+            caching: None,
+            client,
+            pushes,
+        };
+        conn.sync();
+        conn
+    }
+
+    /// 把连接的状态同步到ClientRegistry
+    pub fn sync(&self) {
+        self.client.update(|state| {
+            state.name = self.name.clone();
+            state.db = self.db;
+            state.user = self.user.clone();
+            state.resp = self.protover;
+            state.replica = self.replica;
+            state.no_evict = self.no_evict;
+            state.no_touch = self.no_touch;
+            state.tracking = self.tracking.clone();
+        });
+    }
+
+    /// ACL LOG 中记录的客户端信息, 和CLIENT INFO一样
+    pub fn client_info(&self) -> String {
+        self.sync();
+        self.client.info()
+    }
+}
+
+/// 客户端没有读取回复时检查输出缓存的间隔
+const OUTPUT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+struct RespFrameCodec {
+    /// 最近一次decode出来的frame的长度
+    last_frame_len: usize,
+    /// 只检查客户端发来的请求, 连接其他节点时为None, 这时也不支持inline命令
+    limits: Option<DecodeLimits>,
+    /// 在多次decode之间保存解析到一半的frame
+    parser: RespParser,
+}
+
+/// TCP连接或者TLS连接
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+/// 连接其他redis节点的简单客户端, CLUSTER MEET 等需要访问其他节点的命令使用
+pub struct RespClient {
+    framed: Framed<Box<dyn AsyncStream>, RespFrameCodec>,
+}
+
+impl RespClient {
+    /// tls-cluster 开启时使用TLS连接
+    pub async fn connect(addr: &str, tls: &TlsContext) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let stream: Box<dyn AsyncStream> = match tls.connector() {
+            Some(connector) => Box::new(connector.connect(server_name(addr)?, stream).await?),
+            None => Box::new(stream),
+        };
+        Ok(Self {
+            framed: Framed::new(stream, RespFrameCodec::default()),
+        })
+    }
+
+    /// 发送一个命令并等待回复, 错误回复以RespFrame::SimpleError返回
+    pub async fn call<T: AsRef<[u8]>>(
+        &mut self,
+        args: impl IntoIterator<Item = T>,
+    ) -> Result<RespFrame> {
+        let args = args
+            .into_iter()
+            .map(|arg| BulkString::new(arg.as_ref()).into())
+            .collect::<Vec<RespFrame>>();
+        self.framed.send(RespArray::new(args).into()).await?;
+        match self.framed.next().await {
+            Some(frame) => frame,
+            None => Err(anyhow!("connection closed by peer")),
+        }
+    }
+}
+
+/// 按照tcp-nodelay和tcp-keepalive设置新接受的连接
+pub fn configure_tcp(socket: &TcpStream, config: &Config) -> std::io::Result<()> {
+    socket.set_nodelay(config.get_bool("tcp-nodelay"))?;
+    let keepalive: u64 = config.get_parsed("tcp-keepalive");
+    if keepalive > 0 {
+        // 和redis一样探测间隔是keepalive的三分之一
+        let keepalive = TcpKeepalive::new()
+            .with_time(Duration::from_secs(keepalive))
+            .with_interval(Duration::from_secs((keepalive / 3).max(1)));
+        SockRef::from(socket).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(())
+}
+
+/// unixsocket 配置的监听socket, drop时删除socket文件
+#[derive(Debug)]
+pub struct UnixSocket {
+    listener: UnixListener,
+    path: String,
+}
+
+impl UnixSocket {
+    /// 和redis一样先删除已经存在的socket文件, perm为0时使用默认权限
+    pub fn bind(path: &str, perm: &str) -> Result<Self> {
+        let perm = u32::from_str_radix(perm, 8)?;
+        let _ = std::fs::remove_file(path);
+        let socket = Self {
+            listener: UnixListener::bind(path)?,
+            path: path.to_string(),
+        };
+        if perm != 0 {
+            std::fs::set_permissions(path, Permissions::from_mode(perm))?;
+        }
+        Ok(socket)
+    }
+
+    /// 和redis一样unix socket的地址写成 path:0
+    pub fn addr(&self) -> String {
+        format!("{}:0", self.path)
+    }
+
+    pub async fn accept(&self) -> std::io::Result<UnixStream> {
+        self.listener.accept().await.map(|(socket, _)| socket)
+    }
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// 按照配置限制请求的大小, 未认证的连接只允许很小的请求
+fn decode_limits(config: &Config, authenticated: bool) -> DecodeLimits {
+    let limits = DecodeLimits {
+        max_bulk_len: config.get_parsed("proto-max-bulk-len"),
+        max_query_buffer: config.get_parsed("client-query-buffer-limit"),
+        ..Default::default()
+    };
+    match authenticated {
+        true => limits,
+        false => limits.unauthenticated(),
+    }
+}
 
 /// how to get a frame from a stream
 /// call request_handler with the frame
 /// send the response back to the client
 // The backend here is Arc<BackendInner>
-pub async fn stream_handler(stream: TcpStream, backend: Backend) -> Result<()> {
-    let mut framed = Framed::new(stream, RespFrameCodec);
+// stream 可以是TCP, unix socket 或者TLS连接
+pub async fn stream_handler<S: AsyncStream>(
+    stream: S,
+    addr: &str,
+    laddr: &str,
+    backend: Backend,
+) -> Result<()> {
+    // 超过maxclients时回复错误后直接关闭
+    if backend.clients().len() >= backend.config().get_parsed::<usize>("maxclients") {
+        warn!("Max number of clients reached, rejecting {}", addr);
+        let mut framed = Framed::new(stream, RespFrameCodec::default());
+        framed
+            .send(SimpleError::new("ERR max number of clients reached").into())
+            .await?;
+        return Ok(());
+    }
+    let mut conn = Connection::new(&backend, addr, laddr);
+    backend.shutdown().connection_opened();
+    let ret = connection_loop(stream, backend.clone(), &mut conn).await;
+    // 如果这个连接是replica, 断开后不再计入WAIT
+    backend.replication().remove_replica(conn.id);
+    backend.clients().unregister(conn.id);
+    backend.tracking().disable(conn.id);
+    backend.shutdown().connection_closed();
+    ret
+}
+
+/// tls-port 上的连接, 握手成功之后和普通连接一样处理
+pub async fn tls_stream_handler<S: AsyncStream>(
+    stream: S,
+    acceptor: TlsAcceptor,
+    addr: &str,
+    laddr: &str,
+    backend: Backend,
+) -> Result<()> {
+    let stream = acceptor.accept(stream).await?;
+    stream_handler(stream, addr, laddr, backend).await
+}
+
+async fn connection_loop<S: AsyncStream>(
+    stream: S,
+    backend: Backend,
+    conn: &mut Connection,
+) -> Result<()> {
+    let mut framed = Framed::new(stream, RespFrameCodec::default());
+    let mut closing = backend.shutdown().subscribe();
+    let mut last_request = Instant::now();
     loop {
-        match framed.next().await {
+        // timeout可以用CONFIG SET修改, 每次等待时重新读取
+        let timeout: u64 = backend.config().get_parsed("timeout");
+        framed.codec_mut().limits = Some(decode_limits(backend.config(), conn.authenticated));
+        // 只在等待下一个请求时检查, 正在处理的请求会先回复
+        let frame = tokio::select! {
+            frame = framed.next() => frame,
+            // replica的连接没有请求时也不断开
+            _ = idle_timeout(last_request, timeout), if !conn.replica => {
+                info!("Client {} timed out, closing connection", conn.id);
+                return Ok(());
+            }
+            // watch::Ref 不是Send, 不能留在select的结果中跨过下面的await
+            _ = async { closing.wait_for(|closing| *closing).await.is_ok() } => {
+                info!("Server is shutting down, closing connection");
+                return Ok(());
+            }
+            _ = conn.client.killed() => {
+                info!("Client {} is killed, closing connection", conn.id);
+                return Ok(());
+            }
+            Some(push) = conn.pushes.recv() => {
+                if !send_limited(&mut framed, push, conn, &backend).await? {
+                    return Ok(());
+                }
+                continue;
+            }
+        };
+        match frame {
             Some(Ok(frame)) => {
-                info!("Received frame: {:?}", frame);
+                // 原始的frame中可能有密码, 只有Command的Debug输出会隐藏它们
+                trace!("Received frame of {} bytes", framed.codec().last_frame_len);
+                last_request = Instant::now();
+                conn.client.touch();
+                // 当前用户被ACL DELUSER 或者 ACL LOAD 删除后断开连接
+                if backend.acl().user(&conn.user).is_none() {
+                    info!("User {} was deleted, closing connection", conn.user);
+                    return Ok(());
+                }
                 let request = RedisRequest {
                     frame,
+                    len: framed.codec().last_frame_len,
                     backend: backend.clone(),
                 };
-                let response = match request_handler(request).await {
+                let response = match request_handler(request, conn).await {
                     Ok(response) => response,
                     Err(e) => RedisResponse {
-                        frame: SimpleError::new(e.to_string()).into(),
+                        frame: Some(SimpleError::new(e.to_string()).into()),
                     },
                 };
-                info!("Sending response: {:?}", response.frame);
-                framed.send(response.frame).await?;
+                conn.sync();
+                if let Some(frame) = response.frame {
+                    trace!("Sending response: {:?}", frame);
+                    if !send_limited(&mut framed, frame, conn, &backend).await? {
+                        return Ok(());
+                    }
+                }
             }
+            // 和redis一样回复协议错误之后关闭连接, 缓存的数据已经无法继续解析
             Some(Err(e)) => {
                 warn!("Error decoding frame: {}", e);
                 framed
-                    .send(RespFrame::SimpleError(SimpleError::new(e.to_string())))
-                    .await?
+                    .send(RespFrame::SimpleError(SimpleError::new(format!(
+                        "ERR {}",
+                        e
+                    ))))
+                    .await?;
+                return Ok(());
             }
             None => {
                 return {
@@ -61,20 +363,171 @@ pub async fn stream_handler(stream: TcpStream, backend: Backend) -> Result<()> {
     }
 }
 
-async fn request_handler(request: RedisRequest) -> Result<RedisResponse> {
-    let (frame, backend) = (request.frame, request.backend);
+/// 发送回复或者push消息, 没有发送出去的数据超过client-output-buffer-limit时返回false
+async fn send_limited<S: AsyncStream>(
+    framed: &mut Framed<S, RespFrameCodec>,
+    frame: RespFrame,
+    conn: &Connection,
+    backend: &Backend,
+) -> Result<bool> {
+    let limit = backend.clients().output_limit(&conn.client);
+    framed.feed(frame).await?;
+    let mut soft_since = None;
+    loop {
+        let pending = (framed.write_buffer().len() as u64) + conn.client.pending_pushes();
+        if limit.exceeded(pending, &mut soft_since) {
+            warn!(
+                "Client {} closed for overcoming of output buffer limits",
+                conn.id
+            );
+            backend.clients().record_output_limit_disconnection();
+            return Ok(false);
+        }
+        // 客户端读得慢时定期检查soft limit
+        if let Ok(flushed) = tokio::time::timeout(OUTPUT_CHECK_INTERVAL, framed.flush()).await {
+            flushed?;
+            return Ok(true);
+        }
+    }
+}
+
+/// timeout为0时一直等待
+async fn idle_timeout(since: Instant, timeout: u64) {
+    match timeout {
+        0 => std::future::pending().await,
+        timeout => sleep_until(since + Duration::from_secs(timeout)).await,
+    }
+}
+
+async fn request_handler(request: RedisRequest, conn: &mut Connection) -> Result<RedisResponse> {
+    let frame = request.frame;
+    let backend = match request.backend.select(conn.db) {
+        Some(backend) => backend.with_no_touch(conn.no_touch),
+        None => return Err(anyhow!("ERR DB index is out of range")),
+    };
     let command = Command::try_from(frame)?;
-    info!("Executing command: {:?}", command);
-    let response = command.execute(backend);
-    Ok(RedisResponse { frame: response })
+    debug!("Executing command: {:?}", command);
+    conn.client.update(|state| {
+        state.last_cmd = match command.subcommand() {
+            Some(sub) => format!("{}|{}", command.name(), sub),
+            None => command.name().to_string(),
+        }
+    });
+    // 和redis一样先检查命令和参数, 再检查认证
+    if !conn.authenticated && !command.is_no_auth() && backend.requires_auth() {
+        return Ok(RedisResponse {
+            frame: Some(SimpleError::new("NOAUTH Authentication required.").into()),
+        });
+    }
+    if let Err(denied) = backend.acl().check(&conn.user, &command) {
+        backend.acl_log(&denied, &conn.user, conn.client_info());
+        return Ok(RedisResponse {
+            frame: Some(SimpleError::new(denied.error(&conn.user)).into()),
+        });
+    }
+    let is_write = command.is_write();
+    // replica的连接不受CLIENT PAUSE影响
+    if !conn.replica && !matches!(command, Command::ReplConf(_)) {
+        backend.clients().wait_unpaused(is_write).await;
+    }
+    let asking = std::mem::take(&mut conn.asking) || command.is_asking();
+    let caching = conn.caching.take();
+    // 没有连接开启tracking时不需要复制key
+    let keys: Vec<Bytes> = match backend.tracking().is_active() {
+        true => command
+            .keys()
+            .into_iter()
+            .map(Bytes::copy_from_slice)
+            .collect(),
+        false => Vec::new(),
+    };
+    // SWAPDB 之后两个db中的所有key都变了, 和FLUSHDB一样通知清空缓存
+    let flush = matches!(
+        command,
+        Command::FlushDb(_) | Command::FlushAll(_) | Command::SwapDb(_)
+    );
+    if let Some(redirect) = backend
+        .cluster()
+        .route(&command.keys(), asking, |key| backend.exists(key))
+    {
+        return Ok(RedisResponse {
+            frame: Some(redirect.into()),
+        });
+    }
+    if command.is_denyoom() && !backend.evict_if_needed() {
+        return Ok(RedisResponse {
+            frame: Some(
+                SimpleError::new("OOM command not allowed when used memory > 'maxmemory'.").into(),
+            ),
+        });
+    }
+    // 需要连接状态或者需要异步等待的命令单独处理, 只挂起当前连接
+    let response = match command {
+        Command::Asking(_) => {
+            conn.asking = true;
+            RESP_OK.clone()
+        }
+        Command::Cluster(Cluster::Meet(ip, port)) => Cluster::meet(backend, ip, port).await,
+        Command::Migrate(migrate) => {
+            let response = migrate.migrate(backend.clone()).await;
+            // 迁移失败时key没有被删除, 不需要传播
+            if !matches!(response, RespFrame::SimpleError(_)) {
+                conn.write_offset = backend.replication().propagate(request.len);
+            }
+            response
+        }
+        Command::Wait(wait) => wait.wait(backend, conn.write_offset).await,
+        Command::WaitAof(wait) => wait.wait(backend, conn.write_offset).await,
+        Command::Select(select) => select.apply(&backend, &mut conn.db),
+        Command::Auth(auth) => auth.apply(&backend, conn),
+        Command::Hello(hello) => hello.apply(&backend, conn),
+        Command::Acl(acl) => acl.apply(&backend, conn),
+        Command::Shutdown(shutdown) => {
+            return Ok(RedisResponse {
+                frame: shutdown.shutdown(backend).await,
+            })
+        }
+        Command::Client(client) => client.apply(&backend, conn),
+        Command::ReplConf(replconf) => {
+            if replconf.is_replica_handshake() {
+                conn.replica = true;
+            }
+            return Ok(RedisResponse {
+                frame: replconf.apply(&backend, conn.id),
+            });
+        }
+        command => {
+            let response = command.execute(backend.clone());
+            if is_write {
+                conn.write_offset = backend.replication().propagate(request.len);
+            }
+            response
+        }
+    };
+    // backend 已经被上面的命令消费, tracking 和db无关
+    let backend = &request.backend;
+    // 执行失败的命令没有修改key
+    if !matches!(response, RespFrame::SimpleError(_)) {
+        if flush {
+            backend.invalidate_all();
+        } else if is_write {
+            backend.invalidate(&keys, conn.id);
+        } else if let Some(tracking) = &conn.tracking {
+            if tracking.should_track(caching) {
+                backend.tracking().remember(conn.id, &keys);
+            }
+        }
+    }
+    Ok(RedisResponse {
+        frame: Some(response),
+    })
 }
 
 impl Encoder<RespFrame> for RespFrameCodec {
     type Error = anyhow::Error;
 
     fn encode(&mut self, item: RespFrame, dst: &mut bytes::BytesMut) -> Result<()> {
-        let encoded = item.encode();
-        dst.extend_from_slice(&encoded);
+        item.encode_to(dst);
         Ok(())
     }
 }
@@ -84,10 +537,400 @@ impl Decoder for RespFrameCodec {
     type Error = anyhow::Error;
 
     fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>> {
-        match RespFrame::decode(src) {
-            Ok(frame) => Ok(Some(frame)),
-            Err(RespError::NotCompleteFrame) => Ok(None),
-            Err(e) => Err(e.into()),
+        if let Some(limits) = &self.limits {
+            limits.check_query_buffer(src.len())?;
+        }
+        // 客户端的请求不是以*开头时按inline命令解析, 和redis一样跳过空行,
+        // *-1 和 *0 也直接跳过, 不回复
+        let result = loop {
+            let inline = self.limits.is_some()
+                && self.parser.is_idle()
+                && src.first().is_some_and(|b| *b != b'*');
+            if !inline {
+                match self.parser.parse(src, self.limits.as_ref()) {
+                    Ok(Some((RespFrame::Array(args), _)))
+                        if self.limits.is_some() && args.is_empty() =>
+                    {
+                        continue
+                    }
+                    result => break result,
+                }
+            }
+            let before = src.len();
+            match decode_inline(src) {
+                Ok(args) if args.is_empty() => continue,
+                Ok(args) => break Ok(Some((args.into(), before - src.len()))),
+                Err(RespError::NotCompleteFrame) => break Ok(None),
+                Err(e) => break Err(e),
+            }
+        };
+        match result {
+            Ok(Some((frame, len))) => {
+                self.last_frame_len = len;
+                Ok(Some(frame))
+            }
+            Ok(None) => Ok(None),
+            Err(e @ RespError::ProtocolError(_)) => Err(e.into()),
+            // 其他的解析错误也按协议错误回复
+            Err(e) => Err(RespError::ProtocolError(e.to_string()).into()),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resp::RespPush;
+
+    fn backend(overrides: &[(&str, &str)]) -> Result<Backend> {
+        let overrides: Vec<(String, String)> = overrides
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Ok(Backend::from_config(Config::load(None, &overrides)?)?)
+    }
+
+    fn echo() -> RespFrame {
+        RespArray::new(vec![
+            BulkString::new("echo").into(),
+            BulkString::new("hello").into(),
+        ])
+        .into()
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("redis-unix-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("redis.sock");
+        // 上次没有正常退出时留下的文件
+        std::fs::write(&path, b"stale")?;
+        let path = path.to_str().unwrap().to_string();
+
+        let socket = UnixSocket::bind(&path, "700")?;
+        let mode = std::fs::metadata(&path)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        assert_eq!(socket.addr(), format!("{}:0", path));
+
+        let backend = backend(&[])?;
+        let client = UnixStream::connect(&path).await?;
+        let server = socket.accept().await?;
+        let addr = socket.addr();
+        tokio::spawn(async move { stream_handler(server, &addr, &addr, backend).await });
+        let mut framed = Framed::new(client, RespFrameCodec::default());
+        framed.send(echo()).await?;
+        assert_eq!(
+            framed.next().await.transpose()?,
+            Some(BulkString::new("hello").into())
+        );
+
+        drop(socket);
+        assert!(!std::path::Path::new(&path).exists());
+        assert!(UnixSocket::bind(&path, "8").is_err());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_maxclients() -> Result<()> {
+        let backend = backend(&[("maxclients", "1")])?;
+        let _conn = Connection::new(&backend, "127.0.0.1:1000", "127.0.0.1:6379");
+        let (client, server) = UnixStream::pair()?;
+        stream_handler(server, "127.0.0.1:1001", "127.0.0.1:6379", backend.clone()).await?;
+        let mut framed = Framed::new(client, RespFrameCodec::default());
+        assert_eq!(
+            framed.next().await.transpose()?,
+            Some(SimpleError::new("ERR max number of clients reached").into())
+        );
+        assert_eq!(backend.clients().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_oversized_bulk() -> Result<()> {
+        use tokio_util::codec::BytesCodec;
+
+        let backend = backend(&[("proto-max-bulk-len", "1mb")])?;
+        let (client, server) = UnixStream::pair()?;
+        let handler = tokio::spawn(stream_handler(
+            server,
+            "127.0.0.1:1000",
+            "127.0.0.1:6379",
+            backend,
+        ));
+        // 只发送头部, 不需要等数据到达就能拒绝
+        let mut framed = Framed::new(client, BytesCodec::new());
+        framed
+            .send(Bytes::from_static(
+                b"*3\r\n$3\r\nset\r\n$1\r\nk\r\n$999999999999\r\n",
+            ))
+            .await?;
+        handler.await??;
+        let reply = framed.next().await.transpose()?;
+        assert_eq!(
+            reply.as_deref(),
+            Some(&b"-ERR Protocol error: invalid bulk length\r\n"[..])
+        );
+        assert!(framed.next().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_malformed_requests() -> Result<()> {
+        use tokio_util::codec::BytesCodec;
+
+        type Handler = tokio::task::JoinHandle<Result<()>>;
+
+        fn connect(backend: &Backend) -> Result<(Framed<UnixStream, BytesCodec>, Handler)> {
+            let (client, server) = UnixStream::pair()?;
+            let handler = tokio::spawn(stream_handler(
+                server,
+                "127.0.0.1:1000",
+                "127.0.0.1:6379",
+                backend.clone(),
+            ));
+            Ok((Framed::new(client, BytesCodec::new()), handler))
+        }
+
+        let backend = backend(&[])?;
+        // 无法继续解析的数据, 回复协议错误之后关闭连接
+        let fatal: &[(&[u8], &str)] = &[
+            // 不认识的类型
+            (b"*1\r\n!oops\r\n", "expected '$', got '!'"),
+            (b"*1\r\n?x\r\n", "expected '$', got '?'"),
+            // 负数, 溢出和不是数字的长度
+            (b"*-2\r\n", "Invalid RESP frame length: -2"),
+            (b"*99999999999999999999\r\n", "invalid multibulk length"),
+            (b"*x\r\n", "invalid multibulk length"),
+            (b"*1\r\n$-5\r\n", "Invalid RESP frame length: -5"),
+            (b"*1\r\n$99999999999999999999\r\n", "invalid bulk length"),
+            (b"*1\r\n$-x\r\n", "invalid bulk length"),
+            (b"*1\r\n%-1\r\n", "expected '$', got '%'"),
+            (b"*1\r\n~99999999999999999999\r\n", "expected '$', got '~'"),
+            (b"*1\r\n>x\r\n", "expected '$', got '>'"),
+            // bulk string的数据后面没有CRLF
+            (b"*1\r\n$3\r\nget!!\r\n", "not terminated by CRLF"),
+            // 参数只能是bulk string
+            (b"*1\r\n*1\r\n$4\r\nping\r\n", "expected '$', got '*'"),
+            (b"*2\r\n$3\r\nget\r\n:1\r\n", "expected '$', got ':'"),
+            (b"*2\r\n$3\r\nget\r\n+k\r\n", "expected '$', got '+'"),
+            (b"*1\r\n,1.2.3\r\n", "expected '$', got ','"),
+            (b"*1\r\n#x\r\n", "expected '$', got '#'"),
+        ];
+        for (request, message) in fatal {
+            let (mut framed, handler) = connect(&backend)?;
+            framed.send(Bytes::from_static(request)).await?;
+            let reply = framed.next().await.transpose()?.unwrap_or_default();
+            let reply = String::from_utf8_lossy(&reply);
+            assert!(reply.starts_with("-ERR Protocol error: "), "{reply}");
+            assert!(reply.contains(message), "{reply}");
+            assert!(framed.next().await.is_none());
+            // 连接正常退出, 没有panic
+            handler.await??;
+        }
+
+        // 格式正确但不是合法的命令, 回复错误之后连接可以继续使用
+        let (mut framed, handler) = connect(&backend)?;
+        // *-1 和 *0 直接忽略, 没有回复
+        framed.send(Bytes::from_static(b"*-1\r\n*0\r\n")).await?;
+        framed.send(Bytes::from(echo().encode())).await?;
+        let reply = framed.next().await.transpose()?;
+        assert_eq!(reply.as_deref(), Some(&b"$5\r\nhello\r\n"[..]));
+        let invalid: &[&[u8]] = &[b"*1\r\n$-1\r\n", b"*2\r\n$-1\r\n$1\r\nk\r\n"];
+        for request in invalid {
+            framed.send(Bytes::from_static(request)).await?;
+            let reply = framed.next().await.transpose()?.unwrap_or_default();
+            assert!(reply.starts_with(b"-ERR Protocol error: "), "{reply:?}");
+        }
+        framed.send(Bytes::from(echo().encode())).await?;
+        let reply = framed.next().await.transpose()?;
+        assert_eq!(reply.as_deref(), Some(&b"$5\r\nhello\r\n"[..]));
+        drop(framed);
+        handler.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_inline_commands() -> Result<()> {
+        use tokio_util::codec::BytesCodec;
+
+        let (client, server) = UnixStream::pair()?;
+        let handler = tokio::spawn(stream_handler(
+            server,
+            "127.0.0.1:1000",
+            "127.0.0.1:6379",
+            backend(&[])?,
+        ));
+        let mut framed = Framed::new(client, BytesCodec::new());
+        framed
+            .send(Bytes::from_static(
+                b"\r\nset k \"hello world\"\n\nGET k\r\necho 'a\n",
+            ))
+            .await?;
+        let expected =
+            b"+OK\r\n$11\r\nhello world\r\n-ERR Protocol error: unbalanced quotes in request\r\n";
+        let mut replies = Vec::new();
+        while let Some(reply) = framed.next().await.transpose()? {
+            replies.extend_from_slice(&reply);
+        }
+        assert_eq!(
+            String::from_utf8_lossy(&replies),
+            String::from_utf8_lossy(expected)
+        );
+        handler.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replconf_and_failed_migrate() -> Result<()> {
+        let backend = backend(&[])?;
+        let (client, server) = UnixStream::pair()?;
+        tokio::spawn(stream_handler(
+            server,
+            "127.0.0.1:1000",
+            "127.0.0.1:6379",
+            backend.clone(),
+        ));
+        let mut framed = Framed::new(client, RespFrameCodec::default());
+        let mut call = async |args: &[&str]| -> Result<Option<RespFrame>> {
+            let args = args
+                .iter()
+                .map(|arg| BulkString::new(*arg).into())
+                .collect::<Vec<RespFrame>>();
+            framed.send(RespArray::new(args).into()).await?;
+            framed.next().await.transpose()
+        };
+        // 普通客户端发送的REPLCONF不会让连接变成replica
+        call(&["replconf", "capa", "psync2"]).await?;
+        let list = call(&["client", "list"]).await?;
+        let Some(RespFrame::BulkString(BulkString(Some(list)))) = list else {
+            panic!("unexpected reply: {list:?}");
+        };
+        assert!(String::from_utf8_lossy(&list).contains(" flags=N "));
+
+        call(&["set", "k", "v"]).await?;
+        let offset = backend.replication().master_offset();
+        let reply = call(&["migrate", "127.0.0.1", "1", "k", "0", "100"]).await?;
+        assert!(matches!(reply, Some(RespFrame::SimpleError(_))));
+        assert_eq!(backend.replication().master_offset(), offset);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tracking_invalidation() -> Result<()> {
+        fn command(args: &[&str]) -> RespFrame {
+            RespArray::new(
+                args.iter()
+                    .map(|arg| BulkString::new(*arg).into())
+                    .collect::<Vec<RespFrame>>(),
+            )
+            .into()
+        }
+        fn invalidate(keys: Option<&str>) -> RespFrame {
+            let keys = match keys {
+                Some(key) => RespArray::new(vec![BulkString::new(key).into()]),
+                None => RespArray(None),
+            };
+            RespPush::new(vec![BulkString::new("invalidate").into(), keys.into()]).into()
+        }
+
+        let backend = backend(&[])?;
+        let mut framed = Vec::new();
+        for port in [1000, 1001] {
+            let (client, server) = UnixStream::pair()?;
+            let addr = format!("127.0.0.1:{}", port);
+            let server_backend = backend.clone();
+            tokio::spawn(async move {
+                stream_handler(server, &addr, "127.0.0.1:6379", server_backend).await
+            });
+            framed.push(Framed::new(client, RespFrameCodec::default()));
+        }
+        let [reader, writer] = &mut framed[..] else {
+            unreachable!()
+        };
+        for args in [&["hello", "3"][..], &["client", "tracking", "on"]] {
+            reader.send(command(args)).await?;
+            reader.next().await.transpose()?;
+        }
+        for key in ["a", "b"] {
+            reader.send(command(&["get", key])).await?;
+            reader.next().await.transpose()?;
+        }
+
+        // 失败的RESTORE没有修改a
+        writer.send(command(&["restore", "a", "0", "bad"])).await?;
+        let reply = writer.next().await.transpose()?;
+        assert!(matches!(reply, Some(RespFrame::SimpleError(_))));
+        writer.send(command(&["set", "b", "v"])).await?;
+        writer.next().await.transpose()?;
+        assert_eq!(
+            reader.next().await.transpose()?,
+            Some(invalidate(Some("b")))
+        );
+
+        // MOVE 之后b不在当前db中了
+        reader.send(command(&["get", "b"])).await?;
+        reader.next().await.transpose()?;
+        writer.send(command(&["move", "b", "1"])).await?;
+        assert_eq!(
+            writer.next().await.transpose()?,
+            Some(RespFrame::Integer(1))
+        );
+        assert_eq!(
+            reader.next().await.transpose()?,
+            Some(invalidate(Some("b")))
+        );
+
+        writer.send(command(&["swapdb", "0", "1"])).await?;
+        writer.next().await.transpose()?;
+        assert_eq!(reader.next().await.transpose()?, Some(invalidate(None)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_output_buffer_limit() -> Result<()> {
+        let backend = backend(&[("client-output-buffer-limit", "normal 100 0 0")])?;
+        let (client, server) = UnixStream::pair()?;
+        let handler = tokio::spawn(stream_handler(
+            server,
+            "127.0.0.1:1000",
+            "127.0.0.1:6379",
+            backend.clone(),
+        ));
+        let mut framed = Framed::new(client, RespFrameCodec::default());
+        framed.send(echo()).await?;
+        assert!(framed.next().await.is_some());
+        let big = RespArray::new(vec![
+            BulkString::new("echo").into(),
+            BulkString::new("x".repeat(200)).into(),
+        ]);
+        framed.send(big.into()).await?;
+        handler.await??;
+        assert!(framed.next().await.is_none());
+        assert_eq!(backend.clients().output_limit_disconnections(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() -> Result<()> {
+        let backend = backend(&[("timeout", "1")])?;
+        let (client, server) = UnixStream::pair()?;
+        let start = Instant::now();
+        let handler = tokio::spawn(stream_handler(
+            server,
+            "127.0.0.1:1000",
+            "127.0.0.1:6379",
+            backend,
+        ));
+        let mut framed = Framed::new(client, RespFrameCodec::default());
+        framed.send(echo()).await?;
+        assert_eq!(
+            framed.next().await.transpose()?,
+            Some(BulkString::new("hello").into())
+        );
+        handler.await??;
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!(framed.next().await.is_none());
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use super::{
-    calc_total_length, parse_length, RespDecode, RespEncode, RespError, RespFrame, BUF_CAPACITY,
+    calc_total_length, parse_length, write_line, RespDecode, RespEncode, RespError, RespFrame,
     CRLF_LEN,
 };
 use bytes::{Buf, BytesMut};
@@ -8,16 +8,14 @@ use bytes::{Buf, BytesMut};
 pub struct RespArray(pub(crate) Option<Vec<RespFrame>>);
 
 impl RespEncode for RespArray {
-    fn encode(self) -> Vec<u8> {
+    fn encode_to(self, buf: &mut BytesMut) {
         match self.0 {
-            None => b"*-1\r\n".to_vec(),
+            None => buf.extend_from_slice(b"*-1\r\n"),
             Some(v) => {
-                let mut buf = Vec::with_capacity(BUF_CAPACITY);
-                buf.extend_from_slice(&format!("*{}\r\n", v.len()).into_bytes());
+                write_line(buf, b'*', v.len());
                 for frame in v {
-                    buf.extend_from_slice(&frame.encode());
+                    frame.encode_to(buf);
                 }
-                buf
             }
         }
     }
@@ -51,7 +49,7 @@ impl RespDecode for RespArray {
     fn expect_length(buf: &[u8]) -> Result<usize, RespError> {
         let (end, len) = parse_length(buf, Self::PREFIX, Self::FRAME_TYPE)?;
         if len == -1 {
-            return Ok(4);
+            return Ok(end + CRLF_LEN);
         }
         calc_total_length(buf, end, len as usize, Self::PREFIX)
     }
@@ -98,6 +96,22 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_array_encode_to_non_empty() {
+        let mut buf = BytesMut::from("$3\r\nfoo\r\n");
+        let array = RespArray::new(vec![
+            BulkString::new("bar").into(),
+            RespArray::new(vec![BulkString::new_null_string().into()]).into(),
+            RespArray::new_null_array().into(),
+        ]);
+        array.encode_to(&mut buf);
+        RespArray::new(vec![]).encode_to(&mut buf);
+        assert_eq!(
+            String::from_utf8_lossy(&buf),
+            "$3\r\nfoo\r\n*3\r\n$3\r\nbar\r\n*1\r\n$-1\r\n*-1\r\n*0\r\n"
+        );
+    }
+
     #[test]
     fn test_null_array() {
         let frame: RespFrame = RespArray::new_null_array().into();
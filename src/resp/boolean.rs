@@ -1,10 +1,10 @@
 use bytes::BytesMut;
 
-use super::{extract_fixed_data, RespDecode, RespEncode, RespError};
+use super::{extract_fixed_data, write_line, RespDecode, RespEncode, RespError};
 
 impl RespEncode for bool {
-    fn encode(self) -> Vec<u8> {
-        format!("#{}\r\n", if self { 't' } else { 'f' }).into_bytes()
+    fn encode_to(self, buf: &mut BytesMut) {
+        write_line(buf, b'#', if self { 't' } else { 'f' });
     }
 }
 
@@ -12,13 +12,10 @@ impl RespDecode for bool {
     const PREFIX: &'static str = "#";
     const FRAME_TYPE: &'static str = "Bool";
     fn decode(buf: &mut BytesMut) -> Result<Self, RespError> {
-        match extract_fixed_data(buf, Self::PREFIX, Self::FRAME_TYPE) {
+        match extract_fixed_data(buf, "#t\r\n", Self::FRAME_TYPE) {
             Ok(_) => Ok(true),
             Err(RespError::NotCompleteFrame) => Err(RespError::NotCompleteFrame),
-            Err(_) => match extract_fixed_data(buf, Self::PREFIX, Self::FRAME_TYPE) {
-                Ok(_) => Ok(false),
-                Err(e) => Err(e),
-            },
+            Err(_) => extract_fixed_data(buf, "#f\r\n", Self::FRAME_TYPE).map(|_| false),
         }
     }
 
@@ -40,4 +37,12 @@ mod test {
         let frame: RespFrame = false.into();
         assert_eq!(frame.encode(), b"#f\r\n");
     }
+
+    #[test]
+    fn test_boolean_decode() {
+        let mut buf = BytesMut::from("#t\r\n#f\r\n#x\r\n");
+        assert_eq!(bool::decode(&mut buf), Ok(true));
+        assert_eq!(bool::decode(&mut buf), Ok(false));
+        assert!(bool::decode(&mut buf).is_err());
+    }
 }
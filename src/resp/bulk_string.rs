@@ -1,15 +1,19 @@
-use super::{parse_length, RespDecode, RespEncode, RespError, CRLF_LEN};
-use bytes::{Buf, BytesMut};
+use super::{parse_length, write_line, RespDecode, RespEncode, RespError, CRLF, CRLF_LEN};
+use bytes::{Buf, Bytes, BytesMut};
 use std::{
     fmt::{self, Display, Formatter},
     ops::Deref,
 };
 
+/// 大于这个长度的参数直接引用读缓存, 不再复制, 和redis的PROTO_MBULK_BIG_ARG一样。
+/// 小的参数仍然复制, 否则保存下来的一个小value会让整块读缓存无法释放
+const ZERO_COPY_MIN_LEN: usize = 32 * 1024;
+
 #[derive(Debug, PartialEq, PartialOrd, Clone, Eq)]
-pub struct BulkString(pub(crate) Option<Vec<u8>>);
+pub struct BulkString(pub(crate) Option<Bytes>);
 
 impl Deref for BulkString {
-    type Target = Option<Vec<u8>>;
+    type Target = Option<Bytes>;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
@@ -19,16 +23,14 @@ impl Deref for BulkString {
 // pub struct RespNullBulkString;
 
 impl RespEncode for BulkString {
-    fn encode(self) -> Vec<u8> {
+    fn encode_to(self, buf: &mut BytesMut) {
         match self.as_deref() {
             Some(v) => {
-                let mut buf = Vec::with_capacity(v.len() + 16);
-                buf.extend_from_slice(format!("${}\r\n", v.len()).as_bytes());
+                write_line(buf, b'$', v.len());
                 buf.extend_from_slice(v);
-                buf.extend_from_slice(b"\r\n");
-                buf
+                buf.extend_from_slice(CRLF);
             }
-            None => "$-1\r\n".as_bytes().to_vec(),
+            None => buf.extend_from_slice(b"$-1\r\n"),
         }
     }
 }
@@ -51,8 +53,19 @@ impl RespDecode for BulkString {
             return Err(RespError::NotCompleteFrame);
         }
         buf.advance(end + CRLF_LEN);
-        let data = buf.split_to(len + CRLF_LEN);
-        Ok(BulkString::new(data[..len].to_vec()))
+        if !buf[len..].starts_with(CRLF) {
+            return Err(not_terminated());
+        }
+        let data = match len < ZERO_COPY_MIN_LEN {
+            true => {
+                let data = Bytes::copy_from_slice(&buf[..len]);
+                buf.advance(len);
+                data
+            }
+            false => buf.split_to(len).freeze(),
+        };
+        buf.advance(CRLF_LEN);
+        Ok(BulkString(Some(data)))
     }
 
     fn expect_length(buf: &[u8]) -> Result<usize, RespError> {
@@ -60,13 +73,22 @@ impl RespDecode for BulkString {
         if len == -1 {
             return Ok(end + CRLF_LEN);
         }
-        Ok(end + CRLF_LEN + len as usize + CRLF_LEN)
+        let total = end + CRLF_LEN + len as usize + CRLF_LEN;
+        match buf.get(total - CRLF_LEN..total) {
+            Some(tail) if tail != CRLF => Err(not_terminated()),
+            _ => Ok(total),
+        }
     }
 }
 
+fn not_terminated() -> RespError {
+    RespError::ProtocolError("bulk string is not terminated by CRLF".to_string())
+}
+
 impl BulkString {
+    /// Vec转换成Bytes不需要复制, 已经是Bytes的数据用From<Bytes>
     pub fn new(s: impl Into<Vec<u8>>) -> Self {
-        BulkString(Some(s.into()))
+        BulkString(Some(Bytes::from(s.into())))
     }
     pub fn new_null_string() -> Self {
         BulkString(None)
@@ -76,13 +98,19 @@ impl BulkString {
 /// 这个写法需要好好记一下
 impl<const N: usize> From<&[u8; N]> for BulkString {
     fn from(s: &[u8; N]) -> Self {
-        BulkString(Some(s.to_vec()))
+        BulkString(Some(Bytes::copy_from_slice(s)))
     }
 }
 
 impl From<&str> for BulkString {
     fn from(s: &str) -> Self {
-        BulkString(Some(s.as_bytes().to_vec()))
+        BulkString(Some(Bytes::copy_from_slice(s.as_bytes())))
+    }
+}
+
+impl From<Bytes> for BulkString {
+    fn from(s: Bytes) -> Self {
+        BulkString(Some(s))
     }
 }
 
@@ -135,6 +163,71 @@ mod test {
         assert_eq!(frame, 11);
         Ok(())
     }
+    #[test]
+    fn test_bulk_string_zero_copy() -> Result<()> {
+        let value = vec![b'x'; ZERO_COPY_MIN_LEN];
+        let mut buf = BytesMut::new();
+        BulkString::new(value.clone()).encode_to(&mut buf);
+        let range = buf.as_ptr_range();
+        let frame = BulkString::decode(&mut buf)?;
+        // 大的参数直接引用读缓存
+        let data = frame.as_deref().unwrap_or_default();
+        assert!(range.contains(&data.as_ptr()));
+        assert_eq!(data, value);
+        assert!(buf.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_string_small_copy() -> Result<()> {
+        let value = vec![b'x'; ZERO_COPY_MIN_LEN - 1];
+        let mut buf = BytesMut::new();
+        BulkString::new(value.clone()).encode_to(&mut buf);
+        let range = buf.as_ptr_range();
+        let len = buf.len();
+        let frame = BulkString::decode(&mut buf)?;
+        let data = frame.as_deref().unwrap_or_default();
+        assert!(!range.contains(&data.as_ptr()));
+        assert_eq!(data, value);
+        // 没有被引用的读缓存可以直接重用
+        buf.reserve(len);
+        assert_eq!(buf.as_ptr(), range.start);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_string_zero_copy_boundary() -> Result<()> {
+        let value = vec![b'x'; ZERO_COPY_MIN_LEN];
+        let mut encoded = BytesMut::new();
+        BulkString::new(value.clone()).encode_to(&mut encoded);
+        // 分两次到达, 后面还有下一个请求
+        let (head, tail) = encoded.split_at(encoded.len() / 2);
+        let mut buf = BytesMut::from(head);
+        assert_eq!(
+            BulkString::decode(&mut buf),
+            Err(RespError::NotCompleteFrame)
+        );
+        buf.extend_from_slice(tail);
+        buf.extend_from_slice(b"$1\r\nx\r\n");
+        let len = buf.len();
+        let frame = BulkString::decode(&mut buf)?;
+        assert_eq!(frame.as_deref(), Some(&value[..]));
+        assert_eq!(&buf[..], b"$1\r\nx\r\n");
+        // 读缓存被frame引用, 扩容时不能覆盖它
+        buf.reserve(len);
+        assert_eq!(BulkString::decode(&mut buf)?, BulkString::new("x"));
+        assert_eq!(frame.as_deref(), Some(&value[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_string_encode_to_non_empty() {
+        let mut buf = BytesMut::from("+OK\r\n");
+        BulkString::new("hello").encode_to(&mut buf);
+        BulkString::new_null_string().encode_to(&mut buf);
+        assert_eq!(&buf[..], b"+OK\r\n$5\r\nhello\r\n$-1\r\n");
+    }
+
     #[test]
     fn test_null_bulk_string_decode() -> Result<()> {
         let mut buf = BytesMut::from("$-1\r\n");
@@ -1,11 +1,11 @@
 use super::{extract_simple_frame_data, RespEncode};
 use bytes::BytesMut;
 
-use super::{RespDecode, RespError, CRLF_LEN};
+use super::{write_line, RespDecode, RespError, CRLF_LEN};
 
 impl RespEncode for f64 {
-    fn encode(self) -> Vec<u8> {
-        format!(",{:+e}\r\n", self).into_bytes()
+    fn encode_to(self, buf: &mut BytesMut) {
+        write_line(buf, b',', format_args!("{:+e}", self));
     }
 }
 
@@ -4,8 +4,8 @@ use bytes::BytesMut;
 use enum_dispatch::enum_dispatch;
 
 use crate::resp::{
-    array::RespArray, bulk_string::BulkString, map::RespMap, null::RespNull, set::RespSet,
-    simple_error::SimpleError, simple_string::SimpleString,
+    array::RespArray, bulk_string::BulkString, map::RespMap, null::RespNull, push::RespPush,
+    set::RespSet, simple_error::SimpleError, simple_string::SimpleString,
 };
 
 use super::{RespDecode, RespError};
@@ -26,6 +26,7 @@ pub enum RespFrame {
     Double(f64),
     Map(RespMap),
     Set(RespSet),
+    Push(RespPush),
 }
 
 /// 这里强行加Eq，遇到f64类型应该会报错
@@ -84,8 +85,12 @@ impl RespDecode for RespFrame {
                 let frame = RespSet::decode(buf)?;
                 Ok(frame.into())
             }
+            Some(b'>') => {
+                let frame = RespPush::decode(buf)?;
+                Ok(frame.into())
+            }
+            Some(prefix) => Err(invalid_prefix(**prefix)),
             None => Err(RespError::NotCompleteFrame),
-            _ => todo!(),
         }
     }
 
@@ -104,10 +109,19 @@ impl RespDecode for RespFrame {
             Some(b',') => f64::expect_length(buf),
             Some(b'%') => RespMap::expect_length(buf),
             Some(b'~') => RespSet::expect_length(buf),
-            _ => Err(RespError::NotCompleteFrame),
+            Some(b'>') => RespPush::expect_length(buf),
+            Some(prefix) => Err(invalid_prefix(**prefix)),
+            None => Err(RespError::NotCompleteFrame),
         }
     }
 }
+
+fn invalid_prefix(prefix: u8) -> RespError {
+    RespError::ProtocolError(format!(
+        "invalid frame type prefix '{}'",
+        (prefix as char).escape_default()
+    ))
+}
 impl From<&str> for RespFrame {
     fn from(s: &str) -> Self {
         SimpleString(s.to_string()).into()
@@ -116,14 +130,79 @@ impl From<&str> for RespFrame {
 
 impl From<&[u8]> for RespFrame {
     fn from(s: &[u8]) -> Self {
-        BulkString(Some(s.to_vec())).into()
+        BulkString::new(s.to_vec()).into()
     }
 }
 
 impl<const N: usize> From<&[u8; N]> for RespFrame {
     fn from(s: &[u8; N]) -> Self {
-        BulkString(Some(s.to_vec())).into()
+        BulkString::new(s.to_vec()).into()
     }
 }
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+
+    fn decode(data: &[u8]) -> Result<RespFrame, RespError> {
+        RespFrame::decode(&mut BytesMut::from(data))
+    }
+
+    #[test]
+    fn test_hostile_input() {
+        let invalid: &[&[u8]] = &[
+            b"?foo\r\n",
+            b"\r\n",
+            b"*1\r\n!oops\r\n",
+            b"*x\r\n",
+            b"*-2\r\n",
+            b"$-5\r\n",
+            b"$abc\r\n",
+            b"$3\r\nabcdef\r\n",
+            b"%-1\r\n",
+            b"~-1\r\n",
+            b">-1\r\n",
+            b"*99999999999999999999\r\n",
+            b"$99999999999999999999\r\n",
+            b"%99999999999999999999\r\n",
+            b"~99999999999999999999\r\n",
+            b">99999999999999999999\r\n",
+            b"%x\r\n",
+            b"~x\r\n",
+            b">x\r\n",
+            b"#x\r\n",
+            b"#tt\r\n",
+            b":12a\r\n",
+            b",1.2.3\r\n",
+            b",abc\r\n",
+        ];
+        for data in invalid {
+            // expect_length只负责计算长度, 不要求报错, 但不能panic
+            let _ = RespFrame::expect_length(data);
+            let err = decode(data).unwrap_err();
+            assert_ne!(err, RespError::NotCompleteFrame, "{:?}", data);
+        }
+
+        // 数据不完整时等待更多的数据
+        let incomplete: &[&[u8]] = &[
+            b"",
+            b"*2\r\n$10\r\nab\r\n",
+            b"*3\r\n$3\r\nget\r\n",
+            b"$5\r\nhel",
+            b"%1\r\n+key\r\n",
+            b"*1\r\n*1\r\n*1\r\n",
+        ];
+        for data in incomplete {
+            assert_eq!(decode(data), Err(RespError::NotCompleteFrame), "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn test_null_frames() {
+        let mut buf = BytesMut::from("*-1\r\n$-1\r\n_\r\n");
+        assert_eq!(RespFrame::expect_length(&buf), Ok(5));
+        assert_eq!(RespFrame::decode(&mut buf), Ok(RespArray(None).into()));
+        assert_eq!(RespFrame::decode(&mut buf), Ok(BulkString(None).into()));
+        assert_eq!(RespFrame::decode(&mut buf), Ok(RespNull.into()));
+        assert!(buf.is_empty());
+    }
+}
@@ -0,0 +1,63 @@
+//! inline命令: telnet, redis-cli 的管道模式和健康检查发送的 `PING\r\n` 这样的一行命令
+//!
+//! 和redis的sdssplitargs一样按空白分割参数, 支持单引号和双引号
+
+use bytes::BytesMut;
+
+use super::{frame::RespFrame, BulkString, RespArray, RespError};
+use crate::split_args::split_args;
+
+/// 还没有收到换行时最多缓存的数据, 和redis的PROTO_INLINE_MAX_SIZE一样
+pub(super) const INLINE_MAX_SIZE: usize = 64 * 1024;
+
+fn protocol_error(message: &str) -> RespError {
+    RespError::ProtocolError(message.to_string())
+}
+
+/// 从buf中取出一行并解析成和RESP请求一样的数组, 空行返回空数组
+pub fn decode_inline(buf: &mut BytesMut) -> Result<RespArray, RespError> {
+    let Some(end) = buf.iter().position(|b| *b == b'\n') else {
+        if buf.len() > INLINE_MAX_SIZE {
+            return Err(protocol_error("too big inline request"));
+        }
+        return Err(RespError::NotCompleteFrame);
+    };
+    let line = buf.split_to(end + 1);
+    let line = &line[..end];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let args = split_args(line)
+        .ok_or_else(|| protocol_error("unbalanced quotes in request"))?
+        .into_iter()
+        .map(|arg| BulkString::new(arg).into())
+        .collect::<Vec<RespFrame>>();
+    Ok(RespArray::new(args))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_inline() {
+        let mut buf = BytesMut::from("PING\r\nset k v\nget");
+        assert_eq!(
+            decode_inline(&mut buf),
+            Ok(RespArray::new(vec![BulkString::new("PING").into()]))
+        );
+        assert_eq!(decode_inline(&mut buf).map(|args| args.len()), Ok(3));
+        assert_eq!(decode_inline(&mut buf), Err(RespError::NotCompleteFrame));
+        assert_eq!(&buf[..], b"get");
+
+        let mut buf = BytesMut::from(&b"set \"foo\"bar\n"[..]);
+        assert_eq!(
+            decode_inline(&mut buf),
+            Err(protocol_error("unbalanced quotes in request"))
+        );
+
+        let mut buf = BytesMut::from(&[b'a'; INLINE_MAX_SIZE + 1][..]);
+        assert_eq!(
+            decode_inline(&mut buf),
+            Err(protocol_error("too big inline request"))
+        );
+    }
+}
@@ -1,12 +1,11 @@
 use bytes::BytesMut;
 
-use super::{extract_simple_frame_data, RespDecode, RespEncode, RespError, CRLF_LEN};
+use super::{extract_simple_frame_data, write_line, RespDecode, RespEncode, RespError, CRLF_LEN};
 
 impl RespEncode for i64 {
-    fn encode(self) -> Vec<u8> {
+    fn encode_to(self, buf: &mut BytesMut) {
         // 如果是负数，format自己会加上负号，正数会省略所以补上
-        let sign = if self < 0 { "" } else { "+" };
-        format!(":{}{}\r\n", sign, self).into_bytes()
+        write_line(buf, b':', format_args!("{:+}", self));
     }
 }
 
@@ -0,0 +1,137 @@
+//! 请求大小的限制, 防止客户端声明一个很大的长度让服务端一直缓存数据
+//!
+//! 解析请求时检查每个元素的头部, 不需要等整个请求到达
+
+use super::{find_crlf, inline::INLINE_MAX_SIZE, RespError};
+
+/// 未认证的连接和redis一样只允许很小的请求
+const UNAUTHENTICATED_MULTIBULK_LEN: usize = 10;
+const UNAUTHENTICATED_BULK_LEN: usize = 16384;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// proto-max-bulk-len
+    pub max_bulk_len: usize,
+    /// 一个请求最多的参数个数
+    pub max_multibulk_len: usize,
+    /// client-query-buffer-limit, 缓存的还没有解析的数据的上限
+    pub max_query_buffer: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_bulk_len: 512 << 20,
+            max_multibulk_len: i32::MAX as usize,
+            max_query_buffer: 1 << 30,
+        }
+    }
+}
+
+fn protocol_error(message: &str) -> RespError {
+    RespError::ProtocolError(message.to_string())
+}
+
+fn parse_header(line: &[u8]) -> Option<i64> {
+    std::str::from_utf8(line).ok()?.parse().ok()
+}
+
+impl DecodeLimits {
+    pub fn unauthenticated(self) -> Self {
+        Self {
+            max_bulk_len: self.max_bulk_len.min(UNAUTHENTICATED_BULK_LEN),
+            max_multibulk_len: self.max_multibulk_len.min(UNAUTHENTICATED_MULTIBULK_LEN),
+            ..self
+        }
+    }
+
+    /// 缓存的还没有解析的数据
+    pub fn check_query_buffer(&self, len: usize) -> Result<(), RespError> {
+        match len > self.max_query_buffer {
+            true => Err(protocol_error("query buffer limit exceeded")),
+            false => Ok(()),
+        }
+    }
+
+    /// 检查请求中一个元素的头部, 不需要等数据到达就能拒绝很大的长度,
+    /// 请求的参数只能是bulk string, 不允许嵌套的类型
+    pub fn check_header(&self, buf: &[u8], nested: bool) -> Result<(), RespError> {
+        let (max, invalid, too_big) = match (buf.first(), nested) {
+            (Some(b'*'), false) => (
+                self.max_multibulk_len,
+                "invalid multibulk length",
+                "too big mbulk count string",
+            ),
+            (Some(b'$'), true) => (
+                self.max_bulk_len,
+                "invalid bulk length",
+                "too big bulk count string",
+            ),
+            (Some(prefix), true) => {
+                return Err(protocol_error(&format!(
+                    "expected '$', got '{}'",
+                    (*prefix as char).escape_default()
+                )))
+            }
+            _ => return Ok(()),
+        };
+        let Some(end) = find_crlf(buf) else {
+            return match buf.len() > INLINE_MAX_SIZE {
+                true => Err(protocol_error(too_big)),
+                false => Ok(()),
+            };
+        };
+        match parse_header(&buf[1..end]) {
+            Some(len) if len <= max as i64 => Ok(()),
+            _ => Err(protocol_error(invalid)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_limits() {
+        let limits = DecodeLimits {
+            max_bulk_len: 5,
+            max_multibulk_len: 3,
+            max_query_buffer: 64,
+        };
+        assert_eq!(limits.check_header(b"*2\r\n", false), Ok(()));
+        assert_eq!(limits.check_header(b"$5\r\nhel", true), Ok(()));
+        // 头部还不完整时等待更多数据
+        assert_eq!(limits.check_header(b"*99", false), Ok(()));
+        assert_eq!(
+            limits.check_header(b"$999999999999\r\n", true),
+            Err(protocol_error("invalid bulk length"))
+        );
+        assert_eq!(
+            limits.check_header(b"*4\r\n", false),
+            Err(protocol_error("invalid multibulk length"))
+        );
+        assert_eq!(
+            limits.check_header(b"*1\r\n", true),
+            Err(protocol_error("expected '$', got '*'"))
+        );
+        assert_eq!(
+            limits.check_header(&[b'*'; INLINE_MAX_SIZE + 1], false),
+            Err(protocol_error("too big mbulk count string"))
+        );
+        assert_eq!(
+            limits.check_query_buffer(65),
+            Err(protocol_error("query buffer limit exceeded"))
+        );
+
+        let limits = DecodeLimits::default().unauthenticated();
+        assert_eq!(
+            limits.check_header(b"*11\r\n", false),
+            Err(protocol_error("invalid multibulk length"))
+        );
+        assert_eq!(
+            limits.check_header(b"$20000\r\n", true),
+            Err(protocol_error("invalid bulk length"))
+        );
+    }
+}
@@ -5,8 +5,8 @@ use std::{
 };
 
 use super::{
-    calc_total_length, parse_length, simple_string::SimpleString, RespDecode, RespEncode,
-    RespError, RespFrame, BUF_CAPACITY, CRLF_LEN,
+    calc_total_length, parse_aggregate_length, simple_string::SimpleString, write_line, RespDecode,
+    RespEncode, RespError, RespFrame, CRLF_LEN,
 };
 
 /// Now only support string key which encode to SimpleString
@@ -14,15 +14,12 @@ use super::{
 pub struct RespMap(pub(crate) BTreeMap<String, RespFrame>);
 
 impl RespEncode for RespMap {
-    fn encode(self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(BUF_CAPACITY);
-        buf.extend_from_slice(&format!("%{}\r\n", self.0.len()).into_bytes());
+    fn encode_to(self, buf: &mut BytesMut) {
+        write_line(buf, b'%', self.0.len());
         for (k, v) in self.0 {
-            // buf.extend_from_slice(format!("+{}\r\n", k).as_bytes());
-            buf.extend_from_slice(&SimpleString::new(k).encode());
-            buf.extend_from_slice(&v.encode());
+            write_line(buf, b'+', k);
+            v.encode_to(buf);
         }
-        buf
     }
 }
 
@@ -30,8 +27,7 @@ impl RespDecode for RespMap {
     const PREFIX: &'static str = "%";
     const FRAME_TYPE: &'static str = "RespMap";
     fn decode(buf: &mut BytesMut) -> Result<Self, RespError> {
-        let (end, len) = parse_length(buf, Self::PREFIX, Self::FRAME_TYPE)?;
-        let len = len as usize;
+        let (end, len) = parse_aggregate_length(buf, Self::PREFIX, Self::FRAME_TYPE)?;
         let total_len = calc_total_length(buf, end, len, Self::PREFIX)?;
         if buf.len() < total_len {
             return Err(RespError::NotCompleteFrame);
@@ -47,8 +43,7 @@ impl RespDecode for RespMap {
     }
 
     fn expect_length(buf: &[u8]) -> Result<usize, RespError> {
-        let (end, len) = parse_length(buf, Self::PREFIX, Self::FRAME_TYPE)?;
-        let len = len as usize;
+        let (end, len) = parse_aggregate_length(buf, Self::PREFIX, Self::FRAME_TYPE)?;
         calc_total_length(buf, end, len, "%")
     }
 }
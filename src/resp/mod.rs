@@ -5,24 +5,29 @@ pub mod decode;
 pub mod double;
 pub mod encode;
 pub mod frame;
+pub mod inline;
 pub mod integer;
+pub mod limits;
 pub mod map;
 pub mod null;
+pub mod parser;
+pub mod push;
 pub mod set;
 pub mod simple_error;
 pub mod simple_string;
 pub use crate::resp::{
-    array::RespArray, bulk_string::BulkString, map::RespMap, null::RespNull, set::RespSet,
+    array::RespArray, bulk_string::BulkString, inline::decode_inline, limits::DecodeLimits,
+    map::RespMap, null::RespNull, parser::RespParser, push::RespPush, set::RespSet,
     simple_error::SimpleError, simple_string::SimpleString,
 };
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
 use enum_dispatch::enum_dispatch;
 use frame::RespFrame;
+use std::fmt::{Display, Write};
 use thiserror::Error;
 
 const CRLF: &[u8] = b"\r\n";
 const CRLF_LEN: usize = CRLF.len();
-const BUF_CAPACITY: usize = 4096;
 
 #[derive(Debug, Error, PartialEq, Eq)]
 pub enum RespError {
@@ -32,6 +37,8 @@ pub enum RespError {
     InvalidFrameType(String),
     #[error("Invalid RESP frame length: {0}")]
     InvalidFrameLength(isize),
+    #[error("Protocol error: {0}")]
+    ProtocolError(String),
     #[error("Frame is not complete")]
     NotCompleteFrame,
     #[error("parse int error: {0}")]
@@ -44,7 +51,17 @@ pub enum RespError {
 
 #[enum_dispatch]
 pub trait RespEncode {
-    fn encode(self) -> Vec<u8>;
+    /// 直接写入目标缓存, 不产生中间的Vec
+    fn encode_to(self, buf: &mut BytesMut);
+
+    fn encode(self) -> Vec<u8>
+    where
+        Self: Sized,
+    {
+        let mut buf = BytesMut::new();
+        self.encode_to(&mut buf);
+        buf.into()
+    }
 }
 
 pub trait RespDecode: Sized {
@@ -54,6 +71,14 @@ pub trait RespDecode: Sized {
     fn expect_length(buf: &[u8]) -> Result<usize, RespError>;
 }
 
+/// 写入 `{prefix}{value}\r\n`
+fn write_line(buf: &mut BytesMut, prefix: u8, value: impl Display) {
+    buf.put_u8(prefix);
+    // BytesMut的fmt::Write不会失败
+    let _ = write!(buf, "{value}");
+    buf.extend_from_slice(CRLF);
+}
+
 fn find_crlf(buf: &[u8]) -> Option<usize> {
     buf.windows(2).position(|window| window == CRLF)
 }
@@ -63,10 +88,10 @@ fn calc_total_length(buf: &[u8], end: usize, len: usize, prefix: &str) -> Result
     let mut total = end + CRLF_LEN;
     let mut data = &buf[total..];
     match prefix {
-        "*" | "~" => {
+        "*" | "~" | ">" => {
             for _ in 0..len {
                 let len = RespFrame::expect_length(data)?;
-                data = &data[len..];
+                data = data.get(len..).ok_or(RespError::NotCompleteFrame)?;
                 total += len;
             }
             Ok(total)
@@ -74,10 +99,10 @@ fn calc_total_length(buf: &[u8], end: usize, len: usize, prefix: &str) -> Result
         "%" => {
             for _ in 0..len {
                 let len = SimpleString::expect_length(data)?;
-                data = &data[len..];
+                data = data.get(len..).ok_or(RespError::NotCompleteFrame)?;
                 total += len;
                 let len = RespFrame::expect_length(data)?;
-                data = &data[len..];
+                data = data.get(len..).ok_or(RespError::NotCompleteFrame)?;
                 total += len;
             }
             Ok(total)
@@ -152,6 +177,18 @@ fn parse_length(buf: &[u8], prefix: &str, frame_type: &str) -> Result<(usize, is
     Ok((end, len))
 }
 
+/// map/set/push 没有null, 长度必须是非负数
+fn parse_aggregate_length(
+    buf: &[u8],
+    prefix: &str,
+    frame_type: &str,
+) -> Result<(usize, usize), RespError> {
+    match parse_length(buf, prefix, frame_type)? {
+        (_, -1) => Err(RespError::InvalidFrameLength(-1)),
+        (end, len) => Ok((end, len as usize)),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use anyhow::Result;
@@ -6,8 +6,8 @@ use super::{extract_fixed_data, RespDecode, RespEncode, RespError};
 pub struct RespNull;
 
 impl RespEncode for RespNull {
-    fn encode(self) -> Vec<u8> {
-        b"_\r\n".to_vec()
+    fn encode_to(self, buf: &mut BytesMut) {
+        buf.extend_from_slice(b"_\r\n");
     }
 }
 
@@ -15,7 +15,7 @@ impl RespDecode for RespNull {
     const PREFIX: &'static str = "_";
     const FRAME_TYPE: &'static str = "Null";
     fn decode(data: &mut BytesMut) -> Result<Self, RespError> {
-        extract_fixed_data(data, Self::PREFIX, Self::FRAME_TYPE)?;
+        extract_fixed_data(data, "_\r\n", Self::FRAME_TYPE)?;
         Ok(RespNull)
     }
 
@@ -34,6 +34,7 @@ mod test {
         let mut buf = BytesMut::from("_\r\n");
         let frame = RespNull::decode(&mut buf)?;
         assert_eq!(frame, RespNull);
+        assert!(buf.is_empty());
         Ok(())
     }
 }
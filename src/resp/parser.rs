@@ -0,0 +1,282 @@
+//! 增量的RESP解析器, 在多次decode之间保存还没有收完的聚合类型
+//!
+//! 解析出来的元素立刻从buf中取走, 数据分多次到达时从上次停下的地方继续,
+//! 解析的代价和数据的长度成线性关系, 和数据被拆成多少次到达无关
+
+use bytes::{Buf, BytesMut};
+
+use super::{
+    frame::RespFrame, parse_length, DecodeLimits, RespArray, RespDecode, RespError, RespMap,
+    RespPush, RespSet, SimpleString, CRLF_LEN,
+};
+
+/// 元素个数很大时不预先分配, 防止客户端声明一个很大的长度
+const MAX_PREALLOCATE: usize = 1024;
+
+/// 还没有收到全部元素的array/map/set/push
+#[derive(Debug)]
+struct Pending {
+    prefix: u8,
+    /// 需要的元素个数, map的key和value分别计数
+    len: usize,
+    frames: Vec<RespFrame>,
+}
+
+impl Pending {
+    fn new(prefix: u8, len: usize) -> Self {
+        Self {
+            prefix,
+            len,
+            frames: Vec::with_capacity(len.min(MAX_PREALLOCATE)),
+        }
+    }
+
+    /// map的key只能是SimpleString
+    fn expects_key(&self) -> bool {
+        self.prefix == b'%' && self.frames.len().is_multiple_of(2)
+    }
+
+    fn finish(self) -> RespFrame {
+        match self.prefix {
+            b'~' => RespSet::new(self.frames).into(),
+            b'>' => RespPush::new(self.frames).into(),
+            b'%' => {
+                let mut map = RespMap::new();
+                let mut frames = self.frames.into_iter();
+                while let (Some(RespFrame::SimpleString(key)), Some(value)) =
+                    (frames.next(), frames.next())
+                {
+                    map.insert(key.0, value);
+                }
+                map.into()
+            }
+            _ => RespArray::new(self.frames).into(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RespParser {
+    /// 外层的聚合类型在前面
+    stack: Vec<Pending>,
+    /// 当前frame已经从buf中取走的字节数
+    consumed: usize,
+}
+
+impl RespParser {
+    /// 没有解析到一半的frame, buf的开头是一个新的frame
+    pub fn is_idle(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    /// 解析出一个完整的frame时返回frame和它的总长度, 数据不完整时返回None,
+    /// limits不为None时按客户端的请求检查
+    pub fn parse(
+        &mut self,
+        buf: &mut BytesMut,
+        limits: Option<&DecodeLimits>,
+    ) -> Result<Option<(RespFrame, usize)>, RespError> {
+        let result = self.parse_frame(buf, limits);
+        // 出错之后buf已经无法继续解析, 清空状态
+        if result.is_err() {
+            *self = Self::default();
+        }
+        result
+    }
+
+    fn parse_frame(
+        &mut self,
+        buf: &mut BytesMut,
+        limits: Option<&DecodeLimits>,
+    ) -> Result<Option<(RespFrame, usize)>, RespError> {
+        loop {
+            let before = buf.len();
+            let element = match self.parse_element(buf, limits) {
+                Err(RespError::NotCompleteFrame) => return Ok(None),
+                element => element?,
+            };
+            self.consumed += before - buf.len();
+            let Some(frame) = element else {
+                continue;
+            };
+            if let Some(frame) = self.complete(frame) {
+                return Ok(Some((frame, std::mem::take(&mut self.consumed))));
+            }
+        }
+    }
+
+    /// 解析buf开头的一个元素, 遇到聚合类型的头部时入栈并返回None
+    fn parse_element(
+        &mut self,
+        buf: &mut BytesMut,
+        limits: Option<&DecodeLimits>,
+    ) -> Result<Option<RespFrame>, RespError> {
+        let Some(&prefix) = buf.first() else {
+            return Err(RespError::NotCompleteFrame);
+        };
+        if let Some(limits) = limits {
+            limits.check_header(buf, !self.stack.is_empty())?;
+        }
+        if self.stack.last().is_some_and(Pending::expects_key) {
+            return Ok(Some(SimpleString::decode(buf)?.into()));
+        }
+        let (prefix_str, frame_type) = match prefix {
+            b'*' => (RespArray::PREFIX, RespArray::FRAME_TYPE),
+            b'%' => (RespMap::PREFIX, RespMap::FRAME_TYPE),
+            b'~' => (RespSet::PREFIX, RespSet::FRAME_TYPE),
+            b'>' => (RespPush::PREFIX, RespPush::FRAME_TYPE),
+            _ => return RespFrame::decode(buf).map(Some),
+        };
+        let (end, len) = parse_length(buf, prefix_str, frame_type)?;
+        buf.advance(end + CRLF_LEN);
+        match len {
+            -1 if prefix == b'*' => Ok(Some(RespArray::new_null_array().into())),
+            // map/set/push 没有null
+            -1 => Err(RespError::InvalidFrameLength(len)),
+            0 => Ok(Some(Pending::new(prefix, 0).finish())),
+            len => {
+                let len = len as usize;
+                let len = if prefix == b'%' { len * 2 } else { len };
+                self.stack.push(Pending::new(prefix, len));
+                Ok(None)
+            }
+        }
+    }
+
+    /// 把完整的元素放进外层的聚合类型, 最外层也完整时返回
+    fn complete(&mut self, mut frame: RespFrame) -> Option<RespFrame> {
+        while let Some(mut pending) = self.stack.pop() {
+            pending.frames.push(frame);
+            if pending.frames.len() < pending.len {
+                self.stack.push(pending);
+                return None;
+            }
+            frame = pending.finish();
+        }
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::resp::{BulkString, RespEncode};
+
+    fn request(n: usize) -> Vec<u8> {
+        let args = (0..n)
+            .map(|i| BulkString::new(format!("arg{i}")).into())
+            .collect::<Vec<RespFrame>>();
+        RespFrame::from(RespArray::new(args)).encode()
+    }
+
+    #[test]
+    fn test_fragmented_frame() -> anyhow::Result<()> {
+        let data = b"*3\r\n$3\r\nset\r\n%1\r\n+k\r\n~2\r\n:1\r\n#t\r\n*-1\r\n";
+        let mut parser = RespParser::default();
+        let mut buf = BytesMut::new();
+        // 每次只到达一个字节
+        let mut frames = Vec::new();
+        for byte in data {
+            buf.extend_from_slice(&[*byte]);
+            frames.push(parser.parse(&mut buf, None)?);
+        }
+        let (frame, len) = frames.pop().flatten().unwrap();
+        assert!(frames.iter().all(Option::is_none));
+
+        let mut map = RespMap::new();
+        map.insert(
+            "k".to_string(),
+            RespSet::new(vec![RespFrame::Integer(1), true.into()]).into(),
+        );
+        let expected = RespArray::new(vec![
+            BulkString::new("set").into(),
+            map.into(),
+            RespArray::new_null_array().into(),
+        ]);
+        assert_eq!(frame, expected.into());
+        assert_eq!(len, data.len());
+        assert!(parser.is_idle());
+        assert!(buf.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_consumed_elements() -> anyhow::Result<()> {
+        let data = request(100);
+        let mut parser = RespParser::default();
+        let mut buf = BytesMut::from(&data[..data.len() / 2]);
+        assert!(parser.parse(&mut buf, None)?.is_none());
+        // 已经完整的参数不会留在buf中等待下一次解析
+        assert!(buf.len() < 16);
+        buf.extend_from_slice(&data[data.len() / 2..]);
+        buf.extend_from_slice(b"*0\r\n");
+        let (frame, len) = parser.parse(&mut buf, None)?.unwrap();
+        assert_eq!(frame.encode(), data);
+        assert_eq!(len, data.len());
+        assert_eq!(
+            parser.parse(&mut buf, None)?,
+            Some((RespArray::new(vec![]).into(), 4))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_hostile_input() {
+        let invalid: &[&[u8]] = &[
+            b"?foo\r\n",
+            b"*1\r\n!oops\r\n",
+            b"*-2\r\n",
+            b"*1\r\n$-5\r\n",
+            b"%-1\r\n",
+            b"*1\r\n~-1\r\n",
+            b">-1\r\n",
+            b"*99999999999999999999\r\n",
+            b"*1\r\n$99999999999999999999\r\n",
+            b"%99999999999999999999\r\n",
+            b"~x\r\n",
+            b"*1\r\n$3\r\nget!!\r\n",
+            b"%1\r\n$1\r\nk\r\n:1\r\n",
+            b"*2\r\n#x\r\n",
+            b"*2\r\n,1.2.3\r\n",
+        ];
+        for data in invalid {
+            let mut parser = RespParser::default();
+            let mut buf = BytesMut::from(*data);
+            let err = parser.parse(&mut buf, None).unwrap_err();
+            assert_ne!(err, RespError::NotCompleteFrame, "{:?}", data);
+            assert!(parser.is_idle());
+        }
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let limits = DecodeLimits {
+            max_bulk_len: 5,
+            max_multibulk_len: 3,
+            max_query_buffer: 64,
+        };
+        let cases: &[(&[u8], &str)] = &[
+            (b"*4\r\n", "invalid multibulk length"),
+            (b"*x\r\n", "invalid multibulk length"),
+            // 只收到了头部时也能发现
+            (
+                b"*2\r\n$3\r\nget\r\n$999999999999\r\n",
+                "invalid bulk length",
+            ),
+            (b"*1\r\n$x\r\n", "invalid bulk length"),
+            (b"*2\r\n$3\r\nget\r\n*1\r\n", "expected '$', got '*'"),
+        ];
+        for (data, message) in cases {
+            let mut parser = RespParser::default();
+            let mut buf = BytesMut::from(*data);
+            assert_eq!(
+                parser.parse(&mut buf, Some(&limits)),
+                Err(RespError::ProtocolError(message.to_string()))
+            );
+            assert!(parser.is_idle());
+        }
+        let mut parser = RespParser::default();
+        let mut buf = BytesMut::from("*2\r\n$3\r\nget\r\n$5\r\nhello\r\n");
+        assert!(matches!(parser.parse(&mut buf, Some(&limits)), Ok(Some(_))));
+    }
+}
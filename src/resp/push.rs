@@ -0,0 +1,93 @@
+use std::ops::Deref;
+
+use bytes::{Buf, BytesMut};
+
+use super::{calc_total_length, parse_aggregate_length, CRLF_LEN};
+use super::{write_line, RespDecode, RespEncode, RespError, RespFrame};
+
+/// RESP3的push类型, 服务器主动发送的消息, 例如CLIENT TRACKING的invalidate
+#[derive(Debug, PartialEq, PartialOrd, Clone, Eq)]
+pub struct RespPush(pub(crate) Vec<RespFrame>);
+
+impl RespEncode for RespPush {
+    fn encode_to(self, buf: &mut BytesMut) {
+        write_line(buf, b'>', self.len());
+        for frame in self.0 {
+            frame.encode_to(buf);
+        }
+    }
+}
+
+impl RespDecode for RespPush {
+    const PREFIX: &'static str = ">";
+    const FRAME_TYPE: &'static str = "RespPush";
+    fn decode(buf: &mut BytesMut) -> Result<Self, RespError> {
+        let (end, len) = parse_aggregate_length(buf, Self::PREFIX, Self::FRAME_TYPE)?;
+        let total_len = calc_total_length(buf, end, len, Self::PREFIX)?;
+        if buf.len() < total_len {
+            return Err(RespError::NotCompleteFrame);
+        }
+        buf.advance(end + CRLF_LEN);
+        let mut frames = Vec::with_capacity(len);
+
+        for _ in 0..len {
+            let frame = RespFrame::decode(buf)?;
+            frames.push(frame);
+        }
+        Ok(RespPush::new(frames))
+    }
+
+    fn expect_length(buf: &[u8]) -> Result<usize, RespError> {
+        let (end, len) = parse_aggregate_length(buf, Self::PREFIX, Self::FRAME_TYPE)?;
+        calc_total_length(buf, end, len, Self::PREFIX)
+    }
+}
+
+impl RespPush {
+    pub fn new(s: impl Into<Vec<RespFrame>>) -> Self {
+        RespPush(s.into())
+    }
+}
+
+impl Deref for RespPush {
+    type Target = Vec<RespFrame>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::resp::{frame::RespFrame, BulkString, RespArray, RespDecode, RespEncode, RespPush};
+    use anyhow::Result;
+    use bytes::BytesMut;
+
+    #[test]
+    fn test_push_encode() {
+        let frame: RespFrame = RespPush::new(vec![
+            BulkString::new("invalidate").into(),
+            RespArray::new(vec![BulkString::new("foo").into()]).into(),
+        ])
+        .into();
+        assert_eq!(
+            frame.encode(),
+            b">2\r\n$10\r\ninvalidate\r\n*1\r\n$3\r\nfoo\r\n"
+        );
+    }
+
+    #[test]
+    fn test_push_decode() -> Result<()> {
+        let mut buf = BytesMut::from(">2\r\n$10\r\ninvalidate\r\n*1\r\n$3\r\nfoo\r\n");
+        let frame = RespFrame::decode(&mut buf)?;
+        assert_eq!(
+            frame,
+            RespPush::new(vec![
+                BulkString::new("invalidate").into(),
+                RespArray::new(vec![BulkString::new("foo").into()]).into(),
+            ])
+            .into()
+        );
+        assert!(buf.is_empty());
+        Ok(())
+    }
+}
@@ -2,19 +2,17 @@ use std::ops::Deref;
 
 use bytes::{Buf, BytesMut};
 
-use super::{calc_total_length, parse_length, CRLF_LEN};
-use super::{RespDecode, RespEncode, RespError, RespFrame, BUF_CAPACITY};
+use super::{calc_total_length, parse_aggregate_length, CRLF_LEN};
+use super::{write_line, RespDecode, RespEncode, RespError, RespFrame};
 #[derive(Debug, PartialEq, PartialOrd, Clone, Eq)]
 pub struct RespSet(pub(crate) Vec<RespFrame>);
 
 impl RespEncode for RespSet {
-    fn encode(self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(BUF_CAPACITY);
-        buf.extend_from_slice(&format!("~{}\r\n", self.len()).into_bytes());
+    fn encode_to(self, buf: &mut BytesMut) {
+        write_line(buf, b'~', self.len());
         for frame in self.0 {
-            buf.extend_from_slice(&frame.encode());
+            frame.encode_to(buf);
         }
-        buf
     }
 }
 
@@ -23,8 +21,7 @@ impl RespDecode for RespSet {
     const FRAME_TYPE: &'static str = "RespSet";
     fn decode(buf: &mut BytesMut) -> Result<Self, RespError> {
         // let prefix = "~";
-        let (end, len) = parse_length(buf, Self::PREFIX, Self::FRAME_TYPE)?;
-        let len = len as usize;
+        let (end, len) = parse_aggregate_length(buf, Self::PREFIX, Self::FRAME_TYPE)?;
         let total_len = calc_total_length(buf, end, len, Self::PREFIX)?;
         if buf.len() < total_len {
             return Err(RespError::NotCompleteFrame);
@@ -40,8 +37,7 @@ impl RespDecode for RespSet {
     }
 
     fn expect_length(buf: &[u8]) -> Result<usize, RespError> {
-        let (end, len) = parse_length(buf, Self::PREFIX, Self::FRAME_TYPE)?;
-        let len = len as usize;
+        let (end, len) = parse_aggregate_length(buf, Self::PREFIX, Self::FRAME_TYPE)?;
         calc_total_length(buf, end, len, "~")
     }
 }
@@ -2,14 +2,14 @@ use std::ops::Deref;
 
 use bytes::BytesMut;
 
-use super::{extract_simple_frame_data, RespDecode, RespEncode, RespError, CRLF_LEN};
+use super::{extract_simple_frame_data, write_line, RespDecode, RespEncode, RespError, CRLF_LEN};
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Clone)]
 pub struct SimpleError(pub(crate) String);
 
 impl RespEncode for SimpleError {
-    fn encode(self) -> Vec<u8> {
-        format!("-{}\r\n", *self).into_bytes()
+    fn encode_to(self, buf: &mut BytesMut) {
+        write_line(buf, b'-', &*self);
     }
 }
 
@@ -0,0 +1,125 @@
+//! 和redis的sdssplitargs一样按空白切分参数, 配置文件和inline命令共用
+//!
+//! 支持"..."(带转义, 包括\xHH)和'...', 右引号后面必须是空白或者结尾
+
+/// 引号中的转义, 不认识的转义字符保持原样
+fn unescape(c: u8) -> u8 {
+    match c {
+        b'n' => b'\n',
+        b'r' => b'\r',
+        b't' => b'\t',
+        b'b' => 0x08,
+        b'a' => 0x07,
+        c => c,
+    }
+}
+
+fn hex_byte(hi: u8, lo: u8) -> Option<u8> {
+    let hi = (hi as char).to_digit(16)?;
+    let lo = (lo as char).to_digit(16)?;
+    Some((hi * 16 + lo) as u8)
+}
+
+/// 引号不匹配时返回None
+pub fn split_args(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut args = Vec::new();
+    let mut i = 0;
+    loop {
+        // \0 和空白一样是分隔符, 否则会一直切出空的参数
+        while i < line.len() && (line[i].is_ascii_whitespace() || line[i] == b'\0') {
+            i += 1;
+        }
+        if i == line.len() {
+            return Some(args);
+        }
+        let mut arg = Vec::new();
+        let (mut in_dq, mut in_sq) = (false, false);
+        loop {
+            let Some(&c) = line.get(i) else {
+                if in_dq || in_sq {
+                    return None;
+                }
+                break;
+            };
+            if in_dq {
+                match c {
+                    b'\\' if i + 1 < line.len() => {
+                        let hex = line.get(i + 2..i + 4).filter(|_| line[i + 1] == b'x');
+                        match hex.and_then(|hex| hex_byte(hex[0], hex[1])) {
+                            Some(byte) => {
+                                arg.push(byte);
+                                i += 3;
+                            }
+                            None => {
+                                i += 1;
+                                arg.push(unescape(line[i]));
+                            }
+                        }
+                    }
+                    // 右引号后面必须是空白或者结尾
+                    b'"' => match line.get(i + 1) {
+                        Some(next) if !next.is_ascii_whitespace() => return None,
+                        _ => in_dq = false,
+                    },
+                    c => arg.push(c),
+                }
+            } else if in_sq {
+                match c {
+                    b'\\' if line.get(i + 1) == Some(&b'\'') => {
+                        i += 1;
+                        arg.push(b'\'');
+                    }
+                    b'\'' => match line.get(i + 1) {
+                        Some(next) if !next.is_ascii_whitespace() => return None,
+                        _ => in_sq = false,
+                    },
+                    c => arg.push(c),
+                }
+            } else {
+                match c {
+                    b' ' | b'\n' | b'\r' | b'\t' | b'\0' => break,
+                    b'"' => in_dq = true,
+                    b'\'' => in_sq = true,
+                    c => arg.push(c),
+                }
+            }
+            i += 1;
+        }
+        args.push(arg);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn split(line: &str) -> Option<Vec<String>> {
+        Some(
+            split_args(line.as_bytes())?
+                .into_iter()
+                .map(|arg| String::from_utf8_lossy(&arg).into_owned())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(split("  set  foo bar ").unwrap(), ["set", "foo", "bar"]);
+        assert_eq!(
+            split(r#"set "hello world" 'it\'s'"#).unwrap(),
+            ["set", "hello world", "it's"]
+        );
+        assert_eq!(
+            split(r#"set "a b\n" 'c d' e"#).unwrap(),
+            ["set", "a b\n", "c d", "e"]
+        );
+        assert_eq!(split(r#"echo "a\tb\x41\n""#).unwrap(), ["echo", "a\tbA\n"]);
+        assert_eq!(split(r#"echo """#).unwrap(), ["echo", ""]);
+        assert_eq!(split("").unwrap(), Vec::<String>::new());
+        assert_eq!(split(r#"set "foo"bar"#), None);
+        assert_eq!(split(r#"set "a"#), None);
+        assert_eq!(split("set 'foo"), None);
+        assert_eq!(split("get a\0b").unwrap(), ["get", "a", "b"]);
+        assert_eq!(split("\0get\0\0k\0").unwrap(), ["get", "k"]);
+    }
+}
@@ -0,0 +1,261 @@
+//! TLS: tls-port 上的加密连接, 以及 tls-cluster 开启时 CLUSTER MEET / MIGRATE 连接其他节点时使用TLS
+//!
+//! tls-auth-clients 为yes时客户端必须提供tls-ca-cert-file签发的证书, optional时提供了才检查。
+//! replica和普通客户端一样连接tls-port, 复制流走的就是这个加密连接
+
+use crate::config::Config;
+use anyhow::{anyhow, Context, Result};
+use std::{fmt, fs::File, io::BufReader, sync::Arc};
+use tokio_rustls::{
+    rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+        server::WebPkiClientVerifier,
+        ClientConfig, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+#[derive(Clone, Default)]
+pub struct TlsContext {
+    /// tls-port 不为0时才有
+    acceptor: Option<TlsAcceptor>,
+    /// tls-cluster 为yes时才有
+    connector: Option<TlsConnector>,
+}
+
+impl fmt::Debug for TlsContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsContext")
+            .field("server", &self.acceptor.is_some())
+            .field("client", &self.connector.is_some())
+            .finish()
+    }
+}
+
+fn load_certs(file: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(file).with_context(|| file.to_string())?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| file.to_string())?;
+    if certs.is_empty() {
+        return Err(anyhow!("{}: no certificate found", file));
+    }
+    Ok(certs)
+}
+
+fn load_key(file: &str) -> Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(file).with_context(|| file.to_string())?);
+    rustls_pemfile::private_key(&mut reader)
+        .with_context(|| file.to_string())?
+        .ok_or_else(|| anyhow!("{}: no private key found", file))
+}
+
+fn load_roots(file: &str) -> Result<Arc<RootCertStore>> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(file)? {
+        roots.add(cert).with_context(|| file.to_string())?;
+    }
+    Ok(Arc::new(roots))
+}
+
+/// 开启TLS时必须配置的文件
+fn required<'a>(name: &str, value: &'a str) -> Result<&'a str> {
+    match value {
+        "" => Err(anyhow!("TLS is enabled but {} is not set", name)),
+        value => Ok(value),
+    }
+}
+
+impl TlsContext {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let enabled = config.get_parsed::<u16>("tls-port") != 0;
+        let cluster = config.get_bool("tls-cluster");
+        if !enabled && !cluster {
+            return Ok(Self::default());
+        }
+        let get = |name| config.get(name).unwrap_or_default();
+        let (cert_file, key_file, ca_file) = (
+            get("tls-cert-file"),
+            get("tls-key-file"),
+            get("tls-ca-cert-file"),
+        );
+        let certs = load_certs(required("tls-cert-file", &cert_file)?)?;
+        let key = load_key(required("tls-key-file", &key_file)?)?;
+
+        let acceptor = if enabled {
+            let builder = match get("tls-auth-clients").as_str() {
+                "no" => ServerConfig::builder().with_no_client_auth(),
+                auth => {
+                    let roots = load_roots(required("tls-ca-cert-file", &ca_file)?)?;
+                    let verifier = WebPkiClientVerifier::builder(roots);
+                    let verifier = match auth {
+                        "optional" => verifier.allow_unauthenticated().build()?,
+                        _ => verifier.build()?,
+                    };
+                    ServerConfig::builder().with_client_cert_verifier(verifier)
+                }
+            };
+            let server = builder.with_single_cert(certs.clone(), key.clone_key())?;
+            Some(TlsAcceptor::from(Arc::new(server)))
+        } else {
+            None
+        };
+
+        // 连接其他节点时用同一个证书做客户端认证
+        let connector = if cluster {
+            let roots = load_roots(required("tls-ca-cert-file", &ca_file)?)?;
+            let client = ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_client_auth_cert(certs, key)?;
+            Some(TlsConnector::from(Arc::new(client)))
+        } else {
+            None
+        };
+        Ok(Self {
+            acceptor,
+            connector,
+        })
+    }
+
+    pub fn acceptor(&self) -> Option<&TlsAcceptor> {
+        self.acceptor.as_ref()
+    }
+
+    pub fn connector(&self) -> Option<&TlsConnector> {
+        self.connector.as_ref()
+    }
+}
+
+/// 连接地址中的host部分作为证书校验的名字, ip地址校验证书中的IP SAN
+pub fn server_name(addr: &str) -> Result<ServerName<'static>> {
+    let host = match addr.rsplit_once(':') {
+        Some((host, _)) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => addr,
+    };
+    ServerName::try_from(host.to_string()).map_err(|_| anyhow!("invalid server name: {}", host))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backend::Backend,
+        network::{tls_stream_handler, RespClient},
+        resp::{frame::RespFrame, BulkString},
+    };
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use std::path::PathBuf;
+    use tokio::net::TcpListener;
+
+    /// 自签名的CA, 以及CA签发的127.0.0.1的证书, 返回 (ca, cert, key) 三个文件
+    fn write_certs(name: &str) -> Result<(PathBuf, PathBuf, PathBuf)> {
+        let ca_key = KeyPair::generate()?;
+        let mut params = CertificateParams::new(Vec::<String>::new())?;
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key)?;
+        let key = KeyPair::generate()?;
+        let cert =
+            CertificateParams::new(vec!["127.0.0.1".to_string()])?.signed_by(&key, &ca, &ca_key)?;
+
+        let dir = std::env::temp_dir();
+        let path =
+            |ext: &str| dir.join(format!("redis-tls-{}-{}.{}", name, std::process::id(), ext));
+        let files = (path("ca.pem"), path("cert.pem"), path("key.pem"));
+        std::fs::write(&files.0, ca.pem())?;
+        std::fs::write(&files.1, cert.pem())?;
+        std::fs::write(&files.2, key.serialize_pem())?;
+        Ok(files)
+    }
+
+    fn config(files: &(PathBuf, PathBuf, PathBuf), extra: &[(&str, &str)]) -> Result<Config> {
+        let mut overrides = vec![
+            ("tls-port", "6380"),
+            ("tls-ca-cert-file", files.0.to_str().unwrap()),
+            ("tls-cert-file", files.1.to_str().unwrap()),
+            ("tls-key-file", files.2.to_str().unwrap()),
+        ];
+        overrides.extend_from_slice(extra);
+        let overrides: Vec<(String, String)> = overrides
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Ok(Config::load(None, &overrides)?)
+    }
+
+    /// 在随机端口上接受一个TLS连接, 返回端口
+    async fn serve_once(tls: &TlsContext) -> Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?.to_string();
+        let acceptor = tls.acceptor().cloned().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await?;
+            tls_stream_handler(socket, acceptor, "", "", Backend::new()).await
+        });
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn test_tls_connection() -> Result<()> {
+        let files = write_certs("conn")?;
+        let tls = TlsContext::from_config(&config(&files, &[("tls-cluster", "yes")])?)?;
+        let addr = serve_once(&tls).await?;
+        let mut client = RespClient::connect(&addr, &tls).await?;
+        let reply = client.call(["echo", "hello"]).await?;
+        assert_eq!(reply, RespFrame::from(BulkString::new("hello")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tls_auth_clients() -> Result<()> {
+        let files = write_certs("auth")?;
+        // 不带客户端证书的连接
+        let client = ClientConfig::builder()
+            .with_root_certificates(load_roots(files.0.to_str().unwrap())?)
+            .with_no_client_auth();
+        let no_cert = TlsContext {
+            acceptor: None,
+            connector: Some(TlsConnector::from(Arc::new(client))),
+        };
+
+        let tls = TlsContext::from_config(&config(&files, &[])?)?;
+        let addr = serve_once(&tls).await?;
+        let rejected = match RespClient::connect(&addr, &no_cert).await {
+            Ok(mut client) => client.call(["echo", "hello"]).await.is_err(),
+            Err(_) => true,
+        };
+        assert!(rejected);
+
+        let tls = TlsContext::from_config(&config(&files, &[("tls-auth-clients", "optional")])?)?;
+        let addr = serve_once(&tls).await?;
+        let mut client = RespClient::connect(&addr, &no_cert).await?;
+        assert_eq!(
+            client.call(["echo", "hello"]).await?,
+            RespFrame::from(BulkString::new("hello"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tls_config_errors() -> Result<()> {
+        assert!(TlsContext::from_config(&Config::default())?
+            .acceptor()
+            .is_none());
+        let missing = config(
+            &(
+                PathBuf::new(),
+                PathBuf::from("/nonexistent.pem"),
+                PathBuf::new(),
+            ),
+            &[],
+        )?;
+        let err = TlsContext::from_config(&missing).unwrap_err().to_string();
+        assert_eq!(err, "/nonexistent.pem");
+
+        let files = write_certs("errors")?;
+        let no_key = config(&files, &[("tls-key-file", "")])?;
+        let err = TlsContext::from_config(&no_key).unwrap_err().to_string();
+        assert_eq!(err, "TLS is enabled but tls-key-file is not set");
+        Ok(())
+    }
+}